@@ -3,7 +3,7 @@ use kallsyms_tools::{ldscript::ldscript, symbol::symbols_from_file};
 const LD_COMMAND: &str = "rust-lld";
 
 fn main() {
-    use std::{env, fs, path::Path, process};
+    use std::{env, fs, io::Write, path::Path, process};
 
     let args: Vec<String> = env::args().into_iter().skip(1).collect();
     let (o_pos, _) = args
@@ -41,23 +41,26 @@ fn main() {
         let mut args_tmp = args.clone();
         args_tmp[out_path_pos] = tmp_path_bin.clone();
 
+        // kallsyms.ld, which the .kallsyms section INCLUDEs; the table of
+        // the previous output, or an empty table on the first pass
+        let mut tmp_path_dir = out_dir.to_path_buf();
+        tmp_path_dir.push(format!("{}.kallsyms", tmp_fn_bin));
+        fs::create_dir_all(&tmp_path_dir).expect("Failed to create kallsyms directory");
+        let mut file = fs::File::create(tmp_path_dir.join("kallsyms.ld"))
+            .expect("Failed to create linker script file");
         if i > 0 {
             println!("iteration {}", i);
-
-            // generate kallsyms from previous output
-            let tmp_fn_ld = format!("{}.ld", tmp_fn_bin);
-            let mut tmp_path_ld = out_dir.to_path_buf();
-            tmp_path_ld.push(tmp_fn_ld);
-            let mut file = fs::File::create(tmp_path_ld.as_os_str())
-                .expect("Failed to create linker script file");
             ldscript(&prev_outfn, &mut file);
-            file.sync_all().expect("Failed to write linker script file");
-
-            let tmp_path_ld = tmp_path_ld
-                .to_str()
-                .expect("Failed to convert tmp_path_ld into utf-8");
-            args_tmp.push(format!("-T{}", tmp_path_ld));
+        } else {
+            file.write_all(b"LONG(0); LONG(0); LONG(0);\n")
+                .expect("Failed to write linker script file");
         }
+        file.sync_all().expect("Failed to write linker script file");
+
+        let tmp_path_dir = tmp_path_dir
+            .to_str()
+            .expect("Failed to convert tmp_path_dir into utf-8");
+        args_tmp.push(format!("-L{}", tmp_path_dir));
 
         let mut linker = process::Command::new(LD_COMMAND);
         linker.args(&args_tmp);
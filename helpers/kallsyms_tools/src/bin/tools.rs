@@ -1,8 +1,9 @@
 extern crate clap;
 use clap::{Parser, Subcommand};
 
-extern crate kallsyms;
+extern crate kallsyms_enc;
 
+use kallsyms_tools::coredump::{report, CoreDump, Firmware};
 use kallsyms_tools::ldscript::ldscript;
 use kallsyms_tools::symbol::symbols_from_file;
 
@@ -29,6 +30,15 @@ enum Commands {
         #[clap(value_name = "FILENAME")]
         filename: String,
     },
+
+    /// Print the backtrace and memory map of a kernel core dump
+    Coredump {
+        #[clap(value_name = "FIRMWARE")]
+        firmware: String,
+
+        #[clap(value_name = "CORE")]
+        core: String,
+    },
 }
 
 fn main() {
@@ -48,7 +58,7 @@ fn main() {
                 .into_iter()
                 .map(|s| (s.name, s.addr))
                 .collect();
-            let data = kallsyms::pack(&symbols);
+            let data = kallsyms_enc::pack(&symbols);
             std::io::stdout().write(&data).unwrap();
 
             let plain_len = (symbols.len() * 6)
@@ -67,6 +77,19 @@ fn main() {
             ldscript(filename.into(), &mut std::io::stdout());
         }
 
+        Some(Commands::Coredump { firmware, core }) => {
+            let data = std::fs::read(core).unwrap();
+            let core = match CoreDump::from_bytes(&data) {
+                Ok(core) => core,
+                Err(e) => {
+                    eprintln!("{}: {}", core, e);
+                    std::process::exit(1);
+                }
+            };
+            let firmware = Firmware::from_file(firmware);
+            report(&core, &firmware, &mut std::io::stdout()).unwrap();
+        }
+
         None => {}
     }
 }
@@ -0,0 +1,460 @@
+/*
+
+Post-mortem of a core file written by the kernel (src/coredump.rs): the
+registers and memory it holds are unwound with the unwind tables of the
+firmware ELF, and symbolized with its symbols and line table.
+
+ */
+
+use std::io;
+
+extern crate ehabi;
+use ehabi::{ExidxTable, Regs};
+
+extern crate elf_parser;
+use elf_parser::{ElfParser, ElfSegmentType};
+
+use crate::line::{lines_from_file, Line};
+use crate::symbol::{symbols_from_file, Symbol};
+
+const NT_PRSTATUS: u32 = 1;
+const PRSTATUS_REG_OFF: usize = 72;
+const NT_CRASH: u32 = 1;
+
+const EXC_RETURN_SPSEL: u32 = 1 << 2;
+const EXC_RETURN_FTYPE: u32 = 1 << 4;
+const EXC_RETURN_DCRS: u32 = 1 << 5;
+const XPSR_STKALIGN: u32 = 1 << 9;
+
+/// Contents of the "BARBARA" note
+#[derive(Default, PartialEq, Debug)]
+pub struct CrashInfo {
+    pub exception: u32,
+    pub fault: u32,
+    pub exc_return: u32,
+    pub msp: u32,
+    pub psp: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub sfsr: u32,
+    pub sfar: u32,
+    pub cause: String,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct CoreDump {
+    pub regs: Regs,
+    pub xpsr: u32,
+    pub info: CrashInfo,
+    pub segments: Vec<Segment>,
+}
+
+fn word(data: &[u8], off: usize) -> Option<u32> {
+    let b = data.get(off..off + 4)?;
+    Some(u32::from_le_bytes(<[u8; 4]>::try_from(b).unwrap()))
+}
+
+/// (name, type, desc) of each note
+fn notes(mut data: &[u8]) -> Vec<(&[u8], u32, &[u8])> {
+    let align = |len: usize| (len + 3) & !3;
+    let mut notes = Vec::new();
+    while let (Some(namesz), Some(descsz), Some(typ)) =
+        (word(data, 0), word(data, 4), word(data, 8))
+    {
+        let (namesz, descsz) = (namesz as usize, descsz as usize);
+        let desc_off = 12 + align(namesz);
+        let (name, desc) = match (
+            data.get(12..12 + namesz),
+            data.get(desc_off..desc_off + descsz),
+        ) {
+            (Some(name), Some(desc)) => (name, desc),
+            _ => break,
+        };
+        notes.push((name.strip_suffix(b"\0").unwrap_or(name), typ, desc));
+        data = &data[(desc_off + align(descsz)).min(data.len())..];
+    }
+    notes
+}
+
+fn crash_info(desc: &[u8]) -> Option<CrashInfo> {
+    let w = |i: usize| word(desc, i * 4);
+    let cause = desc.get(44..)?;
+    let len = cause.iter().position(|&c| c == 0).unwrap_or(cause.len());
+    Some(CrashInfo {
+        exception: w(0)?,
+        fault: w(1)?,
+        exc_return: w(2)?,
+        msp: w(3)?,
+        psp: w(4)?,
+        cfsr: w(5)?,
+        hfsr: w(6)?,
+        mmfar: w(7)?,
+        bfar: w(8)?,
+        sfsr: w(9)?,
+        sfar: w(10)?,
+        cause: String::from_utf8_lossy(&cause[..len]).into_owned(),
+    })
+}
+
+impl CoreDump {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let parser = ElfParser::from_bytes(data).map_err(|e| format!("{:?}", e))?;
+
+        let mut core = Self {
+            regs: Regs::default(),
+            xpsr: 0,
+            info: CrashInfo::default(),
+            segments: Vec::new(),
+        };
+        let mut has_regs = false;
+        for seg in parser.segments() {
+            match seg.typ {
+                ElfSegmentType::Load => core.segments.push(Segment {
+                    addr: seg.vaddr as u32,
+                    data: seg.content.to_vec(),
+                }),
+                ElfSegmentType::Note => {
+                    for (name, typ, desc) in notes(seg.content) {
+                        match (name, typ) {
+                            (b"CORE", NT_PRSTATUS) => {
+                                for (i, r) in core.regs.r.iter_mut().enumerate() {
+                                    *r = word(desc, PRSTATUS_REG_OFF + i * 4)
+                                        .ok_or("truncated NT_PRSTATUS")?;
+                                }
+                                core.xpsr = word(desc, PRSTATUS_REG_OFF + 64)
+                                    .ok_or("truncated NT_PRSTATUS")?;
+                                has_regs = true;
+                            }
+                            (b"BARBARA", NT_CRASH) => {
+                                core.info = crash_info(desc).ok_or("truncated crash note")?;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !has_regs {
+            return Err(String::from("no NT_PRSTATUS note"));
+        }
+        Ok(core)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.segments.iter().find_map(|seg| {
+            let off = addr.checked_sub(seg.addr)? as usize;
+            word(&seg.data, off)
+        })
+    }
+}
+
+pub struct Firmware {
+    sections: Vec<(u32, Vec<u8>)>,
+    symbols: Vec<Symbol>,
+    lines: Vec<Line>,
+    exidx: ExidxTable,
+    has_exidx: bool,
+    region_names: Vec<(u32, String)>,
+}
+
+impl Firmware {
+    pub fn from_file(filename: &str) -> Self {
+        let data = std::fs::read(filename).unwrap();
+        let parser = ElfParser::from_bytes(&data).unwrap();
+
+        let mut sections = Vec::new();
+        for name in [&b".vector_table"[..], b".rom"] {
+            if let Some((addr, content)) = parser.section(name) {
+                sections.push((addr as u32, content.to_vec()));
+            }
+        }
+
+        let symbol = |name: &[u8]| {
+            parser
+                .iter_symbols()
+                .filter_map(|s| s.ok())
+                .find(|s| s.name == name)
+                .map(|s| s.value as u32)
+        };
+        let (exidx_s, exidx_e) = match (symbol(b"__exidx_s"), symbol(b"__exidx_e")) {
+            (Some(s), Some(e)) => (s, e),
+            _ => (0, 0),
+        };
+
+        // the regions the kernel dumps start with these sections
+        let region_names = [(".data", "ram"), (".stack", "stack"), (".heap", "heap")]
+            .iter()
+            .filter_map(|(section, name)| {
+                let (addr, _) = parser.section(section.as_bytes())?;
+                Some((addr as u32, String::from(*name)))
+            })
+            .collect();
+
+        Self {
+            sections,
+            symbols: symbols_from_file(filename),
+            lines: lines_from_file(filename),
+            exidx: ExidxTable::new(exidx_s, exidx_e),
+            has_exidx: exidx_s < exidx_e,
+            region_names,
+        }
+    }
+
+    fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.sections.iter().find_map(|(start, data)| {
+            let off = addr.checked_sub(*start)? as usize;
+            word(data, off)
+        })
+    }
+
+    fn in_text(&self, addr: u32) -> bool {
+        match self.symbols.first() {
+            Some(first) => first.addr & !1 <= addr && self.read_u32(addr & !3).is_some(),
+            None => false,
+        }
+    }
+
+    /// "name +0xoff (file:line)" for `addr`, as the kernel prints it
+    pub fn symbolize(&self, addr: u32) -> String {
+        let idx = self.symbols.partition_point(|s| s.addr <= addr);
+        let sym = match idx {
+            0 => return String::new(),
+            i => &self.symbols[i - 1],
+        };
+        let off = addr - sym.addr;
+        let code = if addr & 1 != 0 && off >= 3 {
+            addr - 3
+        } else {
+            addr & !1
+        };
+        let idx = self.lines.partition_point(|l| l.addr <= code);
+        match idx.checked_sub(1).map(|i| &self.lines[i]) {
+            Some(line) if !line.file.is_empty() => {
+                format!("{} +{:#x} ({}:{})", sym.name, off, line.file, line.line)
+            }
+            _ => format!("{} +{:#x}", sym.name, off),
+        }
+    }
+}
+
+/// A line of a backtrace, see the kernel's backtrace.rs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Frame {
+    Call(u32),
+    Exception(u32),
+}
+
+/// Load the registers stacked on exception entry, see the kernel's
+/// `backtrace::unstack`
+fn unstack(core: &CoreDump, exc_return: u32, frame: u32, regs: &mut Regs) -> Option<u32> {
+    let mut addr = frame;
+    if exc_return & EXC_RETURN_DCRS == 0 {
+        for i in 0..8 {
+            regs.r[4 + i] = core.read_u32(addr + 8 + i as u32 * 4)?;
+        }
+        addr += 0x28;
+    }
+    let w = |i: u32| core.read_u32(addr + i * 4);
+    let xpsr = w(7)?;
+    let mut sp = addr + 0x20;
+    if exc_return & EXC_RETURN_FTYPE == 0 {
+        sp += 0x48;
+    }
+    if xpsr & XPSR_STKALIGN != 0 {
+        sp += 4;
+    }
+    for i in 0..4 {
+        regs.r[i] = w(i as u32)?;
+    }
+    regs.r[12] = w(4)?;
+    regs.r[ehabi::LR] = w(5)?;
+    regs.r[ehabi::PC] = w(6)?;
+    regs.r[ehabi::SP] = sp;
+    Some(xpsr)
+}
+
+fn is_exc_return(v: u32) -> bool {
+    v >> 24 == 0xff
+}
+
+/// Unwind from the registers of the core file, with the EHABI tables of the
+/// firmware or, if it has none, the frame pointer chain
+pub fn backtrace(core: &CoreDump, fw: &Firmware, limit: usize) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut regs = core.regs;
+    let mut exception = core.info.exception;
+    let mut is_return_address = false;
+    let mem = |addr: u32| core.read_u32(addr).or_else(|| fw.read_u32(addr));
+
+    while frames.len() < limit {
+        let pc = regs.pc();
+        if is_exc_return(pc) {
+            let frame = if pc & EXC_RETURN_SPSEL != 0 {
+                core.info.psp
+            } else {
+                regs.sp()
+            };
+            match unstack(core, pc, frame, &mut regs) {
+                Some(xpsr) => {
+                    frames.push(Frame::Exception(exception));
+                    exception = xpsr & 0x1ff;
+                    is_return_address = false;
+                    continue;
+                }
+                None => break,
+            }
+        }
+        if !fw.in_text(pc) {
+            break;
+        }
+        frames.push(Frame::Call(pc));
+
+        let sp = regs.sp();
+        if fw.has_exidx {
+            if fw
+                .exidx
+                .unwind_frame(&mem, &mut regs, is_return_address)
+                .is_err()
+            {
+                break;
+            }
+        } else {
+            // {r7, lr} frame records
+            let fp = regs.r[7];
+            match (mem(fp), mem(fp + 4)) {
+                (Some(next), Some(lr)) => {
+                    regs.r[7] = next;
+                    regs.r[ehabi::SP] = fp + 8;
+                    regs.r[ehabi::PC] = lr;
+                }
+                _ => break,
+            }
+        }
+        is_return_address = true;
+        if regs.sp() < sp || (regs.sp() == sp && regs.pc() == pc) {
+            break;
+        }
+    }
+    frames
+}
+
+pub fn report<W: io::Write>(core: &CoreDump, fw: &Firmware, w: &mut W) -> io::Result<()> {
+    let info = &core.info;
+    writeln!(w, "cause: {}", info.cause)?;
+    if info.fault != 0 {
+        writeln!(w, "fault: exception {}", info.fault)?;
+        writeln!(
+            w,
+            "CFSR: {:08x}  HFSR: {:08x}  SFSR: {:08x}",
+            info.cfsr, info.hfsr, info.sfsr
+        )?;
+        writeln!(
+            w,
+            "MMFAR: {:08x}  BFAR: {:08x}  SFAR: {:08x}",
+            info.mmfar, info.bfar, info.sfar
+        )?;
+        writeln!(w, "EXC_RETURN: {:08x}", info.exc_return)?;
+    }
+
+    let r = &core.regs.r;
+    writeln!(w, "pc : {:08x}  lr : {:08x}", r[15], r[14])?;
+    writeln!(w, "sp : {:08x}  r12: {:08x}", r[13], r[12])?;
+    for i in (0..12).rev().step_by(2) {
+        writeln!(
+            w,
+            "r{:<2}: {:08x}  r{:<2}: {:08x}",
+            i,
+            r[i],
+            i - 1,
+            r[i - 1]
+        )?;
+    }
+    writeln!(w, "pstate : {:08x}", core.xpsr)?;
+    writeln!(w, "msp : {:08x}  psp : {:08x}", info.msp, info.psp)?;
+
+    writeln!(w)?;
+    writeln!(w, "Backtrace:")?;
+    for frame in backtrace(core, fw, 32) {
+        match frame {
+            Frame::Call(addr) => writeln!(w, "  {:08x}  {}", addr, fw.symbolize(addr))?,
+            Frame::Exception(n) => writeln!(w, "  --- <exception {}> ---", n)?,
+        }
+    }
+
+    writeln!(w)?;
+    writeln!(w, "Memory map:")?;
+    for seg in core.segments.iter() {
+        let name = fw
+            .region_names
+            .iter()
+            .find(|(addr, _)| *addr == seg.addr)
+            .map(|(_, name)| name.as_str())
+            .unwrap_or("");
+        writeln!(
+            w,
+            "  {:08x}-{:08x}  {:6}  {}",
+            seg.addr,
+            seg.addr + seg.data.len() as u32,
+            seg.data.len(),
+            name
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coredump::{crash_info, notes, CrashInfo};
+
+    #[test]
+    fn notes_1() {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend([5, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]);
+        data.extend(b"CORE\0\0\0\0");
+        data.extend([0xaa, 0xbb, 0, 0]);
+        data.extend([8, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        data.extend(b"BARBARA\0");
+
+        assert_eq!(
+            notes(&data),
+            vec![
+                (&b"CORE"[..], 1, &[0xaa, 0xbb][..]),
+                (&b"BARBARA"[..], 1, &[][..]),
+            ]
+        );
+        assert_eq!(notes(&data[..20]), vec![]);
+    }
+
+    #[test]
+    fn crash_info_1() {
+        let mut desc: Vec<u8> = (1u32..=11).flat_map(|v| v.to_le_bytes()).collect();
+        desc.extend(b"panicked: oops\0\0");
+
+        assert_eq!(
+            crash_info(&desc),
+            Some(CrashInfo {
+                exception: 1,
+                fault: 2,
+                exc_return: 3,
+                msp: 4,
+                psp: 5,
+                cfsr: 6,
+                hfsr: 7,
+                mmfar: 8,
+                bfar: 9,
+                sfsr: 10,
+                sfar: 11,
+                cause: String::from("panicked: oops"),
+            })
+        );
+        assert_eq!(crash_info(&desc[..40]), None);
+    }
+}
@@ -2,25 +2,24 @@ use std::io;
 
 extern crate kallsyms_enc;
 
+/// The contents of the .kallsyms output section, for the linker script to
+/// INCLUDE as kallsyms.ld
 pub fn ldscript<T>(filename: &String, writer: &mut T)
 where
     T: io::Write,
 {
+    use crate::line::lines_from_file;
     use crate::symbol::symbols_from_file;
 
     let symbols: Vec<(String, u32)> = symbols_from_file(filename)
         .into_iter()
         .map(|s| (s.name, s.addr))
         .collect();
-    let data = kallsyms_enc::pack(&symbols);
-
-    writer.write(b"SECTIONS {\n").unwrap();
-    writer.write(b"    .kallsyms : {\n").unwrap();
-    writer
-        .write(b"        . = __kallsyms_dummy + 12;\n")
-        .unwrap();
-    writer.write(b"        . = ALIGN(4);\n").unwrap();
-    writer.write(b"        __kallsyms = .;\n").unwrap();
+    let lines: Vec<(u32, String, u32)> = lines_from_file(filename)
+        .into_iter()
+        .map(|l| (l.addr, l.file, l.line))
+        .collect();
+    let data = kallsyms_enc::pack_with_lines(&symbols, &lines);
 
     for (i, qbytes) in data.chunks(8).enumerate() {
         if qbytes.len() < 8 {
@@ -37,6 +36,4 @@ where
         }
     }
     writer.write(b"\n").unwrap();
-    writer.write(b"    } >ROM\n").unwrap();
-    writer.write(b"}\n").unwrap();
 }
@@ -1,2 +1,4 @@
+pub mod coredump;
 pub mod ldscript;
+pub mod line;
 pub mod symbol;
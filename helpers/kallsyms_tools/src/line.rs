@@ -0,0 +1,168 @@
+extern crate dwarf_line;
+use dwarf_line::{LineRow, Sections};
+
+extern crate elf_parser;
+use elf_parser::ElfParser;
+
+use crate::symbol::symbols_from_file;
+
+#[derive(PartialEq, Debug)]
+pub struct Line {
+    pub addr: u32,
+    pub file: String,
+    pub line: u32,
+}
+
+/// Shorten paths of the standard library, crates.io and the workspace
+fn short_path(path: &str, cwd: &str) -> String {
+    // /rustc/<commit hash>/library/core/src/..
+    if let Some(rest) = path.strip_prefix("/rustc/") {
+        if let Some((_hash, rest)) = rest.split_once('/') {
+            return String::from(rest);
+        }
+    }
+    // ~/.cargo/registry/src/<index>/<crate>-<version>/..
+    if let Some((_, rest)) = path.split_once("/registry/src/") {
+        if let Some((_index, rest)) = rest.split_once('/') {
+            return String::from(rest);
+        }
+    }
+    match path.strip_prefix(cwd) {
+        Some(rest) => String::from(rest.trim_start_matches('/')),
+        None => String::from(path),
+    }
+}
+
+/// Sequences of rows, without those of code discarded by the linker
+fn sequences(rows: Vec<LineRow>, lowest: u64) -> Vec<Vec<LineRow>> {
+    let mut seqs: Vec<Vec<LineRow>> = Vec::new();
+    let mut seq: Vec<LineRow> = Vec::new();
+    for row in rows {
+        let end = row.end_sequence;
+        seq.push(row);
+        if end {
+            let start = seq[0].address;
+            // garbage collected code is left at 0 or at -1/-2
+            if lowest <= start && start < u32::MAX as u64 - 1 {
+                seqs.push(seq);
+            }
+            seq = Vec::new();
+        }
+    }
+    seqs.sort_by(|a, b| a[0].address.cmp(&b[0].address));
+    seqs
+}
+
+/// Rows of the address -> file:line table, sorted by address; an empty
+/// file marks addresses without line, between sequences
+pub fn lines_from_rows(rows: Vec<LineRow>, lowest: u64, cwd: &str) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    for seq in sequences(rows, lowest) {
+        // overlapping sequences would break the ordering
+        if let Some(last) = lines.last() {
+            if seq[0].address < last.addr as u64 {
+                continue;
+            }
+        }
+        for row in seq {
+            let file = if row.end_sequence {
+                String::new()
+            } else {
+                short_path(&row.file, cwd)
+            };
+            lines.push(Line {
+                addr: row.address as u32,
+                file,
+                line: row.line as u32,
+            });
+        }
+    }
+    lines
+}
+
+pub fn lines_from_file(filename: &str) -> Vec<Line> {
+    let data = std::fs::read(filename).unwrap();
+    let parser = ElfParser::from_bytes(&data).unwrap();
+
+    let debug_line = match parser.section_data(b".debug_line") {
+        Some(data) => data,
+        None => return Vec::new(),
+    };
+    let sections = Sections {
+        debug_line,
+        debug_line_str: parser.section_data(b".debug_line_str").unwrap_or(&[]),
+        debug_str: parser.section_data(b".debug_str").unwrap_or(&[]),
+    };
+    let rows = dwarf_line::parse(&sections).expect("DWARF line parse error");
+
+    // function symbols have the Thumb bit set
+    let lowest = match symbols_from_file(filename).first() {
+        Some(sym) => (sym.addr & !1) as u64,
+        None => return Vec::new(),
+    };
+    let cwd = std::env::current_dir().unwrap();
+    lines_from_rows(rows, lowest, cwd.to_str().unwrap_or(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::line::{lines_from_rows, short_path, Line};
+    use dwarf_line::LineRow;
+
+    #[test]
+    fn short_path_1() {
+        let cwd = "/home/user/barbara";
+        assert_eq!(
+            short_path("/rustc/e50aa6fba4e6/library/core/src/option.rs", cwd),
+            "library/core/src/option.rs"
+        );
+        assert_eq!(
+            short_path(
+                "/home/user/.cargo/registry/src/github.com-1ecc6299db9ec823/log-0.4.14/src/lib.rs",
+                cwd
+            ),
+            "log-0.4.14/src/lib.rs"
+        );
+        assert_eq!(
+            short_path("/home/user/barbara/libs/vfs/src/lib.rs", cwd),
+            "libs/vfs/src/lib.rs"
+        );
+        assert_eq!(short_path("src/main.rs", cwd), "src/main.rs");
+    }
+
+    #[test]
+    fn lines_from_rows_1() {
+        let row = |address, file: &str, line, end_sequence| LineRow {
+            address,
+            file: String::from(file),
+            line,
+            end_sequence,
+        };
+        let rows = vec![
+            row(0x2000, "b.rs", 5, false),
+            row(0x2010, "b.rs", 5, true),
+            row(0x0, "dead.rs", 1, false),
+            row(0x8, "dead.rs", 1, true),
+            row(0x1000, "a.rs", 1, false),
+            row(0x1008, "a.rs", 2, false),
+            row(0x1010, "a.rs", 2, true),
+            row(0x1004, "overlap.rs", 1, false),
+            row(0x1008, "overlap.rs", 1, true),
+        ];
+        let line = |addr, file: &str, line| Line {
+            addr,
+            file: String::from(file),
+            line,
+        };
+        assert_eq!(
+            lines_from_rows(rows, 0x1000, "/"),
+            vec![
+                line(0x1000, "a.rs", 1),
+                line(0x1008, "a.rs", 2),
+                line(0x1010, "", 2),
+                line(0x2000, "b.rs", 5),
+                line(0x2010, "", 5),
+            ]
+        );
+    }
+}
@@ -0,0 +1,609 @@
+/*
+    Board descriptions
+
+    boards/<name>/board.toml describes a board for the build: its Rust
+    target, its memory regions and what goes where in RAM. The linker
+    script is generated from it (see ldscript.rs), so a new board needs no
+    linker script of its own.
+
+        target = "thumbv8m.main-none-eabi"
+
+        [memory]                # MEMORY regions, VECTOR, ROM and RAM at least
+        VECTOR = { origin = 0x2800_0000, length = "4K" }
+        ROM = { origin = 0x2800_1000, length = "252K" }
+        RAM = { origin = 0x2804_0000, length = "192K" }
+
+        [stack]                 # the main stack, MSP
+        size = "64K"
+        guard = "2K"            # optional, unmapped by the MPU below the stack
+
+        [heap]                  # the kernel heap
+        size = "64K"
+        region = "RAM"          # optional, RAM by default
+
+        [[noinit]]              # optional, any number of them
+        name = "retained"       # .retained section, __retained_s/__retained_e
+        size = "1K"
+
+        [secure]                # optional: the kernel runs non-secure,
+        stack = "8K"            # under the monitor of secure/
+
+        [secure.memory]         # exactly VECTOR, ROM, VENEER and RAM
+        VECTOR = { origin = 0x1000_0000, length = "4K" }
+        ROM = { origin = 0x1000_1000, length = "60K" }
+        VENEER = { origin = 0x1001_0000, length = "4K" }
+        RAM = { origin = 0x1002_0000, length = "64K" }
+
+    Sizes are bytes or a string with a K or M suffix. What does not fit is
+    reported here when it can be known before linking (the stack, heap and
+    noinit sections), the rest by ASSERTs in the generated script.
+*/
+
+use std::collections::BTreeMap;
+use std::{fmt, fs, path};
+
+/// Regions every board has, with the code and the vector table
+pub const REQUIRED_REGIONS: [&str; 3] = ["VECTOR", "ROM", "RAM"];
+
+/// Regions of the secure monitor; the SAU makes VENEER non-secure callable
+pub const SECURE_REGIONS: [&str; 4] = ["VECTOR", "ROM", "VENEER", "RAM"];
+
+/// Granularity of the SAU, which maps the kernel and the veneers
+const SAU_GRANULE: u32 = 32;
+
+/// Regions that hold no RAM sections
+const CODE_REGIONS: [&str; 2] = ["VECTOR", "ROM"];
+
+/// Output sections of the generated script, which noinit sections may not reuse
+const RESERVED_NAMES: [&str; 8] = [
+    "vector_table",
+    "rom",
+    "kallsyms",
+    "data",
+    "bss",
+    "stack",
+    "heap",
+    "text",
+];
+
+/// Size in bytes, written as a number or as "64K"/"1M" in board.toml
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(try_from = "SizeSpec")]
+pub struct Size(pub u32);
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SizeSpec {
+    Bytes(u32),
+    Text(String),
+}
+
+impl TryFrom<SizeSpec> for Size {
+    type Error = String;
+
+    fn try_from(spec: SizeSpec) -> Result<Self, Self::Error> {
+        let text = match spec {
+            SizeSpec::Bytes(n) => return Ok(Size(n)),
+            SizeSpec::Text(text) => text,
+        };
+        let (digits, unit) = match text.trim().strip_suffix(['K', 'k']) {
+            Some(digits) => (digits, 1024),
+            None => match text.trim().strip_suffix(['M', 'm']) {
+                Some(digits) => (digits, 1024 * 1024),
+                None => (text.trim(), 1),
+            },
+        };
+        let n = match digits.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => digits.parse::<u32>(),
+        };
+        n.ok()
+            .and_then(|n| n.checked_mul(unit))
+            .map(Size)
+            .ok_or_else(|| {
+                format!(
+                    "invalid size \"{}\", expected e.g. 4096, \"4K\" or \"1M\"",
+                    text
+                )
+            })
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            n if n != 0 && n % (1024 * 1024) == 0 => write!(f, "{}M", n / (1024 * 1024)),
+            n if n != 0 && n % 1024 == 0 => write!(f, "{}K", n / 1024),
+            n => write!(f, "{}", n),
+        }
+    }
+}
+
+/// A MEMORY region
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub origin: u32,
+    pub length: Size,
+}
+
+impl Region {
+    fn end(&self) -> u64 {
+        self.origin as u64 + self.length.0 as u64
+    }
+}
+
+fn default_region() -> String {
+    String::from("RAM")
+}
+
+/// Where the heap goes
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Placement {
+    pub size: Size,
+    #[serde(default = "default_region")]
+    pub region: String,
+}
+
+/// Where the stack goes, and the guard below it, if any
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Stack {
+    pub size: Size,
+    #[serde(default = "default_region")]
+    pub region: String,
+    #[serde(default)]
+    pub guard: Size,
+}
+
+/// RAM that `__reset` neither loads nor zeroes
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NoInit {
+    pub name: String,
+    pub size: Size,
+    #[serde(default = "default_region")]
+    pub region: String,
+}
+
+/// The secure monitor of a board whose kernel runs in the non-secure state
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Secure {
+    pub memory: BTreeMap<String, Region>,
+    pub stack: Size,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    #[serde(skip)]
+    pub name: String,
+    pub target: String,
+    pub memory: BTreeMap<String, Region>,
+    pub stack: Stack,
+    pub heap: Placement,
+    #[serde(default)]
+    pub noinit: Vec<NoInit>,
+    pub secure: Option<Secure>,
+}
+
+/// boards/<name>/board.toml
+pub fn path(name: &str) -> path::PathBuf {
+    ["boards", name, "board.toml"].iter().collect()
+}
+
+/// Names of the boards under boards/
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir("boards")
+        .map(|dir| {
+            dir.filter_map(|e| e.ok())
+                .filter(|e| e.path().join("board.toml").is_file())
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+impl Board {
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = path(name);
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(name, &text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut board: Board = toml::from_str(text).map_err(|e| e.to_string())?;
+        board.name = String::from(name);
+        board.validate()?;
+        Ok(board)
+    }
+
+    /// Stack, heap and noinit sections in `region`, as (what, size)
+    pub fn placed_in(&self, region: &str) -> Vec<(String, Size)> {
+        let mut placed = Vec::new();
+        for n in self.noinit.iter().filter(|n| n.region == region) {
+            placed.push((format!("noinit .{}", n.name), n.size));
+        }
+        if self.stack.region == region {
+            if self.stack.guard.0 != 0 {
+                placed.push((String::from("stack guard"), self.stack.guard));
+            }
+            placed.push((String::from("stack"), self.stack.size));
+        }
+        if self.heap.region == region {
+            placed.push((String::from("heap"), self.heap.size));
+        }
+        placed
+    }
+
+    /// The span of the kernel's memory regions, as [origin, end)
+    pub fn span(&self) -> (u32, u64) {
+        let origin = self.memory.values().map(|r| r.origin).min().unwrap_or(0);
+        let end = self.memory.values().map(|r| r.end()).max().unwrap_or(0);
+        (origin, end)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_memory("memory", &self.memory, &REQUIRED_REGIONS)?;
+
+        // the secure monitor's regions share the address space, but not
+        // with the kernel's
+        let mut regions: Vec<(String, &Region)> =
+            self.memory.iter().map(|(n, r)| (n.clone(), r)).collect();
+        if let Some(secure) = &self.secure {
+            validate_memory("secure.memory", &secure.memory, &SECURE_REGIONS)?;
+            if let Some(name) = secure
+                .memory
+                .keys()
+                .find(|n| !SECURE_REGIONS.contains(&n.as_str()))
+            {
+                return Err(format!(
+                    "[secure.memory] has {}, but only {}",
+                    name,
+                    SECURE_REGIONS.join(", ")
+                ));
+            }
+            regions.extend(
+                secure
+                    .memory
+                    .iter()
+                    .map(|(n, r)| (format!("secure {}", n), r)),
+            );
+        }
+        regions.sort_by_key(|(_, r)| r.origin);
+        for (i, (a_name, a)) in regions.iter().enumerate() {
+            for (b_name, b) in regions[i + 1..].iter() {
+                if (a.origin as u64) < b.end() && (b.origin as u64) < a.end() {
+                    return Err(format!(
+                        "memory regions {} ({:#x}-{:#x}) and {} ({:#x}-{:#x}) overlap",
+                        a_name,
+                        a.origin,
+                        a.end(),
+                        b_name,
+                        b.origin,
+                        b.end()
+                    ));
+                }
+            }
+        }
+
+        let check_region = |what: &str, region: &str| {
+            if CODE_REGIONS.contains(&region) {
+                Err(format!(
+                    "{} cannot go in {}, which is for code",
+                    what, region
+                ))
+            } else if !self.memory.contains_key(region) {
+                let names: Vec<&str> = self.memory.keys().map(|k| k.as_str()).collect();
+                Err(format!(
+                    "{} is in region {}, which [memory] does not have ({})",
+                    what,
+                    region,
+                    names.join(", ")
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        let check_size = |what: &str, size: Size| {
+            if size.0 % 8 != 0 {
+                Err(format!("{} size {} is not a multiple of 8", what, size))
+            } else {
+                Ok(())
+            }
+        };
+
+        check_region("stack", &self.stack.region)?;
+        check_size("stack", self.stack.size)?;
+        if self.stack.size.0 == 0 {
+            return Err(String::from("stack size is 0"));
+        }
+        // the MPU's granularity, so that the guard can be left unmapped
+        if self.stack.guard.0 % 32 != 0 {
+            return Err(format!(
+                "stack guard size {} is not a multiple of 32",
+                self.stack.guard
+            ));
+        }
+        check_region("heap", &self.heap.region)?;
+        check_size("heap", self.heap.size)?;
+
+        for (i, n) in self.noinit.iter().enumerate() {
+            let what = format!("noinit section {}", n.name);
+            if !is_ident(&n.name, |c| c.is_ascii_lowercase()) {
+                return Err(format!("{} must be named with a-z, 0-9 and _", what));
+            }
+            if RESERVED_NAMES.contains(&n.name.as_str())
+                || self.memory.keys().any(|r| r.to_lowercase() == n.name)
+                || self.noinit[..i].iter().any(|m| m.name == n.name)
+            {
+                return Err(format!("{} clashes with another section or region", what));
+            }
+            check_region(&what, &n.region)?;
+            check_size(&what, n.size)?;
+        }
+
+        if let Some(secure) = &self.secure {
+            if secure.stack.0 == 0 || secure.stack.0 % 8 != 0 {
+                return Err(format!(
+                    "secure stack size {} is not a non-zero multiple of 8",
+                    secure.stack
+                ));
+            }
+            let veneer = &secure.memory["VENEER"];
+            let (origin, end) = self.span();
+            for (what, origin, end) in [
+                ("secure VENEER", veneer.origin as u64, veneer.end()),
+                ("the kernel's memory", origin as u64, end),
+            ] {
+                if origin % SAU_GRANULE as u64 != 0 || end % SAU_GRANULE as u64 != 0 {
+                    return Err(format!(
+                        "{} ({:#x}-{:#x}) is not 32-byte aligned for the SAU",
+                        what, origin, end
+                    ));
+                }
+            }
+        }
+
+        // .data and .bss are left to the ASSERTs of the generated script
+        for (name, region) in self.memory.iter() {
+            let placed = self.placed_in(name);
+            let total: u64 = placed.iter().map(|(_, size)| size.0 as u64).sum();
+            if total > region.length.0 as u64 {
+                let list: Vec<String> = placed
+                    .iter()
+                    .map(|(what, size)| format!("{} ({})", what, size))
+                    .collect();
+                return Err(format!(
+                    "{} needs {} bytes for {}, but has {}",
+                    name,
+                    total,
+                    list.join(", "),
+                    region.length
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the regions of the `[table]` of board.toml on their own
+fn validate_memory(
+    table: &str,
+    memory: &BTreeMap<String, Region>,
+    required: &[&str],
+) -> Result<(), String> {
+    for name in required {
+        if !memory.contains_key(*name) {
+            return Err(format!("[{}] has no {} region", table, name));
+        }
+    }
+
+    for (name, region) in memory.iter() {
+        if !is_ident(name, |c| c.is_ascii_uppercase()) {
+            return Err(format!(
+                "memory region {} must be named with A-Z, 0-9 and _",
+                name
+            ));
+        }
+        if region.length.0 == 0 {
+            return Err(format!("memory region {} is empty", name));
+        }
+        if region.end() > 1 << 32 {
+            return Err(format!("memory region {} ends past 4G", name));
+        }
+    }
+    Ok(())
+}
+
+fn is_ident(name: &str, letter: fn(&char) -> bool) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if letter(&c) || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| letter(&c) || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{Board, Size};
+
+    const AN505: &str = r#"
+        target = "thumbv8m.main-none-eabi"
+
+        [memory]
+        VECTOR = { origin = 0x1000_0000, length = "4K" }
+        ROM = { origin = 0x3800_0000, length = "256K" }
+        RAM = { origin = 0x3804_0000, length = "192K" }
+
+        [stack]
+        size = "64K"
+
+        [heap]
+        size = 65536
+    "#;
+
+    fn parse_err(text: &str) -> String {
+        Board::parse("test", text).unwrap_err()
+    }
+
+    #[test]
+    fn size_1() {
+        let size = |s: &str| Size::try_from(super::SizeSpec::Text(String::from(s)));
+        assert_eq!(size("4K"), Ok(Size(4096)));
+        assert_eq!(size("1M"), Ok(Size(1 << 20)));
+        assert_eq!(size("0x400"), Ok(Size(1024)));
+        assert_eq!(size("100"), Ok(Size(100)));
+        assert!(size("4G").is_err());
+        assert!(size("8192M").is_err());
+
+        assert_eq!(Size(192 * 1024).to_string(), "192K");
+        assert_eq!(Size(2 << 20).to_string(), "2M");
+        assert_eq!(Size(100).to_string(), "100");
+    }
+
+    #[test]
+    fn parse_1() {
+        let board = Board::parse("mps2-an505", AN505).unwrap();
+        assert_eq!(board.name, "mps2-an505");
+        assert_eq!(board.target, "thumbv8m.main-none-eabi");
+        assert_eq!(board.memory["RAM"].origin, 0x3804_0000);
+        assert_eq!(board.memory["RAM"].length, Size(192 * 1024));
+        assert_eq!(board.stack.size, Size(64 * 1024));
+        assert_eq!(board.stack.guard, Size(0));
+        assert_eq!(board.heap.region, "RAM");
+        assert!(board.noinit.is_empty());
+    }
+
+    #[test]
+    fn validate_1() {
+        let err = parse_err(&AN505.replace("RAM = {", "SRAM = {"));
+        assert_eq!(err, "[memory] has no RAM region");
+
+        let err = parse_err(&AN505.replace("0x3804_0000", "0x3803_0000"));
+        assert!(
+            err.contains("ROM (0x38000000-0x38040000) and RAM"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&AN505.replace("size = \"64K\"", "size = \"150K\""));
+        assert_eq!(
+            err,
+            "RAM needs 219136 bytes for stack (150K), heap (64K), but has 192K"
+        );
+
+        let err = parse_err(&format!("{}region = \"SRAM\"\n", AN505));
+        assert!(err.starts_with("heap is in region SRAM"), "{}", err);
+
+        let err = parse_err(&format!("{}region = \"ROM\"\n", AN505));
+        assert_eq!(err, "heap cannot go in ROM, which is for code");
+
+        let err = parse_err(&AN505.replace("size = \"64K\"", "size = \"64K\"\nguard = 100"));
+        assert_eq!(err, "stack guard size 100 is not a multiple of 32");
+
+        let err = parse_err(&AN505.replace("65536", "65535"));
+        assert_eq!(err, "heap size 65535 is not a multiple of 8");
+
+        let err = parse_err(&AN505.replace("target", "triple"));
+        assert!(err.contains("triple"), "{}", err);
+    }
+
+    #[test]
+    fn secure_1() {
+        let secure = r#"
+            [secure]
+            stack = "8K"
+
+            [secure.memory]
+            VECTOR = { origin = 0x1000_0000, length = "4K" }
+            ROM = { origin = 0x1000_1000, length = "60K" }
+            VENEER = { origin = 0x1001_0000, length = "4K" }
+            RAM = { origin = 0x1002_0000, length = "64K" }
+        "#;
+        let board = Board::parse("test", AN505).unwrap();
+        assert!(board.secure.is_none());
+        assert_eq!(board.span(), (0x1000_0000, 0x3807_0000));
+
+        let ns = AN505
+            .replace("0x1000_0000", "0x2800_0000")
+            .replace("0x3800_0000", "0x2800_1000")
+            .replace("256K", "252K")
+            .replace("0x3804_0000", "0x2804_0000");
+        let text = format!("{}{}", ns, secure);
+        let board = Board::parse("test", &text).unwrap();
+        let monitor = board.secure.as_ref().unwrap();
+        assert_eq!(monitor.stack, Size(8 * 1024));
+        assert_eq!(monitor.memory["VENEER"].origin, 0x1001_0000);
+        assert_eq!(board.span(), (0x2800_0000, 0x2807_0000));
+
+        let err = parse_err(&text.replace("VENEER", "NSC"));
+        assert_eq!(err, "[secure.memory] has no VENEER region");
+        let err = parse_err(&text.replace("RAM = { origin = 0x1002", "SRAM = { origin = 0x1002"));
+        assert_eq!(err, "[secure.memory] has no RAM region");
+        let err = parse_err(&format!(
+            "{}DATA = {{ origin = 0x1003_0000, length = \"4K\" }}\n",
+            text
+        ));
+        assert_eq!(
+            err,
+            "[secure.memory] has DATA, but only VECTOR, ROM, VENEER, RAM"
+        );
+        let err = parse_err(&text.replace("0x1002_0000", "0x2806_0000"));
+        assert!(
+            err.contains("RAM (0x28040000-0x28070000) and secure RAM"),
+            "{}",
+            err
+        );
+        let err = parse_err(&text.replace("0x1001_0000", "0x1001_0010"));
+        assert_eq!(
+            err,
+            "secure VENEER (0x10010010-0x10011010) is not 32-byte aligned for the SAU"
+        );
+        let err = parse_err(&text.replace("\"8K\"", "0"));
+        assert_eq!(err, "secure stack size 0 is not a non-zero multiple of 8");
+    }
+
+    #[test]
+    fn noinit_1() {
+        let text = format!(
+            "{}\n[[noinit]]\nname = \"retained\"\nsize = \"1K\"\n",
+            AN505
+        );
+        let board = Board::parse("test", &text).unwrap();
+        assert_eq!(board.noinit[0].name, "retained");
+        assert_eq!(board.noinit[0].region, "RAM");
+        assert_eq!(
+            board.placed_in("RAM"),
+            vec![
+                (String::from("noinit .retained"), Size(1024)),
+                (String::from("stack"), Size(64 * 1024)),
+                (String::from("heap"), Size(64 * 1024)),
+            ]
+        );
+
+        let err = parse_err(&text.replace("retained", "bss"));
+        assert_eq!(
+            err,
+            "noinit section bss clashes with another section or region"
+        );
+        let err = parse_err(&text.replace("retained", "ram"));
+        assert_eq!(
+            err,
+            "noinit section ram clashes with another section or region"
+        );
+        let err = parse_err(&text.replace("retained", "Retained"));
+        assert_eq!(
+            err,
+            "noinit section Retained must be named with a-z, 0-9 and _"
+        );
+        let err = parse_err(&text.replace("1K", "72K"));
+        assert!(err.starts_with("RAM needs 204800 bytes"), "{}", err);
+    }
+}
@@ -0,0 +1,521 @@
+/*
+    Linker script generation
+
+    The script for a board is generated from its board.toml (see board.rs)
+    into target/boards/<name>/link.x:
+
+        VECTOR   .vector_table
+        ROM      .rom (code, read-only data, shell commands, unwind tables),
+                 .kallsyms, then the initial values of .data
+        RAM      .data, .bss, noinit sections, stack guard and stack, heap
+        others   noinit sections, stack or heap placed there
+
+    Every region gets __<region>_s/__<region>_e symbols, and every section
+    its own; the kernel finds its memory through them. .kallsyms pulls in
+    kallsyms.ld, which the link wrapper of kallsyms_tools writes for each
+    of its passes, so the symbol table needs no fixed placeholder.
+
+    A board with a [secure] table gets target/boards/<name>/secure.x for
+    the monitor too, with its vector table, code, veneers, .data, .bss and
+    stack in the regions of [secure.memory]. The two scripts tell each
+    other where the kernel is (__ns_s, __ns_e, __ns_vector_table) and where
+    the veneers are (__secure_veneers).
+
+    Overflows that only the linker can see are ASSERTs, which lld reports
+    as "error: <board.toml>: ..." before its own region overflow errors.
+*/
+
+use crate::board::{Board, Region, Size};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Exception handlers that are `DefaultExceptionHandler` unless defined
+const HANDLERS: [&str; 10] = [
+    "__nmi",
+    "__hardfault",
+    "__memmanage",
+    "__busfault",
+    "__usagefault",
+    "__securefault",
+    "__svc",
+    "__debugmon",
+    "__pendsv",
+    "__systick",
+];
+
+const ROM_SECTIONS: &str = "
+    .rom ORIGIN(ROM) :
+    {
+        __text_s = .;
+        *(.text .text.*);
+        __text_e = .;
+
+        /* for `-C relocation-model=pic` */
+        *(.got .got.*);
+
+        . = ALIGN(4);
+        __rodata_s = .;
+        *(.rodata .rodata.*);
+
+        /* see shell.rs */
+        . = ALIGN(4);
+        __shell_cmds_s = .;
+        KEEP(*(.shell_cmds));
+        __shell_cmds_e = .;
+
+        /* EHABI unwind tables, see backtrace.rs */
+        . = ALIGN(4);
+        *(.ARM.extab .ARM.extab.*);
+        . = ALIGN(4);
+        __exidx_s = .;
+        *(.ARM.exidx .ARM.exidx.*);
+        __exidx_e = .;
+
+        . = ALIGN(4);
+        __rodata_e = .;
+    } > ROM
+
+    /* see kallsyms.rs; kallsyms.ld is written by the link wrapper */
+    .kallsyms : ALIGN(4)
+    {
+        __kallsyms = .;
+        INCLUDE kallsyms.ld
+    } > ROM
+";
+
+const SECURE_SECTIONS: &str = "
+    .rom ORIGIN(ROM) :
+    {
+        *(.text .text.*);
+        . = ALIGN(4);
+        *(.rodata .rodata.*);
+        . = ALIGN(4);
+    } > ROM
+
+    /* non-secure callable: the SG veneers and nothing else */
+    .veneers ORIGIN(VENEER) :
+    {
+        KEEP(*(.veneers));
+    } > VENEER
+";
+
+const RAM_SECTIONS: &str = "
+    /* loaded from __data_load by __reset */
+    .data : ALIGN(4)
+    {
+        __data_s = .;
+        *(.data .data.*);
+        . = ALIGN(4);
+        __data_e = .;
+    } > RAM AT > ROM
+    __data_load = LOADADDR(.data);
+
+    .bss (NOLOAD) : ALIGN(4)
+    {
+        __bss_s = .;
+        *(.bss .bss.*);
+        . = ALIGN(4);
+        __bss_e = .;
+    } > RAM
+";
+
+/// The linker script for `board`, whose description came from `source`
+pub fn ldscript(board: &Board, source: &str) -> String {
+    let mut s = String::new();
+
+    writeln!(
+        s,
+        "/* generated from {} by `cargo xtask`, do not edit */",
+        source
+    )
+    .unwrap();
+    writeln!(s).unwrap();
+
+    let by_origin = memory(&mut s, &board.memory);
+
+    if let Some(secure) = &board.secure {
+        writeln!(s, "/* the secure monitor's veneers, see secure_abi */").unwrap();
+        writeln!(
+            s,
+            "__secure_veneers = {:#010x};",
+            secure.memory["VENEER"].origin
+        )
+        .unwrap();
+        writeln!(s).unwrap();
+    }
+
+    writeln!(s, "SECTIONS").unwrap();
+    writeln!(s, "{{").unwrap();
+    writeln!(s, "    .vector_table ORIGIN(VECTOR) :").unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        KEEP(*(.vector_table));").unwrap();
+    writeln!(s, "    }} > VECTOR").unwrap();
+    s += ROM_SECTIONS;
+    s += RAM_SECTIONS;
+
+    // RAM first, where .data and .bss already are, then the others
+    let names = by_origin.iter().map(|(name, _)| *name);
+    let mut regions: Vec<&String> = names.clone().filter(|r| *r == "RAM").collect();
+    regions.extend(names.filter(|r| !["VECTOR", "ROM", "RAM"].contains(&r.as_str())));
+    for region in regions.iter() {
+        for n in board.noinit.iter().filter(|n| &n.region == *region) {
+            writeln!(s).unwrap();
+            noinit_section(&mut s, &n.name, n.size, region);
+        }
+        if &board.stack.region == *region {
+            writeln!(s).unwrap();
+            stack_section(&mut s, board.stack.size, board.stack.guard, region);
+        }
+        if &board.heap.region == *region {
+            writeln!(s).unwrap();
+            reserved_section(&mut s, "heap", board.heap.size, region);
+        }
+    }
+
+    writeln!(s).unwrap();
+    writeln!(
+        s,
+        "    ASSERT(SIZEOF(.vector_table) <= LENGTH(VECTOR), \"{}: VECTOR is too small for the vector table\")",
+        source
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "    ASSERT(__data_load + SIZEOF(.data) <= __rom_e, \"{}: ROM is too small for the code, read-only data, kallsyms and .data\")",
+        source
+    )
+    .unwrap();
+    for n in board.noinit.iter() {
+        writeln!(
+            s,
+            "    ASSERT(__{0}_e - __{0}_s == {1:#x}, \"{2}: the contents of .{0} are larger than its {3}\")",
+            n.name, n.size.0, source, n.size
+        )
+        .unwrap();
+    }
+    for region in regions.iter() {
+        let mut contents: Vec<String> = Vec::new();
+        if *region == "RAM" {
+            contents.push(String::from(".data"));
+            contents.push(String::from(".bss"));
+        }
+        let placed = board.placed_in(region);
+        contents.extend(
+            placed
+                .iter()
+                .map(|(what, size)| match what.strip_prefix("noinit ") {
+                    Some(section) => String::from(section),
+                    None => format!("the {} {}", size, what),
+                }),
+        );
+        let last = match placed.last() {
+            Some((what, _)) => what
+                .strip_prefix("noinit ")
+                .unwrap_or(what)
+                .trim_start_matches('.'),
+            None if *region == "RAM" => "bss",
+            None => continue,
+        };
+        writeln!(
+            s,
+            "    ASSERT(__{}_e <= __{}_e, \"{}: {} is too small for {}\")",
+            last,
+            region.to_lowercase(),
+            source,
+            region,
+            join_and(&contents)
+        )
+        .unwrap();
+    }
+    writeln!(s, "}}").unwrap();
+    writeln!(s).unwrap();
+
+    for handler in HANDLERS {
+        writeln!(s, "PROVIDE({:14}= DefaultExceptionHandler);", handler).unwrap();
+    }
+
+    s
+}
+
+/// The linker script for the secure monitor of `board`, which has a
+/// [secure] table
+pub fn secure_ldscript(board: &Board, source: &str) -> String {
+    let secure = board.secure.as_ref().expect("no [secure] table");
+    let mut s = String::new();
+
+    writeln!(
+        s,
+        "/* generated from {} by `cargo xtask`, do not edit */",
+        source
+    )
+    .unwrap();
+    writeln!(s).unwrap();
+
+    let by_origin = memory(&mut s, &secure.memory);
+    let (first, _) = by_origin[0];
+    let (last, _) = by_origin[by_origin.len() - 1];
+    writeln!(s, "__secure_s = __{}_s;", first.to_lowercase()).unwrap();
+    writeln!(s, "__secure_e = __{}_e;", last.to_lowercase()).unwrap();
+    writeln!(s).unwrap();
+
+    let (origin, end) = board.span();
+    writeln!(s, "/* the non-secure kernel */").unwrap();
+    writeln!(s, "__ns_s = {:#010x};", origin).unwrap();
+    writeln!(s, "__ns_e = {:#010x};", end).unwrap();
+    writeln!(
+        s,
+        "__ns_vector_table = {:#010x};",
+        board.memory["VECTOR"].origin
+    )
+    .unwrap();
+    writeln!(s).unwrap();
+
+    writeln!(s, "SECTIONS").unwrap();
+    writeln!(s, "{{").unwrap();
+    writeln!(s, "    .vector_table ORIGIN(VECTOR) :").unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        KEEP(*(.vector_table));").unwrap();
+    writeln!(s, "    }} > VECTOR").unwrap();
+    s += SECURE_SECTIONS;
+    s += RAM_SECTIONS;
+    writeln!(s).unwrap();
+    stack_section(&mut s, secure.stack, Size(0), "RAM");
+
+    writeln!(s).unwrap();
+    writeln!(s, "    /DISCARD/ :").unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        *(.ARM.exidx .ARM.exidx.*);").unwrap();
+    writeln!(s, "        *(.ARM.extab .ARM.extab.*);").unwrap();
+    writeln!(s, "    }}").unwrap();
+
+    writeln!(s).unwrap();
+    writeln!(
+        s,
+        "    ASSERT(SIZEOF(.vector_table) <= LENGTH(VECTOR), \"{}: secure VECTOR is too small for the vector table\")",
+        source
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "    ASSERT(__data_load + SIZEOF(.data) <= __rom_e, \"{}: secure ROM is too small for the code, read-only data and .data\")",
+        source
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "    ASSERT(__stack_e <= __ram_e, \"{}: secure RAM is too small for .data, .bss and the {} stack\")",
+        source, secure.stack
+    )
+    .unwrap();
+    writeln!(s, "}}").unwrap();
+
+    s
+}
+
+/// The MEMORY block and the __<region>_s/__<region>_e symbols of `memory`,
+/// whose regions are returned by origin
+fn memory<'a>(
+    s: &mut String,
+    memory: &'a BTreeMap<String, Region>,
+) -> Vec<(&'a String, &'a Region)> {
+    let mut by_origin: Vec<(&String, &Region)> = memory.iter().collect();
+    by_origin.sort_by_key(|(_, r)| r.origin);
+
+    writeln!(s, "MEMORY").unwrap();
+    writeln!(s, "{{").unwrap();
+    for (name, region) in by_origin.iter() {
+        writeln!(
+            s,
+            "    {:8} : ORIGIN = {:#010x}, LENGTH = {}",
+            name, region.origin, region.length
+        )
+        .unwrap();
+    }
+    writeln!(s, "}}").unwrap();
+    writeln!(s).unwrap();
+
+    writeln!(s, "/* whole memory regions */").unwrap();
+    for (name, _) in by_origin.iter() {
+        let sym = name.to_lowercase();
+        writeln!(s, "__{}_s = ORIGIN({});", sym, name).unwrap();
+        writeln!(s, "__{}_e = ORIGIN({}) + LENGTH({});", sym, name, name).unwrap();
+    }
+    writeln!(s).unwrap();
+
+    by_origin
+}
+
+/// A section of `size` bytes that nothing is linked into, aligned to the
+/// MPU's 32-byte granularity
+fn reserved_section(s: &mut String, name: &str, size: Size, region: &str) {
+    writeln!(s, "    .{} (NOLOAD) : ALIGN(32)", name).unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        __{}_s = .;", name).unwrap();
+    writeln!(s, "        . += {:#x};", size.0).unwrap();
+    writeln!(s, "        __{}_e = .;", name).unwrap();
+    writeln!(s, "    }} > {}", region).unwrap();
+}
+
+/// The stack, after `guard` bytes that the MPU leaves unmapped (see mpu.rs)
+fn stack_section(s: &mut String, size: Size, guard: Size, region: &str) {
+    writeln!(s, "    .stack (NOLOAD) : ALIGN(32)").unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        __stack_guard_s = .;").unwrap();
+    writeln!(s, "        . += {:#x};", guard.0).unwrap();
+    writeln!(s, "        __stack_guard_e = .;").unwrap();
+    writeln!(s, "        __stack_s = .;").unwrap();
+    writeln!(s, "        . += {:#x};", size.0).unwrap();
+    writeln!(s, "        __stack_e = .;").unwrap();
+    writeln!(s, "    }} > {}", region).unwrap();
+}
+
+/// A section of `size` bytes for `#[link_section = ".<name>"]` statics;
+/// MAX() leaves too large contents to the ASSERT instead of moving `.` back
+fn noinit_section(s: &mut String, name: &str, size: Size, region: &str) {
+    writeln!(s, "    .{} (NOLOAD) : ALIGN(8)", name).unwrap();
+    writeln!(s, "    {{").unwrap();
+    writeln!(s, "        __{}_s = .;", name).unwrap();
+    writeln!(s, "        KEEP(*(.{0} .{0}.*));", name).unwrap();
+    writeln!(s, "        . = MAX(., __{}_s + {:#x});", name, size.0).unwrap();
+    writeln!(s, "        __{}_e = .;", name).unwrap();
+    writeln!(s, "    }} > {}", region).unwrap();
+}
+
+/// "a", "a and b", "a, b and c"
+fn join_and(items: &[String]) -> String {
+    match items.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::ldscript::{join_and, ldscript, secure_ldscript};
+
+    const AN500: &str = r#"
+        target = "thumbv7em-none-eabi"
+
+        [memory]
+        VECTOR = { origin = 0x0000_0000, length = "4K" }
+        ROM = { origin = 0x0000_1000, length = "256K" }
+        RAM = { origin = 0x2000_0000, length = "128K" }
+        PSRAM = { origin = 0x6000_0000, length = "64K" }
+
+        [stack]
+        size = "64K"
+
+        [heap]
+        size = "64K"
+        region = "PSRAM"
+
+        [[noinit]]
+        name = "retained"
+        size = "1K"
+    "#;
+
+    const AN505: &str = r#"
+        target = "thumbv8m.main-none-eabi"
+
+        [memory]
+        VECTOR = { origin = 0x2800_0000, length = "4K" }
+        ROM = { origin = 0x2800_1000, length = "252K" }
+        RAM = { origin = 0x2804_0000, length = "192K" }
+
+        [stack]
+        size = "64K"
+
+        [heap]
+        size = "64K"
+
+        [secure]
+        stack = "8K"
+
+        [secure.memory]
+        VECTOR = { origin = 0x1000_0000, length = "4K" }
+        ROM = { origin = 0x1000_1000, length = "60K" }
+        VENEER = { origin = 0x1001_0000, length = "4K" }
+        RAM = { origin = 0x1002_0000, length = "64K" }
+    "#;
+
+    #[test]
+    fn join_and_1() {
+        let items: Vec<String> = ["a", "b", "c"].iter().map(|s| String::from(*s)).collect();
+        assert_eq!(join_and(&items[..0]), "");
+        assert_eq!(join_and(&items[..1]), "a");
+        assert_eq!(join_and(&items[..2]), "a and b");
+        assert_eq!(join_and(&items), "a, b and c");
+    }
+
+    #[test]
+    fn ldscript_1() {
+        let board = Board::parse("mps2-an500", AN500).unwrap();
+        let script = ldscript(&board, "boards/mps2-an500/board.toml");
+
+        assert!(script.contains("    RAM      : ORIGIN = 0x20000000, LENGTH = 128K\n"));
+        assert!(script.contains("    PSRAM    : ORIGIN = 0x60000000, LENGTH = 64K\n"));
+        assert!(script.contains("__psram_e = ORIGIN(PSRAM) + LENGTH(PSRAM);\n"));
+        assert!(script.contains("INCLUDE kallsyms.ld"));
+        assert!(!script.contains("__kallsyms_dummy"));
+
+        // RAM: .data, .bss, .retained, .stack; PSRAM: .heap
+        let pos = |pat: &str| script.find(pat).unwrap();
+        assert!(pos("    .bss (NOLOAD)") < pos("    .retained (NOLOAD)"));
+        assert!(pos("    .retained (NOLOAD)") < pos("    .stack (NOLOAD)"));
+        assert!(pos("    .stack (NOLOAD)") < pos("    .heap (NOLOAD)"));
+        assert!(script.contains("        . += 0x10000;\n        __heap_e = .;\n    } > PSRAM\n"));
+        assert!(script.contains("        . = MAX(., __retained_s + 0x400);\n"));
+        assert!(script.contains(
+            "        __stack_guard_s = .;\n        . += 0x0;\n        __stack_guard_e = .;\n"
+        ));
+
+        assert!(script.contains(
+            "ASSERT(__stack_e <= __ram_e, \"boards/mps2-an500/board.toml: RAM is too small for .data, .bss, .retained and the 64K stack\")"
+        ));
+        assert!(script.contains(
+            "ASSERT(__heap_e <= __psram_e, \"boards/mps2-an500/board.toml: PSRAM is too small for the 64K heap\")"
+        ));
+        assert!(script.contains(
+            "ASSERT(__retained_e - __retained_s == 0x400, \"boards/mps2-an500/board.toml: the contents of .retained are larger than its 1K\")"
+        ));
+        assert!(script.contains("PROVIDE(__securefault = DefaultExceptionHandler);\n"));
+
+        let board = Board::parse(
+            "mps2-an500",
+            &AN500.replacen("size = \"64K\"\n", "size = \"64K\"\nguard = \"1K\"\n", 1),
+        )
+        .unwrap();
+        let script = ldscript(&board, "boards/mps2-an500/board.toml");
+        assert!(script.contains("        . += 0x400;\n        __stack_guard_e = .;\n        __stack_s = .;\n        . += 0x10000;\n"));
+        assert!(script.contains(
+            "RAM is too small for .data, .bss, .retained, the 1K stack guard and the 64K stack"
+        ));
+    }
+
+    #[test]
+    fn secure_ldscript_1() {
+        let board = Board::parse("mps2-an505", AN505).unwrap();
+        let script = ldscript(&board, "boards/mps2-an505/board.toml");
+        assert!(script.contains("__secure_veneers = 0x10010000;\n"));
+
+        let script = secure_ldscript(&board, "boards/mps2-an505/board.toml");
+        assert!(script.contains("    VENEER   : ORIGIN = 0x10010000, LENGTH = 4K\n"));
+        assert!(script.contains("__secure_s = __vector_s;\n__secure_e = __ram_e;\n"));
+        assert!(script.contains(
+            "__ns_s = 0x28000000;\n__ns_e = 0x28070000;\n__ns_vector_table = 0x28000000;\n"
+        ));
+        assert!(script.contains("        KEEP(*(.veneers));\n    } > VENEER\n"));
+        assert!(script.contains("        . += 0x2000;\n        __stack_e = .;\n    } > RAM\n"));
+        assert!(!script.contains("kallsyms"));
+        assert!(!script.contains(".heap"));
+        assert!(script.contains(
+            "ASSERT(__stack_e <= __ram_e, \"boards/mps2-an505/board.toml: secure RAM is too small for .data, .bss and the 8K stack\")"
+        ));
+
+        let board = Board::parse("mps2-an500", AN500).unwrap();
+        let script = ldscript(&board, "boards/mps2-an500/board.toml");
+        assert!(!script.contains("__secure_veneers"));
+    }
+}
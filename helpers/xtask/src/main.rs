@@ -9,6 +9,11 @@ extern crate prettytable;
 extern crate clap;
 use clap::{Parser, Subcommand};
 
+mod board;
+mod ldscript;
+use board::Board;
+use ldscript::{ldscript, secure_ldscript};
+
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Cli {
@@ -19,10 +24,14 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Run {
+        #[clap(long, default_value = DEFAULT_BOARD)]
+        board: String,
         #[clap(last = true)]
         args: Vec<String>,
     },
     Build {
+        #[clap(long, default_value = DEFAULT_BOARD)]
+        board: String,
         #[clap(last = true)]
         args: Vec<String>,
     },
@@ -32,6 +41,63 @@ enum Commands {
     },
 }
 
+const DEFAULT_BOARD: &str = "mps2-an505";
+
+fn find_board(name: &str) -> Board {
+    if !board::path(name).is_file() {
+        eprintln!(
+            "unknown board {}, choose one of: {}",
+            name,
+            board::names().join(", ")
+        );
+        process::exit(1)
+    }
+    match Board::load(name) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1)
+        }
+    }
+}
+
+/// Whether the cargo arguments `args` enable `feature`, with `--all-features`
+/// or in a `--features`/`-F` list, separated by commas or spaces
+fn feature_enabled(args: &[String], feature: &str) -> bool {
+    let mut args = args.iter().map(|s| s.as_str());
+    while let Some(arg) = args.next() {
+        let list = match arg {
+            // the rest is for the program
+            "--" => break,
+            "--all-features" => return true,
+            "--features" | "-F" => args.next().unwrap_or(""),
+            _ => match arg.strip_prefix("--features=").or(arg.strip_prefix("-F")) {
+                Some(list) => list.strip_prefix('=').unwrap_or(list),
+                None => continue,
+            },
+        };
+        // "barbara/gdbstub" names the package's feature too
+        let mut names = list
+            .split([',', ' '])
+            .map(|f| f.rsplit('/').next().unwrap());
+        if names.any(|name| name == feature) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Writes `script` for `board` under target/ and returns its path
+fn generate_ldscript(board: &Board, file: &str, script: String) -> String {
+    let dir: path::PathBuf = ["target", "boards", &board.name].iter().collect();
+    fs::create_dir_all(&dir).expect("failed to create linker script directory");
+    let path = dir.join(file);
+    fs::write(&path, script).expect("failed to write linker script");
+    path.to_str()
+        .expect("failed to convert linker script path into utf-8")
+        .into()
+}
+
 #[derive(serde::Deserialize, Debug)]
 struct CompilerMessage {
     reason: String,
@@ -44,20 +110,10 @@ struct Target {
     name: String,
 }
 
-fn build_linker_wrapper() -> String {
-    let args = vec![
-        "build",
-        "-q",
-        "--package",
-        "kallsyms_tools",
-        "--bin",
-        "link",
-        "--message-format=json",
-    ];
-
-    let mut cargo = process::Command::new("cargo");
+/// Runs `cargo`, which has `--message-format=json`, and returns the path
+/// of the executable it built
+fn cargo_executable(cargo: &mut process::Command) -> String {
     let mut child = cargo
-        .args(args)
         .stdout(process::Stdio::piped())
         .spawn()
         .expect("failed to spawn cargo command");
@@ -80,19 +136,100 @@ fn build_linker_wrapper() -> String {
     program.unwrap()
 }
 
-fn cargo_target(cmd: &str, args: &Vec<String>) {
+fn build_linker_wrapper() -> String {
+    let args = vec![
+        "build",
+        "-q",
+        "--package",
+        "kallsyms_tools",
+        "--bin",
+        "link",
+        "--message-format=json",
+    ];
+
+    let mut cargo = process::Command::new("cargo");
+    cargo.args(args);
+    cargo_executable(&mut cargo)
+}
+
+/// Builds secure/ for `board`, linked with `script`. It has a target
+/// directory of its own, as its RUSTFLAGS differ from the kernel's.
+fn build_secure_monitor(board: &Board, script: &str, args: &[String]) -> String {
+    let mut args_all = vec![
+        "build",
+        "-q",
+        "--package",
+        "secure_monitor",
+        "--target",
+        board.target.as_str(),
+        "--target-dir",
+        "target/secure",
+        "--message-format=json",
+    ];
+    if args.iter().any(|a| a == "--release") {
+        args_all.push("--release");
+    }
+
+    let rustflags = format!("-C link-arg=-T{}", script);
+
+    let mut cargo = process::Command::new("cargo");
+    cargo.args(args_all).env("RUSTFLAGS", rustflags);
+    cargo_executable(&mut cargo)
+}
+
+fn cargo_target(cmd: &str, board: &str, args: &Vec<String>) {
+    let board = find_board(board);
+    let source = board::path(&board.name).to_string_lossy().into_owned();
+    let script = generate_ldscript(&board, "link.x", ldscript(&board, &source));
     let linker = build_linker_wrapper();
 
-    let mut args_all = vec![cmd, "--target", "thumbv8m.main-none-eabi"];
+    // the kernel runs non-secure, under the monitor
+    let monitor = board.secure.as_ref().map(|_| {
+        let script = generate_ldscript(&board, "secure.x", secure_ldscript(&board, &source));
+        build_secure_monitor(&board, &script, args)
+    });
+
+    let mut features = format!("board-{}", board.name);
+    if monitor.is_some() {
+        features += ",nonsecure";
+    }
+    let mut args_all = vec![cmd, "--target", board.target.as_str()];
+    args_all.extend(["--no-default-features", "--features", features.as_str()]);
     args_all.extend(args.iter().map(|s| &**s));
 
     let mut rustflags = format!("-C linker={}", linker);
     rustflags += " -C linker-flavor=ld.lld"; // use LLVM lld with "-flavor gnu" flag
-    rustflags += " -C link-arg=-Tlink.x";
-    rustflags += " -C force-frame-pointers=y";
+    rustflags += &format!(" -C link-arg=-T{}", script);
+    if feature_enabled(args, "unwind-fp") {
+        rustflags += " -C force-frame-pointers=y";
+    } else {
+        // unwind tables for backtraces, which panic=abort leaves out
+        rustflags += " -C force-unwind-tables=yes";
+    }
+
+    let mut runner = format!(
+        "qemu-system-arm -M {} -semihosting -serial stdio",
+        board.name
+    );
+    if feature_enabled(args, "gdbstub") {
+        // UART1 for the GDB stub; QEMU's own stub (-s) takes port 1234
+        runner += " -serial tcp::3333,server,nowait";
+    }
+    if let Some(monitor) = &monitor {
+        // the core resets into the monitor, from its vector table
+        runner += &format!(" -device loader,file={}", monitor);
+    }
+    runner += " -display none -kernel";
+    let runner_var = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        board.target.to_uppercase().replace(['.', '-'], "_")
+    );
 
     let mut cargo = process::Command::new("cargo");
-    cargo.args(args_all).env("RUSTFLAGS", rustflags);
+    cargo
+        .args(args_all)
+        .env("RUSTFLAGS", rustflags)
+        .env(runner_var, runner);
 
     let status = cargo.status().expect("failed to execute cargo process");
 
@@ -375,11 +512,41 @@ fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Run { args }) => cargo_target("run", args),
-        Some(Commands::Build { args }) => cargo_target("build", args),
+        Some(Commands::Run { board, args }) => cargo_target("run", board, args),
+        Some(Commands::Build { board, args }) => cargo_target("build", board, args),
         Some(Commands::Testall { args }) => {
             cargo_testall(args);
         }
         None => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::feature_enabled;
+
+    fn enabled(args: &[&str], feature: &str) -> bool {
+        let args: Vec<String> = args.iter().map(|s| String::from(*s)).collect();
+        feature_enabled(&args, feature)
+    }
+
+    #[test]
+    fn features() {
+        assert!(enabled(&["--features", "unwind-fp"], "unwind-fp"));
+        assert!(enabled(
+            &["--release", "--features=a,unwind-fp"],
+            "unwind-fp"
+        ));
+        assert!(enabled(&["-F", "a unwind-fp"], "unwind-fp"));
+        assert!(enabled(&["-Funwind-fp"], "unwind-fp"));
+        assert!(enabled(&["--features", "barbara/unwind-fp"], "unwind-fp"));
+        assert!(enabled(&["--all-features"], "unwind-fp"));
+
+        assert!(!enabled(&[], "unwind-fp"));
+        assert!(!enabled(&["--features", "unwind-fp-x"], "unwind-fp"));
+        assert!(!enabled(&["--target-dir", "/tmp/unwind-fp"], "unwind-fp"));
+        assert!(!enabled(&["--", "--features", "unwind-fp"], "unwind-fp"));
+        assert!(enabled(&["--features", "unwind-fp,gdbstub"], "gdbstub"));
+        assert!(!enabled(&["--features", "unwind-fp"], "gdbstub"));
+    }
+}
@@ -7,31 +7,31 @@ macro_rules! bitfield {
         ((2 << $left) - 1) ^ ((1 << $right) - 1)
     };
 
-    {@field $stname:ident, $typ:ty { }} => { };
+    {@field $vis:vis $stname:ident, $typ:ty { }} => { };
 
-    {@field $stname:ident, $typ:ty { $name:ident[$left:literal : $right:literal]; $($remain:tt)* }} => {
-        const $name: $stname = $stname(bitfield!{@mask $left, $right});
-        bitfield!{@field $stname, $typ { $($remain)* }}
+    {@field $vis:vis $stname:ident, $typ:ty { $name:ident[$left:literal : $right:literal]; $($remain:tt)* }} => {
+        $vis const $name: $stname = $stname(bitfield!{@mask $left, $right});
+        bitfield!{@field $vis $stname, $typ { $($remain)* }}
     };
 
-    {@field $stname:ident, $typ:ty { $name:ident[$bit:literal]; $($remain:tt)* }} => {
-        const $name: $stname = $stname(bitfield!{@mask $bit, $bit});
-        bitfield!{@field $stname, $typ { $($remain)* }}
+    {@field $vis:vis $stname:ident, $typ:ty { $name:ident[$bit:literal]; $($remain:tt)* }} => {
+        $vis const $name: $stname = $stname(bitfield!{@mask $bit, $bit});
+        bitfield!{@field $vis $stname, $typ { $($remain)* }}
     };
 
-    {@impl $stname:ident, $typ:ty { $($body:tt)* }} => {
+    {@impl $vis:vis $stname:ident, $typ:ty { $($body:tt)* }} => {
         impl $stname {
-            bitfield!{@field $stname, $typ {$($body)*}}
+            bitfield!{@field $vis $stname, $typ {$($body)*}}
 
-            fn is_set(&self, m: $stname) -> bool {
+            $vis fn is_set(&self, m: $stname) -> bool {
                 (self.0 & m.0) == m.0
             }
 
-            fn extract(&self, m: $stname) -> $typ {
+            $vis fn extract(&self, m: $stname) -> $typ {
                 (self.0 & m.0) >> m.0.trailing_zeros()
             }
 
-            fn compose(&self, v: $typ) -> $stname {
+            $vis fn compose(&self, v: $typ) -> $stname {
                 $stname(v << self.0.trailing_zeros())
             }
         }
@@ -59,7 +59,7 @@ macro_rules! bitfield {
     {$vis:vis $stname:ident : $typ:ty { $($body:tt)* }} => {
         #[derive(Clone, Copy, PartialEq, Debug)]
         $vis struct $stname($typ);
-        bitfield!{@impl $stname, $typ { $($body)* }}
+        bitfield!{@impl $vis $stname, $typ { $($body)* }}
     };
 }
 
@@ -159,6 +159,23 @@ mod tests {
         assert_eq!(mode, mode.clone());
     }
 
+    mod public {
+        bitfield! {
+            pub Flags: u32 {
+                A[0];
+                B[3:1];
+            }
+        }
+    }
+
+    #[test]
+    fn test_pub() {
+        use public::Flags;
+        let flags = Flags::A | Flags::B.compose(5);
+        assert!(flags.is_set(Flags::A));
+        assert_eq!(flags.extract(Flags::B), 5);
+    }
+
     #[test]
     fn test_debug() {
         assert_eq!(format!("{:?}", OpenMode::READ), "OpenMode(1)");
@@ -0,0 +1,599 @@
+/*
+
+Reader of the DWARF line number information (.debug_line), versions 2 to 5.
+
+Each unit of .debug_line is a header, with the directories and files the
+unit refers to, followed by a line number program. Running the program
+produces the rows of the address -> file:line matrix; a row with
+`end_sequence` set marks the first address past a sequence of code.
+
+Only little-endian data is supported.
+
+[refs]
+- https://dwarfstd.org/doc/DWARF5.pdf (6.2 Line Number Information)
+
+ */
+
+#[derive(PartialEq, Debug)]
+pub struct DwarfLineError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LineRow {
+    pub address: u64,
+    pub file: String,
+    pub line: u64,
+    pub end_sequence: bool,
+}
+
+/// Sections the line number information may refer to
+#[derive(Clone, Copy, Default)]
+pub struct Sections<'a> {
+    pub debug_line: &'a [u8],
+    pub debug_line_str: &'a [u8],
+    pub debug_str: &'a [u8],
+}
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn error(&self, message: &'static str) -> DwarfLineError {
+        DwarfLineError {
+            offset: self.pos,
+            message,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DwarfLineError> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(b) => {
+                self.pos += len;
+                Ok(b)
+            }
+            None => Err(self.error("unexpected end of data")),
+        }
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, DwarfLineError> {
+        let b = self.bytes(len)?;
+        Ok(b.iter().rev().fold(0, |v, &b| (v << 8) | b as u64))
+    }
+
+    fn u8(&mut self) -> Result<u8, DwarfLineError> {
+        self.uint(1).map(|v| v as u8)
+    }
+
+    fn uleb(&mut self) -> Result<u64, DwarfLineError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= ((b & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, DwarfLineError> {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= ((b & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Result<&'a [u8], DwarfLineError> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        match rest.iter().position(|&c| c == 0) {
+            Some(len) => {
+                self.pos += len + 1;
+                Ok(&rest[..len])
+            }
+            None => Err(self.error("unterminated string")),
+        }
+    }
+}
+
+fn str_at(section: &[u8], off: u64) -> Result<String, DwarfLineError> {
+    let mut r = Reader::new(section, off as usize);
+    Ok(String::from_utf8_lossy(r.cstr()?).into_owned())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() || name.starts_with('/') {
+        String::from(name)
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), name)
+    }
+}
+
+/// An attribute value of a v5 directory or file entry
+enum Value {
+    Str(String),
+    Num(u64),
+    Other,
+}
+
+struct Header {
+    version: u16,
+    min_inst_len: u8,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+    std_opcode_lens: Vec<u8>,
+    dirs: Vec<String>,
+    files: Vec<String>,
+}
+
+impl Header {
+    fn file(&self, index: u64) -> String {
+        // files are numbered from 1 before DWARF 5
+        let i = if self.version >= 5 {
+            index as usize
+        } else {
+            (index as usize).wrapping_sub(1)
+        };
+        self.files.get(i).cloned().unwrap_or_default()
+    }
+
+    fn dir(&self, index: u64) -> String {
+        // directory 0 is the compilation directory, listed only in DWARF 5
+        let i = if self.version >= 5 {
+            index as usize
+        } else {
+            match (index as usize).checked_sub(1) {
+                Some(i) => i,
+                None => return String::new(),
+            }
+        };
+        self.dirs.get(i).cloned().unwrap_or_default()
+    }
+}
+
+fn read_value(
+    r: &mut Reader,
+    form: u64,
+    sections: &Sections,
+    offset_size: usize,
+) -> Result<Value, DwarfLineError> {
+    let v = match form {
+        DW_FORM_STRING => Value::Str(String::from_utf8_lossy(r.cstr()?).into_owned()),
+        DW_FORM_LINE_STRP => Value::Str(str_at(sections.debug_line_str, r.uint(offset_size)?)?),
+        DW_FORM_STRP => Value::Str(str_at(sections.debug_str, r.uint(offset_size)?)?),
+        DW_FORM_UDATA => Value::Num(r.uleb()?),
+        DW_FORM_SDATA => Value::Num(r.sleb()? as u64),
+        DW_FORM_DATA1 => Value::Num(r.uint(1)?),
+        DW_FORM_DATA2 => Value::Num(r.uint(2)?),
+        DW_FORM_DATA4 => Value::Num(r.uint(4)?),
+        DW_FORM_DATA8 => Value::Num(r.uint(8)?),
+        DW_FORM_DATA16 => {
+            r.bytes(16)?;
+            Value::Other
+        }
+        DW_FORM_BLOCK | DW_FORM_BLOCK1 | DW_FORM_BLOCK2 | DW_FORM_BLOCK4 => {
+            let len = match form {
+                DW_FORM_BLOCK1 => r.uint(1)?,
+                DW_FORM_BLOCK2 => r.uint(2)?,
+                DW_FORM_BLOCK4 => r.uint(4)?,
+                _ => r.uleb()?,
+            };
+            r.bytes(len as usize)?;
+            Value::Other
+        }
+        _ => return Err(r.error("unsupported form in entry format")),
+    };
+    Ok(v)
+}
+
+/// DWARF 5 directory and file tables: (path, directory index) per entry
+fn read_entries(
+    r: &mut Reader,
+    sections: &Sections,
+    offset_size: usize,
+) -> Result<Vec<(String, u64)>, DwarfLineError> {
+    let format_count = r.u8()?;
+    let mut format = Vec::new();
+    for _ in 0..format_count {
+        format.push((r.uleb()?, r.uleb()?));
+    }
+
+    let count = r.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut dir = 0;
+        for &(content, form) in format.iter() {
+            match (content, read_value(r, form, sections, offset_size)?) {
+                (DW_LNCT_PATH, Value::Str(s)) => path = s,
+                (DW_LNCT_DIRECTORY_INDEX, Value::Num(n)) => dir = n,
+                _ => {}
+            }
+        }
+        entries.push((path, dir));
+    }
+    Ok(entries)
+}
+
+/// Parse the unit header at `r`; returns it and the end of the unit
+fn read_header(r: &mut Reader, sections: &Sections) -> Result<(Header, usize), DwarfLineError> {
+    let (unit_length, offset_size) = match r.uint(4)? {
+        0xffff_ffff => (r.uint(8)?, 8),
+        len => (len, 4),
+    };
+    let end = r.pos + unit_length as usize;
+
+    let version = r.uint(2)? as u16;
+    if !(2..=5).contains(&version) {
+        return Err(r.error("unsupported version"));
+    }
+    if version >= 5 {
+        let _address_size = r.u8()?;
+        let _segment_selector_size = r.u8()?;
+    }
+    let header_length = r.uint(offset_size)?;
+    let program = r.pos + header_length as usize;
+
+    let min_inst_len = r.u8()?;
+    if version >= 4 {
+        let _max_ops_per_inst = r.u8()?;
+    }
+    let _default_is_stmt = r.u8()?;
+    let line_base = r.u8()? as i8;
+    let line_range = r.u8()?;
+    let opcode_base = r.u8()?;
+    if line_range == 0 || opcode_base == 0 {
+        return Err(r.error("broken header"));
+    }
+    let std_opcode_lens = r.bytes(opcode_base as usize - 1)?.to_vec();
+
+    let mut header = Header {
+        version,
+        min_inst_len,
+        line_base,
+        line_range,
+        opcode_base,
+        std_opcode_lens,
+        dirs: Vec::new(),
+        files: Vec::new(),
+    };
+
+    if version >= 5 {
+        let dirs = read_entries(r, sections, offset_size)?;
+        header.dirs = dirs.into_iter().map(|(path, _)| path).collect();
+        let files = read_entries(r, sections, offset_size)?;
+        for (name, dir) in files {
+            let path = join(&header.dir(dir), &name);
+            header.files.push(path);
+        }
+    } else {
+        loop {
+            let dir = r.cstr()?;
+            if dir.is_empty() {
+                break;
+            }
+            header.dirs.push(String::from_utf8_lossy(dir).into_owned());
+        }
+        loop {
+            let name = r.cstr()?;
+            if name.is_empty() {
+                break;
+            }
+            let dir = r.uleb()?;
+            let _mtime = r.uleb()?;
+            let _len = r.uleb()?;
+            let path = join(&header.dir(dir), &String::from_utf8_lossy(name));
+            header.files.push(path);
+        }
+    }
+
+    r.pos = program;
+    Ok((header, end))
+}
+
+struct State {
+    address: u64,
+    file: u64,
+    line: u64,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            address: 0,
+            file: 1,
+            line: 1,
+        }
+    }
+}
+
+fn run_program(
+    r: &mut Reader,
+    header: &mut Header,
+    end: usize,
+    rows: &mut Vec<LineRow>,
+) -> Result<(), DwarfLineError> {
+    let mut st = State::new();
+    let min_inst_len = header.min_inst_len as u64;
+
+    let mut emit = |st: &State, header: &Header, end_sequence: bool| {
+        rows.push(LineRow {
+            address: st.address,
+            file: header.file(st.file),
+            line: st.line,
+            end_sequence,
+        });
+    };
+
+    while r.pos < end {
+        let op = r.u8()?;
+        if op >= header.opcode_base {
+            let adjusted = op - header.opcode_base;
+            st.address += (adjusted / header.line_range) as u64 * min_inst_len;
+            let delta = header.line_base as i64 + (adjusted % header.line_range) as i64;
+            st.line = st.line.wrapping_add(delta as u64);
+            emit(&st, header, false);
+            continue;
+        }
+
+        match op {
+            0 => {
+                let len = r.uleb()? as usize;
+                let next = r.pos + len;
+                if len == 0 {
+                    continue;
+                }
+                match r.u8()? {
+                    DW_LNE_END_SEQUENCE => {
+                        emit(&st, header, true);
+                        st = State::new();
+                    }
+                    DW_LNE_SET_ADDRESS => st.address = r.uint(len - 1)?,
+                    DW_LNE_DEFINE_FILE => {
+                        let name = String::from_utf8_lossy(r.cstr()?).into_owned();
+                        let dir = r.uleb()?;
+                        let path = join(&header.dir(dir), &name);
+                        header.files.push(path);
+                    }
+                    _ => {}
+                }
+                r.pos = next;
+            }
+            DW_LNS_COPY => emit(&st, header, false),
+            DW_LNS_ADVANCE_PC => st.address += r.uleb()? * min_inst_len,
+            DW_LNS_ADVANCE_LINE => st.line = st.line.wrapping_add(r.sleb()? as u64),
+            DW_LNS_SET_FILE => st.file = r.uleb()?,
+            DW_LNS_CONST_ADD_PC => {
+                let adjusted = 255 - header.opcode_base;
+                st.address += (adjusted / header.line_range) as u64 * min_inst_len;
+            }
+            DW_LNS_FIXED_ADVANCE_PC => st.address += r.uint(2)?,
+            _ => {
+                // column, is_stmt, basic block, prologue/epilogue, isa and
+                // unknown opcodes only take ULEB128 operands
+                for _ in 0..header.std_opcode_lens[op as usize - 1] {
+                    r.uleb()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the line number programs of all the units of .debug_line
+pub fn parse(sections: &Sections) -> Result<Vec<LineRow>, DwarfLineError> {
+    let mut rows = Vec::new();
+    let mut r = Reader::new(sections.debug_line, 0);
+    while r.pos < sections.debug_line.len() {
+        let (mut header, end) = read_header(&mut r, sections)?;
+        if end > sections.debug_line.len() {
+            return Err(r.error("unit exceeds the section"));
+        }
+        run_program(&mut r, &mut header, end, &mut rows)?;
+        r.pos = end;
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, LineRow, Sections};
+
+    fn row(address: u64, file: &str, line: u64, end_sequence: bool) -> LineRow {
+        LineRow {
+            address,
+            file: String::from(file),
+            line,
+            end_sequence,
+        }
+    }
+
+    /// Prepend the unit length
+    fn unit(body: Vec<u8>) -> Vec<u8> {
+        let mut data = (body.len() as u32).to_le_bytes().to_vec();
+        data.extend(body);
+        data
+    }
+
+    /// The header fields after header_length, for opcode_base 13
+    fn params(version: u16) -> Vec<u8> {
+        let mut v = vec![2]; // minimum_instruction_length
+        if version >= 4 {
+            v.push(1); // maximum_operations_per_instruction
+        }
+        v.extend([1, (-5i8) as u8, 14, 13]); // default_is_stmt, line_base, line_range, opcode_base
+        v.extend([0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]); // standard_opcode_lengths
+        v
+    }
+
+    #[rustfmt::skip]
+    fn program(file1: u8, file2: u8) -> Vec<u8> {
+        vec![
+            0x04, file1,                      // set_file
+            0, 5, 2, 0x00, 0x10, 0x00, 0x38, // set_address 0x38001000
+            0x05, 3,                          // set_column 3
+            0x03, 9,                          // advance_line 9 -> 10
+            0x01,                             // copy
+            13 + 5 + 14 * 2,                  // special: address += 2 * 2, line += 0
+            0x04, file2,                      // set_file
+            0x03, 0x7e,                       // advance_line -2 -> 8
+            0x02, 3,                          // advance_pc 3 * 2
+            0x01,                             // copy
+            0x09, 0x10, 0x00,                 // fixed_advance_pc 0x10
+            0, 1, 1,                          // end_sequence
+        ]
+    }
+
+    fn expected(file1: &str, file2: &str) -> Vec<LineRow> {
+        vec![
+            row(0x38001000, file1, 10, false),
+            row(0x38001004, file1, 10, false),
+            row(0x3800100a, file2, 8, false),
+            row(0x3800101a, file2, 8, true),
+        ]
+    }
+
+    #[test]
+    fn version4() {
+        let mut tables = Vec::new();
+        tables.extend(b"src\0/rustc/1234/library/core/src\0\0");
+        tables.extend(b"main.rs\0\x01\0\0");
+        tables.extend(b"option.rs\0\x02\0\0");
+        tables.push(0);
+
+        let mut hdr = params(4);
+        hdr.extend(tables);
+        let mut body = 4u16.to_le_bytes().to_vec();
+        body.extend((hdr.len() as u32).to_le_bytes());
+        body.extend(hdr);
+        body.extend(program(1, 2));
+
+        let data = unit(body);
+        let sections = Sections {
+            debug_line: &data,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse(&sections),
+            Ok(expected(
+                "src/main.rs",
+                "/rustc/1234/library/core/src/option.rs"
+            ))
+        );
+    }
+
+    #[test]
+    fn version5() {
+        let line_str = b"/work\0src\0main.rs\0lib.rs\0";
+
+        let mut tables = Vec::new();
+        // directories: path as line_strp
+        tables.extend([1, 1, 0x1f, 2, 0, 0, 0, 0, 6, 0, 0, 0]);
+        // files: path as line_strp, directory index as udata, MD5
+        tables.extend([3, 1, 0x1f, 2, 0x0b, 5, 0x1e, 3]);
+        tables.extend([10, 0, 0, 0, 1]);
+        tables.extend([0; 16]);
+        tables.extend([18, 0, 0, 0, 1]);
+        tables.extend([0; 16]);
+        tables.extend([10, 0, 0, 0, 0]); // unused file
+        tables.extend([0; 16]);
+
+        let mut hdr = params(5);
+        hdr.extend(tables);
+        let mut body = 5u16.to_le_bytes().to_vec();
+        body.extend([4, 0]); // address_size, segment_selector_size
+        body.extend((hdr.len() as u32).to_le_bytes());
+        body.extend(hdr);
+        // files are numbered from 0 in DWARF 5
+        body.extend(program(0, 1));
+
+        let data = unit(body);
+        let sections = Sections {
+            debug_line: &data,
+            debug_line_str: line_str,
+            ..Default::default()
+        };
+        assert_eq!(parse(&sections), Ok(expected("src/main.rs", "src/lib.rs")));
+    }
+
+    #[test]
+    fn two_units() {
+        let mut hdr = params(3);
+        hdr.extend(b"\0a.rs\0\0\0\0\0");
+        let mut body = 3u16.to_le_bytes().to_vec();
+        body.extend((hdr.len() as u32).to_le_bytes());
+        body.extend(hdr);
+        body.extend([0, 5, 2, 0x00, 0x20, 0, 0, 0x01, 0, 1, 1]);
+
+        let mut data = unit(body.clone());
+        data.extend(unit(body));
+        let sections = Sections {
+            debug_line: &data,
+            ..Default::default()
+        };
+        let rows = parse(&sections).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[2], row(0x2000, "a.rs", 1, false));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = unit(vec![4, 0, 0xff]);
+        let sections = Sections {
+            debug_line: &data,
+            ..Default::default()
+        };
+        assert!(parse(&sections).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+#![cfg_attr(not(test), no_std)]
+
+/*
+
+Stack unwinder for the ARM Exception Handling ABI.
+
+The linker gathers .ARM.exidx into a table sorted by function address with
+one entry of two words per function:
+
+    +------------------------+------------------------------------------+
+    | prel31 to the function | 1: EXIDX_CANTUNWIND                      |
+    |                        | 1 nnn nnnn...: unwind opcodes (compact)  |
+    |                        | 0 prel31 to the .ARM.extab entry         |
+    +------------------------+------------------------------------------+
+
+The unwind opcodes undo the function prologue on a virtual register set:
+they move the stack pointer and pop saved registers. Running them for the
+function that contains pc gives the register state of its caller, which
+works without frame pointers, in leaf functions too.
+
+Memory is read through `Memory`, so the caller decides what is safe to
+touch; the table itself is read that way as well.
+
+[refs]
+- https://github.com/ARM-software/abi-aa/blob/main/ehabi32/ehabi32.rst
+
+ */
+
+pub const SP: usize = 13;
+pub const LR: usize = 14;
+pub const PC: usize = 15;
+
+const EXIDX_CANTUNWIND: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// pc is not covered by the table
+    NoEntry,
+    /// The function is marked as not unwindable
+    CantUnwind,
+    /// The opcodes say the frame cannot be unwound
+    Refused,
+    /// Reserved, spare or unsupported opcode
+    BadOpcode(u8),
+    /// Unknown compact personality routine
+    BadPersonality(u32),
+    /// `Memory` refused the read at this address
+    BadRead(u32),
+}
+
+/// Core registers r0-r15
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Regs {
+    pub r: [u32; 16],
+}
+
+impl Regs {
+    pub fn sp(&self) -> u32 {
+        self.r[SP]
+    }
+
+    pub fn lr(&self) -> u32 {
+        self.r[LR]
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.r[PC]
+    }
+}
+
+pub trait Memory {
+    /// Read the word at `addr`, or `None` if it is not readable
+    fn read_u32(&self, addr: u32) -> Option<u32>;
+}
+
+impl<F> Memory for F
+where
+    F: Fn(u32) -> Option<u32>,
+{
+    fn read_u32(&self, addr: u32) -> Option<u32> {
+        self(addr)
+    }
+}
+
+fn read<M: Memory>(mem: &M, addr: u32) -> Result<u32, Error> {
+    mem.read_u32(addr).ok_or(Error::BadRead(addr))
+}
+
+/// Sign-extend a 31-bit offset relative to `addr`
+fn prel31(addr: u32, word: u32) -> u32 {
+    let off = ((word << 1) as i32 >> 1) as u32;
+    addr.wrapping_add(off)
+}
+
+/// Unwind opcodes, read a byte at a time from the most significant byte of
+/// each word
+struct Opcodes<'a, M: Memory> {
+    mem: &'a M,
+    word: u32,
+    next_addr: u32,  // of the following word
+    bytes_left: u32, // in `word`
+    words_left: u32, // after `word`
+}
+
+impl<'a, M: Memory> Opcodes<'a, M> {
+    /// The opcodes start in the low `bytes` bytes of `word`, followed by
+    /// `words` words from `next_addr`
+    fn new(mem: &'a M, word: u32, bytes: u32, next_addr: u32, words: u32) -> Self {
+        Self {
+            mem,
+            word,
+            next_addr,
+            bytes_left: bytes,
+            words_left: words,
+        }
+    }
+
+    /// Missing bytes read as "finish"
+    fn next(&mut self) -> Result<u8, Error> {
+        if self.bytes_left == 0 {
+            if self.words_left == 0 {
+                return Ok(OP_FINISH);
+            }
+            self.word = read(self.mem, self.next_addr)?;
+            self.next_addr += 4;
+            self.words_left -= 1;
+            self.bytes_left = 4;
+        }
+        self.bytes_left -= 1;
+        Ok((self.word >> (self.bytes_left * 8)) as u8)
+    }
+}
+
+const OP_FINISH: u8 = 0xb0;
+
+/// Virtual register set the opcodes run on
+struct Vrs<'a, M: Memory> {
+    mem: &'a M,
+    regs: &'a mut Regs,
+    vsp: u32,
+    pc_set: bool,
+}
+
+impl<'a, M: Memory> Vrs<'a, M> {
+    fn pop(&mut self) -> Result<u32, Error> {
+        let v = read(self.mem, self.vsp)?;
+        self.vsp = self.vsp.wrapping_add(4);
+        Ok(v)
+    }
+
+    /// Pop the registers in `mask`, bit 0 being `first`
+    fn pop_mask(&mut self, first: usize, mask: u32) -> Result<(), Error> {
+        let mut new_sp = None;
+        for i in 0..16 - first {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            let v = self.pop()?;
+            match first + i {
+                SP => new_sp = Some(v),
+                PC => {
+                    self.regs.r[PC] = v;
+                    self.pc_set = true;
+                }
+                n => self.regs.r[n] = v,
+            }
+        }
+        if let Some(sp) = new_sp {
+            self.vsp = sp;
+        }
+        Ok(())
+    }
+
+    /// Skip VFP registers, which we don't track
+    fn skip_vfp(&mut self, count: u32, fstmfdx: bool) {
+        let len = count * 8 + if fstmfdx { 4 } else { 0 };
+        self.vsp = self.vsp.wrapping_add(len);
+    }
+
+    fn execute(&mut self, ops: &mut Opcodes<M>) -> Result<(), Error> {
+        loop {
+            let op = ops.next()?;
+            match op {
+                0x00..=0x3f => self.vsp = self.vsp.wrapping_add(((op as u32) << 2) + 4),
+                0x40..=0x7f => self.vsp = self.vsp.wrapping_sub((((op & 0x3f) as u32) << 2) + 4),
+                0x80..=0x8f => {
+                    let mask = ((op as u32 & 0xf) << 8) | ops.next()? as u32;
+                    if mask == 0 {
+                        return Err(Error::Refused);
+                    }
+                    self.pop_mask(4, mask)?;
+                }
+                0x9d | 0x9f => return Err(Error::BadOpcode(op)),
+                0x90..=0x9f => self.vsp = self.regs.r[(op & 0xf) as usize],
+                0xa0..=0xaf => {
+                    let mut mask = (1 << ((op & 0x7) + 1)) - 1;
+                    if op & 0x8 != 0 {
+                        mask |= 1 << (LR - 4);
+                    }
+                    self.pop_mask(4, mask)?;
+                }
+                OP_FINISH => return Ok(()),
+                0xb1 => {
+                    let mask = ops.next()?;
+                    if mask == 0 || mask & 0xf0 != 0 {
+                        return Err(Error::BadOpcode(op));
+                    }
+                    self.pop_mask(0, mask as u32)?;
+                }
+                0xb2 => {
+                    let mut value: u32 = 0;
+                    let mut shift = 0;
+                    loop {
+                        let b = ops.next()?;
+                        value |= ((b & 0x7f) as u32) << shift;
+                        shift += 7;
+                        if b & 0x80 == 0 || shift >= 32 {
+                            break;
+                        }
+                    }
+                    self.vsp = self.vsp.wrapping_add(0x204 + (value << 2));
+                }
+                0xb3 => {
+                    let regs = ops.next()?;
+                    self.skip_vfp((regs & 0xf) as u32 + 1, true);
+                }
+                0xb8..=0xbf => self.skip_vfp((op & 0x7) as u32 + 1, true),
+                0xc8 | 0xc9 => {
+                    let regs = ops.next()?;
+                    self.skip_vfp((regs & 0xf) as u32 + 1, false);
+                }
+                0xd0..=0xd7 => self.skip_vfp((op & 0x7) as u32 + 1, false),
+                // spare, and the iWMMX registers that no M profile has
+                _ => return Err(Error::BadOpcode(op)),
+            }
+        }
+    }
+}
+
+/// The .ARM.exidx table between `start` and `end`
+#[derive(Clone, Copy, Debug)]
+pub struct ExidxTable {
+    start: u32,
+    end: u32,
+}
+
+impl ExidxTable {
+    pub const fn new(start: u32, end: u32) -> Self {
+        Self { start, end }
+    }
+
+    fn len(&self) -> u32 {
+        (self.end - self.start) / 8
+    }
+
+    fn entry_addr(&self, i: u32) -> u32 {
+        self.start + i * 8
+    }
+
+    fn function_addr<M: Memory>(&self, mem: &M, i: u32) -> Result<u32, Error> {
+        let addr = self.entry_addr(i);
+        Ok(prel31(addr, read(mem, addr)?))
+    }
+
+    /// Address of the entry of the function containing `addr`
+    pub fn lookup<M: Memory>(&self, mem: &M, addr: u32) -> Result<u32, Error> {
+        // the last entry starting at or below addr
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.function_addr(mem, mid)? <= addr {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        match lo {
+            0 => Err(Error::NoEntry),
+            i => Ok(self.entry_addr(i - 1)),
+        }
+    }
+
+    /// Replace `regs` with the register state of the caller of the function
+    /// containing pc. pc of a caller frame is a return address, which may be
+    /// past the end of the function if the call was its last instruction; if
+    /// `is_return_address`, the entry is looked up for the call instead.
+    pub fn unwind_frame<M: Memory>(
+        &self,
+        mem: &M,
+        regs: &mut Regs,
+        is_return_address: bool,
+    ) -> Result<(), Error> {
+        let mut addr = regs.pc() & !1;
+        if is_return_address {
+            addr = addr.wrapping_sub(2);
+        }
+        let entry = self.lookup(mem, addr)?;
+        let word = read(mem, entry + 4)?;
+
+        let mut ops = if word == EXIDX_CANTUNWIND {
+            return Err(Error::CantUnwind);
+        } else if word & (1 << 31) != 0 {
+            // compact model inline
+            if (word >> 24) & 0xf != 0 {
+                return Err(Error::BadPersonality((word >> 24) & 0xf));
+            }
+            Opcodes::new(mem, word, 3, 0, 0)
+        } else {
+            let extab = prel31(entry + 4, word);
+            let word = read(mem, extab)?;
+            if word & (1 << 31) != 0 {
+                match (word >> 24) & 0xf {
+                    0 => Opcodes::new(mem, word, 3, 0, 0),
+                    1 | 2 => Opcodes::new(mem, word, 2, extab + 4, (word >> 16) & 0xff),
+                    index => return Err(Error::BadPersonality(index)),
+                }
+            } else {
+                // generic model: a personality routine, then the opcodes
+                // prefixed with their number of additional words
+                let word = read(mem, extab + 4)?;
+                Opcodes::new(mem, word, 3, extab + 8, word >> 24)
+            }
+        };
+
+        let mut vrs = Vrs {
+            mem,
+            vsp: regs.sp(),
+            regs,
+            pc_set: false,
+        };
+        vrs.execute(&mut ops)?;
+
+        let (vsp, pc_set) = (vrs.vsp, vrs.pc_set);
+        regs.r[SP] = vsp;
+        if !pc_set {
+            regs.r[PC] = regs.r[LR];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, ExidxTable, Memory, Regs, LR, PC, SP};
+    use std::collections::BTreeMap;
+
+    const TEXT: u32 = 0x1000;
+    const EXIDX: u32 = 0x8000;
+    const EXTAB: u32 = 0x9000;
+    const STACK: u32 = 0x2_0000;
+
+    /// Words at fixed addresses
+    struct Mem(BTreeMap<u32, u32>);
+
+    impl Mem {
+        fn new() -> Self {
+            Self(BTreeMap::new())
+        }
+
+        fn put(&mut self, addr: u32, words: &[u32]) {
+            for (i, &w) in words.iter().enumerate() {
+                self.0.insert(addr + i as u32 * 4, w);
+            }
+        }
+
+        /// Table of (function, second word); a second word that is neither
+        /// CANTUNWIND nor compact is an offset into EXTAB
+        fn table(&mut self, entries: &[(u32, u32)]) -> ExidxTable {
+            for (i, &(func, word)) in entries.iter().enumerate() {
+                let addr = EXIDX + i as u32 * 8;
+                let word = match word {
+                    1 => 1,
+                    w if w & (1 << 31) != 0 => w,
+                    off => (EXTAB + off).wrapping_sub(addr + 4) & 0x7fff_ffff,
+                };
+                self.put(addr, &[func.wrapping_sub(addr) & 0x7fff_ffff, word]);
+            }
+            ExidxTable::new(EXIDX, EXIDX + entries.len() as u32 * 8)
+        }
+    }
+
+    impl Memory for Mem {
+        fn read_u32(&self, addr: u32) -> Option<u32> {
+            self.0.get(&addr).copied()
+        }
+    }
+
+    fn regs(pc: u32, lr: u32, sp: u32) -> Regs {
+        let mut regs = Regs::default();
+        regs.r[PC] = pc;
+        regs.r[LR] = lr;
+        regs.r[SP] = sp;
+        regs
+    }
+
+    #[test]
+    fn lookup() {
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 1), (TEXT + 0x40, 1), (TEXT + 0x100, 1)]);
+
+        assert_eq!(table.lookup(&mem, TEXT - 2), Err(Error::NoEntry));
+        assert_eq!(table.lookup(&mem, TEXT), Ok(EXIDX));
+        assert_eq!(table.lookup(&mem, TEXT + 0x3e), Ok(EXIDX));
+        assert_eq!(table.lookup(&mem, TEXT + 0x40), Ok(EXIDX + 8));
+        assert_eq!(table.lookup(&mem, TEXT + 0x200), Ok(EXIDX + 16));
+    }
+
+    #[test]
+    fn leaf_function() {
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x80b0b0b0)]);
+
+        let mut r = regs(TEXT + 0x10, 0x2001, STACK);
+        table.unwind_frame(&mem, &mut r, false).unwrap();
+        assert_eq!((r.pc(), r.sp()), (0x2001, STACK));
+    }
+
+    #[test]
+    fn compact_inline() {
+        // sub sp, #8; push {r4-r7, lr}
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x8001abb0)]);
+        mem.put(STACK, &[0, 0, 4, 5, 6, 7, 0x2001]);
+
+        let mut r = regs(TEXT + 0x10, 0xffff_fff9, STACK);
+        table.unwind_frame(&mem, &mut r, false).unwrap();
+        assert_eq!(&r.r[4..8], &[4, 5, 6, 7]);
+        assert_eq!((r.pc(), r.sp()), (0x2001, STACK + 28));
+    }
+
+    #[test]
+    fn extab_long() {
+        // push {r4, r11, lr}; vpush {d8}; mov r7, sp
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x80b0b0b0), (TEXT + 0x40, 0x10)]);
+        mem.put(EXTAB + 0x10, &[0x8101_97d0, 0x8481_b0b0]);
+        mem.put(STACK + 0x10, &[4, 11, 0x2001]);
+
+        let mut r = regs(TEXT + 0x44, 0, STACK);
+        r.r[7] = STACK + 0x08;
+        table.unwind_frame(&mem, &mut r, false).unwrap();
+        assert_eq!((r.r[4], r.r[11]), (4, 11));
+        assert_eq!((r.pc(), r.sp()), (0x2001, STACK + 0x1c));
+    }
+
+    #[test]
+    fn generic_model() {
+        // personality routine, then "add vsp, #0x204 + (1 << 2); pop {r4}"
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x20)]);
+        mem.put(EXTAB + 0x20, &[0x7fff_0000, 0x01b2_01a0, 0xb0b0_b0b0]);
+        mem.put(STACK + 0x208, &[44]);
+
+        let mut r = regs(TEXT, 0x3001, STACK);
+        table.unwind_frame(&mem, &mut r, false).unwrap();
+        assert_eq!(r.r[4], 44);
+        assert_eq!((r.pc(), r.sp()), (0x3001, STACK + 0x20c));
+    }
+
+    #[test]
+    fn pop_pc_and_sp() {
+        // pop {r4, sp, pc}
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x808a_01b0)]);
+        mem.put(STACK, &[4, STACK + 0x100, 0x4001]);
+
+        let mut r = regs(TEXT + 4, 0x2001, STACK);
+        table.unwind_frame(&mem, &mut r, false).unwrap();
+        assert_eq!(r.r[4], 4);
+        assert_eq!((r.pc(), r.sp()), (0x4001, STACK + 0x100));
+    }
+
+    #[test]
+    fn return_address_at_function_end() {
+        let mut mem = Mem::new();
+        let table = mem.table(&[(TEXT, 0x80b0b0b0), (TEXT + 0x40, 1)]);
+
+        let mut r = regs(TEXT + 0x41, 0x2001, STACK);
+        assert_eq!(
+            table.unwind_frame(&mem, &mut r, false),
+            Err(Error::CantUnwind)
+        );
+        table.unwind_frame(&mem, &mut r, true).unwrap();
+        assert_eq!(r.pc(), 0x2001);
+    }
+
+    #[test]
+    fn errors() {
+        let mut mem = Mem::new();
+        let table = mem.table(&[
+            (TEXT, 0x808000b0),
+            (TEXT + 0x40, 0x80c6b0b0),
+            (TEXT + 0x80, 0x8100_0000),
+        ]);
+
+        let mut r = regs(TEXT, 0, STACK);
+        assert_eq!(table.unwind_frame(&mem, &mut r, false), Err(Error::Refused));
+        let mut r = regs(TEXT + 0x40, 0, STACK);
+        assert_eq!(
+            table.unwind_frame(&mem, &mut r, false),
+            Err(Error::BadOpcode(0xc6))
+        );
+        let mut r = regs(TEXT + 0x80, 0, STACK);
+        assert_eq!(
+            table.unwind_frame(&mem, &mut r, false),
+            Err(Error::BadPersonality(1))
+        );
+
+        // stack not readable
+        let table = mem.table(&[(TEXT, 0x80a0b0b0)]);
+        let mut r = regs(TEXT, 0, STACK);
+        assert_eq!(
+            table.unwind_frame(&mem, &mut r, false),
+            Err(Error::BadRead(STACK))
+        );
+    }
+}
@@ -1,5 +1,7 @@
 extern crate posix;
 
+use alloc::string::String;
+
 #[derive(PartialEq, Debug)]
 pub struct ElfParserError {
     errno: posix::Errno,
@@ -10,6 +12,10 @@ impl ElfParserError {
     pub(crate) fn new(errno: posix::Errno, message: String) -> Self {
         Self { errno, message }
     }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 #[cfg(test)]
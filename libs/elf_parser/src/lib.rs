@@ -1,3 +1,11 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+use alloc::{format, string::ToString, vec::Vec};
+
+extern crate posix;
+use posix::Errno;
+
 extern crate stpack;
 use stpack::Stpack;
 
@@ -11,13 +19,15 @@ pub use err::ElfParserError;
 pub use symbol::{ElfSymbol, ElfSymbolBind, ElfSymbolType};
 
 pub use raw::{
+    header::ElfType,
     ident::{ElfClass, ElfEndian},
+    program_header::ElfSegmentType,
     section_header::ElfSectionHeaderType,
 };
 
 pub use symtab::ElfSymtabIterator;
 
-use raw::{header::ElfHeader, section_header::ElfSectionHeader};
+use raw::{header::ElfHeader, program_header::ElfProgramHeader, section_header::ElfSectionHeader};
 
 #[derive(PartialEq, Debug)]
 struct ElfSection<'a> {
@@ -32,27 +42,211 @@ struct ElfSection<'a> {
     content: &'a [u8],
 }
 
+/// A segment of the program header table
+#[derive(PartialEq, Debug)]
+pub struct ElfSegment<'a> {
+    pub typ: ElfSegmentType,
+    pub flags: u32,
+    pub vaddr: u64,
+    pub memsz: u64,
+    pub content: &'a [u8],
+}
+
+/// The ELF header, for readers that load the rest of the file themselves
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ElfFileHeader {
+    pub class: ElfClass,
+    pub endian: ElfEndian,
+    pub typ: ElfType,
+    /// e_machine, e.g. 40 for ARM
+    pub machine: u16,
+    /// e_flags, e.g. the EABI version on ARM
+    pub flags: u32,
+    pub entry: u64,
+    /// File offset, entry size and count of the program header table
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+}
+
+/// A program header, without the segment's content
+#[derive(PartialEq, Debug)]
+pub struct ElfSegmentHeader {
+    pub typ: ElfSegmentType,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl ElfFileHeader {
+    /// Size of the header of `class` files
+    pub const fn size(class: ElfClass) -> usize {
+        use raw::header::{Elf32Header, Elf64Header};
+        use raw::ident::ELF_IDENT_SIZE;
+
+        match class {
+            ElfClass::Elf32 => ELF_IDENT_SIZE + Elf32Header::SIZE,
+            ElfClass::Elf64 => ELF_IDENT_SIZE + Elf64Header::SIZE,
+        }
+    }
+
+    /// Parse the header at the start of `data`
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ElfParserError> {
+        use raw::{
+            header::{Elf32Header, Elf64Header},
+            ident::parse_ident,
+            program_header::{Elf32ProgramHeader, Elf64ProgramHeader},
+        };
+
+        let (class, endian) = parse_ident(data)?;
+        match class {
+            ElfClass::Elf32 => Self::parse::<Elf32Header, Elf32ProgramHeader>(data, class, endian),
+            ElfClass::Elf64 => Self::parse::<Elf64Header, Elf64ProgramHeader>(data, class, endian),
+        }
+    }
+
+    fn parse<H, PH>(data: &[u8], class: ElfClass, endian: ElfEndian) -> Result<Self, ElfParserError>
+    where
+        H: Stpack + ElfHeader,
+        PH: Stpack + ElfProgramHeader,
+    {
+        use raw::ident::ELF_IDENT_SIZE;
+
+        let le = endian == ElfEndian::ElfLE;
+        let header = H::unpack(&data[ELF_IDENT_SIZE..], le).or(Err(ElfParserError::new(
+            Errno::EINVAL,
+            "Failed to parse ELF header".to_string(),
+        )))?;
+        if header.get_version() != 1 {
+            return Err(ElfParserError::new(
+                Errno::EINVAL,
+                format!("Unsupported ELF version: {}", header.get_version()),
+            ));
+        }
+        if (header.get_ehsize() as usize) < ELF_IDENT_SIZE + H::SIZE {
+            return Err(ElfParserError::new(
+                Errno::EINVAL,
+                format!("ELF header size too small: {}", header.get_ehsize()),
+            ));
+        }
+        if header.get_phnum() > 0 && (header.get_phentsize() as usize) < PH::SIZE {
+            return Err(ElfParserError::new(
+                Errno::EINVAL,
+                format!("Program header size too small: {}", header.get_phentsize()),
+            ));
+        }
+
+        Ok(Self {
+            class,
+            endian,
+            typ: ElfType::from(header.get_type()),
+            machine: header.get_machine(),
+            flags: header.get_flags(),
+            entry: header.get_entry(),
+            phoff: header.get_phoff(),
+            phentsize: header.get_phentsize(),
+            phnum: header.get_phnum(),
+        })
+    }
+
+    /// Parse a program header of this file, e.g. the `phentsize` bytes at
+    /// `phoff + idx * phentsize`
+    pub fn program_header(&self, data: &[u8]) -> Result<ElfSegmentHeader, ElfParserError> {
+        use raw::program_header::{Elf32ProgramHeader, Elf64ProgramHeader};
+
+        match self.class {
+            ElfClass::Elf32 => self.parse_program_header::<Elf32ProgramHeader>(data),
+            ElfClass::Elf64 => self.parse_program_header::<Elf64ProgramHeader>(data),
+        }
+    }
+
+    fn parse_program_header<PH>(&self, data: &[u8]) -> Result<ElfSegmentHeader, ElfParserError>
+    where
+        PH: Stpack + ElfProgramHeader,
+    {
+        let le = self.endian == ElfEndian::ElfLE;
+        let ph = PH::unpack(data, le).or(Err(ElfParserError::new(
+            Errno::EINVAL,
+            "Failed to parse elf program header".to_string(),
+        )))?;
+        Ok(ElfSegmentHeader {
+            typ: ph.get_type(),
+            flags: ph.get_flags(),
+            offset: ph.get_offset(),
+            vaddr: ph.get_vaddr(),
+            filesz: ph.get_filesz(),
+            memsz: ph.get_memsz(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct ElfParser<'a> {
     pub class: ElfClass,
     pub endian: ElfEndian,
     sections: Vec<ElfSection<'a>>,
+    segments: Vec<ElfSegment<'a>>,
 }
 
 impl<'a> ElfParser<'a> {
     pub fn from_bytes(data: &'a [u8]) -> Result<Self, ElfParserError> {
         use raw::{
             header::{Elf32Header, Elf64Header},
-            ident::parse_ident,
             section_header::{Elf32SectionHeader, Elf64SectionHeader},
         };
 
-        let (class, endian) = parse_ident(data)?;
-        if class == ElfClass::Elf32 {
-            Self::parse_sections::<Elf32Header, Elf32SectionHeader>(data, class, endian)
+        let header = ElfFileHeader::from_bytes(data)?;
+        let (class, endian) = (header.class, header.endian);
+        let mut parser = if class == ElfClass::Elf32 {
+            Self::parse_sections::<Elf32Header, Elf32SectionHeader>(data, class, endian)?
         } else {
-            Self::parse_sections::<Elf64Header, Elf64SectionHeader>(data, class, endian)
+            Self::parse_sections::<Elf64Header, Elf64SectionHeader>(data, class, endian)?
+        };
+        parser.segments = Self::parse_segments(data, &header)?;
+        Ok(parser)
+    }
+
+    fn parse_segments(
+        data: &'a [u8],
+        header: &ElfFileHeader,
+    ) -> Result<Vec<ElfSegment<'a>>, ElfParserError> {
+        let mut segments: Vec<ElfSegment<'a>> = Vec::new();
+        let size = header.phentsize as usize;
+        for idx in 0..header.phnum as usize {
+            let off = header.phoff as usize + (size * idx);
+            let ph = data
+                .get(off..(off + size))
+                .and_then(|ph| header.program_header(ph).ok())
+                .ok_or(ElfParserError::new(
+                    Errno::EINVAL,
+                    format!("Failed to parse elf program header: {}", idx),
+                ))?;
+
+            let off = ph.offset as usize;
+            let content = data
+                .get(off..(off + ph.filesz as usize))
+                .ok_or(ElfParserError::new(
+                    Errno::EINVAL,
+                    format!(
+                        "Elf segment content out of range: \
+                             segment={:#x}--{:#x}, filesize={:#x}",
+                        off,
+                        off + ph.filesz as usize,
+                        data.len()
+                    ),
+                ))?;
+
+            segments.push(ElfSegment {
+                typ: ph.typ,
+                flags: ph.flags,
+                vaddr: ph.vaddr,
+                memsz: ph.memsz,
+                content,
+            });
         }
+        Ok(segments)
     }
 
     fn parse_sections<H, SH>(
@@ -67,9 +261,19 @@ impl<'a> ElfParser<'a> {
         use raw::{section_parser::SectionParser, strtab};
 
         let parser = SectionParser::<H, SH>::new(data, endian)?;
-        let (_, strtab_data) = parser.nth(data, parser.header.get_shstrndx() as usize)?;
-
         let mut sections: Vec<ElfSection<'a>> = Vec::new();
+
+        // e.g. core files have no section at all
+        if parser.header.get_shnum() == 0 {
+            return Ok(Self {
+                class,
+                endian,
+                sections,
+                segments: Vec::new(),
+            });
+        }
+
+        let (_, strtab_data) = parser.nth(data, parser.header.get_shstrndx() as usize)?;
         for idx in 0..parser.header.get_shnum() {
             let (sh, content) = parser.nth(data, idx as usize)?;
             let name = strtab::read_at(strtab_data, sh.get_name() as usize);
@@ -91,17 +295,38 @@ impl<'a> ElfParser<'a> {
             class,
             endian,
             sections,
+            segments: Vec::new(),
         })
     }
 
     pub fn iter_symbols(&'a self) -> ElfSymtabIterator<'a> {
         ElfSymtabIterator::new(self.class, self.endian, &self.sections)
     }
+
+    /// Content of the first section named `name`
+    pub fn section_data(&self, name: &[u8]) -> Option<&'a [u8]> {
+        self.section(name).map(|(_addr, content)| content)
+    }
+
+    /// Address and content of the first section named `name`
+    pub fn section(&self, name: &[u8]) -> Option<(u64, &'a [u8])> {
+        self.sections
+            .iter()
+            .find(|sec| sec.name == name)
+            .map(|sec| (sec.addr, sec.content))
+    }
+
+    pub fn segments(&self) -> &[ElfSegment<'a>] {
+        &self.segments
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ElfClass, ElfEndian, ElfParser, ElfSection, ElfSectionHeaderType, ElfSymbol};
+    use crate::{
+        ElfClass, ElfEndian, ElfFileHeader, ElfParser, ElfSection, ElfSectionHeaderType,
+        ElfSegment, ElfSegmentHeader, ElfSegmentType, ElfSymbol, ElfType,
+    };
 
     #[test]
     fn elf32be() {
@@ -203,6 +428,212 @@ mod tests {
         ElfParser::from_bytes(&data).expect_err("ElfParser::from_bytes unexpectedly succeed");
     }
 
+    #[test]
+    fn elf32be_section_data() {
+        let data: &[u8] = &[
+            // ident
+            0x7f, b'E', b'L', b'F', // magic; should be [0x7f, 'E', 'L', 'F']
+            1,    // 1: 32bit, 2: 64bit, others: error
+            2,    // 1: Little endian, 2: Big endian, others: error
+            1,    // elf version; should be 1
+            3,    // OS ABI
+            0,    // ABI version
+            0, 0, 0, 0, 0, 0, 0, // padding
+            // header
+            0, 2, // type = ET_EXEC (executable file)
+            0, 0, // machine = EM_NONE
+            0, 0, 0, 1, // version = 1
+            0xaa, 0xbb, 0xcc, 0xdd, // entry point
+            0, 0, 0, 0, // ph_off
+            0, 0, 0, 0x34, // sh_off
+            0, 0, 0, 0, // flags
+            0, 0x34, // ehsize
+            0, 0, // phentsize
+            0, 0, // phnum
+            0, 0x28, // shentsize
+            0, 1, // shnum
+            0, 0, // shstrndx
+            // .shstrtab section header
+            0, 0, 0, 1, // name
+            0, 0, 0, 3, // type = SHT_STRTAB
+            0, 0, 0, 0x20, // flags = SHF_STRINGS
+            0, 0, 0, 0, // addr
+            0, 0, 0, 0x5c, // offset
+            0, 0, 0, 0x0b, // size
+            0, 0, 0, 0, // link
+            0, 0, 0, 0, // info
+            0, 0, 0, 1, // addralign
+            0, 0, 0, 0, // entsize
+            // .shstrtab section content
+            0, b'.', b's', b'h', b's', b't', b'r', b't', b'a', b'b', 0,
+        ];
+
+        let parser = ElfParser::from_bytes(&data).unwrap();
+        assert_eq!(parser.section_data(b".shstrtab"), Some(&data[0x5c..]));
+        assert_eq!(parser.section_data(b".debug_line"), None);
+        assert_eq!(parser.section(b".shstrtab"), Some((0, &data[0x5c..])));
+    }
+
+    #[test]
+    fn elf32le_core_segments() {
+        let data: &[u8] = &[
+            0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ident
+            4, 0, // type = ET_CORE
+            40, 0, // machine = EM_ARM
+            1, 0, 0, 0, // version = 1
+            0, 0, 0, 0, // entry point
+            0x34, 0, 0, 0, // ph_off
+            0, 0, 0, 0, // sh_off
+            0, 0, 0, 0, // flags
+            0x34, 0, // ehsize
+            0x20, 0, // phentsize
+            2, 0, // phnum
+            0x28, 0, // shentsize
+            0, 0, // shnum
+            0, 0, // shstrndx
+            // PT_NOTE
+            4, 0, 0, 0, // type
+            0x74, 0, 0, 0, // offset
+            0, 0, 0, 0, // vaddr
+            0, 0, 0, 0, // paddr
+            2, 0, 0, 0, // filesz
+            0, 0, 0, 0, // memsz
+            0, 0, 0, 0, // flags
+            4, 0, 0, 0, // align
+            // PT_LOAD
+            1, 0, 0, 0, // type
+            0x76, 0, 0, 0, // offset
+            0x00, 0x00, 0x04, 0x38, // vaddr
+            0x00, 0x00, 0x04, 0x38, // paddr
+            2, 0, 0, 0, // filesz
+            2, 0, 0, 0, // memsz
+            6, 0, 0, 0, // flags = PF_R | PF_W
+            4, 0, 0, 0, // align
+            // contents
+            0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+
+        let parser = ElfParser::from_bytes(&data).unwrap();
+        assert_eq!(parser.section_data(b".shstrtab"), None);
+        assert_eq!(
+            parser.segments(),
+            &[
+                ElfSegment {
+                    typ: ElfSegmentType::Note,
+                    flags: 0,
+                    vaddr: 0,
+                    memsz: 0,
+                    content: &[0xaa, 0xbb],
+                },
+                ElfSegment {
+                    typ: ElfSegmentType::Load,
+                    flags: 6,
+                    vaddr: 0x38040000,
+                    memsz: 2,
+                    content: &[0xcc, 0xdd],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn elf32le_segment_content_incomplete() {
+        let mut data: Vec<u8> = vec![
+            0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ident
+            4, 0, 40, 0, 1, 0, 0, 0, 0, 0, 0, 0, // type, machine, version, entry
+            0x34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ph_off, sh_off, flags
+            0x34, 0, 0x20, 0, 1, 0, 0x28, 0, 0, 0, 0, 0, // sizes and counts
+        ];
+        data.extend([1, 0, 0, 0, 0x54, 0, 0, 0]); // PT_LOAD at 0x54
+        data.extend([0; 8]);
+        data.extend([0x10, 0, 0, 0, 0x10, 0, 0, 0]); // 16 bytes
+        data.extend([0; 8]);
+
+        assert!(ElfParser::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn elf32le_file_header() {
+        let data: &[u8] = &[
+            0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ident
+            3, 0, // type = ET_DYN
+            40, 0, // machine = EM_ARM
+            1, 0, 0, 0, // version = 1
+            0x01, 0x10, 0, 0, // entry point
+            0x34, 0, 0, 0, // ph_off
+            0, 0, 0, 0, // sh_off
+            0, 0, 0, 5, // flags = EABI version 5
+            0x34, 0, // ehsize
+            0x20, 0, // phentsize
+            1, 0, // phnum
+            0x28, 0, // shentsize
+            0, 0, // shnum
+            0, 0, // shstrndx
+            // PT_LOAD, not part of the header
+            1, 0, 0, 0, // type
+            0, 0, 0, 0, // offset
+            0, 0x10, 0, 0, // vaddr
+            0, 0x10, 0, 0, // paddr
+            0x20, 0, 0, 0, // filesz
+            0x40, 0, 0, 0, // memsz
+            7, 0, 0, 0, // flags = PF_R | PF_W | PF_X
+            4, 0, 0, 0, // align
+        ];
+
+        assert_eq!(ElfFileHeader::size(ElfClass::Elf32), 0x34);
+        let header = ElfFileHeader::from_bytes(&data[..0x34]).unwrap();
+        assert_eq!(
+            header,
+            ElfFileHeader {
+                class: ElfClass::Elf32,
+                endian: ElfEndian::ElfLE,
+                typ: ElfType::Dyn,
+                machine: 40,
+                flags: 0x0500_0000,
+                entry: 0x1001,
+                phoff: 0x34,
+                phentsize: 0x20,
+                phnum: 1,
+            }
+        );
+        assert_eq!(
+            header.program_header(&data[0x34..]),
+            Ok(ElfSegmentHeader {
+                typ: ElfSegmentType::Load,
+                flags: 7,
+                offset: 0,
+                vaddr: 0x1000,
+                filesz: 0x20,
+                memsz: 0x40,
+            })
+        );
+        assert!(header.program_header(&data[0x34..0x50]).is_err());
+    }
+
+    #[test]
+    fn elf32le_file_header_bad_version() {
+        let data: &[u8] = &[
+            0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ident
+            3, 0, 40, 0, 2, 0, 0, 0, 0, 0, 0, 0, // type, machine, version = 2, entry
+            0x34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ph_off, sh_off, flags
+            0x34, 0, 0x20, 0, 0, 0, 0x28, 0, 0, 0, 0, 0, // sizes and counts
+        ];
+
+        ElfFileHeader::from_bytes(data).expect_err("unsupported version accepted");
+    }
+
+    #[test]
+    fn elf32le_file_header_short_phentsize() {
+        let data: &[u8] = &[
+            0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ident
+            3, 0, 40, 0, 1, 0, 0, 0, 0, 0, 0, 0, // type, machine, version, entry
+            0x34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // ph_off, sh_off, flags
+            0x34, 0, 0x10, 0, 1, 0, 0x28, 0, 0, 0, 0, 0, // phentsize = 16
+        ];
+
+        ElfFileHeader::from_bytes(data).expect_err("short program headers accepted");
+    }
+
     #[test]
     fn elf32be_header_parse_error() {
         let data: &[u8] = &[
@@ -441,6 +872,7 @@ mod tests {
             class: ElfClass::Elf32,
             endian: ElfEndian::ElfLE,
             sections: vec![],
+            segments: vec![],
         };
 
         assert_eq!(
@@ -448,7 +880,8 @@ mod tests {
             "ElfParser { \
                     class: Elf32, \
                     endian: ElfLE, \
-                    sections: [] \
+                    sections: [], \
+                    segments: [] \
                     }"
         );
     }
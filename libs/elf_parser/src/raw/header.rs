@@ -3,6 +3,29 @@ use stpack::{stpack, Stpack};
 
 use crate::bits_struct;
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ElfType {
+    None,
+    Rel,
+    Exec,
+    Dyn,
+    Core,
+    Unknown(u16),
+}
+
+impl From<u16> for ElfType {
+    fn from(typ: u16) -> Self {
+        match typ {
+            0 => ElfType::None,
+            1 => ElfType::Rel,
+            2 => ElfType::Exec,
+            3 => ElfType::Dyn,
+            4 => ElfType::Core,
+            t => ElfType::Unknown(t),
+        }
+    }
+}
+
 bits_struct! {
     pub(crate) trait ElfHeader { }
     {
@@ -28,9 +51,16 @@ bits_struct! {
 
 #[cfg(test)]
 mod tests {
-    use crate::raw::header::{Elf32Header, Elf64Header, ElfHeader};
+    use crate::raw::header::{Elf32Header, Elf64Header, ElfHeader, ElfType};
     use crate::stpack::Stpack;
 
+    #[test]
+    fn elftype_from() {
+        assert_eq!(ElfType::from(2), ElfType::Exec);
+        assert_eq!(ElfType::from(3), ElfType::Dyn);
+        assert_eq!(ElfType::from(0xfe00), ElfType::Unknown(0xfe00));
+    }
+
     #[test]
     fn elf32header() {
         let data: Vec<u8> = (0u8..0xffu8).collect();
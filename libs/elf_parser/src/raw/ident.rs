@@ -1,4 +1,5 @@
 extern crate posix;
+use alloc::{format, string::String};
 use posix::Errno;
 
 use crate::ElfParserError;
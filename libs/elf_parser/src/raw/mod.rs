@@ -1,6 +1,7 @@
 mod bits_struct;
 pub mod header;
 pub mod ident;
+pub mod program_header;
 pub mod section_header;
 pub mod section_parser;
 pub mod strtab;
@@ -0,0 +1,139 @@
+extern crate stpack;
+use stpack::{stpack, Stpack};
+
+#[derive(PartialEq, Debug)]
+pub enum ElfSegmentType {
+    Null,
+    Load,
+    Dynamic,
+    Interp,
+    Note,
+    Shlib,
+    Phdr,
+    Tls,
+    Unknown(u32),
+}
+
+impl From<u32> for ElfSegmentType {
+    fn from(typ: u32) -> Self {
+        match typ {
+            0 => ElfSegmentType::Null,
+            1 => ElfSegmentType::Load,
+            2 => ElfSegmentType::Dynamic,
+            3 => ElfSegmentType::Interp,
+            4 => ElfSegmentType::Note,
+            5 => ElfSegmentType::Shlib,
+            6 => ElfSegmentType::Phdr,
+            7 => ElfSegmentType::Tls,
+            t => ElfSegmentType::Unknown(t),
+        }
+    }
+}
+
+// p_flags is after p_type in ELF64 but before p_align in ELF32, so
+// bits_struct! can't describe both
+pub(crate) trait ElfProgramHeader {
+    fn get_type(&self) -> ElfSegmentType;
+    fn get_flags(&self) -> u32;
+    fn get_offset(&self) -> u64;
+    fn get_vaddr(&self) -> u64;
+    fn get_filesz(&self) -> u64;
+    fn get_memsz(&self) -> u64;
+}
+
+stpack! {
+    pub(crate) struct Elf32ProgramHeader {
+        pub typ: u32,
+        pub offset: u32,
+        pub vaddr: u32,
+        pub paddr: u32,
+        pub filesz: u32,
+        pub memsz: u32,
+        pub flags: u32,
+        pub align: u32,
+    }
+}
+
+stpack! {
+    pub(crate) struct Elf64ProgramHeader {
+        pub typ: u32,
+        pub flags: u32,
+        pub offset: u64,
+        pub vaddr: u64,
+        pub paddr: u64,
+        pub filesz: u64,
+        pub memsz: u64,
+        pub align: u64,
+    }
+}
+
+macro_rules! impl_program_header {
+    ($sname:ident) => {
+        impl ElfProgramHeader for $sname {
+            fn get_type(&self) -> ElfSegmentType {
+                ElfSegmentType::from(self.typ)
+            }
+            fn get_flags(&self) -> u32 {
+                self.flags
+            }
+            fn get_offset(&self) -> u64 {
+                u64::from(self.offset)
+            }
+            fn get_vaddr(&self) -> u64 {
+                u64::from(self.vaddr)
+            }
+            fn get_filesz(&self) -> u64 {
+                u64::from(self.filesz)
+            }
+            fn get_memsz(&self) -> u64 {
+                u64::from(self.memsz)
+            }
+        }
+    };
+}
+
+impl_program_header!(Elf32ProgramHeader);
+impl_program_header!(Elf64ProgramHeader);
+
+#[cfg(test)]
+mod tests {
+    use crate::raw::program_header::{
+        Elf32ProgramHeader, Elf64ProgramHeader, ElfProgramHeader, ElfSegmentType,
+    };
+    use crate::stpack::Stpack;
+
+    #[test]
+    fn elf32programheader() {
+        let data: Vec<u8> = (0u8..0xffu8).collect();
+        let ph = Elf32ProgramHeader::unpack(&data, true).unwrap();
+        let ph: &dyn ElfProgramHeader = &ph;
+
+        assert_eq!(ph.get_type(), ElfSegmentType::Unknown(0x03020100));
+        assert_eq!(ph.get_offset(), 0x07060504);
+        assert_eq!(ph.get_vaddr(), 0x0b0a0908);
+        assert_eq!(ph.get_filesz(), 0x13121110);
+        assert_eq!(ph.get_memsz(), 0x17161514);
+        assert_eq!(ph.get_flags(), 0x1b1a1918);
+    }
+
+    #[test]
+    fn elf64programheader() {
+        let data: Vec<u8> = (0u8..0xffu8).collect();
+        let ph = Elf64ProgramHeader::unpack(&data, false).unwrap();
+        let ph: &dyn ElfProgramHeader = &ph;
+
+        assert_eq!(ph.get_type(), ElfSegmentType::Unknown(0x00010203));
+        assert_eq!(ph.get_flags(), 0x04050607);
+        assert_eq!(ph.get_offset(), 0x08090a0b_0c0d0e0f);
+        assert_eq!(ph.get_vaddr(), 0x10111213_14151617);
+        assert_eq!(ph.get_filesz(), 0x20212223_24252627);
+        assert_eq!(ph.get_memsz(), 0x28292a2b_2c2d2e2f);
+    }
+
+    #[test]
+    fn segmenttype_from() {
+        assert_eq!(ElfSegmentType::from(1), ElfSegmentType::Load);
+        assert_eq!(ElfSegmentType::from(4), ElfSegmentType::Note);
+        assert_eq!(ElfSegmentType::from(9), ElfSegmentType::Unknown(9));
+    }
+}
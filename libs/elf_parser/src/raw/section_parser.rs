@@ -1,4 +1,5 @@
 extern crate posix;
+use alloc::{format, string::ToString};
 use posix::Errno;
 
 extern crate stpack;
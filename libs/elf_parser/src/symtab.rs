@@ -1,4 +1,5 @@
 extern crate posix;
+use alloc::{format, string::String, vec::Vec};
 use posix::Errno;
 
 extern crate stpack;
@@ -1,7 +1,7 @@
 extern crate stpack;
 use stpack::Stpack;
 
-use crate::types::{AddrTblEntry, Header, StrTblOff};
+use crate::types::{AddrTblEntry, FileTblOff, Header, LineBlock, LineHeader, StrTblOff};
 
 pub struct KAllSyms {
     base: usize,
@@ -27,11 +27,19 @@ impl KAllSyms {
     }
 
     fn get_u8_array(&self, table_off: u16, i: usize) -> &'static [u8] {
+        self.get_u8_array_at(self.base + table_off as usize, i)
+    }
+
+    fn get_u8_array_at(&self, addr_table: usize, i: usize) -> &'static [u8] {
         use core::mem;
-        let addr_table = self.base + table_off as usize;
         let addr_off = addr_table + ((mem::size_of::<StrTblOff>()) * i);
-        let off = addr_table + unsafe { *(addr_off as *const StrTblOff) as usize };
-        let ptr = off as *const u8;
+        let off = unsafe { core::ptr::read_unaligned(addr_off as *const StrTblOff) as usize };
+        Self::u8_array_at(addr_table + off)
+    }
+
+    /// The bytes after the length byte at `addr`
+    fn u8_array_at(addr: usize) -> &'static [u8] {
+        let ptr = addr as *const u8;
         unsafe { core::slice::from_raw_parts(ptr.add(1), *ptr as usize) }
     }
 
@@ -102,6 +110,92 @@ impl KAllSyms {
             }
         }
     }
+
+    fn line_table(&self) -> Option<(usize, LineHeader)> {
+        if self.header.line_table_off == 0 {
+            return None;
+        }
+        let base = self.base + self.header.line_table_off as usize;
+        let header = unsafe { core::slice::from_raw_parts(base as *const u8, LineHeader::SIZE) };
+        Some((base, LineHeader::unpack_le(header).unwrap()))
+    }
+
+    fn nth_block(&self, table: usize, header: &LineHeader, i: usize) -> LineBlock {
+        let addr = table + header.block_table_off as usize + LineBlock::SIZE * i;
+        let block = unsafe { core::slice::from_raw_parts(addr as *const u8, LineBlock::SIZE) };
+        LineBlock::unpack_le(block).unwrap()
+    }
+
+    fn read_uleb(ptr: &mut *const u8) -> u32 {
+        let mut v: u32 = 0;
+        let mut shift = 0;
+        loop {
+            let b = unsafe { **ptr };
+            *ptr = unsafe { ptr.add(1) };
+            if shift < 32 {
+                v |= ((b & 0x7f) as u32) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return v;
+            }
+        }
+    }
+
+    /// File and line of the code at `addr`
+    pub fn search_line(&self, addr: AddrTblEntry) -> Option<(&'static str, u32)> {
+        let (table, header) = self.line_table()?;
+        if header.block_count == 0 || addr < self.nth_block(table, &header, 0).addr {
+            return None;
+        }
+
+        // last block starting at or below addr
+        let mut left: usize = 0;
+        let mut right: usize = header.block_count as usize;
+        while right - left > 1 {
+            let center = (left + right) / 2;
+            if self.nth_block(table, &header, center).addr <= addr {
+                left = center;
+            } else {
+                right = center;
+            }
+        }
+
+        let block = self.nth_block(table, &header, left);
+        let end = if left + 1 < header.block_count as usize {
+            self.nth_block(table, &header, left + 1).rows_off
+        } else {
+            header.file_table_off
+        } as usize;
+
+        let mut ptr = (table + block.rows_off as usize) as *const u8;
+        let end = (table + end) as *const u8;
+        let (mut row_addr, mut file, mut line) = (block.addr, 0u32, 0u32);
+        let (mut found_file, mut found_line) = (0u32, 0u32);
+        while ptr < end {
+            row_addr += Self::read_uleb(&mut ptr);
+            if row_addr > addr {
+                break;
+            }
+            let v = Self::read_uleb(&mut ptr);
+            let delta = v >> 1;
+            line = line.wrapping_add(((delta >> 1) as i32 ^ -((delta & 1) as i32)) as u32);
+            if v & 1 != 0 {
+                file = Self::read_uleb(&mut ptr);
+            }
+            found_file = file;
+            found_line = line;
+        }
+
+        if found_file == 0 {
+            return None;
+        }
+        let files = table + header.file_table_off as usize;
+        let off_addr = files + core::mem::size_of::<FileTblOff>() * (found_file as usize - 1);
+        let off = unsafe { core::ptr::read_unaligned(off_addr as *const FileTblOff) as usize };
+        let name = Self::u8_array_at(files + off);
+        Some((core::str::from_utf8(name).ok()?, found_line))
+    }
 }
 
 #[cfg(test)]
@@ -109,13 +203,13 @@ mod tests {
     use stpack::Stpack;
 
     extern crate kallsyms_enc;
-    use kallsyms_enc::pack;
+    use kallsyms_enc::{pack, pack_with_lines};
 
     use crate::Header;
 
     #[test]
     fn normal1() {
-        let data = pack(&vec![
+        let data = pack(&[
             (String::from("alloc::vec::Vec<T>::new"), 0x1000),
             (String::from("alloc::raw_vec::alloc_guard"), 0x2000),
             (
@@ -180,4 +274,56 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn lines() {
+        let mut lines = vec![
+            (0x1000, String::from("src/main.rs"), 10),
+            (0x1004, String::from("src/main.rs"), 12),
+            (0x1010, String::from("libs/vfs/src/lib.rs"), 245),
+            (0x1020, String::from(""), 0),
+            (0x2000, String::from("src/main.rs"), 3),
+            (0x2008, String::from(""), 0),
+        ];
+        for i in 0..100 {
+            lines.push((0x3000 + i * 2, format!("src/{}.rs", i % 7), 1000 - i));
+        }
+        lines.push((0x3000 + 200, String::from(""), 0));
+
+        let data = pack_with_lines(&[(String::from("main"), 0x1000)], &lines);
+        let kallsyms = crate::KAllSyms::new(data.as_ptr() as usize);
+
+        assert_eq!(kallsyms.search_line(0x0fff), None);
+        assert_eq!(kallsyms.search_line(0x1000), Some(("src/main.rs", 10)));
+        assert_eq!(kallsyms.search_line(0x1006), Some(("src/main.rs", 12)));
+        assert_eq!(
+            kallsyms.search_line(0x101e),
+            Some(("libs/vfs/src/lib.rs", 245))
+        );
+        assert_eq!(kallsyms.search_line(0x1020), None);
+        assert_eq!(kallsyms.search_line(0x1fff), None);
+        assert_eq!(kallsyms.search_line(0x2004), Some(("src/main.rs", 3)));
+        assert_eq!(kallsyms.search_line(0x2008), None);
+        for i in 0..100 {
+            let file = format!("src/{}.rs", i % 7);
+            assert_eq!(
+                kallsyms.search_line(0x3001 + i * 2),
+                Some((file.as_str(), 1000 - i))
+            );
+        }
+        assert_eq!(kallsyms.search_line(0x4000), None);
+
+        let mut namebuf: [u8; 16] = [0; 16];
+        assert_eq!(
+            kallsyms.safe_search(0x1010, &mut namebuf),
+            Some(("main", 0x10))
+        );
+    }
+
+    #[test]
+    fn no_lines() {
+        let data = pack(&[(String::from("main"), 0x1000)]);
+        let kallsyms = crate::KAllSyms::new(data.as_ptr() as usize);
+        assert_eq!(kallsyms.search_line(0x1000), None);
+    }
 }
@@ -5,6 +5,10 @@ mod types;
 
 pub use crate::kallsyms::KAllSyms;
 pub use types::AddrTblEntry;
+pub use types::FileTblOff;
 pub use types::Header;
+pub use types::LineBlock;
+pub use types::LineHeader;
 pub use types::StrTblOff;
+pub use types::LINE_BLOCK_ROWS;
 pub type KAddress = types::AddrTblEntry;
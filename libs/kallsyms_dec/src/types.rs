@@ -3,7 +3,7 @@ use stpack::{stpack, Stpack};
 
 stpack! {
     pub struct Header {
-        pub reserved: u32,
+        pub line_table_off: u32,
         pub count: u16,
         pub addr_table_off: u16,
         pub name_table_off: u16,
@@ -11,12 +11,33 @@ stpack! {
     }
 }
 
+stpack! {
+    pub struct LineHeader {
+        pub block_count: u32,
+        pub block_table_off: u32,
+        pub file_table_off: u32,
+        pub reserved: u32,
+    }
+}
+
+stpack! {
+    pub struct LineBlock {
+        pub addr: u32,
+        pub rows_off: u32,
+    }
+}
+
 pub type AddrTblEntry = u32;
 pub type StrTblOff = u16;
+/// Offsets in the line table's file table, which holds long paths
+pub type FileTblOff = u32;
+
+/// Rows per block of the line table
+pub const LINE_BLOCK_ROWS: usize = 32;
 
 /*
  *                        --------- .---------------------------.
- *                         ^  ^  ^  |  reserved: u32            |
+ *                         ^  ^  ^  |  line_table_off: u32      |
  *                         |  |  |  |  sym_count: u32           |
  *                         |  |  |  |  addr_table_off: u32      |
  *                         |  |  |  |  name_table_off: u32      |
@@ -70,3 +91,44 @@ pub type StrTblOff = u16;
  *                                  |    +----------------+     |
  *                                  '==========================='
  */
+
+/*
+ * Line table, at line_table_off from the start of kallsyms (0: no table).
+ * Offsets in it are from its own start.
+ *
+ *                        --------- .---------------------------.
+ *                         ^  ^     |  block_count: u32         |
+ *                         |  |     |  block_table_off: u32     |
+ *                         |  |     |  file_table_off: u32      |
+ *                         v  |     |  reserved: u32            |
+ *        block_table_off --- |     +===========================+
+ *                            |     |  block[0]: LineBlock      |
+ *                            |     |    addr: u32              |
+ *                            |     |    rows_off: u32          |
+ *                            |     |  block[1]                 |
+ *                            |     |    ..                     |
+ *                            |     |  block[block_count - 1]   |
+ *                            |     +===========================+ <- block[0].rows_off
+ *                            |     |  row                      |
+ *                            |     |    ..                     |
+ *                            |     |  (LINE_BLOCK_ROWS rows)   |
+ *                            |     +---------------------------+ <- block[1].rows_off
+ *                            |     |    ..                     |
+ *                            v     |                           |
+ *         file_table_off --------- +===========================+
+ *                                  |  file_off[0]: FileTblOff  |
+ *                                  |    ..                     |
+ *                                  |  len: u8, byte[0] ..      |
+ *                                  |    ..                     |
+ *                                  '==========================='
+ *
+ * A row is where a run of code with the same file:line starts:
+ *
+ *   ULEB128  address - address of the previous row
+ *   ULEB128  zigzag(line - line of the previous row) << 1 | file changed
+ *  [ULEB128  file, if changed]
+ *
+ * Decoding of a block starts from address = block.addr, line = 0, file = 0.
+ * Files are numbered from 1, entry (file - 1) of the file table; file 0
+ * marks addresses that don't have a line (gaps between sequences).
+ */
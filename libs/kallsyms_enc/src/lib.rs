@@ -1,4 +1,5 @@
 mod compress;
+mod lines;
 mod pack;
 
-pub use pack::pack;
+pub use pack::{pack, pack_with_lines};
@@ -0,0 +1,162 @@
+extern crate stpack;
+use stpack::Stpack;
+
+extern crate kallsyms_dec;
+use kallsyms_dec::{FileTblOff, LineBlock, LineHeader, LINE_BLOCK_ROWS};
+
+use crate::pack::str_table_sized;
+
+fn uleb(result: &mut Vec<u8>, mut v: u32) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            result.push(b);
+            break;
+        }
+        result.push(b | 0x80);
+    }
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+/// Keep the rows that start a new file:line, the last one of each address
+fn dedup(lines: &[(u32, String, u32)]) -> Vec<(u32, &str, u32)> {
+    let mut rows: Vec<(u32, &str, u32)> = Vec::new();
+    for (addr, file, line) in lines.iter() {
+        let line = if file.is_empty() { 0 } else { *line };
+        if let Some(last) = rows.last_mut() {
+            if last.0 == *addr {
+                *last = (*addr, file, line);
+                continue;
+            }
+            if last.1 == file && last.2 == line {
+                continue;
+            }
+        }
+        rows.push((*addr, file, line));
+    }
+    rows
+}
+
+/// Encode the line table from rows of (address, file, line) sorted by
+/// address, an empty file marking an address without line
+pub fn line_table(lines: &[(u32, String, u32)]) -> Vec<u8> {
+    let rows = dedup(lines);
+
+    let mut files: Vec<&str> = Vec::new();
+    let mut blocks: Vec<LineBlock> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+
+    for chunk in rows.chunks(LINE_BLOCK_ROWS) {
+        blocks.push(LineBlock {
+            addr: chunk[0].0,
+            rows_off: data.len() as u32,
+        });
+
+        let (mut addr, mut file, mut line) = (chunk[0].0, 0usize, 0u32);
+        for &(a, f, l) in chunk.iter() {
+            let f = if f.is_empty() {
+                0
+            } else {
+                match files.iter().position(|&s| s == f) {
+                    Some(i) => i + 1,
+                    None => {
+                        files.push(f);
+                        files.len()
+                    }
+                }
+            };
+
+            uleb(&mut data, a - addr);
+            let delta = zigzag(l.wrapping_sub(line) as i32);
+            uleb(&mut data, delta << 1 | (f != file) as u32);
+            if f != file {
+                uleb(&mut data, f as u32);
+            }
+            addr = a;
+            file = f;
+            line = l;
+        }
+    }
+
+    // paths longer than a length byte keep their end
+    let names: Vec<Vec<u8>> = files
+        .iter()
+        .map(|f| f.as_bytes()[f.len().saturating_sub(255)..].to_vec())
+        .collect();
+    let mut file_table = str_table_sized(&names, core::mem::size_of::<FileTblOff>())
+        .expect("file table too large for FileTblOff offsets");
+
+    let block_table_off = LineHeader::SIZE as u32;
+    let rows_off = block_table_off + (LineBlock::SIZE * blocks.len()) as u32;
+    let file_table_off = rows_off + data.len() as u32;
+
+    let header = LineHeader {
+        block_count: blocks.len() as u32,
+        block_table_off,
+        file_table_off,
+        reserved: 0,
+    };
+
+    let mut result: Vec<u8> = vec![0u8; rows_off as usize];
+    header.pack_le(&mut result[..LineHeader::SIZE]).unwrap();
+    for (i, mut block) in blocks.into_iter().enumerate() {
+        let off = block_table_off as usize + LineBlock::SIZE * i;
+        block.rows_off += rows_off;
+        block
+            .pack_le(&mut result[off..off + LineBlock::SIZE])
+            .unwrap();
+    }
+    result.append(&mut data);
+    result.append(&mut file_table);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lines::{dedup, line_table, zigzag};
+
+    #[test]
+    fn zigzag_1() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+    }
+
+    #[test]
+    fn dedup_1() {
+        let lines = vec![
+            (0x1000, String::from("a.rs"), 1),
+            (0x1000, String::from("a.rs"), 2),
+            (0x1004, String::from("a.rs"), 2),
+            (0x1008, String::from("b.rs"), 2),
+            (0x100c, String::from(""), 7),
+        ];
+        assert_eq!(
+            dedup(&lines),
+            vec![(0x1000, "a.rs", 2), (0x1008, "b.rs", 2), (0x100c, "", 0)]
+        );
+    }
+
+    #[test]
+    fn line_table_1() {
+        let lines = vec![
+            (0x1000, String::from("a.rs"), 10),
+            (0x1004, String::from("a.rs"), 8),
+            (0x1010, String::from(""), 0),
+        ];
+        assert_eq!(
+            line_table(&lines),
+            vec![
+                1, 0, 0, 0, 16, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, // header
+                0x00, 0x10, 0, 0, 24, 0, 0, 0, // blocks
+                0, 41, 1, 4, 6, 12, 31, 0, // rows
+                4, 0, 0, 0, 4, b'a', b'.', b'r', b's', // files
+            ]
+        );
+    }
+}
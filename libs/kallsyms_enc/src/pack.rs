@@ -7,14 +7,21 @@ use kmp_search::kmp_search_all;
 extern crate kallsyms_dec;
 use kallsyms_dec::{AddrTblEntry, Header, StrTblOff};
 
-fn str_table(data: &Vec<Vec<u8>>) -> Vec<u8> {
+/// Table of `data`: the offsets of the strings from the table's start, of
+/// `off_size` bytes each, then the strings after a length byte each; None if
+/// an offset doesn't fit in `off_size` bytes
+pub(crate) fn str_table_sized(data: &[Vec<u8>], off_size: usize) -> Option<Vec<u8>> {
     let mut result: Vec<u8> = Vec::new();
 
     // write offset table
-    let mut off: StrTblOff = (core::mem::size_of::<StrTblOff>() * data.len()) as StrTblOff;
+    let limit = 1u64 << (8 * off_size);
+    let mut off = (off_size * data.len()) as u64;
     for s in data.iter() {
-        result.extend_from_slice(&off.to_le_bytes());
-        off += s.len() as StrTblOff + 1;
+        if off >= limit {
+            return None;
+        }
+        result.extend_from_slice(&off.to_le_bytes()[..off_size]);
+        off += s.len() as u64 + 1;
     }
 
     // write strings
@@ -23,7 +30,13 @@ fn str_table(data: &Vec<Vec<u8>>) -> Vec<u8> {
         result.extend_from_slice(s);
     }
 
-    result
+    Some(result)
+}
+
+/// Table of `data` with StrTblOff offsets
+pub(crate) fn str_table(data: &[Vec<u8>]) -> Vec<u8> {
+    str_table_sized(data, core::mem::size_of::<StrTblOff>())
+        .expect("string table too large for StrTblOff offsets")
 }
 
 fn tokenize(data: &[u8], dic: &Vec<Vec<u8>>) -> Vec<u8> {
@@ -52,8 +65,15 @@ fn tokenize(data: &[u8], dic: &Vec<Vec<u8>>) -> Vec<u8> {
     result
 }
 
-pub fn pack(symbols: &Vec<(String, u32)>) -> Vec<u8> {
+pub fn pack(symbols: &[(String, u32)]) -> Vec<u8> {
+    pack_with_lines(symbols, &[])
+}
+
+/// Pack `symbols` and, if not empty, the line table of `lines`
+/// (see `lines::line_table`)
+pub fn pack_with_lines(symbols: &[(String, u32)], lines: &[(u32, String, u32)]) -> Vec<u8> {
     use crate::compress::make_dic;
+    use crate::lines::line_table;
 
     let dic: Vec<Vec<u8>> = make_dic(
         symbols
@@ -79,8 +99,16 @@ pub fn pack(symbols: &Vec<(String, u32)>) -> Vec<u8> {
     let name_table_off = addr_table_off + (core::mem::size_of::<AddrTblEntry>() as u16 * count);
     let token_table_off = name_table_off + name_table.len() as u16;
 
+    // the line table follows, 4-byte aligned
+    let symbols_len = token_table_off as usize + token_table.len();
+    let line_table_off = if lines.is_empty() {
+        0
+    } else {
+        ((symbols_len + 3) & !3) as u32
+    };
+
     let header = Header {
-        reserved: 0,
+        line_table_off,
         count,
         addr_table_off,
         name_table_off,
@@ -96,17 +124,21 @@ pub fn pack(symbols: &Vec<(String, u32)>) -> Vec<u8> {
     }
     result.append(&mut name_table);
     result.append(&mut token_table);
+    if line_table_off != 0 {
+        result.resize(line_table_off as usize, 0);
+        result.append(&mut line_table(lines));
+    }
     result
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::pack::{str_table, tokenize};
+    use crate::pack::{str_table, str_table_sized, tokenize};
 
     #[test]
     fn str_table_1() {
         assert_eq!(
-            str_table(&vec![vec![0u8, 1], vec![2u8, 3, 4], vec![5u8],]),
+            str_table(&[vec![0u8, 1], vec![2u8, 3, 4], vec![5u8],]),
             vec![
                 // offsets
                 6u8, 0, 9, 0, 13, 0, // payload
@@ -115,6 +147,21 @@ mod tests {
         )
     }
 
+    #[test]
+    fn str_table_sized_1() {
+        assert_eq!(
+            str_table_sized(&[vec![0u8, 1], vec![2u8]], 4),
+            Some(vec![
+                // offsets
+                8u8, 0, 0, 0, 11, 0, 0, 0, // payload
+                2, 0, 1, 1, 2,
+            ])
+        );
+        // 300 strings of 256 bytes each pass 64K
+        assert_eq!(str_table_sized(&vec![vec![0u8; 255]; 300], 2), None);
+        assert!(str_table_sized(&vec![vec![0u8; 255]; 300], 4).is_some());
+    }
+
     #[test]
     fn tokenize_1() {
         assert_eq!(
@@ -0,0 +1,343 @@
+#![cfg_attr(not(test), no_std)]
+
+/*
+
+Building blocks of the kernel log: severity levels, a fixed-size ring of
+log records that overwrites the oldest one when full, and per-module level
+filtering.
+
+Records are numbered by a sequence number that keeps counting across
+overwrites, so readers can tell which records they have missed.
+
+ */
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, str};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 0,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Self::ALL.into_iter().find(|l| l.name() == name)
+    }
+}
+
+/// Fixed-capacity text, formatted into with `write!`; overflowing text is
+/// cut at a character boundary
+#[derive(Clone, Copy)]
+pub struct TextBuf<const LEN: usize> {
+    len: usize,
+    buf: [u8; LEN],
+}
+
+impl<const LEN: usize> TextBuf<LEN> {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            buf: [0; LEN],
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole characters are ever copied in
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl<const LEN: usize> Default for TextBuf<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LEN: usize> fmt::Write for TextBuf<LEN> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LEN - self.len;
+        let mut n = s.len().min(room);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Record<const LEN: usize> {
+    pub seq: u64,
+    pub level: Level,
+    pub timestamp: u64,
+    pub module: &'static str,
+    pub text: TextBuf<LEN>,
+}
+
+pub struct LogBuffer<const SLOTS: usize, const LEN: usize> {
+    records: [Record<LEN>; SLOTS],
+    first_seq: u64,
+    next_seq: u64,
+}
+
+impl<const SLOTS: usize, const LEN: usize> LogBuffer<SLOTS, LEN> {
+    pub const fn new() -> Self {
+        Self {
+            records: [Record {
+                seq: 0,
+                level: Level::Debug,
+                timestamp: 0,
+                module: "",
+                text: TextBuf::new(),
+            }; SLOTS],
+            first_seq: 0,
+            next_seq: 0,
+        }
+    }
+
+    /// Sequence number of the oldest record still held
+    pub fn first_seq(&self) -> u64 {
+        self.first_seq
+            .max(self.next_seq.saturating_sub(SLOTS as u64))
+    }
+
+    /// Sequence number the next record will get
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn len(&self) -> usize {
+        (self.next_seq - self.first_seq()) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append a record, overwriting the oldest one if full; returns its sequence number
+    pub fn push(
+        &mut self,
+        level: Level,
+        timestamp: u64,
+        module: &'static str,
+        text: &TextBuf<LEN>,
+    ) -> u64 {
+        let seq = self.next_seq;
+        self.records[(seq % SLOTS as u64) as usize] = Record {
+            seq,
+            level,
+            timestamp,
+            module,
+            text: *text,
+        };
+        self.next_seq += 1;
+        seq
+    }
+
+    pub fn get(&self, seq: u64) -> Option<&Record<LEN>> {
+        if seq < self.first_seq() || seq >= self.next_seq {
+            return None;
+        }
+        Some(&self.records[(seq % SLOTS as u64) as usize])
+    }
+
+    /// Drop all records; sequence numbers keep counting
+    pub fn clear(&mut self) {
+        self.first_seq = self.next_seq;
+    }
+}
+
+impl<const SLOTS: usize, const LEN: usize> Default for LogBuffer<SLOTS, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Log level threshold per module path. The rule of the longest module
+/// path prefix (on `::` boundaries) applies; the default one otherwise.
+pub struct Filter {
+    default: Level,
+    rules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    pub const fn new(default: Level) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn default_level(&self) -> Level {
+        self.default
+    }
+
+    pub fn set_default(&mut self, level: Level) {
+        self.default = level;
+    }
+
+    pub fn set(&mut self, module: &str, level: Level) {
+        match self.rules.iter_mut().find(|(m, _)| m == module) {
+            Some(rule) => rule.1 = level,
+            None => self.rules.push((String::from(module), level)),
+        }
+    }
+
+    /// Returns false if there was no rule for `module`
+    pub fn remove(&mut self, module: &str) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|(m, _)| m != module);
+        self.rules.len() != len
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&str, Level)> {
+        self.rules.iter().map(|(m, l)| (m.as_str(), *l))
+    }
+
+    pub fn level_for(&self, module: &str) -> Level {
+        let matches = |prefix: &str| {
+            module == prefix
+                || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"))
+        };
+        self.rules
+            .iter()
+            .filter(|(m, _)| matches(m))
+            .max_by_key(|(m, _)| m.len())
+            .map_or(self.default, |(_, l)| *l)
+    }
+
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        level <= self.level_for(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Filter, Level, LogBuffer, TextBuf};
+    use core::fmt::Write;
+
+    fn text<const LEN: usize>(s: &str) -> TextBuf<LEN> {
+        let mut t = TextBuf::new();
+        write!(t, "{}", s).unwrap();
+        t
+    }
+
+    #[test]
+    fn level_names() {
+        for level in Level::ALL {
+            assert_eq!(Level::from_name(level.name()), Some(level));
+        }
+        assert_eq!(Level::from_name("verbose"), None);
+        assert!(Level::Error < Level::Debug);
+    }
+
+    #[test]
+    fn textbuf_truncate() {
+        let t: TextBuf<8> = text("hello world");
+        assert_eq!(t.as_str(), "hello wo");
+
+        // never split a multi-byte character
+        let t: TextBuf<4> = text("abc\u{3042}");
+        assert_eq!(t.as_str(), "abc");
+
+        let mut t: TextBuf<8> = TextBuf::new();
+        write!(t, "{}-{}", 12, 34).unwrap();
+        assert_eq!(t.as_str(), "12-34");
+    }
+
+    #[test]
+    fn push_get() {
+        let mut buf: LogBuffer<4, 16> = LogBuffer::new();
+        assert!(buf.is_empty());
+        assert!(buf.get(0).is_none());
+
+        assert_eq!(buf.push(Level::Info, 100, "a", &text("one")), 0);
+        assert_eq!(buf.push(Level::Warn, 200, "b", &text("two")), 1);
+        assert_eq!(buf.len(), 2);
+
+        let rec = buf.get(1).unwrap();
+        assert_eq!(rec.seq, 1);
+        assert_eq!(rec.level, Level::Warn);
+        assert_eq!(rec.timestamp, 200);
+        assert_eq!(rec.module, "b");
+        assert_eq!(rec.text.as_str(), "two");
+        assert!(buf.get(2).is_none());
+    }
+
+    #[test]
+    fn overwrite_oldest() {
+        let mut buf: LogBuffer<3, 16> = LogBuffer::new();
+        for i in 0..5 {
+            buf.push(Level::Info, i, "m", &text(&format!("{}", i)));
+        }
+        assert_eq!(buf.len(), 3);
+        assert_eq!(buf.first_seq(), 2);
+        assert_eq!(buf.next_seq(), 5);
+        assert!(buf.get(1).is_none());
+        for seq in 2..5 {
+            assert_eq!(buf.get(seq).unwrap().text.as_str(), format!("{}", seq));
+        }
+    }
+
+    #[test]
+    fn clear() {
+        let mut buf: LogBuffer<3, 16> = LogBuffer::new();
+        buf.push(Level::Info, 0, "m", &text("a"));
+        buf.push(Level::Info, 0, "m", &text("b"));
+        buf.clear();
+        assert!(buf.is_empty());
+        assert!(buf.get(1).is_none());
+        assert_eq!(buf.push(Level::Info, 0, "m", &text("c")), 2);
+        assert_eq!(buf.first_seq(), 2);
+        assert_eq!(buf.get(2).unwrap().text.as_str(), "c");
+    }
+
+    #[test]
+    fn filter() {
+        let mut filter = Filter::new(Level::Info);
+        assert!(filter.enabled(Level::Info, "sched"));
+        assert!(!filter.enabled(Level::Debug, "sched"));
+
+        filter.set("arm_uart", Level::Error);
+        filter.set("sched", Level::Debug);
+        filter.set("sched::timer", Level::Warn);
+
+        assert!(!filter.enabled(Level::Warn, "arm_uart"));
+        assert!(filter.enabled(Level::Error, "arm_uart"));
+        assert!(filter.enabled(Level::Debug, "sched"));
+        assert!(filter.enabled(Level::Debug, "sched::queue"));
+        assert!(!filter.enabled(Level::Info, "sched::timer"));
+        assert_eq!(filter.level_for("sched::timer::wheel"), Level::Warn);
+        // not a module path boundary
+        assert_eq!(filter.level_for("scheduler"), Level::Info);
+
+        filter.set("sched", Level::Error);
+        assert_eq!(filter.level_for("sched"), Level::Error);
+        assert_eq!(filter.rules().count(), 3);
+
+        assert!(filter.remove("sched"));
+        assert!(!filter.remove("sched"));
+        assert_eq!(filter.level_for("sched"), Level::Info);
+
+        filter.set_default(Level::Debug);
+        assert_eq!(filter.default_level(), Level::Debug);
+        assert!(filter.enabled(Level::Debug, "hostfs"));
+    }
+}
@@ -1,7 +1,7 @@
 #![cfg_attr(not(test), no_std)]
 #![feature(no_coverage)]
 
-use core::{alloc::GlobalAlloc, cell::UnsafeCell, mem::size_of, ptr};
+use core::{cell::UnsafeCell, mem::size_of, ptr};
 extern crate alloc;
 use alloc::alloc::Layout;
 
@@ -104,6 +104,14 @@ impl AreaList {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HeapStats {
+    pub total: usize,
+    pub free: usize,
+    pub largest_free: usize,
+    pub free_areas: usize,
+}
+
 pub struct LinkedListAllocator {
     initialized: bool,
     free_areas: UnsafeCell<AreaList>,
@@ -148,6 +156,27 @@ impl LinkedListAllocator {
         self.mem_end = mem_end;
     }
 
+    /// Walk the free list; callers must keep the heap from changing meanwhile
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            total: self.mem_end - self.mem_top,
+            free: 0,
+            largest_free: 0,
+            free_areas: 0,
+        };
+        if !self.initialized {
+            return stats;
+        }
+
+        let list = unsafe { &*self.free_areas.get() };
+        for (area, _) in list.iter_with_prev() {
+            stats.free += area.size;
+            stats.largest_free = stats.largest_free.max(area.size);
+            stats.free_areas += 1;
+        }
+        stats
+    }
+
     unsafe fn __alloc(&self, size: usize) -> *mut u8 {
         if !self.initialized {
             panic!("Heap used before initialize allocator");
@@ -250,16 +279,26 @@ impl LinkedListAllocator {
     }
 }
 
-unsafe impl Sync for LinkedListAllocator {}
+// It owns the heap area, wherever it goes. It is not Sync: share it behind
+// a lock.
+unsafe impl Send for LinkedListAllocator {}
 
-unsafe impl GlobalAlloc for LinkedListAllocator {
+impl LinkedListAllocator {
+    /// Null if there is no free area large enough
+    ///
+    /// # Safety
+    ///
+    /// `init()` must have been called.
     #[no_coverage]
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
         self.__alloc(align_up(layout.align(), layout.size()))
     }
 
+    /// # Safety
+    ///
+    /// `ptr` and `layout` have to come from `alloc()` of this allocator.
     #[no_coverage]
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         self.__dealloc(ptr, align_up(layout.align(), layout.size()))
     }
 }
@@ -468,6 +507,29 @@ mod tests {
         assert!(heap.total_free() == total_free);
     }
 
+    #[test]
+    fn stats() {
+        let mut heap = Heap::new();
+        let stats = heap.allocator.stats();
+        assert_eq!(stats.total, heap.buf_size());
+        assert_eq!(stats.free, heap.total_free());
+        assert_eq!(stats.largest_free, stats.free);
+        assert_eq!(stats.free_areas, 1);
+
+        let ptr1 = heap.alloc(0x100);
+        let ptr2 = heap.alloc(0x100);
+        heap.dealloc(ptr1, 0x100);
+        let stats = heap.allocator.stats();
+        assert_eq!(stats.free, heap.total_free());
+        assert_eq!(stats.free_areas, 2);
+        assert!(stats.largest_free < stats.free);
+
+        heap.dealloc(ptr2, 0x100);
+        let stats = heap.allocator.stats();
+        assert_eq!(stats.free, stats.total);
+        assert_eq!(stats.free_areas, 1);
+    }
+
     #[test]
     fn random() {
         use mersenne_twister::MersenneTwister;
@@ -40,6 +40,32 @@ pub struct RegisterW<const OFF: usize, T, BF> {
 }
 impl<const OFF: usize, T: From<BF>, BF> Writeable<OFF, T, BF> for RegisterW<OFF, T, BF> {}
 
+pub struct RegisterArrayRW<const OFF: usize, T, BF, const N: usize> {
+    _t: core::marker::PhantomData<T>,
+    _bf: core::marker::PhantomData<BF>,
+}
+impl<const OFF: usize, T, BF, const N: usize> RegisterArrayRW<OFF, T, BF, N> {
+    fn ptr(&self, idx: usize) -> *mut T {
+        assert!(idx < N, "register index out of range: {} >= {}", idx, N);
+        (self as *const _ as *const u8 as usize + OFF + core::mem::size_of::<T>() * idx) as *mut T
+    }
+
+    pub fn read_at(&self, idx: usize) -> BF
+    where
+        BF: From<T>,
+    {
+        unsafe { self.ptr(idx).read_volatile() }.into()
+    }
+
+    pub fn write_at(&mut self, idx: usize, val: BF)
+    where
+        T: From<BF>,
+    {
+        let v: T = val.into();
+        unsafe { self.ptr(idx).write_volatile(v) }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate bitfield;
@@ -91,4 +117,34 @@ mod tests {
         assert_eq!(uart.state.read(), State::RX_BF);
         assert_eq!(uart.ctrl.read(), Ctrl::TX_EN | Ctrl::TX_INTR_EN);
     }
+
+    use crate::RegisterArrayRW;
+
+    struct Nvic {
+        iser: RegisterArrayRW<0x04, u32, u32, 2>,
+        ipr: RegisterArrayRW<0x0c, u8, u8, 4>,
+    }
+
+    #[test]
+    fn test_array() {
+        let buf: [u32; 4] = [0, 1, 2, 0x44332211];
+        let nvic = unsafe { &mut *(buf.as_ptr() as usize as *mut Nvic) };
+
+        assert_eq!(nvic.iser.read_at(0), 1);
+        assert_eq!(nvic.iser.read_at(1), 2);
+        assert_eq!(nvic.ipr.read_at(2), 0x33);
+
+        nvic.iser.write_at(1, 0xaa);
+        nvic.ipr.write_at(0, 0xbb);
+
+        assert_eq!(buf, [0, 1, 0xaa, 0x443322bb]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_array_out_of_range() {
+        let buf: [u32; 4] = [0; 4];
+        let nvic = unsafe { &mut *(buf.as_ptr() as usize as *mut Nvic) };
+        nvic.iser.read_at(2);
+    }
 }
@@ -1,3 +1,5 @@
+#![cfg_attr(not(test), no_std)]
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Errno {
     EPERM = 1,
@@ -34,6 +36,62 @@ pub enum Errno {
     EPIPE = 32,
     EDOM = 33,
     ERANGE = 34,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOLCK = 37,
+    ENOSYS = 38,
+}
+
+const ERRNOS: [Errno; 38] = [
+    Errno::EPERM,
+    Errno::ENOENT,
+    Errno::ESRCH,
+    Errno::EINTR,
+    Errno::EIO,
+    Errno::ENXIO,
+    Errno::E2BIG,
+    Errno::ENOEXEC,
+    Errno::EBADF,
+    Errno::ECHILD,
+    Errno::EAGAIN,
+    Errno::ENOMEM,
+    Errno::EACCES,
+    Errno::EFAULT,
+    Errno::ENOTBLK,
+    Errno::EBUSY,
+    Errno::EEXIST,
+    Errno::EXDEV,
+    Errno::ENODEV,
+    Errno::ENOTDIR,
+    Errno::EISDIR,
+    Errno::EINVAL,
+    Errno::ENFILE,
+    Errno::EMFILE,
+    Errno::ENOTTY,
+    Errno::ETXTBSY,
+    Errno::EFBIG,
+    Errno::ENOSPC,
+    Errno::ESPIPE,
+    Errno::EROFS,
+    Errno::EMLINK,
+    Errno::EPIPE,
+    Errno::EDOM,
+    Errno::ERANGE,
+    Errno::EDEADLK,
+    Errno::ENAMETOOLONG,
+    Errno::ENOLCK,
+    Errno::ENOSYS,
+];
+
+impl TryFrom<i32> for Errno {
+    type Error = i32;
+
+    fn try_from(v: i32) -> Result<Self, Self::Error> {
+        if v < 1 {
+            return Err(v);
+        }
+        ERRNOS.get((v - 1) as usize).copied().ok_or(v)
+    }
 }
 
 #[cfg(test)]
@@ -45,6 +103,18 @@ mod tests {
         assert_eq!(Errno::EINVAL, Errno::EINVAL.clone());
     }
 
+    #[test]
+    fn errno_try_from() {
+        assert_eq!(Errno::try_from(1), Ok(Errno::EPERM));
+        assert_eq!(Errno::try_from(22), Ok(Errno::EINVAL));
+        assert_eq!(Errno::try_from(38), Ok(Errno::ENOSYS));
+        assert_eq!(Errno::try_from(0), Err(0));
+        assert_eq!(Errno::try_from(39), Err(39));
+        for i in 1..=38 {
+            assert_eq!(Errno::try_from(i).map(|e| e as i32), Ok(i));
+        }
+    }
+
     #[test]
     fn errno_debug() {
         assert_eq!(format!("{:?}", Errno::EINVAL), "EINVAL");
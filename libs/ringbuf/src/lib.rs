@@ -0,0 +1,174 @@
+#![cfg_attr(not(test), no_std)]
+
+/*
+
+Lock-free single-producer single-consumer byte queue.
+
+`head` and `tail` are free-running counters; the slot of a counter is its
+value modulo N. Only the producer writes `head` and only the consumer
+writes `tail`, so both sides can run concurrently (e.g. an interrupt
+handler and a thread) without a lock.
+
+ */
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RingBuffer<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= N
+    }
+
+    /// Producer side. Returns false if the queue is full.
+    pub fn push(&self, v: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) >= N {
+            return false;
+        }
+        unsafe { (*self.buf.get())[head % N] = v };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Consumer side
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let v = unsafe { (*self.buf.get())[tail % N] };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RingBuffer;
+
+    #[test]
+    fn push_pop() {
+        let rb: RingBuffer<4> = RingBuffer::new();
+        assert!(rb.is_empty());
+        assert_eq!(rb.pop(), None);
+
+        assert!(rb.push(1));
+        assert!(rb.push(2));
+        assert_eq!(rb.len(), 2);
+        assert_eq!(rb.pop(), Some(1));
+        assert_eq!(rb.pop(), Some(2));
+        assert_eq!(rb.pop(), None);
+    }
+
+    #[test]
+    fn full() {
+        let rb: RingBuffer<3> = RingBuffer::new();
+        assert_eq!(rb.capacity(), 3);
+        assert!(rb.push(1));
+        assert!(rb.push(2));
+        assert!(rb.push(3));
+        assert!(rb.is_full());
+        assert!(!rb.push(4));
+        assert_eq!(rb.pop(), Some(1));
+        assert!(rb.push(4));
+        assert_eq!(rb.pop(), Some(2));
+        assert_eq!(rb.pop(), Some(3));
+        assert_eq!(rb.pop(), Some(4));
+        assert!(rb.is_empty());
+    }
+
+    #[test]
+    fn wrap_around() {
+        let rb: RingBuffer<5> = RingBuffer::new();
+        for i in 0..1000u32 {
+            assert!(rb.push(i as u8));
+            assert!(rb.push((i + 1) as u8));
+            assert_eq!(rb.pop(), Some(i as u8));
+            assert_eq!(rb.pop(), Some((i + 1) as u8));
+        }
+        assert!(rb.is_empty());
+    }
+
+    #[test]
+    fn counter_overflow() {
+        let rb: RingBuffer<4> = RingBuffer::new();
+        rb.head
+            .store(usize::MAX - 1, core::sync::atomic::Ordering::Relaxed);
+        rb.tail
+            .store(usize::MAX - 1, core::sync::atomic::Ordering::Relaxed);
+        for i in 0..4 {
+            assert!(rb.push(i));
+        }
+        assert!(!rb.push(4));
+        for i in 0..4 {
+            assert_eq!(rb.pop(), Some(i));
+        }
+        assert_eq!(rb.pop(), None);
+    }
+
+    #[test]
+    fn spsc_threads() {
+        use std::sync::Arc;
+
+        let rb: Arc<RingBuffer<16>> = Arc::new(RingBuffer::new());
+        let producer = {
+            let rb = rb.clone();
+            std::thread::spawn(move || {
+                for i in 0..100_000u32 {
+                    while !rb.push(i as u8) {
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        for i in 0..100_000u32 {
+            let v = loop {
+                if let Some(v) = rb.pop() {
+                    break v;
+                }
+                std::thread::yield_now();
+            };
+            assert_eq!(v, i as u8);
+        }
+        producer.join().unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+#![no_std]
+
+/*
+
+Calls from the non-secure kernel into the secure monitor (secure/).
+
+The monitor's VENEER region, which the SAU makes non-secure callable,
+starts with a table of veneers, one per call in the order of the CALL_*
+numbers. Each is an SG instruction and a branch to the entry function, so
+the kernel needs no import library from the monitor's link: it calls
+
+    __secure_veneers + call * VENEER_SIZE
+
+with the Thumb bit set, where the generated linker script of the kernel
+puts `__secure_veneers` at the VENEER region of board.toml.
+
+Arguments and results follow the AAPCS. Negative results are -errno.
+
+ */
+
+/// Bytes per veneer: SG and B.W
+pub const VENEER_SIZE: usize = 8;
+
+/// `fn() -> u32`: the monitor's `VERSION`
+pub const CALL_VERSION: usize = 0;
+/// `fn(slot: u32, buf: *mut u8, len: usize) -> i32`: copy up to `len`
+/// bytes of a storage slot into `buf`, return the slot's length
+pub const CALL_STORAGE_READ: usize = 1;
+/// `fn(slot: u32, buf: *const u8, len: usize) -> i32`: replace the
+/// contents of a storage slot, return 0
+pub const CALL_STORAGE_WRITE: usize = 2;
+
+pub const CALL_COUNT: usize = 3;
+
+/// Bumped whenever a call changes
+pub const VERSION: u32 = 1;
+
+pub const STORAGE_SLOTS: usize = 8;
+pub const STORAGE_SLOT_SIZE: usize = 256;
@@ -0,0 +1,434 @@
+#![cfg_attr(not(test), no_std)]
+
+/*
+
+Hierarchical timer wheel.
+
+Time is counted in ticks. LEVELS wheels of SLOTS slots hold the pending
+timers: level 0 those due within SLOTS ticks, a slot per tick, and level n
+those due within SLOTS^(n+1) ticks, a slot per SLOTS^n ticks. Whenever a
+level wraps around, the slot that comes up on the level above is cascaded:
+its timers are placed again, now on a finer level. Timers further out than
+the top level reaches are parked there and placed again when their slot
+comes up.
+
+    level 3 |  |  | ... |  |  |     SLOTS^3 ticks per slot
+    level 2 |  |  | ... |  |  |     SLOTS^2
+    level 1 |  |  | ... |  |  |     SLOTS
+    level 0 |  |  | ... |  |  |     1
+
+The timers live in a slab and are linked into the list of their slot by
+index, so advancing the wheel, stopping and re-arming a timer never
+allocate; only `insert()` and `remove()` touch the heap. An interrupt
+handler can drive the wheel while allocation stays in thread context.
+
+A timer that came due is handed out once by `poll()` and then stays in the
+slab, stopped, until it is re-armed or removed.
+
+ */
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+const SLOT_BITS: u32 = 6;
+pub const SLOTS: usize = 1 << SLOT_BITS;
+pub const LEVELS: usize = 4;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+
+/// Furthest ahead of the wheel a timer is placed
+const MAX_DELTA: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+const NIL: usize = usize::MAX;
+/// List of the timers that came due and haven't been polled yet
+const EXPIRED: usize = LEVELS * SLOTS;
+/// Most timers a wheel holds, as many as the index of a TimerId can tell
+const MAX_TIMERS: usize = 1 << 16;
+
+/// Handle of a timer, which goes stale when the timer is removed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimerId(u32);
+
+impl TimerId {
+    fn new(index: usize, gen: u16) -> Self {
+        Self(((gen as u32) << 16) | index as u32)
+    }
+
+    fn index(&self) -> usize {
+        (self.0 & 0xffff) as usize
+    }
+
+    fn gen(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    pub fn into_raw(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+}
+
+impl fmt::Display for TimerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct List {
+    head: usize,
+    tail: usize,
+}
+
+impl List {
+    const EMPTY: List = List {
+        head: NIL,
+        tail: NIL,
+    };
+}
+
+struct Timer<T> {
+    data: T,
+    expires: u64,
+    /// List the timer is linked into, NIL while stopped
+    list: usize,
+    prev: usize,
+    next: usize,
+}
+
+struct Slot<T> {
+    gen: u16,
+    timer: Option<Timer<T>>,
+}
+
+pub struct TimerWheel<T> {
+    now: u64,
+    lists: [List; LEVELS * SLOTS + 1],
+    slab: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> TimerWheel<T> {
+    pub const fn new() -> Self {
+        Self {
+            now: 0,
+            lists: [List::EMPTY; LEVELS * SLOTS + 1],
+            slab: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// The last tick the wheel was advanced to
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Add a timer due at tick `expires`; one already due is handed out by
+    /// the next `poll()`
+    pub fn insert(&mut self, expires: u64, data: T) -> TimerId {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                assert!(self.slab.len() < MAX_TIMERS, "too many timers");
+                self.slab.push(Slot {
+                    gen: 0,
+                    timer: None,
+                });
+                self.slab.len() - 1
+            }
+        };
+        self.slab[index].timer = Some(Timer {
+            data,
+            expires,
+            list: NIL,
+            prev: NIL,
+            next: NIL,
+        });
+        self.place(index);
+        TimerId::new(index, self.slab[index].gen)
+    }
+
+    /// Drop the timer, armed or not, and return its data
+    pub fn remove(&mut self, id: TimerId) -> Option<T> {
+        let index = self.lookup(id)?;
+        self.unlink(index);
+        let slot = &mut self.slab[index];
+        let timer = slot.timer.take()?;
+        slot.gen = slot.gen.wrapping_add(1);
+        self.free.push(index);
+        Some(timer.data)
+    }
+
+    /// Disarm the timer; false if it wasn't armed
+    pub fn stop(&mut self, id: TimerId) -> bool {
+        match self.lookup(id) {
+            Some(index) if self.at(index).list != NIL => {
+                self.unlink(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Arm the timer again, or move it, to tick `expires`
+    pub fn rearm(&mut self, id: TimerId, expires: u64) -> bool {
+        match self.lookup(id) {
+            Some(index) => {
+                self.unlink(index);
+                self.at_mut(index).expires = expires;
+                self.place(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, id: TimerId) -> Option<&T> {
+        self.lookup(id).map(|index| &self.at(index).data)
+    }
+
+    pub fn get_mut(&mut self, id: TimerId) -> Option<&mut T> {
+        self.lookup(id).map(|index| &mut self.at_mut(index).data)
+    }
+
+    /// Tick the timer is due at, `None` if it isn't armed
+    pub fn expires(&self, id: TimerId) -> Option<u64> {
+        let timer = self.at(self.lookup(id)?);
+        match timer.list {
+            NIL => None,
+            _ => Some(timer.expires),
+        }
+    }
+
+    /// Advance the wheel up to tick `now` and hand out the next timer due by
+    /// then, which is stopped; `None` once there are no more
+    pub fn poll(&mut self, now: u64) -> Option<TimerId> {
+        loop {
+            let index = self.lists[EXPIRED].head;
+            if index != NIL {
+                self.unlink(index);
+                return Some(TimerId::new(index, self.slab[index].gen));
+            }
+            if self.now >= now {
+                return None;
+            }
+            self.step();
+        }
+    }
+
+    /// Every timer with its data and, if it's armed, the tick it is due at
+    pub fn iter(&self) -> impl Iterator<Item = (TimerId, &T, Option<u64>)> + '_ {
+        self.slab.iter().enumerate().filter_map(|(index, slot)| {
+            slot.timer.as_ref().map(|timer| {
+                let expires = match timer.list {
+                    NIL => None,
+                    _ => Some(timer.expires),
+                };
+                (TimerId::new(index, slot.gen), &timer.data, expires)
+            })
+        })
+    }
+
+    fn lookup(&self, id: TimerId) -> Option<usize> {
+        match self.slab.get(id.index()) {
+            Some(slot) if slot.gen == id.gen() && slot.timer.is_some() => Some(id.index()),
+            _ => None,
+        }
+    }
+
+    fn at(&self, index: usize) -> &Timer<T> {
+        self.slab[index].timer.as_ref().unwrap()
+    }
+
+    fn at_mut(&mut self, index: usize) -> &mut Timer<T> {
+        self.slab[index].timer.as_mut().unwrap()
+    }
+
+    fn step(&mut self) {
+        self.now += 1;
+        let now = self.now;
+        for level in 1..LEVELS {
+            let shift = SLOT_BITS * level as u32;
+            if now & ((1 << shift) - 1) != 0 {
+                break;
+            }
+            self.replace(level * SLOTS + ((now >> shift) & SLOT_MASK) as usize);
+        }
+        self.replace((now & SLOT_MASK) as usize);
+    }
+
+    /// Place every timer of list `n` again
+    fn replace(&mut self, n: usize) {
+        let mut index = self.lists[n].head;
+        self.lists[n] = List::EMPTY;
+        while index != NIL {
+            let next = self.at(index).next;
+            self.place(index);
+            index = next;
+        }
+    }
+
+    /// Link the timer into the list its expiry falls in; its old links are
+    /// ignored
+    fn place(&mut self, index: usize) {
+        let expires = self.at(index).expires;
+        let list = if expires <= self.now {
+            EXPIRED
+        } else {
+            let delta = (expires - self.now).min(MAX_DELTA);
+            let at = self.now + delta;
+            let level = (0..LEVELS)
+                .find(|level| delta >> (SLOT_BITS * (*level as u32 + 1)) == 0)
+                .unwrap();
+            level * SLOTS + ((at >> (SLOT_BITS * level as u32)) & SLOT_MASK) as usize
+        };
+
+        let tail = self.lists[list].tail;
+        let timer = self.at_mut(index);
+        timer.list = list;
+        timer.prev = tail;
+        timer.next = NIL;
+        match tail {
+            NIL => self.lists[list].head = index,
+            _ => self.at_mut(tail).next = index,
+        }
+        self.lists[list].tail = index;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let timer = self.at_mut(index);
+        let (list, prev, next) = (timer.list, timer.prev, timer.next);
+        if list == NIL {
+            return;
+        }
+        timer.list = NIL;
+        timer.prev = NIL;
+        timer.next = NIL;
+        match prev {
+            NIL => self.lists[list].head = next,
+            _ => self.at_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.lists[list].tail = prev,
+            _ => self.at_mut(next).prev = prev,
+        }
+    }
+}
+
+impl<T> Default for TimerWheel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{TimerWheel, MAX_DELTA, SLOTS};
+
+    /// Poll tick by tick up to `end` and collect (tick, data) of what fires
+    fn run(wheel: &mut TimerWheel<u64>, end: u64) -> Vec<(u64, u64)> {
+        let mut fired = Vec::new();
+        for now in wheel.now() + 1..=end {
+            while let Some(id) = wheel.poll(now) {
+                fired.push((now, wheel.remove(id).unwrap()));
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn fires_on_time() {
+        let s = SLOTS as u64;
+        let ticks = [1, 2, s - 1, s, s + 1, s * s - 1, s * s, s * s + 1, 300_000];
+
+        // also from an odd starting point, one tick before a wrap
+        for start in [0, s - 1] {
+            let mut wheel = TimerWheel::new();
+            assert_eq!(wheel.poll(start), None);
+            for t in ticks.iter().rev() {
+                wheel.insert(start + t, start + t);
+            }
+            let fired = run(&mut wheel, start + 300_000);
+            let expected: Vec<(u64, u64)> = ticks.iter().map(|t| (start + t, start + t)).collect();
+            assert_eq!(fired, expected);
+        }
+    }
+
+    #[test]
+    fn beyond_the_top_level() {
+        let mut wheel = TimerWheel::new();
+        let far = MAX_DELTA + 100;
+        wheel.insert(far, far);
+        assert_eq!(run(&mut wheel, far + 10), vec![(far, far)]);
+    }
+
+    #[test]
+    fn due_already() {
+        let mut wheel = TimerWheel::new();
+        assert_eq!(wheel.poll(10), None);
+        let id = wheel.insert(5, 5);
+        assert_eq!(wheel.expires(id), Some(5));
+        assert_eq!(wheel.poll(10), Some(id));
+        assert_eq!(wheel.expires(id), None);
+        assert_eq!(wheel.poll(10), None);
+    }
+
+    #[test]
+    fn same_tick_in_order() {
+        let mut wheel = TimerWheel::new();
+        for n in 0..5 {
+            wheel.insert(100, n);
+        }
+        let fired: Vec<u64> = run(&mut wheel, 100).iter().map(|f| f.1).collect();
+        assert_eq!(fired, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn stop_and_remove() {
+        let mut wheel = TimerWheel::new();
+        let a = wheel.insert(10, 1);
+        let b = wheel.insert(5000, 2);
+        let c = wheel.insert(20, 3);
+
+        assert!(wheel.stop(a));
+        assert!(!wheel.stop(a));
+        assert_eq!(wheel.get(a), Some(&1));
+        assert_eq!(wheel.remove(b), Some(2));
+        assert_eq!(wheel.remove(b), None);
+        assert_eq!(run(&mut wheel, 10_000), vec![(20, 3)]);
+        assert!(wheel.get(c).is_none());
+
+        // a stale id doesn't reach the timer that reuses its slot
+        let d = wheel.insert(10_010, 4);
+        assert!(wheel.get(b).is_none());
+        assert!(!wheel.stop(b));
+        assert_eq!(wheel.get(d), Some(&4));
+        assert_eq!(wheel.iter().count(), 2);
+    }
+
+    #[test]
+    fn rearm_periodic() {
+        let mut wheel = TimerWheel::new();
+        let id = wheel.insert(100, 0);
+        let mut fired = Vec::new();
+        for now in 1..=1000 {
+            while let Some(id) = wheel.poll(now) {
+                fired.push(now);
+                *wheel.get_mut(id).unwrap() += 1;
+                wheel.rearm(id, now + 300);
+            }
+        }
+        assert_eq!(fired, vec![100, 400, 700, 1000]);
+        assert_eq!(wheel.get(id), Some(&4));
+
+        // moving an armed timer
+        assert!(wheel.rearm(id, 1050));
+        assert!(wheel.rearm(id, 1010));
+        let fired: Vec<u64> = run(&mut wheel, 1100).iter().map(|f| f.0).collect();
+        assert_eq!(fired, vec![1010]);
+    }
+}
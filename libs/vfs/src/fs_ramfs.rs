@@ -1,6 +1,6 @@
 use crate::fscore::{DEntry, FsError, NodeId, NodeType, NODE_ID_ROOT};
 
-use alloc::collections::btree_map::BTreeMap;
+use alloc::{borrow::ToOwned, collections::btree_map::BTreeMap, format, string::String, vec::Vec};
 use core::{
     cmp::{max, min},
     iter,
@@ -1,4 +1,5 @@
 use crate::posix;
+use alloc::string::String;
 
 pub type NodeId = usize;
 pub const NODE_ID_ROOT: NodeId = 0;
@@ -13,6 +14,14 @@ impl FsError {
     pub fn new(errno: posix::Errno, message: String) -> Self {
         Self { errno, message }
     }
+
+    pub fn errno(&self) -> posix::Errno {
+        self.errno
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -27,7 +36,7 @@ pub struct DEntry {
     pub ntype: NodeType,
 }
 
-pub trait FileSystem {
+pub trait FileSystem: Send {
     fn readdir(&self, dir: NodeId, pos: usize) -> Result<Option<(DEntry, NodeId)>, FsError>;
     fn create(&mut self, dir: NodeId, dent: &DEntry) -> Result<NodeId, FsError>;
     fn read(&self, file: NodeId, off: usize, data: &mut [u8]) -> Result<usize, FsError>;
@@ -78,6 +87,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fserror_accessors() {
+        let err = FsError::new(posix::Errno::ENOENT, String::from("not found"));
+        assert_eq!(err.errno(), posix::Errno::ENOENT);
+        assert_eq!(err.message(), "not found");
+    }
+
     #[test]
     fn dentry_debug() {
         let dent = DEntry {
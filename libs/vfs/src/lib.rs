@@ -1,10 +1,10 @@
+#![cfg_attr(not(test), no_std)]
 #![feature(const_btree_new)]
-#![feature(no_coverage)]
 
 extern crate alloc;
 extern crate posix;
 
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, string::String, vec::Vec};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, format, string::String, vec::Vec};
 use bitfield::bitfield;
 
 bitfield! {
@@ -21,10 +21,10 @@ mod fs_ramfs;
 mod fscore;
 
 use fs_ramfs::RamFs;
-use fscore::{DEntry, FileSystem, FsError, NodeId, NodeType, NODE_ID_ROOT};
+pub use fscore::{DEntry, FileSystem, FsError, NodeId, NodeType, NODE_ID_ROOT};
 
 type MountId = usize;
-type FileDescriptor = i32;
+pub type FileDescriptor = i32;
 
 struct OpenedFile {
     mount_id: MountId,
@@ -39,7 +39,8 @@ struct Mount {
     filesystem: Box<dyn FileSystem>,
 }
 
-struct Vfs {
+/// Mount table and open files. The kernel keeps one behind a lock.
+pub struct Vfs {
     mount: Vec<Mount>,
     next_mnt_id: MountId,
     opened_files: BTreeMap<FileDescriptor, OpenedFile>,
@@ -47,7 +48,7 @@ struct Vfs {
 }
 
 impl Vfs {
-    const fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             mount: Vec::new(),
             next_mnt_id: 1,
@@ -75,7 +76,12 @@ impl Vfs {
         path_vec
     }
 
-    fn mount(&mut self, mountpoint: &str, filesystem: Box<dyn FileSystem>) -> Result<(), FsError> {
+    /// Mount `filesystem` on `mountpoint`, which has to be an empty directory
+    pub fn mount(
+        &mut self,
+        mountpoint: &str,
+        filesystem: Box<dyn FileSystem>,
+    ) -> Result<(), FsError> {
         if self.mount.is_empty() {
             if mountpoint != "/" {
                 return Err(FsError::new(
@@ -117,7 +123,7 @@ impl Vfs {
         Ok(())
     }
 
-    fn init(&mut self) {
+    pub fn init(&mut self) {
         self.mount("/", Box::new(RamFs::new()))
             .expect("Failed to mount root");
     }
@@ -131,7 +137,8 @@ impl Vfs {
         for m in self.mount.iter_mut() {
             if path
                 .iter()
-                .take(m.mountpoint.len()).copied()
+                .take(m.mountpoint.len())
+                .copied()
                 .eq(m.mountpoint.iter().map(|s| s.as_str()))
             {
                 mount = Some(m);
@@ -162,7 +169,7 @@ impl Vfs {
         Ok((file, mount))
     }
 
-    fn open(&mut self, path: &str, mode: OpenMode) -> Result<FileDescriptor, FsError> {
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> Result<FileDescriptor, FsError> {
         let (mount, mpath) = self.find_mount_by_path_mut(path);
 
         let node_id = if mpath.is_empty() {
@@ -224,7 +231,7 @@ impl Vfs {
         Ok(fd)
     }
 
-    fn read(&mut self, fd: FileDescriptor, data: &mut [u8]) -> Result<usize, FsError> {
+    pub fn read(&mut self, fd: FileDescriptor, data: &mut [u8]) -> Result<usize, FsError> {
         let (file, mount) = self.get_file_mount_from_fd(fd)?;
 
         if !file.mode.is_set(OpenMode::READ) {
@@ -239,7 +246,7 @@ impl Vfs {
         Ok(size)
     }
 
-    fn write(&mut self, fd: FileDescriptor, data: &[u8]) -> Result<usize, FsError> {
+    pub fn write(&mut self, fd: FileDescriptor, data: &[u8]) -> Result<usize, FsError> {
         let (file, mount) = self.get_file_mount_from_fd(fd)?;
 
         if !file.mode.is_set(OpenMode::WRITE) {
@@ -254,7 +261,7 @@ impl Vfs {
         Ok(size)
     }
 
-    fn close(&mut self, fd: FileDescriptor) -> Result<(), FsError> {
+    pub fn close(&mut self, fd: FileDescriptor) -> Result<(), FsError> {
         self.opened_files.remove(&fd).ok_or(FsError::new(
             posix::Errno::EBADF,
             format!("Invalid file descriptor: {}", fd),
@@ -262,7 +269,7 @@ impl Vfs {
         Ok(())
     }
 
-    fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
         let (mount, mpath) = self.find_mount_by_path_mut(path);
 
         if mpath.is_empty() {
@@ -301,7 +308,7 @@ impl Vfs {
         }
     }
 
-    fn readdir(&mut self, fd: FileDescriptor) -> Result<Option<DEntry>, FsError> {
+    pub fn readdir(&mut self, fd: FileDescriptor) -> Result<Option<DEntry>, FsError> {
         let (file, mount) = self.get_file_mount_from_fd(fd)?;
 
         let res = match mount.filesystem.readdir(file.node_id, file.pos)? {
@@ -315,41 +322,10 @@ impl Vfs {
     }
 }
 
-static mut VFS: Vfs = Vfs::new();
-
-#[no_coverage]
-pub unsafe fn init() {
-    VFS.init();
-}
-
-#[no_coverage]
-pub unsafe fn open(path: &str, mode: OpenMode) -> Result<FileDescriptor, FsError> {
-    VFS.open(path, mode)
-}
-
-#[no_coverage]
-pub unsafe fn read(fd: FileDescriptor, data: &mut [u8]) -> Result<usize, FsError> {
-    VFS.read(fd, data)
-}
-
-#[no_coverage]
-pub unsafe fn write(fd: FileDescriptor, data: &[u8]) -> Result<usize, FsError> {
-    VFS.write(fd, data)
-}
-
-#[no_coverage]
-pub unsafe fn close(fd: FileDescriptor) -> Result<(), FsError> {
-    VFS.close(fd)
-}
-
-#[no_coverage]
-pub unsafe fn mkdir(path: &str) -> Result<(), FsError> {
-    VFS.mkdir(path)
-}
-
-#[no_coverage]
-pub unsafe fn readdir(fd: FileDescriptor) -> Result<Option<DEntry>, FsError> {
-    VFS.readdir(fd)
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -818,4 +794,40 @@ mod tests {
         vfs.open("/foo", OpenMode::TRUNC)
             .expect_err("open with OpenMode::TRUNC on a directory unexpectedly succeed");
     }
+
+    #[test]
+    fn mount() {
+        let mut vfs = Vfs::new();
+        vfs.init();
+
+        vfs.mkdir("/mnt").unwrap();
+        vfs.mount("/mnt", Box::new(RamFs::new())).unwrap();
+
+        let fd = vfs
+            .open("/mnt/foo.txt", OpenMode::WRITE | OpenMode::CREATE)
+            .unwrap();
+        vfs.write(fd, "foo\n".as_bytes()).unwrap();
+        vfs.close(fd).unwrap();
+
+        // the file lives in the mounted filesystem, not under the mountpoint
+        let fd = vfs.open("/", OpenMode::READ).unwrap();
+        let dent = vfs.readdir(fd).unwrap().unwrap();
+        assert_eq!(dent.name, "mnt");
+        assert!(vfs.readdir(fd).unwrap().is_none());
+        vfs.close(fd).unwrap();
+
+        let fd = vfs.open("/mnt", OpenMode::READ).unwrap();
+        let dent = vfs.readdir(fd).unwrap().unwrap();
+        assert_eq!(dent.name, "foo.txt");
+        assert_eq!(dent.ntype, NodeType::RegularFile);
+        vfs.close(fd).unwrap();
+
+        vfs.mkdir("/dir").unwrap();
+        let fd = vfs
+            .open("/dir/bar.txt", OpenMode::WRITE | OpenMode::CREATE)
+            .unwrap();
+        vfs.close(fd).unwrap();
+        vfs.mount("/dir", Box::new(RamFs::new()))
+            .expect_err("mount on a non-empty directory unexpectedly succeed");
+    }
 }
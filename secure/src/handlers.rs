@@ -0,0 +1,101 @@
+use core::{arch::asm, panic::PanicInfo, ptr};
+
+use crate::{decl_c_symbol_addr, main, println, scb, semihosting};
+
+decl_c_symbol_addr!(__bss_s, bss_s);
+decl_c_symbol_addr!(__bss_e, bss_e);
+decl_c_symbol_addr!(__data_s, data_s);
+decl_c_symbol_addr!(__data_e, data_e);
+decl_c_symbol_addr!(__data_load, data_load);
+
+// EXC_RETURN: the frame is on the process stack, of the secure state
+const EXC_RETURN_SPSEL: u32 = 1 << 2;
+const EXC_RETURN_S: u32 = 1 << 6;
+
+#[no_mangle]
+unsafe extern "C" fn __reset() {
+    // PRIMASK stays clear: the secure one would mask the kernel's
+    // interrupts too
+
+    let size = bss_e() - bss_s();
+    ptr::write_bytes(bss_s() as *mut u8, 0, size);
+
+    let size = data_e() - data_s();
+    ptr::copy_nonoverlapping(data_load() as *const u8, data_s() as *mut u8, size);
+
+    main()
+}
+
+#[derive(Clone, Copy)]
+union Vector {
+    reserved: u32,
+    handler: unsafe extern "C" fn(),
+}
+
+extern "C" {
+    fn __stack_e();
+}
+
+#[no_mangle]
+#[link_section = ".vector_table"]
+static __vector_table: [Vector; 16] = [
+    Vector { handler: __stack_e }, // initial sp
+    Vector { handler: __reset },
+    Vector { handler: __fault }, // NMI
+    Vector { handler: __fault }, // HardFault
+    Vector { handler: __fault }, // MemManage
+    Vector { handler: __fault }, // BusFault
+    Vector { handler: __fault }, // UsageFault
+    Vector { handler: __fault }, // SecureFault
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { handler: __fault }, // SVCall
+    Vector { handler: __fault }, // DebugMonitor
+    Vector { reserved: 0 },
+    Vector { handler: __fault }, // PendSV
+    Vector { handler: __fault }, // SysTick
+];
+
+/// Every exception that reaches the monitor is fatal
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn __fault() {
+    asm!("mov r0, lr", "bl __report_fault", options(noreturn))
+}
+
+#[no_mangle]
+unsafe extern "C" fn __report_fault(exc_return: u32) -> ! {
+    let ipsr: u32;
+    asm!("mrs {}, ipsr", out(reg) ipsr);
+
+    // the state and the stack the exception was taken from
+    let secure = exc_return & EXC_RETURN_S != 0;
+    let frame: usize;
+    match (secure, exc_return & EXC_RETURN_SPSEL != 0) {
+        (true, false) => asm!("mrs {}, msp", out(reg) frame),
+        (true, true) => asm!("mrs {}, psp", out(reg) frame),
+        (false, false) => asm!("mrs {}, msp_ns", out(reg) frame),
+        (false, true) => asm!("mrs {}, psp_ns", out(reg) frame),
+    }
+    let pc = ptr::read_volatile((frame + 24) as *const u32);
+
+    let st = scb::fault_status();
+    println!(
+        "secure: exception {} from the {} state at pc {:08x}",
+        ipsr & 0x1ff,
+        if secure { "secure" } else { "non-secure" },
+        pc
+    );
+    println!(
+        "secure: SFSR {:08x}  SFAR {:08x}  CFSR {:08x}  HFSR {:08x}",
+        st.sfsr, st.sfar, st.cfsr, st.hfsr
+    );
+    semihosting::exit_failure()
+}
+
+#[panic_handler]
+fn panic(panic_info: &PanicInfo) -> ! {
+    println!("secure: {}", panic_info);
+    semihosting::exit_failure()
+}
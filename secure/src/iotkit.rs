@@ -0,0 +1,115 @@
+/*
+
+Security controllers of the mps2-an505's IoT Kit subsystem.
+
+    IDAU    bit 28 of an address picks the secure alias: 0x0... and 0x2...
+            are the non-secure aliases of the code and SRAM, 0x1... and
+            0x3... their secure ones; 0x4... and 0x5... the same for the
+            peripherals. NSCCFG lets the SAU make parts of the secure code
+            alias non-secure callable, which the veneers are in.
+    MPCs    one in front of each SSRAM, a bit per block telling whether the
+            block is accessed through the secure or the non-secure alias;
+            all secure at reset
+    PPCs    a bit per peripheral, which is secure-only at reset
+
+The SSRAMs that the kernel's memory touches are given to the non-secure
+state as a whole, so none of them may hold the monitor too. The timers,
+the UARTs, SPI, I2C and the FPGA IO go to the kernel; the MPCs themselves,
+the GPIOs and the rest of the AHB expansion stay secure until the kernel
+needs them.
+
+[refs]
+- https://developer.arm.com/documentation/dai0505/latest (AN505, 3.5 Memory map)
+- https://developer.arm.com/documentation/ddi0574/latest (CoreLink SIE-200, TrustZone controllers)
+
+ */
+
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+use core::{arch::asm, ops::Range};
+
+use mmio::{Readable, RegisterArrayRW, RegisterR, RegisterRW, Writeable};
+
+use crate::println;
+
+bitfield! {
+    Nsccfg: u32 {
+        CODENSC[0];
+        RAMNSC[1];
+    }
+}
+
+/// Secure Privilege Control, the part of it for the IDAU and the PPCs
+pub struct SecCtrl {
+    nsccfg: RegisterRW<0x014, u32, Nsccfg>,
+    apbnsppc0: RegisterRW<0x070, u32, u32>,
+    apbnsppcexp: RegisterArrayRW<0x080, u32, u32, 4>,
+}
+
+const SPCTRL: *mut SecCtrl = 0x5008_0000 as *mut SecCtrl;
+
+// APB PPC0: timer 0, timer 1, dual timer
+const APB_PPC0_NS: u32 = 0b111;
+// APB PPC expansion 1: SPI 0-4, UART 0-4, I2C 0-3; 2: SCC, I2S, FPGA IO
+const APB_PPC_EXP1_NS: u32 = 0x3fff;
+const APB_PPC_EXP2_NS: u32 = 0b111;
+
+/// Memory Protection Controller
+pub struct Mpc {
+    blk_max: RegisterR<0x010, u32, u32>,
+    blk_idx: RegisterRW<0x018, u32, u32>,
+    blk_lut: RegisterRW<0x01C, u32, u32>,
+}
+
+/// MPCs and the non-secure alias of the SSRAM behind each
+const MPCS: [(usize, Range<usize>); 3] = [
+    (0x5800_7000, 0x0000_0000..0x0040_0000), // SSRAM1
+    (0x5800_8000, 0x2800_0000..0x2820_0000), // SSRAM2
+    (0x5800_9000, 0x2820_0000..0x2840_0000), // SSRAM3
+];
+
+/// Bit 28 of an address, set in the secure aliases
+const SECURE_ALIAS: usize = 1 << 28;
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Make every block behind `mpc` non-secure
+fn mpc_open(mpc: *mut Mpc) {
+    unsafe {
+        for idx in 0..=(*mpc).blk_max.read() {
+            (*mpc).blk_idx.write(idx);
+            (*mpc).blk_lut.write(!0);
+        }
+    }
+}
+
+/// Give the SSRAMs of the kernel's memory `ns` and the peripherals to the
+/// non-secure state; `monitor` is the monitor's memory, in secure aliases
+pub fn init(ns: &Range<usize>, monitor: &Range<usize>) {
+    let monitor_ns = (monitor.start & !SECURE_ALIAS)..(monitor.end & !SECURE_ALIAS);
+
+    for (base, ram) in MPCS.iter() {
+        if !overlaps(ns, ram) {
+            continue;
+        }
+        assert!(
+            !overlaps(&monitor_ns, ram),
+            "the kernel's memory and the monitor share the SSRAM at {:08x}",
+            ram.start
+        );
+        println!("secure: SSRAM {:08x}-{:08x} non-secure", ram.start, ram.end);
+        mpc_open(*base as *mut Mpc);
+    }
+
+    unsafe {
+        (*SPCTRL).apbnsppc0.write(APB_PPC0_NS);
+        (*SPCTRL).apbnsppcexp.write_at(1, APB_PPC_EXP1_NS);
+        (*SPCTRL).apbnsppcexp.write_at(2, APB_PPC_EXP2_NS);
+        (*SPCTRL).nsccfg.write(Nsccfg::CODENSC);
+        asm!("dsb", "isb");
+    }
+}
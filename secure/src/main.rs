@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(naked_functions)]
+#![feature(cmse_nonsecure_entry)]
+#![feature(abi_c_cmse_nonsecure_call)]
+
+/*
+
+Secure monitor for ARMv8-M boards with the Security Extension.
+
+The core resets in the secure state into this image, which
+
+  - gives the kernel's memory, the peripherals and all interrupts to the
+    non-secure state (SAU, the board's security controllers, NVIC ITNS),
+  - routes BusFault, HardFault and NMI to the kernel, keeping SecureFault,
+  - and starts the kernel in the non-secure state from its vector table.
+
+Afterwards it only runs when the kernel calls one of its entry points
+through the veneers (see secure_abi and storage.rs), or on a SecureFault.
+
+`cargo xtask` builds it for boards with a [secure] table in board.toml,
+links it with a script generated from that table and loads it next to the
+kernel. Besides the monitor's own sections and __secure_s/__secure_e
+around them, the script provides the kernel's memory as __ns_s/__ns_e and
+its vector table as __ns_vector_table.
+
+[refs]
+- https://developer.arm.com/documentation/100720/0200 (ARMv8-M Security Extensions: Requirements on Development Tools)
+- https://developer.arm.com/documentation/ddi0553/latest (ARMv8-M ARM, B3.14 Secure address protection)
+
+ */
+
+mod handlers;
+mod iotkit;
+mod sau;
+mod scb;
+mod semihosting;
+mod storage;
+
+use core::{arch::asm, mem, ops::Range, ptr};
+
+#[macro_export]
+macro_rules! decl_c_symbol_addr {
+    ($sym_name: ident, $wrapper_name: ident) => {
+        extern "C" {
+            static $sym_name: u8;
+        }
+        #[inline]
+        fn $wrapper_name() -> usize {
+            unsafe { &$sym_name as *const _ as usize }
+        }
+    };
+}
+
+decl_c_symbol_addr!(__ns_s, ns_s);
+decl_c_symbol_addr!(__ns_e, ns_e);
+decl_c_symbol_addr!(__ns_vector_table, ns_vector_table);
+decl_c_symbol_addr!(__secure_s, secure_s);
+decl_c_symbol_addr!(__secure_e, secure_e);
+decl_c_symbol_addr!(__veneer_s, veneer_s);
+decl_c_symbol_addr!(__veneer_e, veneer_e);
+
+/// Non-secure alias of the System Control Block's VTOR
+const VTOR_NS: *mut u32 = 0xE002_ED08 as *mut u32;
+
+/// Interrupt Controller Type: INTLINESNUM[3:0], groups of 32 IRQs less one
+const ICTR: *const u32 = 0xE000_E004 as *const u32;
+/// Interrupt Target Non-secure, a bit per IRQ
+const NVIC_ITNS: *mut u32 = 0xE000_E380 as *mut u32;
+
+/// Non-secure alias of the peripherals
+const PERIPHERALS_NS: Range<usize> = 0x4000_0000..0x5000_0000;
+
+pub fn main() -> ! {
+    let ns = ns_s()..ns_e();
+    println!(
+        "secure: kernel memory {:08x}-{:08x}, veneers {:08x}-{:08x}",
+        ns.start,
+        ns.end,
+        veneer_s(),
+        veneer_e()
+    );
+
+    iotkit::init(&ns, &(secure_s()..secure_e()));
+
+    sau::set_region(0, &ns, sau::Attr::NonSecure);
+    sau::set_region(1, &(veneer_s()..veneer_e()), sau::Attr::NonSecureCallable);
+    sau::set_region(2, &PERIPHERALS_NS, sau::Attr::NonSecure);
+    sau::enable();
+
+    scb::route_faults_to_nonsecure();
+    route_irqs_to_nonsecure();
+
+    println!("secure: starting the kernel at {:08x}", ns_vector_table());
+    unsafe { boot_nonsecure(ns_vector_table()) }
+}
+
+fn route_irqs_to_nonsecure() {
+    unsafe {
+        let groups = (ptr::read_volatile(ICTR) & 0xf) as usize + 1;
+        for n in 0..groups {
+            ptr::write_volatile(NVIC_ITNS.add(n), !0);
+        }
+    }
+}
+
+/// Start the reset handler of the vector table at `vector_table` in the
+/// non-secure state, on the stack that the table asks for
+unsafe fn boot_nonsecure(vector_table: usize) -> ! {
+    let sp = ptr::read_volatile(vector_table as *const u32);
+    let reset = ptr::read_volatile((vector_table + 4) as *const u32);
+
+    ptr::write_volatile(VTOR_NS, vector_table as u32);
+    asm!("msr msp_ns, {}", in(reg) sp);
+    asm!("dsb", "isb");
+
+    // BLXNS only changes state with bit 0 of the address clear
+    let reset: extern "C-cmse-nonsecure-call" fn() = mem::transmute(reset as usize & !1);
+    reset();
+
+    panic!("the kernel returned from its reset handler");
+}
@@ -0,0 +1,91 @@
+/*
+
+Security Attribution Unit.
+
+Addresses in no enabled region are Secure. The attribution that applies is
+the more secure of the SAU's and the IDAU's (see iotkit.rs), so a
+non-secure region has to be non-secure for the IDAU too, and a non-secure
+callable one non-secure callable at least.
+
+[refs]
+- https://developer.arm.com/documentation/100235/0100/The-Cortex-M33-Peripherals/Security-Attribution-and-Memory-Protection/Security-Attribution-Unit
+
+ */
+
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+use core::{arch::asm, ops::Range};
+
+use mmio::{Readable, RegisterR, RegisterRW, Writeable};
+
+bitfield! {
+    Ctrl: u32 {
+        ENABLE[0];
+        ALLNS[1];
+    }
+}
+
+bitfield! {
+    Type: u32 {
+        SREGION[7:0];
+    }
+}
+
+// LADDR[31:5] is the 32-byte aligned address of the last block; RBAR is
+// the base address itself
+bitfield! {
+    Rlar: u32 {
+        ENABLE[0];
+        NSC[1];
+    }
+}
+
+pub struct Sau {
+    ctrl: RegisterRW<0x00, u32, Ctrl>,
+    typ: RegisterR<0x04, u32, Type>,
+    rnr: RegisterRW<0x08, u32, u32>,
+    rbar: RegisterRW<0x0C, u32, u32>,
+    rlar: RegisterRW<0x10, u32, Rlar>,
+}
+
+const SAU: *mut Sau = 0xE000_EDD0 as *mut Sau;
+
+const GRANULE: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Attr {
+    NonSecure,
+    NonSecureCallable,
+}
+
+/// Attribute `range`, which has to be 32-byte aligned, with region `n`
+pub fn set_region(n: usize, range: &Range<usize>, attr: Attr) {
+    let regions = unsafe { (*SAU).typ.read().extract(Type::SREGION) } as usize;
+    assert!(n < regions, "SAU region {} of {}", n, regions);
+    assert!(
+        range.start < range.end && range.start % GRANULE == 0 && range.end % GRANULE == 0,
+        "SAU region {:08x}-{:08x} is not 32-byte aligned",
+        range.start,
+        range.end
+    );
+
+    let mut rlar = Rlar::from((range.end - GRANULE) as u32) | Rlar::ENABLE;
+    if attr == Attr::NonSecureCallable {
+        rlar = rlar | Rlar::NSC;
+    }
+    unsafe {
+        (*SAU).rnr.write(n as u32);
+        (*SAU).rbar.write(range.start as u32);
+        (*SAU).rlar.write(rlar);
+    }
+}
+
+/// Turn the attribution of the regions on; everything else stays Secure
+pub fn enable() {
+    unsafe {
+        (*SAU).ctrl.write(Ctrl::ENABLE);
+        asm!("dsb", "isb");
+    }
+}
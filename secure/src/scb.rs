@@ -0,0 +1,73 @@
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+
+use mmio::{Readable, RegisterRW, Writeable};
+
+bitfield! {
+    Aircr: u32 {
+        PRIGROUP[10:8];
+        BFHFNMINS[13];
+        VECTKEY[30:16];
+    }
+}
+
+bitfield! {
+    Shcsr: u32 {
+        MEMFAULTENA[16];
+        USGFAULTENA[18];
+        SECUREFAULTENA[19];
+    }
+}
+
+/// System Control Block, the secure one
+pub struct Scb {
+    aircr: RegisterRW<0x0C, u32, Aircr>,
+    shcsr: RegisterRW<0x24, u32, Shcsr>,
+    cfsr: RegisterRW<0x28, u32, u32>,
+    hfsr: RegisterRW<0x2C, u32, u32>,
+    sfsr: RegisterRW<0xE4, u32, u32>,
+    sfar: RegisterRW<0xE8, u32, u32>,
+}
+
+const SCB: *mut Scb = 0xE000_ED00 as *mut Scb;
+
+// AIRCR writes are ignored without it; VECTKEY[15] is left out, as bit 31
+// does not fit a bitfield
+const AIRCR_KEY: u32 = 0x05fa;
+
+/// Fault status and address registers
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub sfsr: u32,
+    pub sfar: u32,
+}
+
+/// Take BusFault, HardFault and NMI in the kernel, which reports them,
+/// and keep SecureFault and the monitor's own faults here
+pub fn route_faults_to_nonsecure() {
+    unsafe {
+        let v = (*SCB).aircr.read();
+        let prigroup = Aircr::PRIGROUP.compose(v.extract(Aircr::PRIGROUP));
+        (*SCB)
+            .aircr
+            .write(Aircr::VECTKEY.compose(AIRCR_KEY) | prigroup | Aircr::BFHFNMINS);
+        let v = (*SCB).shcsr.read();
+        (*SCB)
+            .shcsr
+            .write(v | Shcsr::MEMFAULTENA | Shcsr::USGFAULTENA | Shcsr::SECUREFAULTENA);
+    }
+}
+
+pub fn fault_status() -> FaultStatus {
+    unsafe {
+        FaultStatus {
+            cfsr: (*SCB).cfsr.read(),
+            hfsr: (*SCB).hfsr.read(),
+            sfsr: (*SCB).sfsr.read(),
+            sfar: (*SCB).sfar.read(),
+        }
+    }
+}
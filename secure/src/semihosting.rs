@@ -0,0 +1,58 @@
+/*
+
+The monitor's only output: ARM semihosting, which works before and
+regardless of the kernel's UART, in either security state.
+
+[refs]
+- https://github.com/ARM-software/abi-aa/blob/main/semihosting/semihosting.rst
+
+ */
+
+use core::{arch::asm, fmt};
+
+const SYS_WRITE0: usize = 0x04;
+const SYS_EXIT: usize = 0x18;
+
+const ADP_STOPPED_RUNTIME_ERROR_UNKNOWN: usize = 0x20023;
+
+unsafe fn call(op: usize, param: usize) -> isize {
+    let ret: usize;
+    asm!(
+        "bkpt 0xab",
+        inout("r0") op => ret,
+        in("r1") param,
+        options(nostack)
+    );
+    ret as isize
+}
+
+/// Semihosting console, written in NUL-terminated chunks
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut buf = [0u8; 64];
+        for chunk in s.as_bytes().chunks(buf.len() - 1) {
+            buf[..chunk.len()].copy_from_slice(chunk);
+            buf[chunk.len()] = 0;
+            unsafe { call(SYS_WRITE0, buf.as_ptr() as usize) };
+        }
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        let _ = writeln!($crate::semihosting::Console, $($arg)*);
+    }};
+}
+
+/// Stop the emulator with a failure status
+pub fn exit_failure() -> ! {
+    unsafe { call(SYS_EXIT, ADP_STOPPED_RUNTIME_ERROR_UNKNOWN) };
+    loop {
+        unsafe { asm!("wfi") }
+    }
+}
@@ -0,0 +1,183 @@
+/*
+
+Secure storage prototype, and the veneers that the kernel calls it through.
+
+STORAGE_SLOTS slots of up to STORAGE_SLOT_SIZE bytes live in secure RAM,
+out of the kernel's reach but through the calls of secure_abi. Nothing
+survives a reset yet.
+
+The entry functions are `cmse_nonsecure_entry`: they return with BXNS and
+clear the registers that could leak secure data. The veneer table branches
+to their `__acle_se_` symbols, which the compiler emits for entry functions
+only, so a missing attribute fails the link.
+
+The kernel may call an entry from an interrupt handler while another one
+runs, so the slots are only touched with interrupts of both states masked.
+
+Pointers come from the kernel, which could name secure memory it can't
+reach itself; TTA checks every 32-byte block (the SAU and MPU granularity)
+against the non-secure state's view before the monitor touches it.
+
+[refs]
+- https://developer.arm.com/documentation/100720/0200 (3.4 TT instruction, 5.4 Entry functions)
+
+ */
+
+extern crate posix;
+extern crate secure_abi;
+
+use core::{arch::asm, arch::global_asm, cell::UnsafeCell, slice};
+use posix::Errno;
+use secure_abi::{STORAGE_SLOTS, STORAGE_SLOT_SIZE, VERSION};
+
+// In the order of secure_abi's CALL_* numbers, VENEER_SIZE bytes each
+global_asm!(
+    ".section .veneers, \"ax\"",
+    ".syntax unified",
+    ".thumb",
+    "sg",
+    "b.w __acle_se_secure_version",
+    "sg",
+    "b.w __acle_se_secure_storage_read",
+    "sg",
+    "b.w __acle_se_secure_storage_write",
+);
+
+// TT result: non-secure read and read-write access, with the S bit folded in
+const TT_NSR: u32 = 1 << 20;
+const TT_NSRW: u32 = 1 << 21;
+
+const GRANULE: usize = 32;
+
+struct Storage {
+    slots: [[u8; STORAGE_SLOT_SIZE]; STORAGE_SLOTS],
+    lengths: [usize; STORAGE_SLOTS],
+}
+
+/// Only touched through `with_storage()`
+struct StorageCell(UnsafeCell<Storage>);
+
+unsafe impl Sync for StorageCell {}
+
+static STORAGE: StorageCell = StorageCell(UnsafeCell::new(Storage {
+    slots: [[0; STORAGE_SLOT_SIZE]; STORAGE_SLOTS],
+    lengths: [0; STORAGE_SLOTS],
+}));
+
+/// Call `func` on the storage with PRIMASK_S set, which masks the
+/// non-secure interrupts as well
+fn with_storage<F, R>(func: F) -> R
+where
+    F: FnOnce(&mut Storage) -> R,
+{
+    let primask: u32;
+    unsafe { asm!("mrs {}, primask", "cpsid i", out(reg) primask) };
+    let res = func(unsafe { &mut *STORAGE.0.get() });
+    if primask & 1 == 0 {
+        unsafe { asm!("cpsie i") };
+    }
+    res
+}
+
+fn tta(addr: usize) -> u32 {
+    let tt: u32;
+    unsafe { asm!("tta {}, {}", out(reg) tt, in(reg) addr, options(nomem, nostack)) };
+    tt
+}
+
+/// Whether the non-secure state (privileged) may access `len` bytes at
+/// `addr` itself
+fn ns_accessible(addr: usize, len: usize, write: bool) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    let flag = if write { TT_NSRW } else { TT_NSR };
+    let mut a = addr;
+    while a < end {
+        if tta(a) & flag == 0 {
+            return false;
+        }
+        a = match (a & !(GRANULE - 1)).checked_add(GRANULE) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    true
+}
+
+fn ns_slice<'a>(addr: *const u8, len: usize) -> Result<&'a [u8], Errno> {
+    match len {
+        0 => Ok(&[]),
+        _ if ns_accessible(addr as usize, len, false) => {
+            Ok(unsafe { slice::from_raw_parts(addr, len) })
+        }
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+fn ns_slice_mut<'a>(addr: *mut u8, len: usize) -> Result<&'a mut [u8], Errno> {
+    match len {
+        0 => Ok(&mut []),
+        _ if ns_accessible(addr as usize, len, true) => {
+            Ok(unsafe { slice::from_raw_parts_mut(addr, len) })
+        }
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+fn slot_index(slot: u32) -> Result<usize, Errno> {
+    match slot as usize {
+        n if n < STORAGE_SLOTS => Ok(n),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn storage_read(slot: u32, buf: *mut u8, len: usize) -> Result<usize, Errno> {
+    let n = slot_index(slot)?;
+    with_storage(|storage| {
+        let stored = storage.lengths[n];
+        let count = len.min(stored);
+        let buf = ns_slice_mut(buf, count)?;
+        buf.copy_from_slice(&storage.slots[n][..count]);
+        Ok(stored)
+    })
+}
+
+fn storage_write(slot: u32, buf: *const u8, len: usize) -> Result<usize, Errno> {
+    let n = slot_index(slot)?;
+    if len > STORAGE_SLOT_SIZE {
+        return Err(Errno::EFBIG);
+    }
+    let data = ns_slice(buf, len)?;
+    with_storage(|storage| {
+        storage.slots[n][..len].copy_from_slice(data);
+        storage.lengths[n] = len;
+    });
+    Ok(0)
+}
+
+fn result(res: Result<usize, Errno>) -> i32 {
+    match res {
+        Ok(n) => n as i32,
+        Err(e) => -(e as i32),
+    }
+}
+
+#[no_mangle]
+#[cmse_nonsecure_entry]
+extern "C" fn secure_version() -> u32 {
+    VERSION
+}
+
+#[no_mangle]
+#[cmse_nonsecure_entry]
+extern "C" fn secure_storage_read(slot: u32, buf: *mut u8, len: usize) -> i32 {
+    result(storage_read(slot, buf, len))
+}
+
+#[no_mangle]
+#[cmse_nonsecure_entry]
+extern "C" fn secure_storage_write(slot: u32, buf: *const u8, len: usize) -> i32 {
+    result(storage_write(slot, buf, len))
+}
@@ -0,0 +1,160 @@
+/*
+
+CMSDK APB dual timer (an SP804).
+
+Two down counters, each with its own block of registers, 0x20 apart, and
+a shared interrupt. A counter wraps around from zero in free-running mode,
+reloads from LOAD in periodic mode and stops at zero in one-shot mode. The
+prescaler divides the APB clock by 1, 16 or 256. Writing BGLOAD changes
+the period from the next reload on, without restarting the count.
+
+[refs]
+- https://developer.arm.com/documentation/ddi0479/latest (Cortex-M System Design Kit, 4.5 APB dual-input timer)
+- https://developer.arm.com/documentation/ddi0271/latest (SP804 Dual-Timer Module)
+
+ */
+
+extern crate bitfield;
+extern crate mmio;
+extern crate posix;
+
+use bitfield::bitfield;
+
+use mmio::{Readable, RegisterR, RegisterRW, RegisterW, Writeable};
+
+bitfield! {
+    Control: u32 {
+        ONESHOT[0];
+        SIZE32[1];
+        PRESCALE[3:2];
+        INT_EN[5];
+        PERIODIC[6];
+        EN[7];
+    }
+}
+
+bitfield! {
+    Intr: u32 {
+        IRQ[0];
+    }
+}
+
+/// One of the two counters
+#[allow(dead_code)]
+pub struct DualTimerCounter {
+    load: RegisterRW<0x000, u32, u32>,
+    value: RegisterR<0x004, u32, u32>,
+    control: RegisterRW<0x008, u32, Control>,
+    intclr: RegisterW<0x00C, u32, Intr>,
+    ris: RegisterR<0x010, u32, Intr>,
+    mis: RegisterR<0x014, u32, Intr>,
+    bgload: RegisterRW<0x018, u32, u32>,
+}
+
+#[allow(dead_code)]
+pub struct ArmDualTimer {}
+
+const COUNTERS: usize = 2;
+const COUNTER_STRIDE: usize = 0x20;
+
+use posix::Errno;
+
+use crate::irq;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Wrap around from zero to 0xffff_ffff
+    FreeRunning,
+    /// Reload from LOAD at zero
+    Periodic,
+    /// Stop at zero
+    OneShot,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Prescale {
+    Div1 = 0,
+    Div16 = 1,
+    Div256 = 2,
+}
+
+#[allow(dead_code)]
+impl DualTimerCounter {
+    /// Count down from `load` in `mode`, interrupting at zero if `irq`
+    pub fn start(&mut self, mode: Mode, load: u32, prescale: Prescale, irq: bool) {
+        self.control.write(Control::from(0));
+        self.intclr.write(Intr::IRQ);
+        self.load.write(load);
+
+        let mut control =
+            Control::EN | Control::SIZE32 | Control::PRESCALE.compose(prescale as u32);
+        control = match mode {
+            Mode::FreeRunning => control,
+            Mode::Periodic => control | Control::PERIODIC,
+            Mode::OneShot => control | Control::ONESHOT,
+        };
+        if irq {
+            control = control | Control::INT_EN;
+        }
+        self.control.write(control);
+    }
+
+    pub fn stop(&mut self) {
+        self.control.write(Control::from(0));
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value.read()
+    }
+
+    /// Count down from `load` after the next reload of periodic mode
+    pub fn set_background_load(&mut self, load: u32) {
+        self.bgload.write(load)
+    }
+
+    /// Interrupt raised, whether enabled or not
+    pub fn raw_interrupt(&self) -> bool {
+        self.ris.read().is_set(Intr::IRQ)
+    }
+
+    /// Interrupt raised and enabled
+    pub fn interrupt_pending(&self) -> bool {
+        self.mis.read().is_set(Intr::IRQ)
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        self.intclr.write(Intr::IRQ)
+    }
+}
+
+#[allow(dead_code)]
+impl ArmDualTimer {
+    /// Counter 0 or 1
+    pub fn counter(&mut self, n: usize) -> &mut DualTimerCounter {
+        assert!(n < COUNTERS, "invalid dual timer counter: {}", n);
+        let base = self as *mut ArmDualTimer as usize + n * COUNTER_STRIDE;
+        unsafe { &mut *(base as *mut DualTimerCounter) }
+    }
+
+    /// Call `func` with the number of each counter that interrupts, after
+    /// clearing its interrupt
+    pub fn start_irq<F>(&'static mut self, irq: usize, mut func: F) -> Result<(), Errno>
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        let timer = self as *mut ArmDualTimer as usize;
+
+        irq::register(irq, move || {
+            let timer = unsafe { &mut *(timer as *mut ArmDualTimer) };
+            for n in 0..COUNTERS {
+                let counter = timer.counter(n);
+                if counter.interrupt_pending() {
+                    counter.clear_interrupt();
+                    func(n)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,99 @@
+/*
+
+CMSDK APB timer.
+
+A 32-bit down counter on the APB clock, which reloads from RELOAD when it
+reaches zero and raises its interrupt there if INTEN is set. There is no
+one-shot mode; stop the timer in the interrupt for that.
+
+[refs]
+- https://developer.arm.com/documentation/ddi0479/latest (Cortex-M System Design Kit, 4.4 APB timer)
+
+ */
+
+extern crate bitfield;
+extern crate mmio;
+extern crate posix;
+
+use bitfield::bitfield;
+
+use mmio::{Readable, RegisterRW, Writeable};
+
+bitfield! {
+    Ctrl: u32 {
+        EN[0];
+        EXTIN_EN[1];
+        EXTIN_CLK[2];
+        INT_EN[3];
+    }
+}
+
+bitfield! {
+    Intr: u32 {
+        IRQ[0];
+    }
+}
+
+pub struct ArmTimer {
+    ctrl: RegisterRW<0x000, u32, Ctrl>,
+    value: RegisterRW<0x004, u32, u32>,
+    reload: RegisterRW<0x008, u32, u32>,
+    intr: RegisterRW<0x00C, u32, Intr>, // INTSTATUS on read, INTCLEAR on write
+}
+
+use posix::Errno;
+
+use crate::irq;
+
+impl ArmTimer {
+    /// Count down from `reload` to zero over and over, i.e. with a period of
+    /// `reload + 1` APB clock cycles, interrupting at every zero if `irq`
+    pub fn start(&mut self, reload: u32, irq: bool) {
+        self.ctrl.write(Ctrl::from(0));
+        self.reload.write(reload);
+        self.value.write(reload);
+        self.intr.write(Intr::IRQ);
+        self.ctrl.write(if irq {
+            Ctrl::EN | Ctrl::INT_EN
+        } else {
+            Ctrl::EN
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn stop(&mut self) {
+        self.ctrl.write(Ctrl::from(0));
+    }
+
+    #[allow(dead_code)]
+    pub fn value(&self) -> u32 {
+        self.value.read()
+    }
+
+    #[allow(dead_code)]
+    pub fn reload(&self) -> u32 {
+        self.reload.read()
+    }
+
+    #[allow(dead_code)]
+    pub fn interrupt_pending(&self) -> bool {
+        self.intr.read().is_set(Intr::IRQ)
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        self.intr.write(Intr::IRQ)
+    }
+
+    /// Call `func` in the timer's interrupt, after clearing it
+    pub fn start_irq<F>(&'static mut self, irq: usize, mut func: F) -> Result<(), Errno>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let timer = self as *mut ArmTimer as usize;
+
+        irq::register(irq, move || {
+            unsafe { (*(timer as *mut ArmTimer)).clear_interrupt() };
+            func()
+        })
+    }
+}
@@ -1,14 +1,46 @@
+/*
+
+CMSDK APB UART, with a driver instance for each UART of the board.
+
+Received bytes are moved by the RX interrupt into the instance's lock-free
+ring buffer and handed out by `try_getc()`, which is meant to be called by
+a single reader. Transmission is polled until `start_irq()` enables the TX
+interrupt; from then on `putc()` queues bytes in thread mode and the TX
+interrupt feeds them to the UART. Exception handlers and code running with
+IRQs masked fall back to polling, after draining what is still queued so
+that output stays in order. The RX interrupt wakes the waker set by
+`set_rx_waker()` and the TX interrupt the one set by `set_tx_waker()`, for
+async I/O through the console (see console.rs); a reader and a writer can
+wait at the same time.
+
+The UART has no framing or parity errors, only overruns: a byte received
+while the previous one is still unread, or written while the TX buffer is
+full. They are counted with the other statistics, from the shared overrun
+interrupt or when polling runs into them. On mps2-an385/386/500 only
+UART0-2 are wired to that interrupt.
+
+The baud rate is the APB clock divided by BAUDDIV, which is at least 16.
+
+[refs]
+- https://developer.arm.com/documentation/ddi0479/latest (Cortex-M System Design Kit, 4.3 APB UART)
+
+ */
+
 extern crate bitfield;
 extern crate mmio;
+extern crate posix;
+extern crate ringbuf;
 
 use bitfield::bitfield;
 
-use mmio::{Readable, RegisterR, RegisterRW, Writeable};
+use mmio::{Readable, RegisterRW, Writeable};
 
 bitfield! {
     State: u32 {
         TX_BF[0];
         RX_BF[1];
+        TX_OVR[2];
+        RX_OVR[3];
     }
 }
 
@@ -18,28 +50,398 @@ bitfield! {
         RX_EN[1];
         TX_INTR_EN[2];
         RX_INTR_EN[3];
+        TX_OVR_INTR_EN[4];
+        RX_OVR_INTR_EN[5];
     }
 }
 
-pub struct ArmUart {
+bitfield! {
+    Intr: u32 {
+        TX[0];
+        RX[1];
+        TX_OVR[2];
+        RX_OVR[3];
+    }
+}
+
+struct Regs {
     data: RegisterRW<0x000, u8, u8>,
-    state: RegisterR<0x004, u32, State>,
+    state: RegisterRW<0x004, u32, State>, // overrun bits are cleared by writing 1
     ctrl: RegisterRW<0x008, u32, Ctrl>,
+    intr: RegisterRW<0x00C, u32, Intr>, // INTSTATUS on read, INTCLEAR on write
+    bauddiv: RegisterRW<0x010, u32, u32>,
 }
 
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::Waker;
+use core::time::Duration;
+use posix::Errno;
+use ringbuf::RingBuffer;
+
 use crate::console::Console;
+use crate::sync::IrqSafeLock;
+use crate::time::Deadline;
+use crate::{board, cpu, irq};
+
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+const MIN_BAUDDIV: u32 = 16;
+
+// Give up a byte rather than hang forever on a stuck transmitter
+const TX_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Counters of a UART since boot
+#[derive(Clone, Copy, Debug)]
+pub struct UartStats {
+    pub rx: u32,
+    pub tx: u32,
+    /// Received bytes that found the ring buffer full
+    pub rx_dropped: u32,
+    /// Bytes given up on a stuck transmitter
+    pub tx_dropped: u32,
+    pub rx_overruns: u32,
+    pub tx_overruns: u32,
+}
+
+struct Counters {
+    rx: AtomicU32,
+    tx: AtomicU32,
+    rx_dropped: AtomicU32,
+    tx_dropped: AtomicU32,
+    rx_overruns: AtomicU32,
+    tx_overruns: AtomicU32,
+}
+
+fn inc(counter: &AtomicU32) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn wake(waker: &IrqSafeLock<Option<Waker>>) {
+    let waker = waker.lock().take();
+    if let Some(waker) = waker {
+        waker.wake()
+    }
+}
+
+pub struct ArmUart {
+    regs: *mut Regs,
+    uart: board::Uart,
+    baud_rate: AtomicU32,
+    rx_buf: RingBuffer<256>,
+    tx_buf: RingBuffer<1024>,
+    rx_irq: AtomicBool,
+    tx_irq: AtomicBool,
+    counters: Counters,
+    rx_waker: IrqSafeLock<Option<Waker>>,
+    tx_waker: IrqSafeLock<Option<Waker>>,
+}
+
+// Only reaches its own registers; the rest of its state is atomics, the
+// single-reader ring buffers and a lock
+unsafe impl Send for ArmUart {}
+unsafe impl Sync for ArmUart {}
+
+static UARTS: [ArmUart; 5] = [
+    ArmUart::new(board::UARTS[0]),
+    ArmUart::new(board::UARTS[1]),
+    ArmUart::new(board::UARTS[2]),
+    ArmUart::new(board::UARTS[3]),
+    ArmUart::new(board::UARTS[4]),
+];
+
+/// The driver of UART `n`
+pub fn uart(n: usize) -> Option<&'static ArmUart> {
+    UARTS.get(n)
+}
+
+pub fn count() -> usize {
+    UARTS.len()
+}
+
+/// Count the overruns of every UART in the shared overrun interrupt
+pub fn start_overrun_irq() -> Result<(), Errno> {
+    irq::register(board::UART_OVERRUN_IRQ, || {
+        for uart in UARTS.iter() {
+            uart.check_overruns();
+        }
+    })
+}
+
+impl ArmUart {
+    const fn new(uart: board::Uart) -> Self {
+        Self {
+            regs: uart.base as *mut Regs,
+            uart,
+            baud_rate: AtomicU32::new(0),
+            rx_buf: RingBuffer::new(),
+            tx_buf: RingBuffer::new(),
+            rx_irq: AtomicBool::new(false),
+            tx_irq: AtomicBool::new(false),
+            counters: Counters {
+                rx: AtomicU32::new(0),
+                tx: AtomicU32::new(0),
+                rx_dropped: AtomicU32::new(0),
+                tx_dropped: AtomicU32::new(0),
+                rx_overruns: AtomicU32::new(0),
+                tx_overruns: AtomicU32::new(0),
+            },
+            rx_waker: IrqSafeLock::new(None),
+            tx_waker: IrqSafeLock::new(None),
+        }
+    }
+
+    fn regs(&self) -> &'static mut Regs {
+        unsafe { &mut *self.regs }
+    }
+
+    /// Set BAUDDIV for `baud_rate`, as close as the APB clock allows
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<(), Errno> {
+        let div = match baud_rate {
+            0 => 0,
+            _ => board::SYSCLK_HZ / baud_rate,
+        };
+        if div < MIN_BAUDDIV {
+            return Err(Errno::EINVAL);
+        }
+        self.regs().bauddiv.write(div);
+        self.baud_rate
+            .store(board::SYSCLK_HZ / div, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 0 until `set_baud_rate()` or `init()`
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> UartStats {
+        let c = &self.counters;
+        UartStats {
+            rx: c.rx.load(Ordering::Relaxed),
+            tx: c.tx.load(Ordering::Relaxed),
+            rx_dropped: c.rx_dropped.load(Ordering::Relaxed),
+            tx_dropped: c.tx_dropped.load(Ordering::Relaxed),
+            rx_overruns: c.rx_overruns.load(Ordering::Relaxed),
+            tx_overruns: c.tx_overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    fn tx_full(&self) -> bool {
+        self.regs().state.read().is_set(State::TX_BF)
+    }
+
+    /// Wait for room in the transmit buffer; false if `deadline` expired first
+    pub fn wait_tx_ready(&self, deadline: Deadline) -> bool {
+        while self.tx_full() {
+            if deadline.expired() {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn putc_polled(&self, byte: u8) {
+        if self.tx_full() && !self.wait_tx_ready(Deadline::after(TX_TIMEOUT)) {
+            inc(&self.counters.tx_dropped);
+            return;
+        }
+        self.regs().data.write(byte);
+        inc(&self.counters.tx);
+    }
+
+    pub fn getc_polled(&self) -> Option<u8> {
+        let state = self.regs().state.read();
+        if state.is_set(State::RX_OVR) {
+            self.check_overruns();
+        }
+        if state.is_set(State::RX_BF) {
+            inc(&self.counters.rx);
+            Some(self.regs().data.read())
+        } else {
+            None
+        }
+    }
+
+    /// Count and clear the overrun flags
+    fn check_overruns(&self) {
+        let state = self.regs().state.read();
+        let overruns = u32::from(state) & u32::from(State::RX_OVR | State::TX_OVR);
+        if overruns == 0 {
+            return;
+        }
+        if state.is_set(State::RX_OVR) {
+            inc(&self.counters.rx_overruns);
+        }
+        if state.is_set(State::TX_OVR) {
+            inc(&self.counters.tx_overruns);
+        }
+        // the same bit positions in both registers
+        self.regs().state.write(State::from(overruns));
+        self.regs().intr.write(Intr::from(overruns));
+    }
+
+    /// Move queued bytes to the UART while it accepts them; IRQs must be masked
+    fn tx_kick(&self) {
+        while !self.tx_full() {
+            match self.tx_buf.pop() {
+                Some(byte) => {
+                    self.regs().data.write(byte);
+                    inc(&self.counters.tx);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn rx_interrupt(&self) {
+        self.regs().intr.write(Intr::RX);
+        while let Some(byte) = self.getc_polled() {
+            // dropped if nobody reads
+            if !self.rx_buf.push(byte) {
+                inc(&self.counters.rx_dropped);
+            }
+        }
+        wake(&self.rx_waker);
+    }
+
+    fn tx_interrupt(&self) {
+        self.regs().intr.write(Intr::TX);
+        let primask = cpu::irq_save();
+        self.tx_kick();
+        cpu::irq_restore(primask);
+        wake(&self.tx_waker);
+    }
+
+    /// Whether `putc()` has to poll the UART rather than queue
+    fn tx_polled(&self) -> bool {
+        !self.tx_irq.load(Ordering::Relaxed) || !cpu::in_thread_mode() || cpu::irq_masked()
+    }
+
+    /// Switch receiving, and transmitting if `tx`, to interrupts; a second
+    /// call only adds what the first one left out
+    pub fn start_irq(&'static self, tx: bool) -> Result<(), Errno> {
+        let mut ctrl = self.regs().ctrl.read() | Ctrl::RX_OVR_INTR_EN | Ctrl::TX_OVR_INTR_EN;
+
+        if !self.rx_irq.load(Ordering::Relaxed) {
+            irq::register(self.uart.rx_irq, move || self.rx_interrupt())?;
+            ctrl = ctrl | Ctrl::RX_INTR_EN;
+            self.rx_irq.store(true, Ordering::Relaxed);
+        }
+
+        if tx && !self.tx_irq.load(Ordering::Relaxed) {
+            irq::register(self.uart.tx_irq, move || self.tx_interrupt())?;
+            ctrl = ctrl | Ctrl::TX_INTR_EN;
+            self.tx_irq.store(true, Ordering::Relaxed);
+        }
+
+        self.regs().ctrl.write(ctrl);
+        Ok(())
+    }
+
+    /// Hand each received byte to `func` in the RX interrupt, bypassing the
+    /// ring buffer
+    #[allow(dead_code)]
+    pub fn start_rx_irq_with<F>(&'static self, mut func: F) -> Result<(), Errno>
+    where
+        F: FnMut(u8) + Send + 'static,
+    {
+        irq::register(self.uart.rx_irq, move || {
+            self.regs().intr.write(Intr::RX);
+            while let Some(byte) = self.getc_polled() {
+                func(byte)
+            }
+        })?;
+        let ctrl = self.regs().ctrl.read() | Ctrl::RX_INTR_EN | Ctrl::RX_OVR_INTR_EN;
+        self.regs().ctrl.write(ctrl);
+        Ok(())
+    }
+}
 
 impl Console for ArmUart {
-    fn init(&mut self) {
-        self.ctrl.write(Ctrl::TX_EN)
+    fn init(&self) {
+        if self.baud_rate() == 0 {
+            let _ = self.set_baud_rate(DEFAULT_BAUD_RATE);
+        }
+        let ctrl = self.regs().ctrl.read() | Ctrl::TX_EN | Ctrl::RX_EN;
+        self.regs().ctrl.write(ctrl)
+    }
+
+    fn start_irq(&'static self) -> Result<(), Errno> {
+        ArmUart::start_irq(self, true)
+    }
+
+    fn putc(&self, byte: u8) {
+        if self.tx_polled() {
+            let primask = cpu::irq_save();
+            while let Some(queued) = self.tx_buf.pop() {
+                self.putc_polled(queued);
+            }
+            self.putc_polled(byte);
+            cpu::irq_restore(primask);
+            return;
+        }
+
+        let deadline = Deadline::after(TX_TIMEOUT);
+        loop {
+            let primask = cpu::irq_save();
+            let queued = self.tx_buf.push(byte);
+            self.tx_kick();
+            cpu::irq_restore(primask);
+            if queued {
+                break;
+            }
+            if deadline.expired() {
+                inc(&self.counters.tx_dropped);
+                break;
+            }
+        }
+    }
+
+    fn try_putc(&self, byte: u8) -> bool {
+        if self.tx_polled() {
+            self.putc(byte);
+            return true;
+        }
+        let primask = cpu::irq_save();
+        let queued = self.tx_buf.push(byte);
+        self.tx_kick();
+        cpu::irq_restore(primask);
+        queued
+    }
+
+    fn set_rx_waker(&self, waker: &Waker) -> bool {
+        if !self.rx_irq.load(Ordering::Relaxed) {
+            return false;
+        }
+        *self.rx_waker.lock() = Some(waker.clone());
+        true
     }
 
-    fn putc(&mut self, byte: u8) {
-        while self.state.read().is_set(State::TX_BF) {}
-        self.data.write(byte)
+    fn set_tx_waker(&self, waker: &Waker) -> bool {
+        if !self.tx_irq.load(Ordering::Relaxed) {
+            return false;
+        }
+        *self.tx_waker.lock() = Some(waker.clone());
+        true
     }
 
     fn flush(&self) {
-        while self.state.read().is_set(State::TX_BF) {}
+        loop {
+            let primask = cpu::irq_save();
+            self.tx_kick();
+            let empty = self.tx_buf.is_empty();
+            cpu::irq_restore(primask);
+            if empty {
+                break;
+            }
+        }
+        while self.tx_full() {}
+    }
+
+    fn try_getc(&self) -> Option<u8> {
+        if self.rx_irq.load(Ordering::Relaxed) {
+            self.rx_buf.pop()
+        } else {
+            self.getc_polled()
+        }
     }
 }
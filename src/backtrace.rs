@@ -1,5 +1,13 @@
 /*
 
+Stack unwinding, with one of two unwinders chosen at build time.
+
+By default, the EHABI unwind tables (.ARM.exidx/.ARM.extab, kept by the
+linker script) are interpreted by the `ehabi` crate. This needs
+`-C force-unwind-tables=yes` but no frame pointers, and unwinds leaf
+functions and functions that don't save r7.
+
+With the "unwind-fp" feature, the frame pointer chain is followed instead.
 On armv8m (T32 ISA), frame pointer is stored in R7
 (R11 for A32, X29 for AArch64)
 
@@ -8,52 +16,369 @@ function:
     // function body
     pop  {r7, pc}
 
+A frame returning to EXC_RETURN (0xFFxxxxxx) was entered by an exception;
+the walk continues in the interrupted code from the registers stacked by
+hardware, on MSP or PSP as EXC_RETURN says, after a
+"--- <exception N> ---" line.
+
 [refs]
 - https://developer.arm.com/documentation/100067/0607/armclang-Command-line-Options/-fomit-frame-pointer---fno-omit-frame-pointer
 - linux/arch/arm64/kernel/stacktrace.c
 
  */
 
+extern crate ehabi;
+
 use core::arch::asm;
 
-use crate::decl_c_symbol_addr;
+pub use ehabi::Regs;
+
+use crate::{decl_c_symbol_addr, kallsyms, println, sched};
 decl_c_symbol_addr!(__text_s, text_s);
 decl_c_symbol_addr!(__text_e, text_e);
 decl_c_symbol_addr!(__stack_s, stack_s);
 decl_c_symbol_addr!(__stack_e, stack_e);
 
+#[cfg(not(feature = "unwind-fp"))]
+decl_c_symbol_addr!(__rodata_e, rodata_e);
+#[cfg(not(feature = "unwind-fp"))]
+decl_c_symbol_addr!(__exidx_s, exidx_s);
+#[cfg(not(feature = "unwind-fp"))]
+decl_c_symbol_addr!(__exidx_e, exidx_e);
+
+fn in_stack(fp: usize) -> bool {
+    if stack_s() <= fp && fp < stack_e() {
+        return true;
+    }
+    match sched::current_stack() {
+        Some((s, e)) => s <= fp && fp < e,
+        None => false,
+    }
+}
+
+fn in_text(addr: usize) -> bool {
+    text_s() <= addr && addr < text_e()
+}
+
+fn read_stack(addr: usize) -> Option<u32> {
+    if addr % 4 == 0 && in_stack(addr) {
+        Some(unsafe { *(addr as *const u32) })
+    } else {
+        None
+    }
+}
+
+fn psp() -> usize {
+    let psp: usize;
+    unsafe { asm!("mrs {}, psp", out(reg) psp) };
+    psp
+}
+
+/// A line of a backtrace
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Frame {
+    /// pc, or the return address of a caller
+    Call(usize),
+    /// The frames above ran in the handler of exception N, which interrupted
+    /// the frames below
+    Exception(u32),
+}
+
+pub const EXC_RETURN_MODE: u32 = 1 << 3; // returns to Thread mode
+pub const EXC_RETURN_SPSEL: u32 = 1 << 2; // frame is on PSP
+const EXC_RETURN_FTYPE: u32 = 1 << 4; // no FP context stacked
+const EXC_RETURN_DCRS: u32 = 1 << 5; // r4-r11 not stacked by hardware
+const XPSR_STKALIGN: u32 = 1 << 9;
+
+/// Whether `lr` is an EXC_RETURN value rather than a return address
+pub fn is_exc_return(lr: u32) -> bool {
+    lr >> 24 == 0xff
+}
+
+/// Load the registers of the code interrupted by the exception returning
+/// with `exc_return`, whose hardware frame is at `frame`, and return its
+/// xPSR. r4-r11 are left as they are unless the frame holds them too.
+pub fn unstack(exc_return: u32, frame: usize, regs: &mut Regs) -> Option<u32> {
+    let mut addr = frame;
+    if exc_return & EXC_RETURN_DCRS == 0 {
+        // integrity signature, reserved, r4-r11
+        for i in 0..8 {
+            regs.r[4 + i] = read_stack(addr + 8 + i * 4)?;
+        }
+        addr += 0x28;
+    }
+
+    let mut words: [u32; 8] = [0; 8];
+    for (i, w) in words.iter_mut().enumerate() {
+        *w = read_stack(addr + i * 4)?;
+    }
+    let [r0, r1, r2, r3, r12, lr, pc, xpsr] = words;
+
+    // basic frame, then s0-s15, FPSCR and a reserved word
+    let mut sp = addr as u32 + 0x20;
+    if exc_return & EXC_RETURN_FTYPE == 0 {
+        sp += 0x48;
+    }
+    if xpsr & XPSR_STKALIGN != 0 {
+        sp += 4;
+    }
+
+    regs.r[0..4].copy_from_slice(&[r0, r1, r2, r3]);
+    regs.r[12] = r12;
+    regs.r[ehabi::SP] = sp;
+    regs.r[ehabi::LR] = lr;
+    regs.r[ehabi::PC] = pc;
+    Some(xpsr)
+}
+
+#[cfg(feature = "gdbstub")]
+fn write_stack(addr: usize, v: u32) -> Option<()> {
+    if addr % 4 == 0 && in_stack(addr) {
+        unsafe { *(addr as *mut u32) = v };
+        Some(())
+    } else {
+        None
+    }
+}
+
+/// The reverse of `unstack()`: store `regs` and `xpsr` in the hardware frame
+/// for the exception return to load them. sp stays where the frame is.
+#[cfg(feature = "gdbstub")]
+pub fn restack(exc_return: u32, frame: usize, regs: &Regs, xpsr: u32) -> Option<()> {
+    let mut addr = frame;
+    if exc_return & EXC_RETURN_DCRS == 0 {
+        for i in 0..8 {
+            write_stack(addr + 8 + i * 4, regs.r[4 + i])?;
+        }
+        addr += 0x28;
+    }
+
+    // the padding is fixed by where the frame is
+    let stkalign = read_stack(addr + 0x1c)? & XPSR_STKALIGN;
+    let r = &regs.r;
+    let words = [
+        r[0],
+        r[1],
+        r[2],
+        r[3],
+        r[12],
+        r[ehabi::LR],
+        r[ehabi::PC] & !1,
+        (xpsr & !XPSR_STKALIGN) | stkalign,
+    ];
+    for (i, w) in words.iter().enumerate() {
+        write_stack(addr + i * 4, *w)?;
+    }
+    Some(())
+}
+
+/// Exception number of the running code, 0 in Thread mode
+fn current_exception() -> u32 {
+    let ipsr: u32;
+    unsafe { asm!("mrs {}, ipsr", out(reg) ipsr) };
+    ipsr & 0x1ff
+}
+
+/// r4-r11, sp, lr and pc of the code this is inlined in
+#[inline(always)]
+pub fn current_regs() -> Regs {
+    let mut regs = Regs::default();
+    unsafe {
+        asm!(
+            "stm r0, {{r4-r11}}",
+            "mov r1, sp",
+            "mov r2, lr",
+            "mov r3, pc",
+            "str r1, [r0, #36]",
+            "str r2, [r0, #40]",
+            "str r3, [r0, #44]",
+            in("r0") &mut regs.r[4] as *mut u32,
+            out("r1") _,
+            out("r2") _,
+            out("r3") _,
+            options(nostack, preserves_flags)
+        )
+    }
+    regs
+}
+
+#[cfg(not(feature = "unwind-fp"))]
+fn read_word(addr: u32) -> Option<u32> {
+    let addr = addr as usize;
+    if addr % 4 == 0 && text_s() <= addr && addr < rodata_e() {
+        Some(unsafe { *(addr as *const u32) })
+    } else {
+        read_stack(addr)
+    }
+}
+
+#[cfg(not(feature = "unwind-fp"))]
+fn exidx() -> ehabi::ExidxTable {
+    ehabi::ExidxTable::new(exidx_s() as u32, exidx_e() as u32)
+}
+
+#[cfg(not(feature = "unwind-fp"))]
+fn walk(
+    mut regs: Regs,
+    mut exception: u32,
+    mut is_return_address: bool,
+    limit: u32,
+    func: fn(Frame),
+) {
+    let table = exidx();
+    let mut n = 0;
+    while n < limit {
+        if is_exc_return(regs.pc()) {
+            let exc_return = regs.pc();
+            let frame = if exc_return & EXC_RETURN_SPSEL != 0 {
+                psp()
+            } else {
+                regs.sp() as usize
+            };
+            match unstack(exc_return, frame, &mut regs) {
+                Some(xpsr) => {
+                    func(Frame::Exception(exception));
+                    exception = xpsr & 0x1ff;
+                    // pc is where the code was interrupted, not a return address
+                    is_return_address = false;
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        let pc = regs.pc() as usize;
+        if !in_text(pc) {
+            break;
+        }
+        func(Frame::Call(pc));
+        n += 1;
+
+        let sp = regs.sp();
+        if table
+            .unwind_frame(&read_word, &mut regs, is_return_address)
+            .is_err()
+        {
+            break;
+        }
+        is_return_address = true;
+
+        // callers' frames are above, and the walk must make progress
+        if regs.sp() < sp || (regs.sp() == sp && regs.pc() as usize == pc) {
+            break;
+        }
+    }
+}
+
+/// Call `func` on pc of `regs`, then on the return addresses of the callers.
+/// `exception` is the exception number the code at pc runs in.
+#[cfg(not(feature = "unwind-fp"))]
+pub fn unwind_walk(regs: &Regs, exception: u32, limit: u32, func: fn(Frame)) {
+    walk(*regs, exception, false, limit, func);
+}
+
+/// Call `func` on the return addresses of the callers of `trace()`
+#[cfg(not(feature = "unwind-fp"))]
+#[inline(never)]
+pub fn trace(limit: u32, func: fn(Frame)) {
+    let mut regs = current_regs();
+    if exidx().unwind_frame(&read_word, &mut regs, false).is_ok() {
+        walk(regs, current_exception(), true, limit, func);
+    }
+}
+
+#[cfg(feature = "unwind-fp")]
 #[derive(Clone, Copy)]
 struct StackFrame {
     fp: usize,
     lr: usize,
 }
 
-pub fn unwind_walk(pc: usize, fp: usize, limit: u32, func: fn(usize)) {
-    let mut fp_ = fp;
+/// Call `func` on pc of `regs`, then on the return addresses of the callers.
+/// `exception` is the exception number the code at pc runs in.
+#[cfg(feature = "unwind-fp")]
+pub fn unwind_walk(regs: &Regs, exception: u32, limit: u32, func: fn(Frame)) {
+    let mut exception = exception;
+    let mut fp_ = regs.r[7] as usize;
 
-    func(pc);
+    func(Frame::Call(regs.pc() as usize));
 
     for _i in 1..limit {
-        if fp_ < stack_s() || fp_ >= stack_e() {
+        if !in_stack(fp_) {
             break;
         }
 
         let frame: StackFrame = unsafe { *(fp_ as *const StackFrame) };
-        if frame.lr < text_s() || frame.lr >= text_e() {
+        if is_exc_return(frame.lr as u32) {
+            // {r7, lr} are the first thing a handler pushes, right below
+            // the exception frame
+            let exc_return = frame.lr as u32;
+            let stacked = if exc_return & EXC_RETURN_SPSEL != 0 {
+                psp()
+            } else {
+                fp_ + 8
+            };
+            let mut regs = Regs::default();
+            regs.r[7] = frame.fp as u32;
+            match unstack(exc_return, stacked, &mut regs) {
+                Some(xpsr) => {
+                    func(Frame::Exception(exception));
+                    func(Frame::Call(regs.pc() as usize));
+                    exception = xpsr & 0x1ff;
+                    fp_ = regs.r[7] as usize;
+                    continue;
+                }
+                None => break,
+            }
+        }
+        if !in_text(frame.lr) {
             break;
         }
 
-        func(frame.lr);
+        func(Frame::Call(frame.lr));
         fp_ = frame.fp;
     }
 }
 
-#[allow(dead_code)]
-pub fn trace(limit: u32, func: fn(usize)) {
+/// Call `func` on the return addresses of the callers of `trace()`
+#[cfg(feature = "unwind-fp")]
+pub fn trace(limit: u32, func: fn(Frame)) {
     let frame: StackFrame = unsafe {
         let fp: usize;
         asm!("mov {}, r7", out(reg) fp);
         *(fp as *const StackFrame)
     };
-    unwind_walk(frame.lr, frame.fp, limit, func);
+    let mut regs = Regs::default();
+    regs.r[ehabi::PC] = frame.lr as u32;
+    regs.r[7] = frame.fp as u32;
+    unwind_walk(&regs, current_exception(), limit, func);
+}
+
+/// Print `addr` with the symbol and, if known, the file:line it belongs to
+pub fn print_addr(addr: usize) {
+    let mut buf: [u8; 128] = [0; 128];
+    let (name, off) = match kallsyms::safe_search(addr, &mut buf) {
+        Some(found) => found,
+        None => {
+            println!("  {:08x}", addr);
+            return;
+        }
+    };
+    // a return address has the Thumb bit set; its line is the call's
+    let code = if addr & 1 != 0 && off >= 3 {
+        addr - 3
+    } else {
+        addr & !1
+    };
+    match kallsyms::search_line(code) {
+        Some((file, line)) => println!("  {:08x}  {} +{:#x} ({}:{})", addr, name, off, file, line),
+        None => println!("  {:08x}  {} +{:#x}", addr, name, off),
+    }
+}
+
+pub fn print_frame(frame: Frame) {
+    match frame {
+        Frame::Call(addr) => print_addr(addr),
+        Frame::Exception(n) => println!("  --- <exception {}> ---", n),
+    }
 }
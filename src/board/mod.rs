@@ -0,0 +1,92 @@
+/*
+
+Board configuration.
+
+Every supported machine implements `Board`; the `board-*` Cargo feature
+picks the one built, `Current`, whose settings are re-exported here. The
+memory map is in boards/<name>/board.toml, from which `cargo xtask`
+generates the linker script.
+
+ */
+
+#[cfg(any(
+    feature = "board-mps2-an385",
+    feature = "board-mps2-an386",
+    feature = "board-mps2-an500"
+))]
+mod mps2;
+#[cfg(feature = "board-mps2-an505")]
+mod mps2_an505;
+
+#[cfg(feature = "board-mps2-an385")]
+pub use mps2::Mps2An385 as Current;
+#[cfg(feature = "board-mps2-an386")]
+pub use mps2::Mps2An386 as Current;
+#[cfg(feature = "board-mps2-an500")]
+pub use mps2::Mps2An500 as Current;
+#[cfg(feature = "board-mps2-an505")]
+pub use mps2_an505::Mps2An505 as Current;
+
+#[cfg(not(any(
+    feature = "board-mps2-an385",
+    feature = "board-mps2-an386",
+    feature = "board-mps2-an500",
+    feature = "board-mps2-an505"
+)))]
+compile_error!("no board selected, enable one of the board-* features");
+
+use core::ops::Range;
+
+/// A CMSDK UART and its interrupts
+#[derive(Clone, Copy)]
+pub struct Uart {
+    pub base: usize,
+    pub rx_irq: usize,
+    pub tx_irq: usize,
+}
+
+/// A CMSDK timer and its interrupt
+#[derive(Clone, Copy)]
+pub struct Timer {
+    pub base: usize,
+    pub irq: usize,
+}
+
+pub trait Board {
+    /// Machine name, as QEMU's `-M` takes it
+    const NAME: &'static str;
+    /// Processor clock, which also drives SysTick and the APB timers
+    const SYSCLK_HZ: u32;
+    /// Number of external interrupts wired to the NVIC
+    const IRQ_COUNT: usize;
+    /// UART0-4
+    const UARTS: [Uart; 5];
+    /// Interrupt the overruns of the UARTs share
+    const UART_OVERRUN_IRQ: usize;
+    /// The UART the console starts on, an index in UARTS
+    const CONSOLE: usize;
+    /// The UART the GDB stub talks on
+    const DEBUG_UART: usize;
+    /// The two APB timers; the first one drives the software timers
+    const TIMERS: [Timer; 2];
+    const DUAL_TIMER: Timer;
+    /// ARMv8-M Mainline, which adds SecureFault and its status registers
+    const ARMV8M: bool;
+    /// Memory-mapped peripherals, which the MPU maps as Device memory
+    const PERIPHERALS: Range<usize>;
+}
+
+pub const NAME: &str = <Current as Board>::NAME;
+pub const SYSCLK_HZ: u32 = <Current as Board>::SYSCLK_HZ;
+pub const IRQ_COUNT: usize = <Current as Board>::IRQ_COUNT;
+pub const UARTS: [Uart; 5] = <Current as Board>::UARTS;
+pub const UART_OVERRUN_IRQ: usize = <Current as Board>::UART_OVERRUN_IRQ;
+pub const CONSOLE: usize = <Current as Board>::CONSOLE;
+#[allow(dead_code)]
+pub const DEBUG_UART: usize = <Current as Board>::DEBUG_UART;
+pub const TIMERS: [Timer; 2] = <Current as Board>::TIMERS;
+#[allow(dead_code)]
+pub const DUAL_TIMER: Timer = <Current as Board>::DUAL_TIMER;
+pub const ARMV8M: bool = <Current as Board>::ARMV8M;
+#[allow(dead_code)]
+pub const PERIPHERALS: Range<usize> = <Current as Board>::PERIPHERALS;
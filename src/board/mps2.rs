@@ -0,0 +1,85 @@
+/*
+
+mps2-an385 (Cortex-M3), mps2-an386 (Cortex-M4) and mps2-an500 (Cortex-M7):
+the same peripherals around different ARMv7-M cores
+
+ */
+
+use super::{Board, Timer, Uart};
+use core::ops::Range;
+
+macro_rules! mps2_board {
+    ($(#[$attr:meta])* $stname:ident, $name:literal) => {
+        $(#[$attr])*
+        pub struct $stname;
+
+        $(#[$attr])*
+        impl Board for $stname {
+            const NAME: &'static str = $name;
+            const SYSCLK_HZ: u32 = 25_000_000;
+            const IRQ_COUNT: usize = 32;
+            const UARTS: [Uart; 5] = [
+                Uart {
+                    base: 0x4000_4000,
+                    rx_irq: 0,
+                    tx_irq: 1,
+                },
+                Uart {
+                    base: 0x4000_5000,
+                    rx_irq: 2,
+                    tx_irq: 3,
+                },
+                Uart {
+                    base: 0x4000_6000,
+                    rx_irq: 4,
+                    tx_irq: 5,
+                },
+                Uart {
+                    base: 0x4000_7000,
+                    rx_irq: 18,
+                    tx_irq: 19,
+                },
+                Uart {
+                    base: 0x4000_9000,
+                    rx_irq: 20,
+                    tx_irq: 21,
+                },
+            ];
+            const UART_OVERRUN_IRQ: usize = 12;
+            const CONSOLE: usize = 0;
+            const DEBUG_UART: usize = 1;
+            const TIMERS: [Timer; 2] = [
+                Timer {
+                    base: 0x4000_0000,
+                    irq: 8,
+                },
+                Timer {
+                    base: 0x4000_1000,
+                    irq: 9,
+                },
+            ];
+            const DUAL_TIMER: Timer = Timer {
+                base: 0x4000_2000,
+                irq: 10,
+            };
+            const ARMV8M: bool = false;
+            const PERIPHERALS: Range<usize> = 0x4000_0000..0x6000_0000;
+        }
+    };
+}
+
+mps2_board!(
+    #[cfg(feature = "board-mps2-an385")]
+    Mps2An385,
+    "mps2-an385"
+);
+mps2_board!(
+    #[cfg(feature = "board-mps2-an386")]
+    Mps2An386,
+    "mps2-an386"
+);
+mps2_board!(
+    #[cfg(feature = "board-mps2-an500")]
+    Mps2An500,
+    "mps2-an500"
+);
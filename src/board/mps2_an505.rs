@@ -0,0 +1,62 @@
+/*
+
+mps2-an505: Cortex-M33 (ARMv8-M Mainline) on the IoT Kit subsystem
+
+ */
+
+use super::{Board, Timer, Uart};
+use core::ops::Range;
+
+pub struct Mps2An505;
+
+impl Board for Mps2An505 {
+    const NAME: &'static str = "mps2-an505";
+    const SYSCLK_HZ: u32 = 20_000_000;
+    const IRQ_COUNT: usize = 92;
+    const UARTS: [Uart; 5] = [
+        Uart {
+            base: 0x4020_0000,
+            rx_irq: 32,
+            tx_irq: 33,
+        },
+        Uart {
+            base: 0x4020_1000,
+            rx_irq: 34,
+            tx_irq: 35,
+        },
+        Uart {
+            base: 0x4020_2000,
+            rx_irq: 36,
+            tx_irq: 37,
+        },
+        Uart {
+            base: 0x4020_3000,
+            rx_irq: 38,
+            tx_irq: 39,
+        },
+        Uart {
+            base: 0x4020_4000,
+            rx_irq: 40,
+            tx_irq: 41,
+        },
+    ];
+    const UART_OVERRUN_IRQ: usize = 47;
+    const CONSOLE: usize = 0;
+    const DEBUG_UART: usize = 1;
+    const TIMERS: [Timer; 2] = [
+        Timer {
+            base: 0x4000_0000,
+            irq: 3,
+        },
+        Timer {
+            base: 0x4000_1000,
+            irq: 4,
+        },
+    ];
+    const DUAL_TIMER: Timer = Timer {
+        base: 0x4000_2000,
+        irq: 5,
+    };
+    const ARMV8M: bool = true;
+    const PERIPHERALS: Range<usize> = 0x4000_0000..0x6000_0000;
+}
@@ -1,42 +1,473 @@
+/*
+
+Console registry.
+
+Output goes to every registered console that takes it, and there are two
+kinds: `print!` and the kernel log's echo (see log.rs), so the log can go
+to one port and a protocol to another. Input comes from the active
+console, which the shell reads. Both can be changed at runtime with the
+`console` command. The consoles are
+
+    uart0..uart4    the board's UARTs; the GDB stub keeps its own
+    semihosting     the host's stdout, output only
+    buffer          the last BUFFER_SIZE bytes of output, kept in RAM
+
+A console is initialized when it is first selected, and gets its
+interrupts once the heap is up.
+
+`read()` and `write()` are the async versions of `try_getc()` and
+`try_putc()`, for tasks on the executor, on a console taken with `claim()`
+so that its input isn't split with the shell. A console that can't wake a
+task when I/O is possible again is polled instead, by waking the task right
+away; the other tasks still run in between.
+
+ */
+
+extern crate bitfield;
+extern crate posix;
+
+use alloc::vec::Vec;
 use core::fmt;
 use core::fmt::Write;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use posix::Errno;
+
+use bitfield::bitfield;
+
+use crate::sync::IrqSafeLock;
+use crate::{arm_uart, board, sched, semihosting, shell_command};
 
-pub trait Console {
-    fn init(&mut self) {}
-    fn putc(&mut self, byte: u8);
+pub trait Console: Sync {
+    fn init(&self) {}
+    fn putc(&self, byte: u8);
     fn flush(&self);
+
+    /// Queue a byte without waiting; false if there is no room for it now
+    fn try_putc(&self, byte: u8) -> bool {
+        self.putc(byte);
+        true
+    }
+
+    /// Have `waker` woken once `try_getc()` may succeed again; false if
+    /// the console can't
+    fn set_rx_waker(&self, _waker: &Waker) -> bool {
+        false
+    }
+
+    /// Have `waker` woken once `try_putc()` may succeed again; false if
+    /// the console can't
+    fn set_tx_waker(&self, _waker: &Waker) -> bool {
+        false
+    }
+
+    /// Switch to interrupt-driven I/O; needs the heap
+    fn start_irq(&'static self) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Next received byte, if any
+    fn try_getc(&self) -> Option<u8> {
+        None
+    }
+
+    /// Wait for a byte, giving up the CPU to other threads meanwhile
+    fn getc(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_getc() {
+                return byte;
+            }
+            if sched::current().is_some() {
+                sched::yield_now();
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Read a line into `buf` with echo and backspace handling, and return
+    /// its length without the line terminator. Input beyond `buf` is dropped.
+    fn read_line(&self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        loop {
+            match self.getc() {
+                b'\r' | b'\n' => {
+                    self.putc(b'\r');
+                    self.putc(b'\n');
+                    return len;
+                }
+                0x08 | 0x7f => {
+                    if len > 0 {
+                        len -= 1;
+                        for byte in b"\x08 \x08" {
+                            self.putc(*byte);
+                        }
+                    }
+                }
+                byte if (b' '..=b'~').contains(&byte) => {
+                    if len < buf.len() {
+                        buf[len] = byte;
+                        len += 1;
+                        self.putc(byte);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
-struct Writer {
-    console: *mut dyn Console,
+bitfield! {
+    pub Output: u32 {
+        PRINT[0];
+        LOG[1];
+    }
 }
 
-impl Writer {
-    fn new(console: *mut dyn Console) -> Self {
-        Writer { console }
+struct Semihosting;
+
+impl Console for Semihosting {
+    fn putc(&self, byte: u8) {
+        semihosting::writec(byte)
     }
 
-    fn putc(&mut self, byte: u8) {
-        unsafe { (*self.console).putc(byte) }
+    fn flush(&self) {}
+}
+
+const BUFFER_SIZE: usize = 4096;
+
+/// The last BUFFER_SIZE bytes of output, without the carriage returns
+struct Buffer {
+    ring: IrqSafeLock<BufferRing>,
+}
+
+struct BufferRing {
+    buf: [u8; BUFFER_SIZE],
+    next: usize,
+    wrapped: bool,
+}
+
+impl Buffer {
+    /// Contents, oldest first
+    fn contents(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(BUFFER_SIZE);
+        let ring = self.ring.lock();
+        if ring.wrapped {
+            v.extend_from_slice(&ring.buf[ring.next..]);
+        }
+        v.extend_from_slice(&ring.buf[..ring.next]);
+        v
     }
 }
 
-impl fmt::Write for Writer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            if byte == b'\n' {
-                self.putc(b'\r')
-            }
-            self.putc(byte)
+impl Console for Buffer {
+    fn putc(&self, byte: u8) {
+        if byte == b'\r' {
+            return;
         }
-        Ok(())
+        let mut ring = self.ring.lock();
+        let next = ring.next;
+        ring.buf[next] = byte;
+        ring.next = (next + 1) % BUFFER_SIZE;
+        if ring.next == 0 {
+            ring.wrapped = true;
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static SEMIHOSTING: Semihosting = Semihosting;
+static BUFFER: Buffer = Buffer {
+    ring: IrqSafeLock::new(BufferRing {
+        buf: [0; BUFFER_SIZE],
+        next: 0,
+        wrapped: false,
+    }),
+};
+
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    console: &'static dyn Console,
+    output: Output,
+    started: bool,
+    /// Taken by `claim()`
+    claimed: bool,
+}
+
+const MAX_CONSOLES: usize = 8;
+const NO_ENTRY: Option<Entry> = None;
+
+struct Registry {
+    entries: [Option<Entry>; MAX_CONSOLES],
+    /// Index of the console input comes from
+    active: usize,
+}
+
+impl Registry {
+    fn entry(&mut self, idx: usize) -> &mut Entry {
+        self.entries[idx].as_mut().unwrap()
+    }
+}
+
+/// Consoles are only called with copies of the entries, never under the
+/// lock, since output may wait for the UART
+static CONSOLES: IrqSafeLock<Registry> = IrqSafeLock::new(Registry {
+    entries: [NO_ENTRY; MAX_CONSOLES],
+    active: 0,
+});
+static IRQ_READY: AtomicBool = AtomicBool::new(false);
+
+pub const UART_NAMES: [&str; 5] = ["uart0", "uart1", "uart2", "uart3", "uart4"];
+
+/// Add `console` under `name`, taking no output yet
+pub fn register(name: &'static str, console: &'static dyn Console) -> Result<(), Errno> {
+    let mut consoles = CONSOLES.lock();
+    if consoles.entries.iter().flatten().any(|e| e.name == name) {
+        return Err(Errno::EEXIST);
+    }
+    match consoles.entries.iter_mut().find(|e| e.is_none()) {
+        Some(slot) => {
+            *slot = Some(Entry {
+                name,
+                console,
+                output: Output::from(0),
+                started: false,
+                claimed: false,
+            });
+            Ok(())
+        }
+        None => Err(Errno::ENOSPC),
+    }
+}
+
+fn find(name: &str) -> Result<usize, Errno> {
+    CONSOLES
+        .lock()
+        .entries
+        .iter()
+        .position(|e| matches!(e, Some(e) if e.name == name))
+        .ok_or(Errno::ENOENT)
+}
+
+/// Copies of the registered consoles
+fn entries() -> [Option<Entry>; MAX_CONSOLES] {
+    CONSOLES.lock().entries
+}
+
+/// Initialize the console at `idx` if it isn't yet, and start its interrupts
+/// once they are available
+fn start(idx: usize) -> Result<(), Errno> {
+    let entry = *CONSOLES.lock().entry(idx);
+    if !entry.started {
+        entry.console.init();
+        CONSOLES.lock().entry(idx).started = true;
+    }
+    match IRQ_READY.load(Ordering::Relaxed) {
+        true => entry.console.start_irq(),
+        false => Ok(()),
+    }
+}
+
+/// Read input from console `name`, which also takes `print!` from now on;
+/// EBUSY if it is claimed
+pub fn set_active(name: &str) -> Result<(), Errno> {
+    let idx = find(name)?;
+    if CONSOLES.lock().entry(idx).claimed {
+        return Err(Errno::EBUSY);
+    }
+    start(idx)?;
+    let mut consoles = CONSOLES.lock();
+    let entry = consoles.entry(idx);
+    entry.output = entry.output | Output::PRINT;
+    consoles.active = idx;
+    Ok(())
+}
+
+/// Console `name`, initialized, for I/O of its own like `read()` and
+/// `write()`. It can't be the active console, nor become it; EBUSY if it
+/// is, or if it is claimed already.
+pub fn claim(name: &str) -> Result<&'static dyn Console, Errno> {
+    let idx = find(name)?;
+    {
+        let mut consoles = CONSOLES.lock();
+        if consoles.active == idx || consoles.entry(idx).claimed {
+            return Err(Errno::EBUSY);
+        }
+        consoles.entry(idx).claimed = true;
+    }
+    if let Err(e) = start(idx) {
+        CONSOLES.lock().entry(idx).claimed = false;
+        return Err(e);
     }
+    Ok(CONSOLES.lock().entry(idx).console)
 }
 
+/// Choose the kinds of output console `name` takes
+pub fn set_output(name: &str, output: Output) -> Result<(), Errno> {
+    let idx = find(name)?;
+    if u32::from(output) != 0 {
+        start(idx)?;
+    }
+    CONSOLES.lock().entry(idx).output = output;
+    Ok(())
+}
+
+/// Register the consoles and start the board's console UART; runs before
+/// the heap is up
 pub fn init() {
-    use crate::__CONSOLE;
-    unsafe {
-        (*__CONSOLE).init();
+    for (n, name) in UART_NAMES.iter().enumerate().take(arm_uart::count()) {
+        if cfg!(feature = "gdbstub") && n == board::DEBUG_UART {
+            continue;
+        }
+        let _ = register(name, arm_uart::uart(n).unwrap());
+    }
+    let _ = register("semihosting", &SEMIHOSTING);
+    let _ = register("buffer", &BUFFER);
+
+    let console = UART_NAMES[board::CONSOLE];
+    let _ = set_active(console);
+    let _ = set_output(console, Output::PRINT | Output::LOG);
+}
+
+/// Switch the consoles in use to interrupt-driven I/O; needs the heap
+pub fn init_irq() {
+    if let Err(e) = arm_uart::start_overrun_irq() {
+        crate::error!("failed to enable the UART overrun interrupt: {:?}", e);
+    }
+
+    IRQ_READY.store(true, Ordering::Relaxed);
+    let entries = entries();
+    let started = |idx: &usize| matches!(&entries[*idx], Some(e) if e.started);
+    for idx in (0..MAX_CONSOLES).filter(started) {
+        if let Err(e) = start(idx) {
+            crate::error!("failed to enable interrupts: {:?}", e);
+        }
+    }
+}
+
+/// Wait until everything queued has been sent
+pub fn flush() {
+    for entry in entries().iter().flatten() {
+        if entry.started {
+            entry.console.flush();
+        }
+    }
+}
+
+fn active_console() -> &'static dyn Console {
+    let mut consoles = CONSOLES.lock();
+    let active = consoles.active;
+    consoles.entry(active).console
+}
+
+#[allow(dead_code)]
+pub fn try_getc() -> Option<u8> {
+    active_console().try_getc()
+}
+
+#[allow(dead_code)]
+pub fn getc() -> u8 {
+    active_console().getc()
+}
+
+#[allow(dead_code)]
+pub fn read_line(buf: &mut [u8]) -> usize {
+    active_console().read_line(buf)
+}
+
+/// Wait until `func` succeeds on `console`, woken through `set_waker`
+struct Poller<'a, F> {
+    console: &'a dyn Console,
+    func: F,
+    set_waker: fn(&dyn Console, &Waker) -> bool,
+}
+
+impl<'a, F, R> Future for Poller<'a, F>
+where
+    F: FnMut(&dyn Console) -> Option<R> + Unpin,
+{
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let this = self.get_mut();
+        if let Some(res) = (this.func)(this.console) {
+            return Poll::Ready(res);
+        }
+        if !(this.set_waker)(this.console, cx.waker()) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        // it may have become possible before the waker was set
+        match (this.func)(this.console) {
+            Some(res) => Poll::Ready(res),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Wait for input, then read what has arrived, up to `buf.len()` bytes
+#[allow(dead_code)]
+pub async fn read(console: &dyn Console, buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    buf[0] = Poller {
+        console,
+        func: |c: &dyn Console| c.try_getc(),
+        set_waker: |c, waker| c.set_rx_waker(waker),
+    }
+    .await;
+
+    let mut len = 1;
+    while len < buf.len() {
+        match console.try_getc() {
+            Some(byte) => buf[len] = byte,
+            None => break,
+        }
+        len += 1;
+    }
+    len
+}
+
+/// Write all of `data`, waiting whenever the console is full
+#[allow(dead_code)]
+pub async fn write(console: &dyn Console, data: &[u8]) {
+    for &byte in data {
+        Poller {
+            console,
+            func: move |c: &dyn Console| match c.try_putc(byte) {
+                true => Some(()),
+                false => None,
+            },
+            set_waker: |c, waker| c.set_tx_waker(waker),
+        }
+        .await
+    }
+}
+
+/// Writes to every console taking `output`
+struct Writer {
+    output: Output,
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for entry in entries().iter().flatten() {
+            if !entry.output.is_set(self.output) {
+                continue;
+            }
+            for byte in s.bytes() {
+                if byte == b'\n' {
+                    entry.console.putc(b'\r')
+                }
+                entry.console.putc(byte)
+            }
+        }
+        Ok(())
     }
 }
 
@@ -53,7 +484,118 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn print_fmt(args: fmt::Arguments) {
-    use crate::__CONSOLE;
-    let mut writer = Writer::new(__CONSOLE);
+    let mut writer = Writer {
+        output: Output::PRINT,
+    };
     let _ = writer.write_fmt(args);
 }
+
+/// Print the kernel log's echo
+pub fn log_fmt(args: fmt::Arguments) {
+    let mut writer = Writer {
+        output: Output::LOG,
+    };
+    let _ = writer.write_fmt(args);
+}
+
+fn list() {
+    println!("NAME         IN  PRINT  LOG");
+    let flag = |on: bool| if on { "*" } else { "" };
+    let (entries, active) = {
+        let consoles = CONSOLES.lock();
+        (consoles.entries, consoles.active)
+    };
+    for (idx, entry) in entries.iter().enumerate() {
+        if let Some(e) = entry {
+            println!(
+                "{:11}  {:2}  {:5}  {}",
+                e.name,
+                flag(idx == active),
+                flag(e.output.is_set(Output::PRINT)),
+                flag(e.output.is_set(Output::LOG))
+            );
+        }
+    }
+}
+
+fn cmd_console(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] => list(),
+        [_, "use", name] => set_active(name)?,
+        [_, "out", name, kinds @ ..] => {
+            let mut output = Output::from(0);
+            for kind in kinds {
+                output = output
+                    | match *kind {
+                        "print" => Output::PRINT,
+                        "log" => Output::LOG,
+                        "none" => Output::from(0),
+                        _ => return Err(Errno::EINVAL),
+                    };
+            }
+            if find(name)? == CONSOLES.lock().active && !output.is_set(Output::PRINT) {
+                println!("the active console takes print output");
+                return Err(Errno::EINVAL);
+            }
+            set_output(name, output)?
+        }
+        [_, "buffer"] => {
+            let text = BUFFER.contents();
+            print!("{}", core::str::from_utf8(&text).unwrap_or("(binary)"));
+        }
+        _ => {
+            println!("usage: console [use <name> | out <name> [print] [log] | buffer]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_CONSOLE,
+    "console",
+    "list the consoles, choose where input comes from and output goes",
+    cmd_console
+);
+
+fn cmd_uart(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] => {
+            println!("UART      BAUD        RX        TX  RX DROP  TX DROP  RX OVR  TX OVR");
+            for n in 0..arm_uart::count() {
+                let uart = arm_uart::uart(n).unwrap();
+                let s = uart.stats();
+                println!(
+                    "{:4}  {:>8}  {:>8}  {:>8}  {:>7}  {:>7}  {:>6}  {:>6}",
+                    n,
+                    uart.baud_rate(),
+                    s.rx,
+                    s.tx,
+                    s.rx_dropped,
+                    s.tx_dropped,
+                    s.rx_overruns,
+                    s.tx_overruns
+                );
+            }
+        }
+        [_, n, "baud", rate] => {
+            let n = n.parse::<usize>().or(Err(Errno::EINVAL))?;
+            let rate = rate.parse::<u32>().or(Err(Errno::EINVAL))?;
+            arm_uart::uart(n)
+                .ok_or(Errno::ENODEV)?
+                .set_baud_rate(rate)?;
+        }
+        _ => {
+            println!("usage: uart [<n> baud <rate>]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_UART,
+    "uart",
+    "show the UART counters, or set a baud rate",
+    cmd_uart
+);
@@ -0,0 +1,361 @@
+/*
+
+ELF core dump of the crashed kernel, written to a host file via semihosting.
+
+    +---------------------------+
+    |  ELF header (ET_CORE)     |
+    |  PT_NOTE                  |
+    |  PT_LOAD  ram             |  __data_s .. __bss_e
+    |  PT_LOAD  stack           |  __stack_s .. __stack_e (MSP)
+    |  PT_LOAD  heap            |  __heap_s .. __heap_e (threads' PSP stacks)
+    +---------------------------+
+    |  "CORE"    NT_PRSTATUS    |  r0-r15, xPSR, as Linux/ARM lays them out
+    |  "BARBARA" NT_CRASH       |  see CrashInfo
+    +---------------------------+
+    |  contents of the regions  |
+    +---------------------------+
+
+`kallsyms_tools coredump` reads it back with the firmware ELF; GDB can load
+it too (`target core`).
+
+ */
+
+extern crate posix;
+
+use core::{fmt, mem::size_of, slice};
+use posix::Errno;
+
+use crate::backtrace::Regs;
+use crate::semihosting::{self, Handle, Mode};
+use crate::sync::IrqSafeLock;
+use crate::{decl_c_symbol_addr, println, scb, shell_command};
+
+decl_c_symbol_addr!(__data_s, data_s);
+decl_c_symbol_addr!(__bss_e, bss_e);
+decl_c_symbol_addr!(__stack_s, stack_s);
+decl_c_symbol_addr!(__stack_e, stack_e);
+decl_c_symbol_addr!(__heap_s, heap_s);
+decl_c_symbol_addr!(__heap_e, heap_e);
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const ET_CORE: u16 = 4;
+const EM_ARM: u16 = 40;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const PRSTATUS_SIZE: usize = 148;
+const PRSTATUS_REG_OFF: usize = 72;
+
+/// Type of the "BARBARA" note
+pub const NT_CRASH: u32 = 1;
+
+const SIGABRT: u32 = 6;
+const SIGSEGV: u32 = 11;
+
+const CAUSE_LEN: usize = 128;
+
+/// Desc of the "BARBARA" note, followed by the cause, NUL-terminated
+#[repr(C)]
+struct CrashInfo {
+    /// Exception number the code at pc ran in
+    exception: u32,
+    /// Exception number of the fault, 0 for a panic
+    fault: u32,
+    exc_return: u32,
+    msp: u32,
+    psp: u32,
+    cfsr: u32,
+    hfsr: u32,
+    mmfar: u32,
+    bfar: u32,
+    sfsr: u32,
+    sfar: u32,
+}
+
+const DEFAULT_PATH: &str = "barbara.core";
+const PATH_MAX: usize = 64;
+
+#[derive(Clone, Copy)]
+struct Path {
+    buf: [u8; PATH_MAX],
+    len: usize,
+}
+
+impl Path {
+    const fn initial() -> Self {
+        let mut buf = [0; PATH_MAX];
+        let mut i = 0;
+        while i < DEFAULT_PATH.len() {
+            buf[i] = DEFAULT_PATH.as_bytes()[i];
+            i += 1;
+        }
+        Self {
+            buf,
+            len: DEFAULT_PATH.len(),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+/// Where the dump goes, relative to the directory QEMU runs in; empty to
+/// disable
+static PATH: IrqSafeLock<Path> = IrqSafeLock::new(Path::initial());
+
+fn path() -> Path {
+    *PATH.lock()
+}
+
+fn set_path(path: &str) -> Result<(), Errno> {
+    if path.len() > PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let mut p = PATH.lock();
+    p.buf[..path.len()].copy_from_slice(path.as_bytes());
+    p.len = path.len();
+    Ok(())
+}
+
+fn regions() -> [(usize, usize); 3] {
+    [
+        (data_s(), bss_e()),
+        (stack_s(), stack_e()),
+        (heap_s(), heap_e()),
+    ]
+}
+
+/// Formats the cause into a fixed buffer, cutting it short if needed
+struct CauseWriter {
+    buf: [u8; CAUSE_LEN],
+    len: usize,
+}
+
+impl fmt::Write for CauseWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // keep a NUL at the end
+        let n = s.len().min(CAUSE_LEN - 1 - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+fn write_all(handle: Handle, mut data: &[u8]) -> Result<(), Errno> {
+    while !data.is_empty() {
+        let n = semihosting::write(handle, data)?;
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// Headers and notes are gathered in a buffer so that they take a few
+/// semihosting calls rather than one per field
+const BUF_SIZE: usize = 512;
+
+struct CoreFile {
+    handle: Handle,
+    buf: [u8; BUF_SIZE],
+    len: usize,
+}
+
+impl CoreFile {
+    fn new(handle: Handle) -> Self {
+        Self {
+            handle,
+            buf: [0; BUF_SIZE],
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Errno> {
+        let len = core::mem::replace(&mut self.len, 0);
+        write_all(self.handle, &self.buf[..len])
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Errno> {
+        if self.len + data.len() > BUF_SIZE {
+            self.flush()?;
+        }
+        if data.len() > BUF_SIZE {
+            // region contents go straight out
+            return write_all(self.handle, data);
+        }
+        self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn put_u16(&mut self, v: u16) -> Result<(), Errno> {
+        self.put(&v.to_le_bytes())
+    }
+
+    fn put_u32(&mut self, v: u32) -> Result<(), Errno> {
+        self.put(&v.to_le_bytes())
+    }
+
+    fn put_ehdr(&mut self, phnum: u16) -> Result<(), Errno> {
+        self.put(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0")?;
+        self.put_u16(ET_CORE)?;
+        self.put_u16(EM_ARM)?;
+        self.put_u32(1)?; // version
+        self.put_u32(0)?; // entry
+        self.put_u32(EHDR_SIZE as u32)?; // phoff
+        self.put_u32(0)?; // shoff
+        self.put_u32(0x0500_0000)?; // flags: EABI version 5
+        self.put_u16(EHDR_SIZE as u16)?;
+        self.put_u16(PHDR_SIZE as u16)?;
+        self.put_u16(phnum)?;
+        self.put_u16(0)?; // shentsize
+        self.put_u16(0)?; // shnum
+        self.put_u16(0) // shstrndx
+    }
+
+    fn put_phdr(&mut self, typ: u32, off: usize, addr: usize, size: usize) -> Result<(), Errno> {
+        // notes take no memory
+        let (addr, memsz, flags) = match typ {
+            PT_LOAD => (addr as u32, size as u32, PF_R | PF_W),
+            _ => (0, 0, 0),
+        };
+        for v in [typ, off as u32, addr, addr, size as u32, memsz, flags, 4] {
+            self.put_u32(v)?;
+        }
+        Ok(())
+    }
+
+    fn put_note_header(&mut self, name: &[u8], typ: u32, descsz: usize) -> Result<(), Errno> {
+        self.put_u32(name.len() as u32 + 1)?;
+        self.put_u32(descsz as u32)?;
+        self.put_u32(typ)?;
+        self.put(name)?;
+        self.put(&[0u8; 4][..note_size(name.len() + 1) - name.len()])
+    }
+}
+
+fn note_size(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn note_len(name: &[u8], descsz: usize) -> usize {
+    12 + note_size(name.len() + 1) + note_size(descsz)
+}
+
+fn write(
+    file: &mut CoreFile,
+    regs: &Regs,
+    xpsr: u32,
+    info: &CrashInfo,
+    cause: &CauseWriter,
+) -> Result<(), Errno> {
+    let regions = regions();
+    let cause_len = note_size(cause.len + 1);
+    let crash_len = size_of::<CrashInfo>() + cause_len;
+    let notes_off = EHDR_SIZE + PHDR_SIZE * (1 + regions.len());
+    let notes_len = note_len(b"CORE", PRSTATUS_SIZE) + note_len(b"BARBARA", crash_len);
+
+    file.put_ehdr(1 + regions.len() as u16)?;
+    file.put_phdr(PT_NOTE, notes_off, 0, notes_len)?;
+    let mut off = notes_off + notes_len;
+    for &(s, e) in regions.iter() {
+        file.put_phdr(PT_LOAD, off, s, e - s)?;
+        off += e - s;
+    }
+
+    // elf_prstatus: siginfo, cursig, then pid and times we don't have
+    let signo = if info.fault != 0 { SIGSEGV } else { SIGABRT };
+    file.put_note_header(b"CORE", NT_PRSTATUS, PRSTATUS_SIZE)?;
+    file.put_u32(signo)?;
+    file.put(&[0u8; 8])?;
+    file.put_u16(signo as u16)?;
+    file.put(&[0u8; PRSTATUS_REG_OFF - 14])?;
+    for r in regs.r.iter() {
+        file.put_u32(*r)?;
+    }
+    file.put_u32(xpsr)?;
+    file.put_u32(regs.r[0])?; // orig_r0
+    file.put_u32(0)?; // pr_fpvalid
+
+    file.put_note_header(b"BARBARA", NT_CRASH, crash_len)?;
+    let info =
+        unsafe { slice::from_raw_parts(info as *const _ as *const u8, size_of::<CrashInfo>()) };
+    file.put(info)?;
+    file.put(&cause.buf[..cause_len])?;
+
+    for &(s, e) in regions.iter() {
+        file.put(unsafe { slice::from_raw_parts(s as *const u8, e - s) })?;
+    }
+    file.flush()
+}
+
+/// Write the core file for a crash with registers `regs` and `xpsr`.
+/// `exc_return` is 0 for a panic.
+pub fn dump(regs: &Regs, xpsr: u32, fault: u32, exc_return: u32, cause: fmt::Arguments) {
+    let path = path();
+    let path = path.as_str();
+    if path.is_empty() {
+        return;
+    }
+
+    let mut writer = CauseWriter {
+        buf: [0; CAUSE_LEN],
+        len: 0,
+    };
+    let _ = fmt::write(&mut writer, cause);
+
+    let (msp, psp): (u32, u32);
+    unsafe {
+        core::arch::asm!("mrs {}, msp", out(reg) msp);
+        core::arch::asm!("mrs {}, psp", out(reg) psp);
+    }
+    let st = scb::fault_status();
+    let info = CrashInfo {
+        exception: xpsr & 0x1ff,
+        fault,
+        exc_return,
+        msp,
+        psp,
+        cfsr: u32::from(st.cfsr),
+        hfsr: u32::from(st.hfsr),
+        mmfar: st.mmfar,
+        bfar: st.bfar,
+        sfsr: u32::from(st.sfsr),
+        sfar: st.sfar,
+    };
+
+    println!("writing core dump to {} ...", path);
+    let result = semihosting::open(path, Mode::Write).and_then(|handle| {
+        let mut file = CoreFile::new(handle);
+        let result = write(&mut file, regs, xpsr, &info, &writer);
+        semihosting::close(handle).and(result)
+    });
+    match result {
+        Ok(()) => println!("core dumped"),
+        Err(e) => println!("core dump failed: {:?}", e),
+    }
+}
+
+fn cmd_coredump(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] if path().as_str().is_empty() => println!("off"),
+        [_] => println!("{}", path().as_str()),
+        [_, "off"] => set_path("")?,
+        [_, path] => set_path(path)?,
+        _ => {
+            println!("usage: coredump [off|<host file>]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_COREDUMP,
+    "coredump",
+    "show or set the host file crash dumps are written to",
+    cmd_coredump
+);
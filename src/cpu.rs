@@ -0,0 +1,63 @@
+use core::arch::asm;
+
+/// Mask IRQs and return the previous PRIMASK value
+#[inline]
+pub fn irq_save() -> u32 {
+    let primask: u32;
+    unsafe {
+        asm!(
+            "mrs {}, primask",
+            "cpsid i",
+            out(reg) primask,
+        )
+    }
+    primask
+}
+
+/// Restore PRIMASK saved by `irq_save()`
+#[inline]
+pub fn irq_restore(primask: u32) {
+    if primask & 1 == 0 {
+        unsafe { asm!("cpsie i") }
+    }
+}
+
+/// Whether IRQs are masked by PRIMASK
+#[inline]
+pub fn irq_masked() -> bool {
+    let primask: u32;
+    unsafe { asm!("mrs {}, primask", out(reg) primask) }
+    primask & 1 != 0
+}
+
+/// Whether we are in Thread mode, i.e. not running an exception handler
+#[inline]
+pub fn in_thread_mode() -> bool {
+    let ipsr: u32;
+    unsafe { asm!("mrs {}, ipsr", out(reg) ipsr) }
+    ipsr & 0x1ff == 0
+}
+
+/// Whether Thread mode is unprivileged (CONTROL.nPRIV); in a handler, that
+/// of the code it interrupted
+#[inline]
+pub fn thread_unprivileged() -> bool {
+    let control: u32;
+    unsafe { asm!("mrs {}, control", out(reg) control) }
+    control & 1 != 0
+}
+
+#[inline]
+pub fn irq_enable() {
+    unsafe { asm!("cpsie i") }
+}
+
+#[inline]
+pub fn wfi() {
+    unsafe { asm!("wfi") }
+}
+
+#[inline]
+pub fn barrier() {
+    unsafe { asm!("dsb", "isb") }
+}
@@ -0,0 +1,89 @@
+/*
+
+Device files, mounted on /dev.
+
+    console     writes are printed like `print!`; reads give end of file
+
+It gives unprivileged threads a way to the console through the write
+system call, since they can't touch the UARTs or their buffers themselves.
+
+ */
+
+extern crate posix;
+extern crate vfs;
+
+use alloc::{boxed::Box, format, string::String};
+use posix::Errno;
+use vfs::{DEntry, FileSystem, FsError, NodeId, NodeType, NODE_ID_ROOT};
+
+use crate::{fs, print};
+
+const NODE_ID_CONSOLE: NodeId = NODE_ID_ROOT + 1;
+
+struct DevFs;
+
+fn no_node(id: NodeId) -> FsError {
+    FsError::new(Errno::ENOENT, format!("No such node: id={}", id))
+}
+
+impl FileSystem for DevFs {
+    fn readdir(&self, dir: NodeId, pos: usize) -> Result<Option<(DEntry, NodeId)>, FsError> {
+        if dir != NODE_ID_ROOT {
+            return Err(FsError::new(
+                Errno::ENOTDIR,
+                format!("Not a directory: id={}", dir),
+            ));
+        }
+        Ok((pos == 0).then(|| {
+            let dent = DEntry {
+                name: String::from("console"),
+                ntype: NodeType::RegularFile,
+            };
+            (dent, NODE_ID_CONSOLE)
+        }))
+    }
+
+    fn create(&mut self, _dir: NodeId, dent: &DEntry) -> Result<NodeId, FsError> {
+        Err(FsError::new(
+            Errno::EROFS,
+            format!("Cannot create {} in devfs", dent.name),
+        ))
+    }
+
+    fn read(&self, file: NodeId, _off: usize, _data: &mut [u8]) -> Result<usize, FsError> {
+        match file {
+            NODE_ID_CONSOLE => Ok(0),
+            _ => Err(no_node(file)),
+        }
+    }
+
+    fn write(&mut self, file: NodeId, _off: usize, data: &[u8]) -> Result<usize, FsError> {
+        match file {
+            NODE_ID_CONSOLE => {
+                print!("{}", String::from_utf8_lossy(data));
+                Ok(data.len())
+            }
+            _ => Err(no_node(file)),
+        }
+    }
+
+    fn truncate(&mut self, file: NodeId, _len: usize) -> Result<(), FsError> {
+        match file {
+            NODE_ID_CONSOLE => Ok(()),
+            _ => Err(no_node(file)),
+        }
+    }
+
+    fn getsize(&self, file: NodeId) -> Result<usize, FsError> {
+        match file {
+            NODE_ID_CONSOLE => Ok(0),
+            _ => Err(no_node(file)),
+        }
+    }
+}
+
+/// Make `mountpoint` and mount the device files there
+pub fn mount(mountpoint: &str) -> Result<(), FsError> {
+    fs::mkdir(mountpoint)?;
+    fs::mount(mountpoint, Box::new(DevFs))
+}
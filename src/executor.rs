@@ -0,0 +1,228 @@
+/*
+
+Async executor.
+
+Tasks are futures kept in a fixed arena of MAX_TASKS slots; a slot's index
+is the task's ID. Their wakers only set the task's bit in the READY mask,
+so interrupt handlers can wake tasks, and the executor polls the tasks
+whose bits are set. When none is, it sleeps: on the "executor" thread by
+sleeping the thread, which lets the idle thread WFI, or with WFI itself if
+`run()` is called before the scheduler starts.
+
+Tasks share the executor's stack and only need heap for their futures, so
+protocol code can be written as straight-line `async fn`s without a thread
+for each. A task must not block; waiting is done by awaiting a future like
+`timer::sleep()` or `console::read()`.
+
+ */
+
+extern crate posix;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::time::Duration;
+use posix::Errno;
+
+use crate::sync::IrqSafeLock;
+use crate::{console, cpu, println, sched, shell_command, timer};
+
+pub type TaskId = usize;
+
+const MAX_TASKS: usize = 32;
+
+type TaskFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task {
+    name: &'static str,
+    /// Taken out while it is polled
+    future: Option<TaskFuture>,
+}
+
+const NO_TASK: Option<Task> = None;
+
+static TASKS: IrqSafeLock<[Option<Task>; MAX_TASKS]> = IrqSafeLock::new([NO_TASK; MAX_TASKS]);
+static READY: AtomicU32 = AtomicU32::new(0);
+// Thread running the executor; 0, the idle thread's ID, if none
+static RUNNER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init() {
+    sched::spawn("executor", || run());
+}
+
+/// Add a task, to be polled first by the executor's next round
+pub fn spawn<F>(name: &'static str, future: F) -> Result<TaskId, Errno>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let future: TaskFuture = Box::pin(future);
+    let id = {
+        let mut tasks = TASKS.lock();
+        let id = tasks
+            .iter()
+            .position(|t| t.is_none())
+            .ok_or(Errno::ENOSPC)?;
+        tasks[id] = Some(Task {
+            name,
+            future: Some(future),
+        });
+        id
+    };
+    wake(id);
+    Ok(id)
+}
+
+fn wake(id: TaskId) {
+    READY.fetch_or(1 << id, Ordering::Release);
+    let runner = RUNNER.load(Ordering::Relaxed);
+    if runner != 0 {
+        sched::wake(runner);
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_raw, wake_raw, wake_raw, drop_raw);
+
+fn raw_waker(id: TaskId) -> RawWaker {
+    RawWaker::new(id as *const (), &VTABLE)
+}
+
+unsafe fn clone_raw(data: *const ()) -> RawWaker {
+    raw_waker(data as TaskId)
+}
+
+unsafe fn wake_raw(data: *const ()) {
+    wake(data as TaskId)
+}
+
+unsafe fn drop_raw(_data: *const ()) {}
+
+/// Poll task `id` once, and drop it if it completed
+fn poll(id: TaskId) {
+    // A stale wake-up may hit an empty slot, or a newer task in it; futures
+    // put up with spurious polls.
+    let future = TASKS.lock()[id].as_mut().and_then(|t| t.future.take());
+    let mut future = match future {
+        Some(future) => future,
+        None => return,
+    };
+
+    let waker = unsafe { Waker::from_raw(raw_waker(id)) };
+    let mut cx = Context::from_waker(&waker);
+    match future.as_mut().poll(&mut cx) {
+        Poll::Ready(()) => {
+            TASKS.lock()[id] = None;
+            // dropped without the lock, it may cancel timers and such
+            drop(future);
+        }
+        Poll::Pending => {
+            if let Some(task) = TASKS.lock()[id].as_mut() {
+                task.future = Some(future);
+            }
+        }
+    }
+}
+
+/// Wait for a task to be woken
+fn idle() {
+    let primask = cpu::irq_save();
+    if READY.load(Ordering::Acquire) == 0 {
+        match sched::current() {
+            // the switch waits for IRQs to be unmasked, so wake() can't
+            // be missed
+            Some(_) => sched::sleep_until(u64::MAX),
+            // a pending IRQ ends WFI even while masked
+            None => cpu::wfi(),
+        }
+    }
+    cpu::irq_restore(primask);
+}
+
+/// Poll the tasks as they are woken, forever
+pub fn run() -> ! {
+    RUNNER.store(sched::current().unwrap_or(0), Ordering::Relaxed);
+    loop {
+        let ready = READY.swap(0, Ordering::Acquire);
+        if ready == 0 {
+            idle();
+            continue;
+        }
+        for id in 0..MAX_TASKS {
+            if ready & (1 << id) != 0 {
+                poll(id);
+            }
+        }
+    }
+}
+
+fn list() {
+    let tasks: Vec<_> = TASKS
+        .lock()
+        .iter()
+        .enumerate()
+        .filter_map(|(id, t)| t.as_ref().map(|t| (id, t.name)))
+        .collect();
+    let ready = READY.load(Ordering::Relaxed);
+
+    println!("  ID  READY  NAME");
+    for (id, name) in tasks {
+        let ready = if ready & (1 << id) != 0 { "yes" } else { "no" };
+        println!("{:4}  {:5}  {}", id, ready, name);
+    }
+}
+
+async fn echo(console: &'static dyn console::Console) {
+    let mut buf = [0; 32];
+    loop {
+        let len = console::read(console, &mut buf).await;
+        console::write(console, &buf[..len]).await;
+    }
+}
+
+async fn tick(period: Duration, count: u32) {
+    for i in 0..count {
+        timer::sleep(period).await;
+        println!("tick {}", i);
+    }
+}
+
+fn cmd_tasks(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] => list(),
+        [_, "echo", name] => {
+            if !console::UART_NAMES.contains(name) {
+                return Err(Errno::ENOENT);
+            }
+            let console = match console::claim(name) {
+                Ok(console) => console,
+                Err(Errno::EBUSY) => {
+                    println!("{} is in use, by the shell or another task", name);
+                    return Err(Errno::EBUSY);
+                }
+                Err(e) => return Err(e),
+            };
+            let id = spawn("echo", echo(console))?;
+            println!("task {}", id);
+        }
+        [_, "tick", ms, count] => {
+            let ms = ms.parse::<u64>().or(Err(Errno::EINVAL))?;
+            let count = count.parse::<u32>().or(Err(Errno::EINVAL))?;
+            let id = spawn("tick", tick(Duration::from_millis(ms), count))?;
+            println!("task {}", id);
+        }
+        _ => {
+            println!("usage: tasks [echo <uart> | tick <ms> <count>]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_TASKS,
+    "tasks",
+    "list the async tasks, or start an echo on a UART or a ticker",
+    cmd_tasks
+);
@@ -0,0 +1,149 @@
+/*
+
+Decoding of the fault status registers for the crash report.
+
+With `scb::enable_faults()`, MemManage, BusFault, UsageFault and SecureFault
+are taken as such; anything else escalates to HardFault, which then has
+FORCED set in HFSR and the original cause left in CFSR.
+
+[refs]
+- https://developer.arm.com/documentation/100235/0100/The-Cortex-M33-Peripherals/System-Control-Block
+
+ */
+
+use core::fmt;
+
+use crate::mpu;
+use crate::println;
+use crate::scb::{self, Cfsr, Hfsr, Sfsr};
+
+const CFSR_CAUSES: [(Cfsr, &str); 18] = [
+    (Cfsr::IACCVIOL, "MemManage: instruction access violation"),
+    (Cfsr::DACCVIOL, "MemManage: data access violation"),
+    (
+        Cfsr::MUNSTKERR,
+        "MemManage: fault on exception return unstacking",
+    ),
+    (
+        Cfsr::MSTKERR,
+        "MemManage: fault on exception entry stacking",
+    ),
+    (
+        Cfsr::MLSPERR,
+        "MemManage: fault on lazy FP state preservation",
+    ),
+    (Cfsr::IBUSERR, "BusFault: instruction bus error"),
+    (Cfsr::PRECISERR, "BusFault: precise data bus error"),
+    (Cfsr::IMPRECISERR, "BusFault: imprecise data bus error"),
+    (
+        Cfsr::UNSTKERR,
+        "BusFault: fault on exception return unstacking",
+    ),
+    (Cfsr::STKERR, "BusFault: fault on exception entry stacking"),
+    (
+        Cfsr::LSPERR,
+        "BusFault: fault on lazy FP state preservation",
+    ),
+    (Cfsr::UNDEFINSTR, "UsageFault: undefined instruction"),
+    (
+        Cfsr::INVSTATE,
+        "UsageFault: invalid state (EPSR.T cleared?)",
+    ),
+    (Cfsr::INVPC, "UsageFault: invalid EXC_RETURN"),
+    (Cfsr::NOCP, "UsageFault: coprocessor disabled or absent"),
+    (Cfsr::STKOF, "UsageFault: stack overflow (stack limit)"),
+    (Cfsr::UNALIGNED, "UsageFault: unaligned access"),
+    (Cfsr::DIVBYZERO, "UsageFault: division by zero"),
+];
+
+const HFSR_CAUSES: [(Hfsr, &str); 2] = [
+    (Hfsr::VECTTBL, "HardFault: vector table read error"),
+    (
+        Hfsr::FORCED,
+        "HardFault: escalated from a configurable fault",
+    ),
+];
+
+const SFSR_CAUSES: [(Sfsr, &str); 7] = [
+    (Sfsr::INVEP, "SecureFault: invalid Secure entry point"),
+    (Sfsr::INVIS, "SecureFault: invalid integrity signature"),
+    (Sfsr::INVER, "SecureFault: invalid exception return"),
+    (Sfsr::AUVIOL, "SecureFault: attribution unit violation"),
+    (Sfsr::INVTRAN, "SecureFault: invalid transition"),
+    (
+        Sfsr::LSPERR,
+        "SecureFault: fault on lazy FP state preservation",
+    ),
+    (Sfsr::LSERR, "SecureFault: lazy state error"),
+];
+
+/// Name of the exception number in IPSR
+pub struct ExceptionName(u32);
+
+impl fmt::Display for ExceptionName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.0 {
+            0 => "Thread mode",
+            1 => "Reset",
+            2 => "NMI",
+            3 => "HardFault",
+            4 => "MemManage",
+            5 => "BusFault",
+            6 => "UsageFault",
+            7 => "SecureFault",
+            11 => "SVCall",
+            12 => "DebugMonitor",
+            14 => "PendSV",
+            15 => "SysTick",
+            n if n >= 16 => return write!(f, "IRQ {}", n - 16),
+            _ => "Reserved",
+        };
+        f.write_str(name)
+    }
+}
+
+pub fn exception_name(ipsr: u32) -> ExceptionName {
+    ExceptionName(ipsr & 0x1ff)
+}
+
+/// Print the fault status registers and the causes and addresses they report
+pub fn report(ipsr: u32) {
+    let st = scb::fault_status();
+    let (cfsr, hfsr, sfsr) = (u32::from(st.cfsr), u32::from(st.hfsr), u32::from(st.sfsr));
+    if cfsr == 0 && hfsr == 0 && sfsr == 0 {
+        if (3..=7).contains(&(ipsr & 0x1ff)) {
+            println!("no fault status recorded");
+        }
+        return;
+    }
+
+    println!("CFSR: {:08x}  HFSR: {:08x}  SFSR: {:08x}", cfsr, hfsr, sfsr);
+    for (bit, cause) in HFSR_CAUSES {
+        if st.hfsr.is_set(bit) {
+            println!("  {}", cause);
+        }
+    }
+    for (bit, cause) in CFSR_CAUSES {
+        if st.cfsr.is_set(bit) {
+            println!("  {}", cause);
+        }
+    }
+    for (bit, cause) in SFSR_CAUSES {
+        if st.sfsr.is_set(bit) {
+            println!("  {}", cause);
+        }
+    }
+
+    if st.cfsr.is_set(Cfsr::MMARVALID) {
+        println!("  MemManage fault address: {:08x}", st.mmfar);
+        if let Some(what) = mpu::describe(st.mmfar as usize) {
+            println!("  {}", what);
+        }
+    }
+    if st.cfsr.is_set(Cfsr::BFARVALID) {
+        println!("  BusFault address: {:08x}", st.bfar);
+    }
+    if st.sfsr.is_set(Sfsr::SFARVALID) {
+        println!("  SecureFault address: {:08x}", st.sfar);
+    }
+}
@@ -0,0 +1,61 @@
+/*
+
+The kernel's VFS instance (see libs/vfs).
+
+It is behind a sleeping Mutex, since an operation can take long, e.g. a
+semihosting call to the host with hostfs, and interrupts must go on
+meanwhile. So only threads and system calls may use it, not interrupt
+handlers. A system call that finds it busy is restarted once it is free
+(see syscall.rs).
+
+ */
+
+extern crate vfs;
+
+use alloc::boxed::Box;
+use vfs::{DEntry, FileDescriptor, FileSystem, FsError, OpenMode, Vfs};
+
+use crate::sync::{Mutex, MutexGuard};
+
+static VFS: Mutex<Vfs> = Mutex::new(Vfs::new());
+
+pub fn init() {
+    VFS.lock().init()
+}
+
+/// Mount `filesystem` on `mountpoint`, which has to be an empty directory
+pub fn mount(mountpoint: &str, filesystem: Box<dyn FileSystem>) -> Result<(), FsError> {
+    VFS.lock().mount(mountpoint, filesystem)
+}
+
+pub fn open(path: &str, mode: OpenMode) -> Result<FileDescriptor, FsError> {
+    VFS.lock().open(path, mode)
+}
+
+pub fn read(fd: FileDescriptor, data: &mut [u8]) -> Result<usize, FsError> {
+    VFS.lock().read(fd, data)
+}
+
+#[allow(dead_code)]
+pub fn write(fd: FileDescriptor, data: &[u8]) -> Result<usize, FsError> {
+    VFS.lock().write(fd, data)
+}
+
+pub fn close(fd: FileDescriptor) -> Result<(), FsError> {
+    VFS.lock().close(fd)
+}
+
+pub fn mkdir(path: &str) -> Result<(), FsError> {
+    VFS.lock().mkdir(path)
+}
+
+#[allow(dead_code)]
+pub fn readdir(fd: FileDescriptor) -> Result<Option<DEntry>, FsError> {
+    VFS.lock().readdir(fd)
+}
+
+/// The VFS for a system call; None if a thread holds it, and then the
+/// caller is woken once it is released
+pub fn lock_for_syscall() -> Option<MutexGuard<'static, Vfs>> {
+    VFS.lock_or_queue()
+}
@@ -0,0 +1,613 @@
+/*
+
+GDB remote serial protocol stub, built with the "gdbstub" feature.
+
+It talks on UART1, polled, from the DebugMonitor exception: `bkpt`, single
+steps (DEMCR.MON_STEP) and ^C from GDB, which the UART1 RX interrupt turns
+into a pending DebugMonitor. Unhandled exceptions end up here too after
+their report, instead of shutting down.
+
+    $ cargo xtask run -- --features gdbstub    (UART1 on tcp port 3333)
+    $ arm-none-eabi-gdb target/thumbv8m.main-none-eabi/debug/barbara
+    (gdb) target remote :3333
+
+The registers are those of the interrupted code: r4-r11 where
+DefaultExceptionHandler pushed them, the others in the hardware frame.
+Changes are stored back there before the exception returns, except for sp,
+which can't move away from the frame.
+
+Breakpoints replace a halfword with `bkpt 0`. A `bkpt` that isn't one of
+them, like the panic policy's, is stepped over when reported so that
+continuing goes on after it. Stepping works where DebugMonitor can preempt;
+a `bkpt` where it can't escalates to HardFault and is reported all the same.
+
+Memory accesses are limited to the regions of the linker script and the
+System Control Space.
+
+[refs]
+- https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+ */
+
+extern crate posix;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use posix::Errno;
+
+use crate::arm_uart::{self, ArmUart};
+use crate::backtrace::{self, Regs};
+use crate::console::Console;
+use crate::scb::{self, Cfsr};
+use crate::sync::IrqSafeLock;
+use crate::{board, cpu, decl_c_symbol_addr, fault, info, mpu, println, semihosting};
+
+decl_c_symbol_addr!(__vector_s, vector_s);
+decl_c_symbol_addr!(__vector_e, vector_e);
+decl_c_symbol_addr!(__rom_s, rom_s);
+decl_c_symbol_addr!(__rom_e, rom_e);
+decl_c_symbol_addr!(__ram_s, ram_s);
+decl_c_symbol_addr!(__ram_e, ram_e);
+decl_c_symbol_addr!(__stack_s, stack_s);
+decl_c_symbol_addr!(__stack_e, stack_e);
+decl_c_symbol_addr!(__heap_s, heap_s);
+decl_c_symbol_addr!(__heap_e, heap_e);
+
+const SCS: (usize, usize) = (0xe000_e000, 0xe000_f000);
+
+// signals of the stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+const PACKET_SIZE: usize = 1024;
+const BREAKPOINT_COUNT: usize = 16;
+const BKPT: u16 = 0xbe00;
+const XPSR: usize = 16;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?>"#,
+    r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+    r#"<target version="1.0">"#,
+    r#"<architecture>arm</architecture>"#,
+    r#"<feature name="org.gnu.gdb.arm.m-profile">"#,
+    r#"<reg name="r0" bitsize="32"/>"#,
+    r#"<reg name="r1" bitsize="32"/>"#,
+    r#"<reg name="r2" bitsize="32"/>"#,
+    r#"<reg name="r3" bitsize="32"/>"#,
+    r#"<reg name="r4" bitsize="32"/>"#,
+    r#"<reg name="r5" bitsize="32"/>"#,
+    r#"<reg name="r6" bitsize="32"/>"#,
+    r#"<reg name="r7" bitsize="32"/>"#,
+    r#"<reg name="r8" bitsize="32"/>"#,
+    r#"<reg name="r9" bitsize="32"/>"#,
+    r#"<reg name="r10" bitsize="32"/>"#,
+    r#"<reg name="r11" bitsize="32"/>"#,
+    r#"<reg name="r12" bitsize="32"/>"#,
+    r#"<reg name="sp" bitsize="32" type="data_ptr"/>"#,
+    r#"<reg name="lr" bitsize="32"/>"#,
+    r#"<reg name="pc" bitsize="32" type="code_ptr"/>"#,
+    r#"<reg name="xpsr" bitsize="32"/>"#,
+    r#"</feature>"#,
+    r#"</target>"#,
+);
+
+fn uart() -> &'static ArmUart {
+    arm_uart::uart(board::DEBUG_UART).unwrap()
+}
+
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+static STEPPING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Address and original instruction of each breakpoint
+static BREAKPOINTS: IrqSafeLock<[Option<(usize, u16)>; BREAKPOINT_COUNT]> =
+    IrqSafeLock::new([None; BREAKPOINT_COUNT]);
+
+/// Set up UART1 and have `bkpt` and ^C raise DebugMonitor; needs the heap
+pub fn init() {
+    let uart = uart();
+    uart.init();
+    scb::enable_debug_monitor();
+
+    let res = uart.start_rx_irq_with(|byte| {
+        // anything else is a stray byte of a finished session
+        if byte == 0x03 {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            scb::pend_debug_monitor();
+        }
+    });
+    match res {
+        Ok(()) => info!("GDB stub listening on UART1"),
+        Err(e) => crate::error!("GDB stub can't take ^C: {:?}", e),
+    }
+}
+
+fn getc() -> u8 {
+    loop {
+        if let Some(byte) = uart().getc_polled() {
+            return byte;
+        }
+    }
+}
+
+fn putc(byte: u8) {
+    uart().putc_polled(byte)
+}
+
+fn hex_digit(v: u8) -> u8 {
+    b"0123456789abcdef"[(v & 0xf) as usize]
+}
+
+fn unhex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() {
+        return None;
+    }
+    s.iter().try_fold(0usize, |v, &b| {
+        v.checked_mul(16)?.checked_add(unhex(b)? as usize)
+    })
+}
+
+/// "<addr>,<len>"
+fn parse_range(s: &[u8]) -> Option<(usize, usize)> {
+    let comma = s.iter().position(|&b| b == b',')?;
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
+
+/// Little-endian word of 8 hex digits
+fn parse_word(s: &[u8]) -> Option<u32> {
+    let mut bytes = [0u8; 4];
+    decode_hex(s, &mut bytes)?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn decode_hex(s: &[u8], out: &mut [u8]) -> Option<()> {
+    if s.len() != out.len() * 2 {
+        return None;
+    }
+    for (o, pair) in out.iter_mut().zip(s.chunks(2)) {
+        *o = unhex(pair[0])? << 4 | unhex(pair[1])?;
+    }
+    Some(())
+}
+
+/// Wait for a packet with a good checksum, acknowledge it, and return its
+/// length in `buf`
+fn recv(buf: &mut [u8; PACKET_SIZE]) -> usize {
+    loop {
+        while getc() != b'$' {}
+
+        let mut len = 0;
+        let mut sum: u8 = 0;
+        let mut overflow = false;
+        loop {
+            let byte = getc();
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            if len < PACKET_SIZE {
+                buf[len] = byte;
+                len += 1;
+            } else {
+                overflow = true;
+            }
+        }
+
+        let check = (unhex(getc()), unhex(getc()));
+        match check {
+            (Some(hi), Some(lo)) if hi << 4 | lo == sum && !overflow => {
+                putc(b'+');
+                return len;
+            }
+            _ => putc(b'-'),
+        }
+    }
+}
+
+/// Send a packet until GDB acknowledges it
+fn send(data: &[u8]) {
+    loop {
+        let mut sum: u8 = 0;
+        putc(b'$');
+        for &byte in data {
+            putc(byte);
+            sum = sum.wrapping_add(byte);
+        }
+        putc(b'#');
+        putc(hex_digit(sum >> 4));
+        putc(hex_digit(sum));
+
+        loop {
+            match getc() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A reply being put together
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(hex_digit(byte >> 4));
+            self.push(hex_digit(byte));
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+fn regions() -> [(usize, usize); 6] {
+    [
+        (vector_s(), vector_e()),
+        (rom_s(), rom_e()),
+        (ram_s(), ram_e()),
+        (stack_s(), stack_e()),
+        (heap_s(), heap_e()),
+        SCS,
+    ]
+}
+
+/// Whether `len` bytes at `addr` are all in one of the regions
+fn accessible(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => regions().iter().any(|&(s, e)| s <= addr && end <= e),
+        None => false,
+    }
+}
+
+// whole words where possible, since some SCS registers take nothing else
+fn read_mem(addr: usize, out: &mut [u8]) {
+    if addr % 4 == 0 && out.len() % 4 == 0 {
+        for (i, chunk) in out.chunks_mut(4).enumerate() {
+            let v = unsafe { ((addr + i * 4) as *const u32).read_volatile() };
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+    } else {
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = unsafe { ((addr + i) as *const u8).read_volatile() };
+        }
+    }
+}
+
+fn write_mem(addr: usize, data: &[u8]) {
+    if addr % 4 == 0 && data.len() % 4 == 0 {
+        for (i, chunk) in data.chunks(4).enumerate() {
+            let v = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            unsafe { ((addr + i * 4) as *mut u32).write_volatile(v) };
+        }
+    } else {
+        for (i, &byte) in data.iter().enumerate() {
+            unsafe { ((addr + i) as *mut u8).write_volatile(byte) };
+        }
+    }
+    // the code may have changed
+    cpu::barrier();
+}
+
+fn is_bkpt(addr: usize) -> bool {
+    accessible(addr, 2) && unsafe { (addr as *const u16).read_volatile() } >> 8 == 0xbe
+}
+
+fn is_breakpoint(addr: usize) -> bool {
+    BREAKPOINTS.lock().iter().flatten().any(|&(a, _)| a == addr)
+}
+
+fn insert_breakpoint(addr: usize) -> Result<(), Errno> {
+    if addr % 2 != 0 || !accessible(addr, 2) {
+        return Err(Errno::EFAULT);
+    }
+    if is_breakpoint(addr) {
+        return Ok(());
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints.iter_mut().find(|b| b.is_none());
+    let slot = slot.ok_or(Errno::ENOSPC)?;
+    let insn = addr as *mut u16;
+    unsafe {
+        *slot = Some((addr, insn.read_volatile()));
+        insn.write_volatile(BKPT);
+    }
+    cpu::barrier();
+    Ok(())
+}
+
+fn remove_breakpoint(addr: usize) -> Result<(), Errno> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints
+        .iter_mut()
+        .find(|b| matches!(b, Some((a, _)) if *a == addr));
+    let slot = slot.ok_or(Errno::ENOENT)?;
+    if let Some((addr, insn)) = slot.take() {
+        unsafe { (addr as *mut u16).write_volatile(insn) };
+    }
+    cpu::barrier();
+    Ok(())
+}
+
+fn remove_all_breakpoints() {
+    for slot in BREAKPOINTS.lock().iter_mut() {
+        if let Some((addr, insn)) = slot.take() {
+            unsafe { (addr as *mut u16).write_volatile(insn) };
+        }
+    }
+    cpu::barrier();
+}
+
+/// Signal of the stop by exception `exception`; a `bkpt` of the code is
+/// stepped over
+fn stop_signal(exception: u32, regs: &mut Regs) -> u8 {
+    let stepping = STEPPING.swap(false, Ordering::Relaxed);
+    let interrupted = INTERRUPTED.swap(false, Ordering::Relaxed);
+    let pc = regs.r[ehabi::PC] as usize;
+
+    match exception {
+        12 if stepping => SIGTRAP,
+        3 | 12 if is_bkpt(pc) => {
+            if !is_breakpoint(pc) {
+                regs.r[ehabi::PC] += 2;
+            }
+            SIGTRAP
+        }
+        12 if interrupted => SIGINT,
+        12 => SIGTRAP,
+        5 => SIGBUS,
+        6 if scb::fault_status().cfsr.is_set(Cfsr::DIVBYZERO) => SIGFPE,
+        6 => SIGILL,
+        3..=7 => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+enum Resume {
+    Continue,
+    Step,
+}
+
+/// The interrupted code, as GDB sees it
+struct Stop {
+    regs: Regs,
+    xpsr: u32,
+    signal: u8,
+}
+
+impl Stop {
+    fn reg(&self, n: usize) -> Option<u32> {
+        match n {
+            0..=15 => Some(self.regs.r[n]),
+            XPSR => Some(self.xpsr),
+            _ => None,
+        }
+    }
+
+    fn set_reg(&mut self, n: usize, v: u32) -> Result<(), Errno> {
+        match n {
+            ehabi::SP if v != self.regs.r[ehabi::SP] => return Err(Errno::EINVAL),
+            0..=15 => self.regs.r[n] = v,
+            XPSR => self.xpsr = v,
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+}
+
+fn handle(packet: &[u8], stop: &mut Stop, reply: &mut Reply) -> Result<Option<Resume>, Errno> {
+    let (&cmd, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+
+    match cmd {
+        b'?' => {
+            let _ = write!(reply, "S{:02x}", stop.signal);
+        }
+        b'g' => {
+            for n in 0..=XPSR {
+                reply.hex(&stop.reg(n).unwrap_or(0).to_le_bytes());
+            }
+        }
+        b'G' => {
+            if args.len() != (XPSR + 1) * 8 {
+                return Err(Errno::EINVAL);
+            }
+            for (n, word) in args.chunks(8).enumerate() {
+                let v = parse_word(word).ok_or(Errno::EINVAL)?;
+                // GDB writes sp back as it read it
+                if n != ehabi::SP {
+                    stop.set_reg(n, v)?;
+                }
+            }
+            let _ = reply.write_str("OK");
+        }
+        b'p' => {
+            let n = parse_hex(args).ok_or(Errno::EINVAL)?;
+            let v = stop.reg(n).ok_or(Errno::EINVAL)?;
+            reply.hex(&v.to_le_bytes());
+        }
+        b'P' => {
+            let eq = args.iter().position(|&b| b == b'=').ok_or(Errno::EINVAL)?;
+            let n = parse_hex(&args[..eq]).ok_or(Errno::EINVAL)?;
+            let v = parse_word(&args[eq + 1..]).ok_or(Errno::EINVAL)?;
+            stop.set_reg(n, v)?;
+            let _ = reply.write_str("OK");
+        }
+        b'm' => {
+            let (addr, len) = parse_range(args).ok_or(Errno::EINVAL)?;
+            let len = len.min(PACKET_SIZE / 2);
+            if !accessible(addr, len) {
+                return Err(Errno::EFAULT);
+            }
+            let mut data = [0u8; PACKET_SIZE / 2];
+            read_mem(addr, &mut data[..len]);
+            reply.hex(&data[..len]);
+        }
+        b'M' => {
+            let colon = args.iter().position(|&b| b == b':').ok_or(Errno::EINVAL)?;
+            let (addr, len) = parse_range(&args[..colon]).ok_or(Errno::EINVAL)?;
+            if len > PACKET_SIZE / 2 {
+                return Err(Errno::EINVAL);
+            }
+            if !accessible(addr, len) {
+                return Err(Errno::EFAULT);
+            }
+            let mut data = [0u8; PACKET_SIZE / 2];
+            decode_hex(&args[colon + 1..], &mut data[..len]).ok_or(Errno::EINVAL)?;
+            write_mem(addr, &data[..len]);
+            let _ = reply.write_str("OK");
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                stop.regs.r[ehabi::PC] = addr as u32;
+            }
+            return Ok(Some(match cmd {
+                b'c' => Resume::Continue,
+                _ => Resume::Step,
+            }));
+        }
+        // software breakpoints only: "Z0,<addr>,<kind>"
+        b'Z' | b'z' if args.starts_with(b"0,") => {
+            let (addr, _kind) = parse_range(&args[2..]).ok_or(Errno::EINVAL)?;
+            match cmd {
+                b'Z' => insert_breakpoint(addr)?,
+                _ => remove_breakpoint(addr)?,
+            }
+            let _ = reply.write_str("OK");
+        }
+        b'H' => {
+            let _ = reply.write_str("OK");
+        }
+        b'D' => {
+            remove_all_breakpoints();
+            ATTACHED.store(false, Ordering::Relaxed);
+            let _ = reply.write_str("OK");
+            return Ok(Some(Resume::Continue));
+        }
+        b'k' => {
+            println!("gdbstub: killed");
+            semihosting::exit(1)
+        }
+        b'q' if args.starts_with(b"Supported") => {
+            let _ = write!(reply, "PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+        }
+        b'q' if args.starts_with(b"Attached") => {
+            let _ = reply.write_str("1");
+        }
+        b'q' if args.starts_with(b"Xfer:features:read:target.xml:") => {
+            let range = &args[b"Xfer:features:read:target.xml:".len()..];
+            let (off, len) = parse_range(range).ok_or(Errno::EINVAL)?;
+            let xml = TARGET_XML.as_bytes();
+            let start = off.min(xml.len());
+            let end = start + len.min(PACKET_SIZE - 1).min(xml.len() - start);
+            reply.push(if end == xml.len() { b'l' } else { b'm' });
+            for &byte in &xml[start..end] {
+                reply.push(byte);
+            }
+        }
+        // unsupported: an empty reply
+        _ => {}
+    }
+    Ok(None)
+}
+
+/// Serve GDB about the code stopped by exception `ipsr`, until it resumes
+/// it. r4-r11 are at `callee` and the others in the hardware frame at
+/// `frame`, where the changes GDB made are stored back.
+pub fn enter(callee: *mut [u32; 8], frame: usize, exc_return: u32, ipsr: u32) {
+    scb::set_monitor_step(false);
+    // GDB may read the guards and write breakpoints into ROM
+    let mpu_on = mpu::suspend();
+
+    let mut regs = Regs::default();
+    regs.r[4..12].copy_from_slice(unsafe { &*callee });
+    let xpsr = match backtrace::unstack(exc_return, frame, &mut regs) {
+        Some(xpsr) => xpsr,
+        None => {
+            println!("gdbstub: exception frame at {:08x} is not readable", frame);
+            semihosting::exit(1)
+        }
+    };
+    let signal = stop_signal(ipsr & 0x1ff, &mut regs);
+    let mut stop = Stop { regs, xpsr, signal };
+
+    let mut reply = Reply {
+        buf: [0; PACKET_SIZE],
+        len: 0,
+    };
+    if ATTACHED.load(Ordering::Relaxed) {
+        let _ = write!(reply, "S{:02x}", signal);
+        send(reply.data());
+    } else {
+        println!(
+            "gdbstub: {} at {:08x}, waiting for GDB on UART1",
+            fault::exception_name(ipsr),
+            stop.regs.r[ehabi::PC]
+        );
+    }
+
+    let mut buf = [0u8; PACKET_SIZE];
+    let resume = loop {
+        let len = recv(&mut buf);
+        ATTACHED.store(true, Ordering::Relaxed);
+
+        reply.len = 0;
+        match handle(&buf[..len], &mut stop, &mut reply) {
+            Ok(Some(resume)) => {
+                if reply.len > 0 {
+                    send(reply.data());
+                }
+                break resume;
+            }
+            Ok(None) => send(reply.data()),
+            Err(e) => {
+                reply.len = 0;
+                let _ = write!(reply, "E{:02x}", e as i32);
+                send(reply.data());
+            }
+        }
+    };
+
+    if backtrace::restack(exc_return, frame, &stop.regs, stop.xpsr).is_none() {
+        println!("gdbstub: can't write the exception frame at {:08x}", frame);
+    }
+    unsafe { (*callee).copy_from_slice(&stop.regs.r[4..12]) };
+
+    // a fault would be reported again with the old status otherwise
+    scb::clear_fault_status();
+    mpu::resume(mpu_on);
+    if let Resume::Step = resume {
+        STEPPING.store(true, Ordering::Relaxed);
+        scb::set_monitor_step(true);
+    }
+}
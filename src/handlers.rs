@@ -1,4 +1,10 @@
+extern crate posix;
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use core::{arch::asm, panic::PanicInfo, ptr};
+use posix::Errno;
+
+use crate::{println, shell_command};
 
 #[macro_export]
 macro_rules! decl_c_symbol_addr {
@@ -17,7 +23,7 @@ decl_c_symbol_addr!(__bss_s, bss_s);
 decl_c_symbol_addr!(__bss_e, bss_e);
 decl_c_symbol_addr!(__data_s, data_s);
 decl_c_symbol_addr!(__data_e, data_e);
-decl_c_symbol_addr!(__rodata_s, rodata_s);
+decl_c_symbol_addr!(__data_load, data_load);
 
 #[no_mangle]
 unsafe extern "C" fn __reset() {
@@ -28,31 +34,148 @@ unsafe extern "C" fn __reset() {
     ptr::write_bytes(bss_s() as *mut u8, 0, size);
 
     let size = data_e() - data_s();
-    ptr::copy_nonoverlapping(rodata_s() as *const u8, data_s() as *mut u8, size);
+    ptr::copy_nonoverlapping(data_load() as *const u8, data_s() as *mut u8, size);
 
     use crate::main;
     main()
 }
 
+/// What to do after reporting a panic
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PanicPolicy {
+    /// Exit the emulator with status 101
+    Shutdown,
+    /// Mask interrupts and sleep forever
+    Halt,
+    /// Stop in the debugger with `bkpt 0x80`
+    Breakpoint,
+}
+
+impl PanicPolicy {
+    const ALL: [PanicPolicy; 3] = [Self::Shutdown, Self::Halt, Self::Breakpoint];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Shutdown => "poweroff",
+            Self::Halt => "halt",
+            Self::Breakpoint => "bkpt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|p| p.name() == name)
+    }
+}
+
+/// Index of the policy in PanicPolicy::ALL
+static PANIC_POLICY: AtomicU8 = AtomicU8::new(0);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_panic_policy(policy: PanicPolicy) {
+    let idx = PanicPolicy::ALL.iter().position(|p| *p == policy).unwrap();
+    PANIC_POLICY.store(idx as u8, Ordering::Relaxed);
+}
+
+pub fn panic_policy() -> PanicPolicy {
+    PanicPolicy::ALL[PANIC_POLICY.load(Ordering::Relaxed) as usize]
+}
+
+fn panic_stop(policy: PanicPolicy) -> ! {
+    use crate::{console, semihosting};
+    console::flush();
+    match policy {
+        PanicPolicy::Shutdown => semihosting::exit(101),
+        PanicPolicy::Breakpoint => unsafe { asm!("bkpt 0x80") },
+        PanicPolicy::Halt => {}
+    }
+    loop {
+        unsafe { asm!("wfi") }
+    }
+}
+
 #[panic_handler]
 fn panic(panic_info: &PanicInfo) -> ! {
-    use crate::println;
-    if let Some(message) = panic_info.message() {
-        println!("{}", *message);
+    use crate::{backtrace, coredump, cpu, log, sched};
+
+    // r4-r11 as the panicking code left them, sp, lr and pc in this handler
+    let regs = backtrace::current_regs();
+    cpu::irq_save();
+
+    // a panic while reporting one: don't try again
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        println!("panic while panicking");
+        panic_stop(panic_policy());
+    }
+
+    log::flush_console();
+
+    println!("==== KERNEL PANIC ====");
+    match panic_info.message() {
+        Some(message) => println!("panicked: {}", *message),
+        None => println!("panicked"),
     }
     if let Some(location) = panic_info.location() {
-        println!("location: {}:{}", location.file(), location.line(),);
+        println!("location: {}:{}", location.file(), location.line());
+    }
+    if let Some(name) = sched::current_name() {
+        println!("thread: {}", name);
+    }
+
+    let r = &regs.r;
+    println!("pc : {:08x}  lr : {:08x}", regs.pc(), regs.lr());
+    println!("sp : {:08x}", regs.sp());
+    println!("r11: {:08x}  r10: {:08x}", r[11], r[10]);
+    println!("r9 : {:08x}  r8 : {:08x}", r[9], r[8]);
+    println!("r7 : {:08x}  r6 : {:08x}", r[7], r[6]);
+    println!("r5 : {:08x}  r4 : {:08x}", r[5], r[4]);
+
+    println!();
+    println!("Backtrace:");
+    backtrace::trace(16, backtrace::print_frame);
+
+    let xpsr: u32;
+    unsafe { asm!("mrs {}, xpsr", out(reg) xpsr) };
+    match panic_info.message() {
+        Some(message) => coredump::dump(&regs, xpsr, 0, 0, format_args!("panicked: {}", message)),
+        None => coredump::dump(&regs, xpsr, 0, 0, format_args!("panicked")),
     }
 
-    unsafe { asm!("bkpt 0x80") }
-    loop {}
+    let policy = panic_policy();
+    println!("panic policy: {}", policy.name());
+    panic_stop(policy)
+}
+
+fn cmd_panicpolicy(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] => println!("{}", panic_policy().name()),
+        [_, name] => set_panic_policy(PanicPolicy::from_name(name).ok_or(Errno::EINVAL)?),
+        _ => {
+            println!("usage: panicpolicy [poweroff|halt|bkpt]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
 }
 
+shell_command!(
+    CMD_PANICPOLICY,
+    "panicpolicy",
+    "show or set what to do on a panic",
+    cmd_panicpolicy
+);
+
+#[derive(Clone, Copy)]
 union Vector {
     reserved: u32,
     handler: unsafe extern "C" fn(),
 }
 
+#[repr(C)]
+struct VectorTable {
+    exceptions: [Vector; 16],
+    irqs: [Vector; board::IRQ_COUNT],
+}
+
 extern "C" {
     fn __stack_e();
     fn __nmi();
@@ -67,103 +190,90 @@ extern "C" {
     fn __systick();
 }
 
+use crate::{board, irq::__irq};
+
 #[no_mangle]
 #[link_section = ".vector_table"]
-static __vector_table: [Vector; 16] = [
-    Vector { handler: __stack_e }, // initial sp
-    Vector { handler: __reset },
-    Vector { handler: __nmi },
-    Vector {
-        handler: __hardfault,
-    },
-    Vector {
-        handler: __memmanage,
-    },
-    Vector {
-        handler: __busfault,
-    },
-    Vector {
-        handler: __usagefault,
-    },
-    Vector {
-        handler: __securefault,
-    },
-    Vector { reserved: 0 },
-    Vector { reserved: 0 },
-    Vector { reserved: 0 },
-    Vector { handler: __svc },
-    Vector {
-        handler: __debugmon,
-    },
-    Vector { reserved: 0 },
-    Vector { handler: __pendsv },
-    Vector { handler: __systick },
-];
-
-struct ExceptionRegs {
-    r13: u32,
-    r4: u32,
-    r5: u32,
-    r6: u32,
-    r7: u32,
-    r8: u32,
-    r9: u32,
-    r10: u32,
-    r11: u32,
-    r0: u32,
-    r1: u32,
-    r2: u32,
-    r3: u32,
-    r12: u32,
-    r14: u32,
-    return_address: u32,
-    pstate: u32,
-}
+static __vector_table: VectorTable = VectorTable {
+    exceptions: [
+        Vector { handler: __stack_e }, // initial sp
+        Vector { handler: __reset },
+        Vector { handler: __nmi },
+        Vector {
+            handler: __hardfault,
+        },
+        Vector {
+            handler: __memmanage,
+        },
+        Vector {
+            handler: __busfault,
+        },
+        Vector {
+            handler: __usagefault,
+        },
+        Vector {
+            handler: __securefault,
+        },
+        Vector { reserved: 0 },
+        Vector { reserved: 0 },
+        Vector { reserved: 0 },
+        Vector { handler: __svc },
+        Vector {
+            handler: __debugmon,
+        },
+        Vector { reserved: 0 },
+        Vector { handler: __pendsv },
+        Vector { handler: __systick },
+    ],
+    irqs: [Vector { handler: __irq }; board::IRQ_COUNT],
+};
 
 #[no_mangle]
 #[naked]
 unsafe extern "C" fn DefaultExceptionHandler() {
     /*
 
-    +----------------+  <- Next SP
-    |    R13 (SP)    |
-    |       R4       |
-    |       R5       |
-    |       R6       |
-    |       R7       |
-    |       R8       |
-    |       R9       |
-    |       R10      |
-    |       R11      |
-    +----------------+  <- Current SP
-    |       R0       |
-    |       R1       |
-    |       R2       |
-    |       R3       |
-    |       R12      |
-    |    R14 (LR)    |
-    | Return address |
-    |      xPSR      |
-    +----------------+  <- Previous SP
-    |                |
-    +----------------+
+    The hardware frame is on the stack that was in use when the exception
+    was taken, PSP for threads; EXC_RETURN in LR tells which.
+
+    MSP                                 MSP or PSP
+    +----------------+  <- r0           +----------------+  <- r1
+    |       R4       |                  |       R0       |
+    |       R5       |                  |       R1       |
+    |       R6       |                  |       R2       |
+    |       R7       |                  |       R3       |
+    |       R8       |                  |       R12      |
+    |       R9       |                  |    R14 (LR)    |
+    |      R10       |                  | Return address |
+    |      R11       |                  |      xPSR      |
+    +----------------+                  +----------------+  <- Previous SP
+                                                               (+ FP context,
+                                                                alignment pad)
 
      */
 
     asm!(
-        // calculate previous SP -> r3
-        "mov r3, sp",
-        "add r3, r3, #32",
-        "stmfd sp!, {{r3-r11}}",
+        "tst lr, #4",
+        "ite eq",
+        "mrseq r1, msp",
+        "mrsne r1, psp",
+        "push {{r4-r11}}",
         "mov r0, sp",
-        "b __unhandled_exception",
+        "mov r2, lr",
+        // EXC_RETURN, twice to keep sp 8-byte aligned
+        "push {{r2, lr}}",
+        "bl __unhandled_exception",
+        // back only if the GDB stub resumes the code
+        "pop {{r2, lr}}",
+        "pop {{r4-r11}}",
+        "bx lr",
         options(noreturn)
     )
 }
 
 #[no_mangle]
-unsafe extern "C" fn __unhandled_exception(regs_addr: usize) {
-    let ref regs: ExceptionRegs = *(regs_addr as *const ExceptionRegs);
+unsafe extern "C" fn __unhandled_exception(callee: *mut [u32; 8], frame: usize, exc_return: u32) {
+    use crate::backtrace::{self, Regs, EXC_RETURN_MODE, EXC_RETURN_SPSEL};
 
     let ipsr: u32;
 
@@ -172,39 +282,86 @@ unsafe extern "C" fn __unhandled_exception(regs_addr: usize) {
         out(reg) ipsr,
     );
 
-    use crate::println;
+    // breakpoint, step or ^C
+    #[cfg(feature = "gdbstub")]
+    if ipsr & 0x1ff == 12 {
+        use crate::gdbstub;
+        return gdbstub::enter(callee, frame, exc_return, ipsr);
+    }
+
+    let mut regs = Regs::default();
+    regs.r[4..12].copy_from_slice(&*callee);
+    let pstate = backtrace::unstack(exc_return, frame, &mut regs);
+
+    use crate::{fault, log};
+
+    // records logged from interrupt handlers may not have been printed yet
+    log::flush_console();
 
     println!("==== KERNEL PANIC ====");
-    println!("Unhandled exception: ipsr={:08x}", ipsr);
-    println!("pc : {:08x}  lr : {:08x}", regs.return_address, regs.r14);
-    println!("sp : {:08x}  r12: {:08x}", regs.r13, regs.r12);
-    println!("r11: {:08x}  r10: {:08x}", regs.r11, regs.r10);
-    println!("r9 : {:08x}  r8 : {:08x}", regs.r9, regs.r8);
-    println!("r7 : {:08x}  r6 : {:08x}", regs.r7, regs.r6);
-    println!("r5 : {:08x}  r4 : {:08x}", regs.r5, regs.r4);
-    println!("r3 : {:08x}  r2 : {:08x}", regs.r3, regs.r2);
-    println!("r1 : {:08x}  r0 : {:08x}", regs.r1, regs.r0);
-    println!("pstate : {:08x}", regs.pstate);
+    println!(
+        "Unhandled exception: ipsr={:08x} ({})",
+        ipsr,
+        fault::exception_name(ipsr)
+    );
+    fault::report(ipsr);
+    println!(
+        "EXC_RETURN: {:08x} ({} mode, {} active)",
+        exc_return,
+        if exc_return & EXC_RETURN_MODE != 0 {
+            "Thread"
+        } else {
+            "Handler"
+        },
+        if exc_return & EXC_RETURN_SPSEL != 0 {
+            "PSP"
+        } else {
+            "MSP"
+        },
+    );
+    let pstate = match pstate {
+        Some(pstate) => pstate,
+        None => {
+            println!("exception frame at {:08x} is not readable", frame);
+            0
+        }
+    };
+    let r = &regs.r;
+    println!("pc : {:08x}  lr : {:08x}", r[15], r[14]);
+    println!("sp : {:08x}  r12: {:08x}", r[13], r[12]);
+    println!("r11: {:08x}  r10: {:08x}", r[11], r[10]);
+    println!("r9 : {:08x}  r8 : {:08x}", r[9], r[8]);
+    println!("r7 : {:08x}  r6 : {:08x}", r[7], r[6]);
+    println!("r5 : {:08x}  r4 : {:08x}", r[5], r[4]);
+    println!("r3 : {:08x}  r2 : {:08x}", r[3], r[2]);
+    println!("r1 : {:08x}  r0 : {:08x}", r[1], r[0]);
+    println!("pstate : {:08x}", pstate);
 
     println!();
     println!("Backtrace:");
-    use crate::backtrace;
-    backtrace::unwind_walk(
-        regs.return_address as usize,
-        regs.r7 as usize,
-        10,
-        |addr: usize| {
-            use crate::kallsyms;
-            let mut buf: [u8; 128] = [0; 128];
-            match kallsyms::safe_search(addr, &mut buf) {
-                Some((name, off)) => println!("  {:08x}  {} +{:#x}", addr, name, off),
-                None => println!("  {:08x}", addr),
-            }
-        },
+    backtrace::unwind_walk(&regs, pstate & 0x1ff, 16, backtrace::print_frame);
+
+    use crate::coredump;
+    coredump::dump(
+        &regs,
+        pstate,
+        ipsr & 0x1ff,
+        exc_return,
+        format_args!("{}", fault::exception_name(ipsr)),
     );
 
-    use crate::semihosting;
-    semihosting::shutdown();
+    use crate::console;
+    console::flush();
+
+    #[cfg(feature = "gdbstub")]
+    {
+        use crate::gdbstub;
+        gdbstub::enter(callee, frame, exc_return, ipsr);
+    }
 
-    loop {}
+    #[cfg(not(feature = "gdbstub"))]
+    {
+        use crate::semihosting;
+        semihosting::exit(1)
+    }
 }
@@ -1,20 +1,44 @@
 extern crate alloc;
 extern crate linked_list_allocator;
-use alloc::alloc::Layout;
+use alloc::alloc::{GlobalAlloc, Layout};
 
 use crate::decl_c_symbol_addr;
+use crate::sync::IrqSafeLock;
 decl_c_symbol_addr!(__heap_s, heap_s);
 decl_c_symbol_addr!(__heap_e, heap_e);
 
+pub use linked_list_allocator::HeapStats;
 use linked_list_allocator::LinkedListAllocator;
+
+/// The allocator behind a lock, so that interrupt handlers may allocate too
+struct Heap(IrqSafeLock<LinkedListAllocator>);
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(ptr, layout)
+    }
+}
+
 #[global_allocator]
-static mut HEAP: LinkedListAllocator = LinkedListAllocator::new();
+static HEAP: Heap = Heap(IrqSafeLock::new(LinkedListAllocator::new()));
 
 pub fn init() {
-    unsafe { HEAP.init(heap_s(), heap_e()) };
+    HEAP.0.lock().init(heap_s(), heap_e());
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.0.lock().stats()
 }
 
 #[alloc_error_handler]
-fn alloc_error(_: Layout) -> ! {
-    panic!("OOM error");
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "OOM error: {} bytes, align {}",
+        layout.size(),
+        layout.align()
+    );
 }
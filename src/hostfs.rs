@@ -0,0 +1,343 @@
+/*
+
+Filesystem on the host, reached through semihosting file operations.
+
+Semihosting can neither list directories nor tell them from files, so the
+tree is taken from an optional manifest file in the host directory, with
+one path per line, relative to that directory; directories end with '/':
+
+    # comment
+    fixtures/
+    fixtures/input.bin
+    logs/
+
+Names missing from the manifest are probed on the host when looked up. A
+name the host opens but fails to read with EISDIR is taken as a directory.
+Creating directories is not possible.
+
+Names are passed to the host as they are, so ".", ".." and names with a
+path separator are refused; they could reach outside the host directory.
+
+ */
+
+extern crate posix;
+extern crate vfs;
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, format, string::String, vec::Vec};
+use core::cell::RefCell;
+use posix::Errno;
+use vfs::{DEntry, FileSystem, FsError, NodeId, NodeType, NODE_ID_ROOT};
+
+use crate::semihosting::{self, Handle, Mode};
+use crate::{fs, warn};
+
+pub const MANIFEST: &str = "hostfs.manifest";
+
+/// Whether `name` stays in its directory on the host
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+struct HostNode {
+    path: String, // relative to the root
+    name: String,
+    ntype: NodeType,
+    children: Vec<NodeId>,
+}
+
+impl HostNode {
+    fn child_path(&self, name: &str) -> String {
+        if self.path.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", self.path, name)
+        }
+    }
+}
+
+struct Nodes {
+    map: BTreeMap<NodeId, HostNode>,
+    next_id: NodeId,
+}
+
+impl Nodes {
+    fn get(&self, id: NodeId) -> Result<&HostNode, FsError> {
+        self.map.get(&id).ok_or(FsError::new(
+            Errno::ENOENT,
+            format!("No such node: id={}", id),
+        ))
+    }
+
+    fn child(&self, dir: NodeId, name: &str) -> Option<NodeId> {
+        let dir = self.map.get(&dir)?;
+        dir.children
+            .iter()
+            .copied()
+            .find(|id| self.map[id].name == name)
+    }
+
+    fn add(&mut self, dir: NodeId, name: &str, ntype: NodeType) -> NodeId {
+        if let Some(id) = self.child(dir, name) {
+            return id;
+        }
+
+        let parent = self.map.get_mut(&dir).unwrap();
+        let path = parent.child_path(name);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        parent.children.push(id);
+        self.map.insert(
+            id,
+            HostNode {
+                path,
+                name: String::from(name),
+                ntype,
+                children: Vec::new(),
+            },
+        );
+        id
+    }
+}
+
+pub struct HostFs {
+    root: String,
+    nodes: RefCell<Nodes>,
+}
+
+fn host_error(errno: Errno, path: &str) -> FsError {
+    FsError::new(errno, format!("Host file error: {}: {:?}", path, errno))
+}
+
+impl HostFs {
+    /// Serve the host directory `root`, with the tree listed in `manifest`
+    /// (relative to `root`) if it exists
+    pub fn new(root: &str, manifest: Option<&str>) -> Result<Self, FsError> {
+        let mut map = BTreeMap::new();
+        map.insert(
+            NODE_ID_ROOT,
+            HostNode {
+                path: String::new(),
+                name: String::new(),
+                ntype: NodeType::Directory,
+                children: Vec::new(),
+            },
+        );
+
+        let fs = Self {
+            root: String::from(root),
+            nodes: RefCell::new(Nodes {
+                map,
+                next_id: NODE_ID_ROOT + 1,
+            }),
+        };
+
+        if let Some(manifest) = manifest {
+            match fs.load_manifest(manifest) {
+                Err(e) if e.errno() == Errno::ENOENT => {}
+                res => res?,
+            }
+        }
+        Ok(fs)
+    }
+
+    fn host_path(&self, path: &str) -> String {
+        match (self.root.is_empty(), path.is_empty()) {
+            (true, _) => String::from(path),
+            (false, true) => self.root.clone(),
+            (false, false) => format!("{}/{}", self.root, path),
+        }
+    }
+
+    fn open_path(&self, path: &str, mode: Mode) -> Result<Handle, FsError> {
+        let path = self.host_path(path);
+        semihosting::open(&path, mode).map_err(|e| host_error(e, &path))
+    }
+
+    /// Run `func` on the file `id` opened on the host with `mode`
+    fn with_file<F, T>(&self, id: NodeId, mode: Mode, func: F) -> Result<T, FsError>
+    where
+        F: FnOnce(Handle) -> Result<T, Errno>,
+    {
+        let path = {
+            let nodes = self.nodes.borrow();
+            let node = nodes.get(id)?;
+            if node.ntype != NodeType::RegularFile {
+                return Err(FsError::new(
+                    Errno::EISDIR,
+                    format!("Is a directory: {}", node.path),
+                ));
+            }
+            node.path.clone()
+        };
+
+        let handle = self.open_path(&path, mode)?;
+        let res = func(handle);
+        let _ = semihosting::close(handle);
+        res.map_err(|e| host_error(e, &path))
+    }
+
+    fn load_manifest(&self, manifest: &str) -> Result<(), FsError> {
+        let handle = self.open_path(manifest, Mode::Read)?;
+        let res = semihosting::flen(handle).and_then(|len| {
+            let mut buf = alloc::vec![0u8; len];
+            let len = semihosting::read(handle, &mut buf)?;
+            buf.truncate(len);
+            Ok(buf)
+        });
+        let _ = semihosting::close(handle);
+        let text = res.map_err(|e| host_error(e, manifest))?;
+
+        let mut nodes = self.nodes.borrow_mut();
+        for line in text.split(|&c| c == b'\n') {
+            let line = core::str::from_utf8(line)
+                .or(Err(FsError::new(
+                    Errno::EINVAL,
+                    format!("Manifest is not UTF-8: {}", manifest),
+                )))?
+                .trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let names: Vec<&str> = line.split('/').filter(|s| !s.is_empty()).collect();
+            if !names.iter().all(|name| valid_name(name)) {
+                warn!("{}: invalid path skipped: {}", manifest, line);
+                continue;
+            }
+            let mut dir = NODE_ID_ROOT;
+            for (i, name) in names.iter().enumerate() {
+                let ntype = if i + 1 < names.len() || line.ends_with('/') {
+                    NodeType::Directory
+                } else {
+                    NodeType::RegularFile
+                };
+                dir = nodes.add(dir, name, ntype);
+            }
+        }
+        Ok(())
+    }
+
+    /// Find out whether `path` exists on the host and what it is
+    fn probe(&self, path: &str) -> Option<NodeType> {
+        let handle = self.open_path(path, Mode::Read).ok()?;
+        let mut buf: [u8; 1] = [0; 1];
+        let ntype = match semihosting::read(handle, &mut buf) {
+            Err(Errno::EISDIR) => NodeType::Directory,
+            _ => NodeType::RegularFile,
+        };
+        let _ = semihosting::close(handle);
+        Some(ntype)
+    }
+}
+
+impl FileSystem for HostFs {
+    fn readdir(&self, dir: NodeId, pos: usize) -> Result<Option<(DEntry, NodeId)>, FsError> {
+        let nodes = self.nodes.borrow();
+        let dir_node = nodes.get(dir)?;
+
+        if dir_node.ntype != NodeType::Directory {
+            return Err(FsError::new(
+                Errno::EBADF,
+                format!("Attempt to readdir() for a file: {}", dir_node.path),
+            ));
+        }
+
+        match dir_node.children.get(pos) {
+            Some(&id) => {
+                let node = nodes.get(id)?;
+                Ok(Some((
+                    DEntry {
+                        name: node.name.clone(),
+                        ntype: node.ntype,
+                    },
+                    id,
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn create(&mut self, dir: NodeId, dent: &DEntry) -> Result<NodeId, FsError> {
+        if !valid_name(&dent.name) {
+            return Err(FsError::new(
+                Errno::EINVAL,
+                format!("Invalid name on the host: {}", dent.name),
+            ));
+        }
+        if dent.ntype == NodeType::Directory {
+            return Err(FsError::new(
+                Errno::ENOSYS,
+                format!("Cannot create a directory on the host: {}", dent.name),
+            ));
+        }
+
+        let path = self.nodes.borrow().get(dir)?.child_path(&dent.name);
+
+        let handle = self.open_path(&path, Mode::Write)?;
+        let _ = semihosting::close(handle);
+
+        Ok(self.nodes.borrow_mut().add(dir, &dent.name, dent.ntype))
+    }
+
+    fn read(&self, file: NodeId, off: usize, data: &mut [u8]) -> Result<usize, FsError> {
+        self.with_file(file, Mode::Read, |handle| {
+            semihosting::seek(handle, off)?;
+            semihosting::read(handle, data)
+        })
+    }
+
+    fn write(&mut self, file: NodeId, off: usize, data: &[u8]) -> Result<usize, FsError> {
+        self.with_file(file, Mode::ReadWrite, |handle| {
+            semihosting::seek(handle, off)?;
+            semihosting::write(handle, data)
+        })
+    }
+
+    fn truncate(&mut self, file: NodeId, len: usize) -> Result<(), FsError> {
+        if len == 0 {
+            // "wb" truncates
+            return self.with_file(file, Mode::Write, |_| Ok(()));
+        }
+        if self.getsize(file)? == len {
+            return Ok(());
+        }
+        Err(FsError::new(
+            Errno::ENOSYS,
+            format!("Cannot truncate a host file to {} bytes", len),
+        ))
+    }
+
+    fn getsize(&self, file: NodeId) -> Result<usize, FsError> {
+        self.with_file(file, Mode::Read, semihosting::flen)
+    }
+
+    fn lookup(&self, dir: NodeId, name: &str) -> Result<Option<NodeId>, FsError> {
+        if !valid_name(name) {
+            return Ok(None);
+        }
+        if let Some(id) = self.nodes.borrow().child(dir, name) {
+            return Ok(Some(id));
+        }
+
+        let path = {
+            let nodes = self.nodes.borrow();
+            let dir_node = nodes.get(dir)?;
+            if dir_node.ntype != NodeType::Directory {
+                return Ok(None);
+            }
+            dir_node.child_path(name)
+        };
+
+        Ok(self
+            .probe(&path)
+            .map(|ntype| self.nodes.borrow_mut().add(dir, name, ntype)))
+    }
+}
+
+/// Make `mountpoint` and mount the host directory `root` there
+pub fn mount(mountpoint: &str, root: &str) -> Result<(), FsError> {
+    let hostfs = HostFs::new(root, Some(MANIFEST))?;
+    fs::mkdir(mountpoint)?;
+    fs::mount(mountpoint, Box::new(hostfs))
+}
@@ -0,0 +1,142 @@
+/*
+
+External interrupt dispatch.
+
+Every external entry of the vector table points to `__irq`, which looks up
+the handler registered for the active IRQ number. IRQs without a handler
+go on to DefaultExceptionHandler and get the usual register dump.
+
+A handler is taken out of the table while it runs, so that a nested IRQ
+which unregisters it can't free it under its feet.
+
+ */
+
+extern crate posix;
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use posix::Errno;
+
+use crate::sync::IrqSafeLock;
+use crate::{board, nvic};
+
+type Handler = Box<dyn FnMut() + Send>;
+
+struct Slot {
+    registered: bool,
+    /// Taken out while it runs
+    handler: Option<Handler>,
+}
+
+const NO_HANDLER: Slot = Slot {
+    registered: false,
+    handler: None,
+};
+static HANDLERS: IrqSafeLock<[Slot; board::IRQ_COUNT]> =
+    IrqSafeLock::new([NO_HANDLER; board::IRQ_COUNT]);
+
+/// Attach `handler` to `irq` and enable it in the NVIC
+pub fn register<F>(irq: usize, handler: F) -> Result<(), Errno>
+where
+    F: FnMut() + Send + 'static,
+{
+    if irq >= board::IRQ_COUNT {
+        return Err(Errno::EINVAL);
+    }
+
+    let handler: Handler = Box::new(handler);
+
+    let res = {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[irq];
+        if slot.registered {
+            Err(Errno::EBUSY)
+        } else {
+            slot.registered = true;
+            slot.handler = Some(handler);
+            Ok(())
+        }
+    };
+
+    if res.is_ok() {
+        nvic::clear_pending(irq);
+        nvic::enable(irq);
+    }
+    res
+}
+
+/// Disable `irq` and drop its handler; if it is running, it is dropped once
+/// it returns
+#[allow(dead_code)]
+pub fn unregister(irq: usize) -> Result<(), Errno> {
+    if irq >= board::IRQ_COUNT {
+        return Err(Errno::EINVAL);
+    }
+
+    nvic::disable(irq);
+
+    let handler = {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[irq];
+        if !slot.registered {
+            return Err(Errno::ENOENT);
+        }
+        slot.registered = false;
+        slot.handler.take()
+    };
+    drop(handler);
+    Ok(())
+}
+
+#[no_mangle]
+unsafe extern "C" fn __irq_dispatch(ipsr: u32) -> bool {
+    let irq = (ipsr & 0x1ff) as usize - 16;
+    if irq >= board::IRQ_COUNT {
+        return false;
+    }
+
+    // Run it without the lock, so that IRQs can nest; an IRQ doesn't
+    // preempt itself, so nothing else finds the slot empty but registered.
+    let mut handler = {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[irq];
+        if !slot.registered {
+            return false;
+        }
+        match slot.handler.take() {
+            Some(handler) => handler,
+            None => return true,
+        }
+    };
+    handler();
+
+    let mut handlers = HANDLERS.lock();
+    let slot = &mut handlers[irq];
+    if slot.registered && slot.handler.is_none() {
+        slot.handler = Some(handler);
+    } else {
+        // unregistered meanwhile, maybe replaced too
+        drop(handlers);
+        drop(handler);
+    }
+    true
+}
+
+#[no_mangle]
+#[naked]
+pub unsafe extern "C" fn __irq() {
+    asm!(
+        "mrs r0, ipsr",
+        // a frame record for the unwinders, see backtrace.rs
+        "push {{r7, lr}}",
+        ".save {{r7, lr}}",
+        "mov r7, sp",
+        "bl __irq_dispatch",
+        "pop {{r7, lr}}",
+        "cbnz r0, 2f",
+        "b DefaultExceptionHandler",
+        "2:",
+        "bx lr",
+        options(noreturn)
+    )
+}
@@ -11,3 +11,9 @@ pub fn safe_search<'a>(addr: usize, buf: &'a mut [u8]) -> Option<(&'a str, usize
         None => None,
     }
 }
+
+/// File and line of the code at `addr`
+pub fn search_line(addr: usize) -> Option<(&'static str, u32)> {
+    let kallsyms = KAllSyms::new(kallsyms_addr());
+    kallsyms.search_line(addr as KAddress)
+}
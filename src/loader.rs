@@ -0,0 +1,394 @@
+/*
+
+Program loader.
+
+`run()` loads an ELF executable from the VFS and runs it in an
+unprivileged thread on a stack of its own, until it calls the exit system
+call, so that applications can be shipped apart from the kernel, e.g. on
+the host directory.
+
+The program has to be a 32-bit little-endian ARM ELF linked
+position-independent (ET_DYN, e.g. `ld -pie`). Fixed-address executables
+(ET_EXEC) are refused: the program goes wherever the heap has room, which
+is never the address it was linked at. Its PT_LOAD segments are read from
+the file straight into a zeroed heap allocation, without buffering the
+file, and the R_ARM_RELATIVE relocations listed in its PT_DYNAMIC are
+applied for that address. Other relocations need symbols resolved, i.e. a
+dynamic linker, and are refused.
+
+The image spans the segments' addresses, so padding between them costs
+heap. ld aligns them to 64K pages by default; link programs with
+`-z max-page-size=4096`.
+
+While the program runs the MPU maps its image read-write and executable
+(see mpu.rs), which it does for one program at a time. Besides the image
+and its stack, the program can only read and run the kernel's code.
+
+[refs]
+- https://github.com/ARM-software/abi-aa/blob/main/aaelf32/aaelf32.rst (ELF for the Arm Architecture)
+
+ */
+
+extern crate elf_parser;
+extern crate posix;
+extern crate vfs;
+
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+use core::slice;
+use elf_parser::{ElfClass, ElfEndian, ElfFileHeader, ElfParserError, ElfSegmentType, ElfType};
+use posix::Errno;
+use vfs::{FileDescriptor, OpenMode};
+
+use crate::sync::IrqSafeLock;
+use crate::{cpu, fs, mpu, println, sched, shell_command, warn};
+
+const EM_ARM: u16 = 40;
+
+/// Most program headers accepted
+const MAX_PHNUM: usize = 16;
+
+// d_tag of the PT_DYNAMIC entries
+const DT_NULL: u32 = 0;
+const DT_PLTRELSZ: u32 = 2;
+const DT_RELA: u32 = 7;
+const DT_REL: u32 = 17;
+const DT_RELSZ: u32 = 18;
+const DT_JMPREL: u32 = 23;
+
+const R_ARM_NONE: u32 = 0;
+const R_ARM_RELATIVE: u32 = 23;
+
+/// Largest image accepted
+const MAX_SIZE: usize = 256 * 1024;
+
+/// A program's memory
+struct Image {
+    ptr: *mut u8,
+    layout: Layout,
+    /// Lowest address the program is linked at, which is at `ptr`
+    vaddr: usize,
+}
+
+impl Image {
+    /// Zeroed memory for the program's `vaddrs`; ENOEXEC if they are empty
+    fn new(vaddrs: Range<usize>) -> Result<Self, Errno> {
+        if vaddrs.is_empty() {
+            warn!("nothing to load");
+            return Err(Errno::ENOEXEC);
+        }
+        let size = (vaddrs.end - vaddrs.start + mpu::GRANULE - 1) & !(mpu::GRANULE - 1);
+        let layout = Layout::from_size_align(size, mpu::GRANULE).or(Err(Errno::ENOMEM))?;
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(Errno::ENOMEM);
+        }
+        Ok(Self {
+            ptr,
+            layout,
+            vaddr: vaddrs.start,
+        })
+    }
+
+    fn range(&self) -> Range<usize> {
+        let start = self.ptr as usize;
+        start..start + self.layout.size()
+    }
+
+    /// What is added to a linked address to get the loaded one
+    fn bias(&self) -> u32 {
+        (self.ptr as usize).wrapping_sub(self.vaddr) as u32
+    }
+
+    /// The `len` bytes at linked address `vaddr`; ENOEXEC if they are
+    /// outside the image
+    fn at(&mut self, vaddr: usize, len: usize) -> Result<&mut [u8], Errno> {
+        let off = vaddr
+            .checked_sub(self.vaddr)
+            .filter(|off| off.saturating_add(len) <= self.layout.size())
+            .ok_or(Errno::ENOEXEC)?;
+        Ok(unsafe { slice::from_raw_parts_mut(self.ptr.add(off), len) })
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// The program file, read front to back; the VFS has no seek
+struct File<'a> {
+    path: &'a str,
+    fd: FileDescriptor,
+    pos: usize,
+}
+
+impl<'a> File<'a> {
+    fn open(path: &'a str) -> Result<Self, Errno> {
+        let fd = fs::open(path, OpenMode::READ).map_err(|e| e.errno())?;
+        Ok(Self { path, fd, pos: 0 })
+    }
+
+    /// Fill `buf` from offset `off`; ENOEXEC if the file ends before
+    fn read_at(&mut self, off: usize, buf: &mut [u8]) -> Result<(), Errno> {
+        if off < self.pos {
+            // back to the start
+            let fd = fs::open(self.path, OpenMode::READ).map_err(|e| e.errno())?;
+            let _ = fs::close(core::mem::replace(&mut self.fd, fd));
+            self.pos = 0;
+        }
+        let mut skip = [0u8; 256];
+        while self.pos < off {
+            let n = (off - self.pos).min(skip.len());
+            self.fill(&mut skip[..n])?;
+        }
+        self.fill(buf)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), Errno> {
+        let mut done = 0;
+        while done < buf.len() {
+            match fs::read(self.fd, &mut buf[done..]) {
+                Ok(0) => return Err(Errno::ENOEXEC),
+                Ok(n) => done += n,
+                Err(e) => return Err(e.errno()),
+            }
+        }
+        self.pos += done;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for File<'a> {
+    fn drop(&mut self) {
+        let _ = fs::close(self.fd);
+    }
+}
+
+fn word(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Apply the relocations listed in the dynamic section `dynamic`
+fn relocate(image: &mut Image, dynamic: &[u8]) -> Result<(), Errno> {
+    // (DT_REL, DT_RELSZ) and (DT_JMPREL, DT_PLTRELSZ)
+    let mut tables = [(None, 0), (None, 0)];
+    for entry in dynamic.chunks_exact(8) {
+        let val = word(&entry[4..]);
+        match word(entry) {
+            DT_NULL => break,
+            DT_REL => tables[0].0 = Some(val),
+            DT_RELSZ => tables[0].1 = val,
+            DT_JMPREL => tables[1].0 = Some(val),
+            DT_PLTRELSZ => tables[1].1 = val,
+            DT_RELA => {
+                warn!("RELA relocations are not supported");
+                return Err(Errno::ENOEXEC);
+            }
+            _ => {}
+        }
+    }
+
+    let bias = image.bias();
+    for (addr, size) in tables {
+        let addr = match addr {
+            Some(addr) => addr,
+            None => continue,
+        };
+        let rels = image.at(addr as usize, size as usize)?.to_vec();
+        for rel in rels.chunks_exact(8) {
+            let (offset, info) = (word(rel), word(&rel[4..]));
+            match info & 0xff {
+                R_ARM_NONE => {}
+                R_ARM_RELATIVE => {
+                    // the addend is in place
+                    let target = image.at(offset as usize, 4)?;
+                    let value = word(target).wrapping_add(bias);
+                    target.copy_from_slice(&value.to_le_bytes());
+                }
+                typ => {
+                    warn!("unsupported relocation type {} at {:#x}", typ, offset);
+                    return Err(Errno::ENOEXEC);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A PT_LOAD or PT_DYNAMIC program header
+struct Segment {
+    typ: ElfSegmentType,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+/// Whether `header` is of a program this loader can run
+fn check_header(header: &ElfFileHeader) -> Result<(), Errno> {
+    if header.class != ElfClass::Elf32 || header.endian != ElfEndian::ElfLE {
+        warn!("not a 32-bit little-endian ELF");
+        return Err(Errno::ENOEXEC);
+    }
+    if header.machine != EM_ARM {
+        warn!("not an ARM ELF");
+        return Err(Errno::ENOEXEC);
+    }
+    match header.typ {
+        ElfType::Dyn => {}
+        ElfType::Exec => {
+            warn!("fixed-address executable; programs go wherever the heap has room, link it with -pie");
+            return Err(Errno::ENOEXEC);
+        }
+        typ => {
+            warn!("not an executable ({:?})", typ);
+            return Err(Errno::ENOEXEC);
+        }
+    }
+    if header.phnum as usize > MAX_PHNUM {
+        warn!("too many program headers: {}", header.phnum);
+        return Err(Errno::ENOEXEC);
+    }
+    Ok(())
+}
+
+fn elf_error(e: ElfParserError) -> Errno {
+    warn!("{}", e.message());
+    Errno::ENOEXEC
+}
+
+/// Load the ELF at `path`, reading only its headers and segments; the
+/// image and the address of its entry point
+fn load(path: &str) -> Result<(Image, usize), Errno> {
+    let mut file = File::open(path)?;
+    let mut ehdr = [0u8; ElfFileHeader::size(ElfClass::Elf32)];
+    file.read_at(0, &mut ehdr)?;
+    let header = ElfFileHeader::from_bytes(&ehdr).map_err(elf_error)?;
+    check_header(&header)?;
+
+    let mut phdr = vec![0u8; header.phentsize as usize];
+    let mut segments = Vec::new();
+    for i in 0..header.phnum as usize {
+        file.read_at(header.phoff as usize + i * phdr.len(), &mut phdr)?;
+        let ph = header.program_header(&phdr).map_err(elf_error)?;
+        if ph.typ != ElfSegmentType::Load && ph.typ != ElfSegmentType::Dynamic {
+            continue;
+        }
+        // a 32-bit ELF's fields fit
+        let seg = Segment {
+            typ: ph.typ,
+            offset: ph.offset as usize,
+            vaddr: ph.vaddr as usize,
+            filesz: ph.filesz as usize,
+            memsz: ph.memsz as usize,
+        };
+        if seg.filesz > seg.memsz || seg.vaddr.checked_add(seg.memsz).is_none() {
+            return Err(Errno::ENOEXEC);
+        }
+        segments.push(seg);
+    }
+
+    let loads = || segments.iter().filter(|s| s.typ == ElfSegmentType::Load);
+    let start = loads().map(|s| s.vaddr).min().ok_or(Errno::ENOEXEC)?;
+    let end = loads().map(|s| s.vaddr + s.memsz).max().unwrap();
+    if end - start > MAX_SIZE {
+        return Err(Errno::E2BIG);
+    }
+
+    let mut image = Image::new(start..end)?;
+    for seg in loads() {
+        // the rest up to memsz, .bss, is zero already
+        file.read_at(seg.offset, image.at(seg.vaddr, seg.filesz)?)?;
+    }
+    drop(file);
+
+    if let Some(seg) = segments.iter().find(|s| s.typ == ElfSegmentType::Dynamic) {
+        // it is part of a PT_LOAD, so it was read into the image already
+        let dynamic = image.at(seg.vaddr, seg.filesz)?.to_vec();
+        relocate(&mut image, &dynamic)?;
+    }
+
+    let entry = image.at(header.entry as usize & !1, 2)?.as_ptr() as usize;
+    // Thumb only
+    Ok((image, entry | 1))
+}
+
+/// The program running
+struct Program {
+    thread: sched::ThreadId,
+    image: Range<usize>,
+    /// Set once it called exit
+    code: Option<i32>,
+}
+
+static PROGRAM: IrqSafeLock<Option<Program>> = IrqSafeLock::new(None);
+
+/// Called by the exit system call
+pub fn exited(code: i32) {
+    let mut program = PROGRAM.lock();
+    if let Some(program) = program.as_mut() {
+        if sched::current() == Some(program.thread) {
+            program.code = Some(code);
+        }
+    }
+}
+
+/// Image of the program, if the current thread runs one
+pub fn current_image() -> Option<Range<usize>> {
+    let program = PROGRAM.lock();
+    program
+        .as_ref()
+        .filter(|p| sched::current() == Some(p.thread))
+        .map(|p| p.image.clone())
+}
+
+/// Load the program at `path`, run it, and wait until it exits; its exit
+/// code. EBUSY if another program is running.
+pub fn run(path: &str) -> Result<Option<i32>, Errno> {
+    let (image, entry) = load(path)?;
+
+    mpu::map_program(image.range())?;
+    cpu::barrier();
+    let entry: extern "C" fn() = unsafe { core::mem::transmute(entry) };
+    let id = {
+        // before it can make system calls
+        let mut program = PROGRAM.lock();
+        let thread = sched::spawn_user("program", entry);
+        *program = Some(Program {
+            thread,
+            image: image.range(),
+            code: None,
+        });
+        thread
+    };
+    sched::join(id);
+
+    let code = PROGRAM.lock().take().and_then(|p| p.code);
+    mpu::unmap_program();
+    drop(image);
+    Ok(code)
+}
+
+fn cmd_exec(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_, path] => match run(path)? {
+            Some(code) => println!("exit code {}", code),
+            None => println!("exited"),
+        },
+        _ => {
+            println!("usage: exec <path>");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_EXEC,
+    "exec",
+    "run an ELF program from the VFS, e.g. /host/app.elf",
+    cmd_exec
+);
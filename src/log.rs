@@ -0,0 +1,248 @@
+/*
+
+Kernel log.
+
+    info!("mounted {} on {}", fs, path);
+
+Records are kept in a ring buffer in RAM and echoed to the consoles that
+take the log (see console.rs). Echo is deferred when logging from an
+exception handler; the pending records are printed by the next log call
+from Thread mode, by the idle thread, or by the crash handler. `dmesg`
+prints the records still in the buffer and `loglevel` changes the level
+threshold of a module at runtime.
+
+ */
+
+extern crate klog;
+extern crate posix;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use klog::{Filter, LogBuffer, Record, TextBuf};
+use posix::Errno;
+
+pub use klog::Level;
+
+use crate::sync::IrqSafeLock;
+use crate::{console, cpu, println, shell_command, time};
+
+const SLOTS: usize = 64;
+const TEXT_LEN: usize = 96;
+
+struct Log {
+    buffer: LogBuffer<SLOTS, TEXT_LEN>,
+    filter: Filter,
+    /// Sequence number of the next record to echo to the console
+    console_seq: u64,
+}
+
+static LOG: IrqSafeLock<Log> = IrqSafeLock::new(Log {
+    buffer: LogBuffer::new(),
+    filter: Filter::new(Level::Info),
+    console_seq: 0,
+});
+static TIMESTAMPS: AtomicBool = AtomicBool::new(true);
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => (
+        $crate::log::log($level, module_path!(), format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// "barbara::sched" -> "sched"
+fn short_module(module: &'static str) -> &'static str {
+    match module.split_once("::") {
+        Some((_, rest)) => rest,
+        None => module,
+    }
+}
+
+#[doc(hidden)]
+pub fn log(level: Level, module: &'static str, args: fmt::Arguments) {
+    let module = short_module(module);
+
+    if !LOG.lock().filter.enabled(level, module) {
+        return;
+    }
+
+    let mut text: TextBuf<TEXT_LEN> = TextBuf::new();
+    let _ = text.write_fmt(args);
+    let timestamp = time::now().as_nanos();
+
+    LOG.lock().buffer.push(level, timestamp, module, &text);
+
+    if cpu::in_thread_mode() {
+        flush_console();
+    }
+}
+
+/// Print `rec` with `print`, i.e. to the console or to the log's outputs
+fn write_record(print: fn(fmt::Arguments), rec: &Record<TEXT_LEN>) {
+    let level = rec.level.name();
+    if TIMESTAMPS.load(Ordering::Relaxed) {
+        let secs = rec.timestamp / 1_000_000_000;
+        let micros = (rec.timestamp / 1000) % 1_000_000;
+        let text = rec.text.as_str();
+        print(format_args!(
+            "[{:5}.{:06}] {:5} {}: {}\n",
+            secs, micros, level, rec.module, text
+        ));
+    } else {
+        print(format_args!(
+            "{:5} {}: {}\n",
+            level,
+            rec.module,
+            rec.text.as_str()
+        ));
+    }
+}
+
+fn print_record(rec: &Record<TEXT_LEN>) {
+    write_record(console::print_fmt, rec)
+}
+
+/// Echo the records not printed yet to the consoles taking the log
+pub fn flush_console() {
+    loop {
+        // claim one record at a time, so that concurrent callers print each
+        // once. The lock is only held elsewhere if this is a fault handler
+        // that interrupted its holder; the records wait then.
+        let (rec, lost) = {
+            let mut log = match LOG.try_lock() {
+                Some(log) => log,
+                None => return,
+            };
+            let first = log.buffer.first_seq();
+            let lost = first.saturating_sub(log.console_seq);
+            log.console_seq = log.console_seq.max(first);
+            let rec = log.buffer.get(log.console_seq).copied();
+            if rec.is_some() {
+                log.console_seq += 1;
+            }
+            (rec, lost)
+        };
+
+        if lost > 0 {
+            console::log_fmt(format_args!("** {} log records lost **\n", lost));
+        }
+        match rec {
+            Some(rec) => write_record(console::log_fmt, &rec),
+            None => break,
+        }
+    }
+}
+
+/// Call `func` on each record in the buffer, oldest first
+pub fn dmesg<F>(mut func: F)
+where
+    F: FnMut(&Record<TEXT_LEN>),
+{
+    let mut seq = LOG.lock().buffer.first_seq();
+    loop {
+        let rec = {
+            let log = LOG.lock();
+            seq = seq.max(log.buffer.first_seq());
+            log.buffer.get(seq).copied()
+        };
+
+        match rec {
+            Some(rec) => func(&rec),
+            None => break,
+        }
+        seq += 1;
+    }
+}
+
+pub fn clear() {
+    LOG.lock().buffer.clear();
+}
+
+/// Set the level threshold of `module` and its submodules, or the default
+/// one if `module` is `None`
+pub fn set_level(module: Option<&str>, level: Level) {
+    let mut log = LOG.lock();
+    match module {
+        Some(module) => log.filter.set(module, level),
+        None => log.filter.set_default(level),
+    }
+}
+
+#[allow(dead_code)]
+pub fn set_timestamps(on: bool) {
+    TIMESTAMPS.store(on, Ordering::Relaxed);
+}
+
+fn cmd_dmesg(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_] => dmesg(print_record),
+        [_, "-c"] => {
+            dmesg(print_record);
+            clear();
+        }
+        _ => {
+            println!("usage: dmesg [-c]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_loglevel(args: &[&str]) -> Result<(), Errno> {
+    let parse = |name: &str| Level::from_name(name).ok_or(Errno::EINVAL);
+    match args {
+        [_] => {
+            // copied out, not to print with IRQs masked
+            let (default, rules) = {
+                let log = LOG.lock();
+                let rules: Vec<(String, Level)> = log
+                    .filter
+                    .rules()
+                    .map(|(module, level)| (String::from(module), level))
+                    .collect();
+                (log.filter.default_level(), rules)
+            };
+
+            println!("default: {}", default.name());
+            for (module, level) in rules {
+                println!("{}: {}", module, level.name());
+            }
+        }
+        [_, level] => set_level(None, parse(level)?),
+        [_, module, level] => set_level(Some(module), parse(level)?),
+        _ => {
+            println!("usage: loglevel [[module] error|warn|info|debug]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(CMD_DMESG, "dmesg", "print the kernel log", cmd_dmesg);
+shell_command!(
+    CMD_LOGLEVEL,
+    "loglevel",
+    "show or set log levels",
+    cmd_loglevel
+);
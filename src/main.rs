@@ -7,16 +7,42 @@
 
 extern crate alloc;
 
+mod arm_dualtimer;
+mod arm_timer;
 mod arm_uart;
 mod backtrace;
+mod board;
 mod console;
+mod coredump;
+mod cpu;
+mod devfs;
+mod executor;
+mod fault;
+mod fs;
+#[cfg(feature = "gdbstub")]
+mod gdbstub;
 mod handlers;
 mod heap;
+mod hostfs;
+mod irq;
 mod kallsyms;
+mod loader;
+mod log;
+mod mpu;
+mod nvic;
+mod scb;
+mod sched;
+#[cfg(feature = "nonsecure")]
+mod secure;
 mod semihosting;
-
-use arm_uart::ArmUart;
-const __CONSOLE: *mut ArmUart = 0x4020_0000 as *mut ArmUart;
+mod shell;
+mod sync;
+mod syscall;
+mod systick;
+mod time;
+mod timer;
+mod user;
+mod workqueue;
 
 use core::arch::asm;
 
@@ -25,9 +51,81 @@ pub fn main() -> ! {
     println!("=========================================");
     println!("   Cortex-M 'Hello world' demo in Rust   ");
     println!("=========================================");
+    println!("board: {}", board::NAME);
 
+    scb::enable_faults();
+    mpu::init();
+    #[cfg(feature = "nonsecure")]
+    secure::init();
     heap::init();
+    console::init_irq();
+    #[cfg(feature = "gdbstub")]
+    gdbstub::init();
+    fs::init();
+    if let Err(e) = devfs::mount("/dev") {
+        warn!("failed to mount /dev: {}", e.message());
+    }
+    match hostfs::mount("/host", ".") {
+        Ok(()) => info!("host directory mounted on /host"),
+        Err(e) => warn!("failed to mount /host: {}", e.message()),
+    }
+    time::init();
+    workqueue::init();
+    timer::init();
+    executor::init();
+
+    sched::start(init)
+}
+
+fn worker_a() {
+    for i in 0..3 {
+        println!("[{}] worker a: {}", time::now(), i);
+        time::sleep_ms(100);
+    }
+}
+
+fn worker_b() {
+    for i in 0..3 {
+        println!("[{}] worker b: {}", time::now(), i);
+        time::sleep_ms(150);
+    }
+}
+
+/// Write `parts` to `fd`; unprivileged code has no `print!`
+fn app_print(fd: vfs::FileDescriptor, parts: &[&[u8]]) {
+    for part in parts {
+        user::write(fd, part).unwrap();
+    }
+}
 
+extern "C" fn app() {
+    use vfs::OpenMode;
+
+    let out = user::open("/dev/console", OpenMode::WRITE).unwrap();
+
+    let fd = user::open("/hello.txt", OpenMode::WRITE | OpenMode::CREATE).unwrap();
+    user::write(fd, b"written from an unprivileged thread").unwrap();
+    user::close(fd).unwrap();
+
+    let mut buf: [u8; 64] = [0; 64];
+    let fd = user::open("/hello.txt", OpenMode::READ).unwrap();
+    let len = user::read(fd, &mut buf).unwrap();
+    user::close(fd).unwrap();
+    app_print(out, &[b"app: ", &buf[..len], b"\n"]);
+
+    user::mkdir("/app").unwrap();
+
+    let mut dirent = user::Dirent::new();
+    let fd = user::open("/", OpenMode::READ).unwrap();
+    while user::readdir(fd, &mut dirent).unwrap() {
+        let suffix: &[u8] = if dirent.is_dir() { b"/" } else { b"" };
+        app_print(out, &[b"app: /", dirent.name().as_bytes(), suffix, b"\n"]);
+    }
+    user::close(fd).unwrap();
+    user::close(out).unwrap();
+}
+
+fn init() {
     use alloc::vec::Vec;
     let mut v = Vec::new();
     for i in 0..10 {
@@ -35,7 +133,18 @@ pub fn main() -> ! {
     }
     println!("vector: {:?}", v);
 
-    println!();
+    let a = sched::spawn("worker_a", worker_a);
+    let b = sched::spawn("worker_b", worker_b);
+    sched::join(a);
+    sched::join(b);
+
+    let app = sched::spawn_user("app", app);
+    sched::join(app);
+
+    shell::run()
+}
+
+fn cmd_fault(_args: &[&str]) -> Result<(), posix::Errno> {
     println!("make panic");
 
     unsafe {
@@ -46,7 +155,12 @@ pub fn main() -> ! {
             out("r11") _
         )
     }
-
-    semihosting::shutdown();
-    loop {}
+    Ok(())
 }
+
+shell_command!(
+    CMD_FAULT,
+    "fault",
+    "raise an exception to test the crash dump",
+    cmd_fault
+);
@@ -0,0 +1,471 @@
+/*
+
+Memory protection with the ARMv8-M (PMSAv8) MPU.
+
+    region   range                           access  attributes
+    vector   __vector_s .. __vector_e        RO      XN
+    rom      __rom_s .. __rom_e              RO  U
+    ram      __ram_s .. __stack_guard_s      RW      XN     .data, .bss, noinit
+    (guard)  __stack_guard_s .. __stack_s    -              stack guard
+    stack    __stack_s .. __ram_e            RW      XN     stack, heap
+    heap     __heap_s .. __heap_e            RW      XN     if not in RAM
+    devices  board::PERIPHERALS              RW      XN     Device-nGnRE
+    program  a loaded program's image        RW  U          while it runs
+    thread   stack of the unprivileged       RW  U   XN     while it runs
+             thread running
+    (guard)  0 .. NULL_GUARD                 -              null guard
+
+PMSAv8 has no no-access permission, so the guards are holes between the
+regions: the default memory map is off for privileged code too
+(PRIVDEFENA clear) and anything unmapped faults, the System Control Space
+excepted.
+
+Unprivileged code (U) may only run the kernel's code, which the built-in
+app is part of, and use its own stack and program image; kernel RAM and
+the devices are for privileged code only. The thread region follows the
+scheduler: it is rewritten when it switches to an unprivileged thread, or
+away from one.
+
+A stack overflow faults on the stack guard, and the MemManage exception
+taken for it can't be stacked either; it escalates to HardFault, which runs
+with the MPU off (HFNMIENA clear) and reports it on the guard itself,
+hence the guard's size in board.toml.
+
+Regions must not overlap, so the program image and the thread stack are
+cut out of the region that holds them, which leaves up to two regions
+around each.
+
+The MPU of the ARMv7-M boards (PMSAv7) is different and left off.
+
+[refs]
+- https://developer.arm.com/documentation/100235/0100/The-Cortex-M33-Peripherals/Security-Attribution-and-Memory-Protection/Memory-Protection-Unit
+- https://developer.arm.com/documentation/ddi0553/latest (ARMv8-M ARM, B3.5)
+
+ */
+
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+use core::ops::Range;
+use posix::Errno;
+
+use mmio::{Readable, RegisterR, RegisterRW, Writeable};
+
+use crate::sync::IrqSafeLock;
+use crate::{board, cpu, decl_c_symbol_addr, info, println, shell_command, warn};
+
+decl_c_symbol_addr!(__vector_s, vector_s);
+decl_c_symbol_addr!(__vector_e, vector_e);
+decl_c_symbol_addr!(__rom_s, rom_s);
+decl_c_symbol_addr!(__rom_e, rom_e);
+decl_c_symbol_addr!(__ram_s, ram_s);
+decl_c_symbol_addr!(__ram_e, ram_e);
+decl_c_symbol_addr!(__stack_guard_s, stack_guard_s);
+decl_c_symbol_addr!(__stack_s, stack_s);
+decl_c_symbol_addr!(__heap_s, heap_s);
+decl_c_symbol_addr!(__heap_e, heap_e);
+
+bitfield! {
+    Type: u32 {
+        DREGION[15:8];
+    }
+}
+
+bitfield! {
+    Ctrl: u32 {
+        ENABLE[0];
+        HFNMIENA[1];
+        PRIVDEFENA[2];
+    }
+}
+
+// BASE[31:5] is the 32-byte aligned base address itself
+bitfield! {
+    Rbar: u32 {
+        XN[0];
+        AP[2:1];
+        SH[4:3];
+    }
+}
+
+// LIMIT[31:5] is the 32-byte aligned address of the last block
+bitfield! {
+    Rlar: u32 {
+        EN[0];
+        ATTRINDX[3:1];
+    }
+}
+
+/// Memory Protection Unit
+pub struct Mpu {
+    typ: RegisterR<0x00, u32, Type>,
+    ctrl: RegisterRW<0x04, u32, Ctrl>,
+    rnr: RegisterRW<0x08, u32, u32>,
+    rbar: RegisterRW<0x0C, u32, Rbar>,
+    rlar: RegisterRW<0x10, u32, Rlar>,
+    mair0: RegisterRW<0x30, u32, u32>,
+}
+
+const MPU: *mut Mpu = 0xE000_ED90 as *mut Mpu;
+
+/// Region address granularity
+pub const GRANULE: usize = 32;
+
+/// Unmapped from address 0, to fault on null pointers and small offsets
+/// from them
+const NULL_GUARD: usize = 0x1000;
+
+// MAIR0 Attr0: Normal, Write-Back non-transient, read and write allocate;
+// Attr1: Device-nGnRE
+const MAIR0: u32 = 0x04ff;
+const ATTR_NORMAL: u32 = 0;
+const ATTR_DEVICE: u32 = 1;
+
+// RBAR.AP
+const AP_RW_PRIV: u32 = 0b00;
+const AP_RW: u32 = 0b01;
+const AP_RO_PRIV: u32 = 0b10;
+const AP_RO: u32 = 0b11;
+
+const MAX_REGIONS: usize = 12;
+
+/// Image of the program the loader runs
+static PROGRAM: IrqSafeLock<Option<Range<usize>>> = IrqSafeLock::new(None);
+
+/// Stack of the unprivileged thread running
+static THREAD_STACK: IrqSafeLock<Option<Range<usize>>> = IrqSafeLock::new(None);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy)]
+struct Region {
+    name: &'static str,
+    start: usize,
+    end: usize,
+    access: Access,
+    exec: bool,
+    device: bool,
+    /// Unprivileged code has access too
+    user: bool,
+}
+
+impl Region {
+    const fn new(name: &'static str, start: usize, end: usize, access: Access) -> Self {
+        Self {
+            name,
+            start,
+            end,
+            access,
+            exec: false,
+            device: false,
+            user: false,
+        }
+    }
+}
+
+/// The regions of the table above, empty ones left out
+fn regions() -> Result<([Region; MAX_REGIONS], usize), &'static str> {
+    if stack_guard_s() < ram_s() || ram_e() < stack_s() {
+        return Err("the stack is not in RAM");
+    }
+    let heap_in_ram = ram_s() <= heap_s() && heap_e() <= ram_e();
+    let devices: Range<usize> = board::PERIPHERALS;
+
+    let all = [
+        Region::new("vector", vector_s(), vector_e(), Access::ReadOnly),
+        Region {
+            exec: true,
+            user: true,
+            ..Region::new("rom", rom_s(), rom_e(), Access::ReadOnly)
+        },
+        Region::new("ram", ram_s(), stack_guard_s(), Access::ReadWrite),
+        Region::new("stack", stack_s(), ram_e(), Access::ReadWrite),
+        if heap_in_ram {
+            Region::new("heap", 0, 0, Access::ReadWrite)
+        } else {
+            Region::new("heap", heap_s(), heap_e(), Access::ReadWrite)
+        },
+        Region {
+            device: true,
+            ..Region::new("devices", devices.start, devices.end, Access::ReadWrite)
+        },
+    ];
+
+    // cut out of the regions that hold them, in address order
+    let program = PROGRAM.lock().clone().map(|p| Region {
+        exec: true,
+        user: true,
+        ..Region::new("program", p.start, p.end, Access::ReadWrite)
+    });
+    let stack = THREAD_STACK.lock().clone().map(|s| Region {
+        user: true,
+        ..Region::new("thread", s.start, s.end, Access::ReadWrite)
+    });
+    let mut windows = [program, stack];
+    windows.sort_by_key(|w| w.map(|w| w.start));
+
+    let mut regions = [all[0]; MAX_REGIONS];
+    let mut n = 0;
+    let mut add = |r: Region| {
+        if r.start < r.end {
+            regions[n] = r;
+            n += 1;
+        }
+    };
+    for r in all.iter().filter(|r| r.start < r.end) {
+        if r.start % GRANULE != 0 {
+            return Err("a region is not 32-byte aligned");
+        }
+        let mut start = r.start;
+        for w in windows.iter().flatten() {
+            if r.start <= w.start && w.end <= r.end {
+                add(Region {
+                    start,
+                    end: w.start,
+                    ..*r
+                });
+                add(*w);
+                start = w.end;
+            }
+        }
+        add(Region { start, ..*r });
+    }
+    for w in windows.iter().flatten() {
+        if !regions[..n].iter().any(|r| r.start == w.start && r.user) {
+            return Err("a program or thread stack is not in RAM");
+        }
+    }
+    Ok((regions, n))
+}
+
+fn set_region(n: usize, r: &Region) {
+    let ap = match (r.access, r.user) {
+        (Access::ReadOnly, true) => AP_RO,
+        (Access::ReadOnly, false) => AP_RO_PRIV,
+        (Access::ReadWrite, true) => AP_RW,
+        (Access::ReadWrite, false) => AP_RW_PRIV,
+    };
+    let mut rbar = Rbar::from(r.start as u32) | Rbar::AP.compose(ap);
+    if !r.exec {
+        rbar = rbar | Rbar::XN;
+    }
+    let attr = if r.device { ATTR_DEVICE } else { ATTR_NORMAL };
+    // the limit rounds up to the end of its 32-byte block
+    let limit = (r.end - 1) as u32 & !(GRANULE as u32 - 1);
+    let rlar = Rlar::from(limit) | Rlar::ATTRINDX.compose(attr) | Rlar::EN;
+    unsafe {
+        (*MPU).rnr.write(n as u32);
+        (*MPU).rbar.write(rbar);
+        (*MPU).rlar.write(rlar);
+    }
+}
+
+fn slots() -> usize {
+    unsafe { (*MPU).typ.read().extract(Type::DREGION) as usize }
+}
+
+/// Replace all the regions and enable the MPU
+fn write_regions(regions: &[Region], slots: usize) {
+    unsafe {
+        (*MPU).ctrl.write(Ctrl::from(0));
+        cpu::barrier();
+        (*MPU).mair0.write(MAIR0);
+        for n in 0..slots {
+            (*MPU).rnr.write(n as u32);
+            (*MPU).rlar.write(Rlar::from(0));
+        }
+    }
+    for (n, r) in regions.iter().enumerate() {
+        set_region(n, r);
+    }
+    unsafe { (*MPU).ctrl.write(Ctrl::ENABLE) };
+    cpu::barrier();
+}
+
+/// Program and enable the MPU, on ARMv8-M boards
+pub fn init() {
+    if !board::ARMV8M {
+        info!("no PMSAv8 MPU on this board, memory protection is off");
+        return;
+    }
+
+    let (regions, count) = match regions() {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("{}, memory protection is off", e);
+            return;
+        }
+    };
+    let slots = slots();
+    if slots < count {
+        warn!(
+            "{} regions needed, {} implemented; memory protection is off",
+            count, slots
+        );
+        return;
+    }
+    if regions[..count].iter().any(|r| r.start < NULL_GUARD) {
+        warn!("memory is mapped at address 0, no null guard");
+    }
+
+    write_regions(&regions[..count], slots);
+
+    info!(
+        "{} regions, stack guard at {:08x}-{:08x}",
+        count,
+        stack_guard_s(),
+        stack_s()
+    );
+}
+
+fn enabled() -> bool {
+    board::ARMV8M && unsafe { (*MPU).ctrl.read().is_set(Ctrl::ENABLE) }
+}
+
+/// Turn the MPU off, e.g. for a debugger to patch code in ROM; returns
+/// whether it was on, for `resume()`
+#[allow(dead_code)]
+pub fn suspend() -> bool {
+    let on = enabled();
+    if on {
+        unsafe { (*MPU).ctrl.write(Ctrl::from(0)) };
+        cpu::barrier();
+    }
+    on
+}
+
+/// Turn the MPU back on if `suspend()` found it on
+#[allow(dead_code)]
+pub fn resume(on: bool) {
+    if on {
+        unsafe { (*MPU).ctrl.write(Ctrl::ENABLE) };
+        cpu::barrier();
+    }
+}
+
+/// Rewrite the regions for a change of the program's image or the thread
+/// stack, if the MPU is on
+fn update() -> Result<(), Errno> {
+    if !enabled() {
+        return Ok(());
+    }
+    let (regions, count) = regions().map_err(|e| {
+        warn!("{}", e);
+        Errno::EINVAL
+    })?;
+    if slots() < count {
+        warn!("{} regions needed, {} implemented", count, slots());
+        return Err(Errno::ENOSPC);
+    }
+    let primask = cpu::irq_save();
+    write_regions(&regions[..count], slots());
+    cpu::irq_restore(primask);
+    Ok(())
+}
+
+/// Make `image`, GRANULE-aligned RAM, executable for the program the loader
+/// runs; EBUSY if there is one already
+pub fn map_program(image: Range<usize>) -> Result<(), Errno> {
+    {
+        let mut program = PROGRAM.lock();
+        if program.is_some() {
+            return Err(Errno::EBUSY);
+        }
+        *program = Some(image);
+    }
+    let res = update();
+    if res.is_err() {
+        *PROGRAM.lock() = None;
+    }
+    res
+}
+
+/// Take the program's image back
+pub fn unmap_program() {
+    *PROGRAM.lock() = None;
+    if let Err(e) = update() {
+        warn!("failed to unmap the program: {:?}", e);
+    }
+}
+
+/// Give unprivileged code `stack`, GRANULE-aligned, instead of the last
+/// one; for the scheduler, which passes None when it switches to a
+/// privileged thread
+pub fn map_thread_stack(stack: Option<Range<usize>>) {
+    {
+        let mut mapped = THREAD_STACK.lock();
+        if *mapped == stack {
+            return;
+        }
+        *mapped = stack;
+    }
+    if let Err(e) = update() {
+        warn!("failed to map the thread stack: {:?}", e);
+    }
+}
+
+/// Whether `addr` is in the stack guard
+pub fn in_stack_guard(addr: usize) -> bool {
+    enabled() && stack_guard_s() <= addr && addr < stack_s()
+}
+
+/// What a MemManage fault at `addr` ran into, if the MPU explains it
+pub fn describe(addr: usize) -> Option<&'static str> {
+    if !enabled() {
+        return None;
+    }
+    if addr < NULL_GUARD {
+        return Some("null pointer dereference (null guard)");
+    }
+    if in_stack_guard(addr) {
+        return Some("kernel stack overflow (stack guard)");
+    }
+    let (regions, count) = regions().ok()?;
+    match regions[..count]
+        .iter()
+        .find(|r| r.start <= addr && addr < r.end)
+    {
+        Some(r) if r.access == Access::ReadOnly => Some("write to read-only memory"),
+        Some(_) => None,
+        None => Some("access to unmapped memory"),
+    }
+}
+
+fn cmd_mpu(_args: &[&str]) -> Result<(), Errno> {
+    if !enabled() {
+        println!("MPU off");
+        return Ok(());
+    }
+    let (regions, count) = regions().map_err(|_| Errno::EINVAL)?;
+    for (n, r) in regions[..count].iter().enumerate() {
+        println!(
+            "{:2}  {:08x}-{:08x}  {}{}{}  {:7}  {}",
+            n,
+            r.start,
+            r.end,
+            if r.access == Access::ReadWrite {
+                "rw"
+            } else {
+                "r-"
+            },
+            if r.exec { "x" } else { "-" },
+            if r.user { "u" } else { "-" },
+            if r.device { "device" } else { "normal" },
+            r.name
+        );
+    }
+    println!(
+        " -  {:08x}-{:08x}  ----  guard    stack",
+        stack_guard_s(),
+        stack_s()
+    );
+    println!(" -  {:08x}-{:08x}  ----  guard    null", 0, NULL_GUARD);
+    Ok(())
+}
+
+shell_command!(CMD_MPU, "mpu", "show the MPU regions", cmd_mpu);
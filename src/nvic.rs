@@ -0,0 +1,98 @@
+extern crate mmio;
+
+use mmio::RegisterArrayRW;
+
+use crate::board;
+
+/// Nested Vectored Interrupt Controller
+pub struct Nvic {
+    iser: RegisterArrayRW<0x000, u32, u32, 16>,
+    icer: RegisterArrayRW<0x080, u32, u32, 16>,
+    ispr: RegisterArrayRW<0x100, u32, u32, 16>,
+    icpr: RegisterArrayRW<0x180, u32, u32, 16>,
+    iabr: RegisterArrayRW<0x200, u32, u32, 16>,
+    itns: RegisterArrayRW<0x280, u32, u32, 16>,
+    ipr: RegisterArrayRW<0x300, u8, u8, 496>,
+}
+
+const NVIC: *mut Nvic = 0xE000_E100 as *mut Nvic;
+
+/// Security state an interrupt is routed to
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    Secure,
+    NonSecure,
+}
+
+fn split(irq: usize) -> (usize, u32) {
+    assert!(irq < board::IRQ_COUNT, "invalid irq number: {}", irq);
+    (irq / 32, 1 << (irq % 32))
+}
+
+pub fn enable(irq: usize) {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).iser.write_at(n, bit) }
+}
+
+pub fn disable(irq: usize) {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).icer.write_at(n, bit) }
+}
+
+#[allow(dead_code)]
+pub fn is_enabled(irq: usize) -> bool {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).iser.read_at(n) & bit != 0 }
+}
+
+#[allow(dead_code)]
+pub fn set_pending(irq: usize) {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).ispr.write_at(n, bit) }
+}
+
+pub fn clear_pending(irq: usize) {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).icpr.write_at(n, bit) }
+}
+
+#[allow(dead_code)]
+pub fn is_pending(irq: usize) -> bool {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).ispr.read_at(n) & bit != 0 }
+}
+
+#[allow(dead_code)]
+pub fn is_active(irq: usize) -> bool {
+    let (n, bit) = split(irq);
+    unsafe { (*NVIC).iabr.read_at(n) & bit != 0 }
+}
+
+/// Lower value is higher priority; unimplemented low bits are ignored
+#[allow(dead_code)]
+pub fn set_priority(irq: usize, prio: u8) {
+    split(irq);
+    unsafe { (*NVIC).ipr.write_at(irq, prio) }
+}
+
+#[allow(dead_code)]
+pub fn priority(irq: usize) -> u8 {
+    split(irq);
+    unsafe { (*NVIC).ipr.read_at(irq) }
+}
+
+/// Route the interrupt to the Secure or Non-secure state (ITNS). A single
+/// core ARMv8-M has no other affinity to choose.
+#[allow(dead_code)]
+pub fn set_affinity(irq: usize, target: Target) {
+    let (n, bit) = split(irq);
+    unsafe {
+        let v = (*NVIC).itns.read_at(n);
+        let v = match target {
+            Target::Secure => v & !bit,
+            Target::NonSecure => v | bit,
+        };
+        (*NVIC).itns.write_at(n, v)
+    }
+}
@@ -0,0 +1,231 @@
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+
+use mmio::{Readable, RegisterRW, Writeable};
+
+use crate::board;
+
+bitfield! {
+    Icsr: u32 {
+        PENDSVSET[28];
+    }
+}
+
+bitfield! {
+    Ccr: u32 {
+        UNALIGN_TRP[3];
+        DIV_0_TRP[4];
+    }
+}
+
+bitfield! {
+    Shcsr: u32 {
+        MEMFAULTENA[16];
+        BUSFAULTENA[17];
+        USGFAULTENA[18];
+        SECUREFAULTENA[19];
+    }
+}
+
+// Configurable Fault Status: MMFSR[7:0], BFSR[15:8], UFSR[31:16]
+bitfield! {
+    pub Cfsr: u32 {
+        IACCVIOL[0];
+        DACCVIOL[1];
+        MUNSTKERR[3];
+        MSTKERR[4];
+        MLSPERR[5];
+        MMARVALID[7];
+        IBUSERR[8];
+        PRECISERR[9];
+        IMPRECISERR[10];
+        UNSTKERR[11];
+        STKERR[12];
+        LSPERR[13];
+        BFARVALID[15];
+        UNDEFINSTR[16];
+        INVSTATE[17];
+        INVPC[18];
+        NOCP[19];
+        STKOF[20];
+        UNALIGNED[24];
+        DIVBYZERO[25];
+    }
+}
+
+// HardFault Status
+bitfield! {
+    pub Hfsr: u32 {
+        VECTTBL[1];
+        FORCED[30];
+    }
+}
+
+// SecureFault Status, ARMv8-M only
+bitfield! {
+    pub Sfsr: u32 {
+        INVEP[0];
+        INVIS[1];
+        INVER[2];
+        AUVIOL[3];
+        INVTRAN[4];
+        LSPERR[5];
+        SFARVALID[6];
+        LSERR[7];
+    }
+}
+
+// Debug Exception and Monitor Control
+bitfield! {
+    Demcr: u32 {
+        MON_EN[16];
+        MON_PEND[17];
+        MON_STEP[18];
+    }
+}
+
+/// System Control Block
+pub struct Scb {
+    icsr: RegisterRW<0x04, u32, Icsr>,
+    ccr: RegisterRW<0x14, u32, Ccr>,
+    shpr2: RegisterRW<0x1C, u32, u32>,
+    shpr3: RegisterRW<0x20, u32, u32>,
+    shcsr: RegisterRW<0x24, u32, Shcsr>,
+    cfsr: RegisterRW<0x28, u32, Cfsr>,
+    hfsr: RegisterRW<0x2C, u32, Hfsr>,
+    mmfar: RegisterRW<0x34, u32, u32>,
+    bfar: RegisterRW<0x38, u32, u32>,
+    sfsr: RegisterRW<0xE4, u32, Sfsr>,
+    sfar: RegisterRW<0xE8, u32, u32>,
+    demcr: RegisterRW<0xFC, u32, Demcr>,
+}
+
+/// Fault status and address registers
+pub struct FaultStatus {
+    pub cfsr: Cfsr,
+    pub hfsr: Hfsr,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub sfsr: Sfsr,
+    pub sfar: u32,
+}
+
+const SCB: *mut Scb = 0xE000_ED00 as *mut Scb;
+
+pub const PRIO_LOWEST: u8 = 0xff;
+/// For the clock interrupts: above system calls, which run at PRIO_LOWEST
+/// like PendSV, so that no tick is lost to a long one
+pub const PRIO_TICK: u8 = 0x80;
+
+pub fn set_pendsv() {
+    unsafe { (*SCB).icsr.write(Icsr::PENDSVSET) }
+}
+
+pub fn set_pendsv_priority(prio: u8) {
+    unsafe {
+        let v = (*SCB).shpr3.read();
+        (*SCB)
+            .shpr3
+            .write((v & !(0xff << 16)) | ((prio as u32) << 16));
+    }
+}
+
+pub fn set_svc_priority(prio: u8) {
+    unsafe {
+        let v = (*SCB).shpr2.read();
+        (*SCB)
+            .shpr2
+            .write((v & !(0xff << 24)) | ((prio as u32) << 24));
+    }
+}
+
+pub fn set_systick_priority(prio: u8) {
+    unsafe {
+        let v = (*SCB).shpr3.read();
+        (*SCB)
+            .shpr3
+            .write((v & !(0xff << 24)) | ((prio as u32) << 24));
+    }
+}
+
+/// Give MemManage, BusFault, UsageFault and, on ARMv8-M, SecureFault their own handlers
+/// instead of escalating to HardFault, and trap divisions by zero
+pub fn enable_faults() {
+    unsafe {
+        let mut v =
+            (*SCB).shcsr.read() | Shcsr::MEMFAULTENA | Shcsr::BUSFAULTENA | Shcsr::USGFAULTENA;
+        if board::ARMV8M {
+            v = v | Shcsr::SECUREFAULTENA;
+        }
+        (*SCB).shcsr.write(v);
+        let v = (*SCB).ccr.read();
+        (*SCB).ccr.write(v | Ccr::DIV_0_TRP);
+    }
+}
+
+pub fn fault_status() -> FaultStatus {
+    unsafe {
+        FaultStatus {
+            cfsr: (*SCB).cfsr.read(),
+            hfsr: (*SCB).hfsr.read(),
+            mmfar: (*SCB).mmfar.read(),
+            bfar: (*SCB).bfar.read(),
+            sfsr: if board::ARMV8M {
+                (*SCB).sfsr.read()
+            } else {
+                Sfsr::from(0)
+            },
+            sfar: if board::ARMV8M { (*SCB).sfar.read() } else { 0 },
+        }
+    }
+}
+
+/// Clear the fault status registers, which are write-one-to-clear
+#[allow(dead_code)]
+pub fn clear_fault_status() {
+    unsafe {
+        let v = (*SCB).cfsr.read();
+        (*SCB).cfsr.write(v);
+        let v = (*SCB).hfsr.read();
+        (*SCB).hfsr.write(v);
+        if board::ARMV8M {
+            let v = (*SCB).sfsr.read();
+            (*SCB).sfsr.write(v);
+        }
+    }
+}
+
+/// Take `bkpt` and the events below as DebugMonitor exceptions rather than
+/// HardFaults
+#[allow(dead_code)]
+pub fn enable_debug_monitor() {
+    unsafe {
+        let v = (*SCB).demcr.read();
+        (*SCB).demcr.write(v | Demcr::MON_EN);
+    }
+}
+
+/// Raise DebugMonitor as soon as the priority allows
+#[allow(dead_code)]
+pub fn pend_debug_monitor() {
+    unsafe {
+        let v = (*SCB).demcr.read();
+        (*SCB).demcr.write(v | Demcr::MON_PEND);
+    }
+}
+
+/// Raise DebugMonitor again after one instruction once the handler returns
+#[allow(dead_code)]
+pub fn set_monitor_step(step: bool) {
+    unsafe {
+        let v = (*SCB).demcr.read();
+        let v = if step {
+            v | Demcr::MON_STEP
+        } else {
+            Demcr::from(u32::from(v) & !u32::from(Demcr::MON_STEP))
+        };
+        (*SCB).demcr.write(v);
+    }
+}
@@ -0,0 +1,409 @@
+/*
+
+Every thread runs in Thread mode on its own PSP stack carved from the heap.
+SysTick counts down the time slice of the running thread and pends PendSV,
+which saves the callee-saved registers on top of the hardware exception
+frame and switches PSP to the next ready thread (round-robin). The MPU
+gives an unprivileged thread its own stack while it runs (see mpu.rs).
+
+Context frame on the thread stack:
+
+    +----------------+  <- Thread::sp
+    |    CONTROL     |
+    |       R4       |
+    |       R5       |
+    |       R6       |
+    |       R7       |
+    |       R8       |
+    |       R9       |
+    |       R10      |
+    |       R11      |
+    |   EXC_RETURN   |
+    +----------------+  <- PSP on exception entry
+    |       R0       |
+    |       R1       |
+    |       R2       |
+    |       R3       |
+    |       R12      |
+    |    R14 (LR)    |
+    | Return address |
+    |      xPSR      |
+    +----------------+  <- PSP before exception
+
+ */
+
+extern crate posix;
+
+use alloc::{boxed::Box, format, vec, vec::Vec};
+use core::{arch::asm, mem::size_of, ops::Range, ptr};
+use posix::Errno;
+
+use crate::sync::IrqSafeLock;
+use crate::{cpu, log, mpu, println, scb, shell_command, user};
+
+pub type ThreadId = usize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping,
+    Dead,
+}
+
+const STACK_SIZE: usize = 4096;
+const TIME_SLICE: u32 = 10; // ticks
+
+const IDLE: usize = 0; // index of the idle thread in THREADS
+
+const EXC_RETURN_THREAD_PSP: u32 = 0xffff_fffd;
+const XPSR_T: u32 = 1 << 24;
+const CONTROL_NPRIV: u32 = 1 << 0;
+
+#[repr(C)]
+struct ContextFrame {
+    control: u32,
+    r4: u32,
+    r5: u32,
+    r6: u32,
+    r7: u32,
+    r8: u32,
+    r9: u32,
+    r10: u32,
+    r11: u32,
+    exc_return: u32,
+    r0: u32,
+    r1: u32,
+    r2: u32,
+    r3: u32,
+    r12: u32,
+    lr: u32,
+    return_address: u32,
+    xpsr: u32,
+}
+
+/// Stacks are made of these, so that the MPU can map those of
+/// unprivileged threads
+#[repr(C, align(32))]
+#[derive(Clone, Copy)]
+struct StackBlock([u8; mpu::GRANULE]);
+
+struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    state: ThreadState,
+    wake_at: u64,
+    sp: usize,
+    privileged: bool,
+    stack: Box<[StackBlock]>,
+}
+
+impl Thread {
+    fn new(id: ThreadId, name: &'static str, entry: usize, privileged: bool) -> Self {
+        let blocks = STACK_SIZE / size_of::<StackBlock>();
+        let stack = vec![StackBlock([0; mpu::GRANULE]); blocks].into_boxed_slice();
+        let sp = stack.as_ptr() as usize + STACK_SIZE - size_of::<ContextFrame>();
+
+        let (control, start) = if privileged {
+            (0, thread_start as usize)
+        } else {
+            (CONTROL_NPRIV, user::thread_start as usize)
+        };
+
+        let frame = ContextFrame {
+            control,
+            r4: 0,
+            r5: 0,
+            r6: 0,
+            r7: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            exc_return: EXC_RETURN_THREAD_PSP,
+            r0: entry as u32,
+            r1: 0,
+            r2: 0,
+            r3: 0,
+            r12: 0,
+            lr: 0,
+            return_address: (start & !1) as u32,
+            xpsr: XPSR_T,
+        };
+        unsafe { ptr::write(sp as *mut ContextFrame, frame) };
+
+        Self {
+            id,
+            name,
+            state: ThreadState::Ready,
+            wake_at: 0,
+            sp,
+            privileged,
+            stack,
+        }
+    }
+
+    fn stack_range(&self) -> Range<usize> {
+        let start = self.stack.as_ptr() as usize;
+        start..start + self.stack.len() * size_of::<StackBlock>()
+    }
+}
+
+struct Scheduler {
+    threads: Vec<Thread>,
+    /// Index of the running thread in `threads`
+    current: Option<usize>,
+    next_id: ThreadId,
+    /// Ticks left of the running thread's time slice
+    slice: u32,
+}
+
+impl Scheduler {
+    fn pick_next(&self) -> usize {
+        let n = self.threads.len();
+        let start = self.current.map_or(0, |cur| cur + 1);
+        for i in 0..n {
+            let idx = (start + i) % n;
+            if idx != IDLE && self.threads[idx].state == ThreadState::Ready {
+                return idx;
+            }
+        }
+        IDLE
+    }
+
+    fn current(&self) -> Option<&Thread> {
+        self.current.map(|cur| &self.threads[cur])
+    }
+
+    fn current_mut(&mut self) -> Option<&mut Thread> {
+        self.current.map(move |cur| &mut self.threads[cur])
+    }
+}
+
+static SCHED: IrqSafeLock<Scheduler> = IrqSafeLock::new(Scheduler {
+    threads: Vec::new(),
+    current: None,
+    next_id: 1,
+    slice: 0,
+});
+
+extern "C" fn thread_start(entry: usize) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    entry();
+    exit()
+}
+
+fn idle() {
+    loop {
+        reap();
+        log::flush_console();
+        cpu::wfi();
+    }
+}
+
+/// Free the stacks of exited threads
+fn reap() {
+    SCHED
+        .lock()
+        .threads
+        .retain(|t| t.state != ThreadState::Dead);
+}
+
+fn do_spawn(name: &'static str, entry: usize, privileged: bool) -> ThreadId {
+    let mut sched = SCHED.lock();
+    let id = sched.next_id;
+    sched.next_id += 1;
+    sched.threads.push(Thread::new(id, name, entry, privileged));
+    id
+}
+
+pub fn spawn(name: &'static str, entry: fn()) -> ThreadId {
+    do_spawn(name, entry as usize, true)
+}
+
+/// Spawn a thread running unprivileged; it has to use `user::*` to reach the kernel
+pub fn spawn_user(name: &'static str, entry: extern "C" fn()) -> ThreadId {
+    do_spawn(name, entry as usize, false)
+}
+
+/// Start scheduling with `init` as the first thread. The boot flow is abandoned.
+pub fn start(init: fn()) -> ! {
+    {
+        let mut sched = SCHED.lock();
+        sched
+            .threads
+            .insert(IDLE, Thread::new(0, "idle", idle as fn() as usize, true));
+
+        // The first PendSV saves a context of the boot flow on PSP, which is
+        // discarded; let it go to the unused part of the idle stack.
+        unsafe { asm!("msr psp, {}", in(reg) sched.threads[IDLE].sp) };
+    }
+
+    spawn("init", init);
+
+    scb::set_pendsv_priority(scb::PRIO_LOWEST);
+    // interrupts preempt system calls, which switch threads on return
+    scb::set_svc_priority(scb::PRIO_LOWEST);
+
+    yield_now();
+    cpu::irq_enable();
+
+    loop {}
+}
+
+pub fn yield_now() {
+    scb::set_pendsv();
+    cpu::barrier();
+}
+
+/// Mark the current thread exited and request a switch; it won't be resumed
+pub fn terminate_current() {
+    if let Some(t) = SCHED.lock().current_mut() {
+        t.state = ThreadState::Dead;
+    }
+
+    yield_now();
+}
+
+pub fn exit() -> ! {
+    terminate_current();
+    loop {}
+}
+
+/// Block the current thread until the tick count reaches `tick`
+pub fn sleep_until(tick: u64) {
+    if let Some(t) = SCHED.lock().current_mut() {
+        t.wake_at = tick;
+        t.state = ThreadState::Sleeping;
+    }
+
+    yield_now();
+}
+
+/// Make a sleeping thread ready before its wake-up tick; for interrupt
+/// handlers too
+pub fn wake(id: ThreadId) {
+    let mut sched = SCHED.lock();
+    if let Some(t) = sched.threads.iter_mut().find(|t| t.id == id) {
+        if t.state == ThreadState::Sleeping {
+            t.state = ThreadState::Ready;
+            if sched.current == Some(IDLE) {
+                scb::set_pendsv();
+            }
+        }
+    }
+}
+
+pub fn current() -> Option<ThreadId> {
+    SCHED.lock().current().map(|t| t.id)
+}
+
+/// None also while the scheduler is busy, i.e. for a fault or panic there
+pub fn current_name() -> Option<&'static str> {
+    SCHED.try_lock()?.current().map(|t| t.name)
+}
+
+/// Stack area of the current thread, for the unwinder; None also while the
+/// scheduler is busy
+pub fn current_stack() -> Option<(usize, usize)> {
+    let stack = SCHED.try_lock()?.current()?.stack_range();
+    Some((stack.start, stack.end))
+}
+
+fn state_of(id: ThreadId) -> Option<ThreadState> {
+    let sched = SCHED.lock();
+    let thread = sched.threads.iter().find(|t| t.id == id);
+    thread.map(|t| t.state)
+}
+
+/// Wait until the thread exits
+pub fn join(id: ThreadId) {
+    while let Some(state) = state_of(id) {
+        if state == ThreadState::Dead {
+            break;
+        }
+        yield_now();
+    }
+}
+
+/// Call `func` for each thread, as they were when it was called
+pub fn for_each<F>(mut func: F)
+where
+    F: FnMut(ThreadId, &'static str, ThreadState),
+{
+    let threads: Vec<_> = {
+        let sched = SCHED.lock();
+        sched
+            .threads
+            .iter()
+            .map(|t| (t.id, t.name, t.state))
+            .collect()
+    };
+    for (id, name, state) in threads {
+        func(id, name, state);
+    }
+}
+
+fn cmd_ps(_args: &[&str]) -> Result<(), Errno> {
+    println!("  ID  STATE     NAME");
+    for_each(|id, name, state| {
+        println!("{:4}  {:8}  {}", id, format!("{:?}", state), name);
+    });
+    Ok(())
+}
+
+shell_command!(CMD_PS, "ps", "list threads", cmd_ps);
+
+#[no_mangle]
+extern "C" fn __sched_switch(sp: usize) -> usize {
+    let (sp, stack) = {
+        let mut sched = SCHED.lock();
+        if let Some(t) = sched.current_mut() {
+            t.sp = sp;
+            if t.state == ThreadState::Running {
+                t.state = ThreadState::Ready;
+            }
+        }
+
+        let next = sched.pick_next();
+        sched.current = Some(next);
+        sched.slice = TIME_SLICE;
+        let t = &mut sched.threads[next];
+        t.state = ThreadState::Running;
+        (t.sp, (!t.privileged).then(|| t.stack_range()))
+    };
+    mpu::map_thread_stack(stack);
+    sp
+}
+
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn __pendsv() {
+    asm!(
+        "mrs r0, psp",
+        "mrs r1, control",
+        "stmdb r0!, {{r1, r4-r11, lr}}",
+        "bl __sched_switch",
+        "ldmia r0!, {{r1, r4-r11, lr}}",
+        "msr control, r1",
+        "isb",
+        "msr psp, r0",
+        "bx lr",
+        options(noreturn)
+    )
+}
+
+/// Called from SysTick with the new tick count
+pub fn tick(now: u64) {
+    let mut sched = SCHED.lock();
+    for t in sched.threads.iter_mut() {
+        if t.state == ThreadState::Sleeping && t.wake_at <= now {
+            t.state = ThreadState::Ready;
+        }
+    }
+
+    sched.slice = sched.slice.saturating_sub(1);
+    if sched.slice == 0 || sched.current == Some(IDLE) {
+        scb::set_pendsv();
+    }
+}
@@ -0,0 +1,113 @@
+/*
+
+Calls into the secure monitor (secure/), for a kernel that runs in the
+non-secure state under it.
+
+The calls and their veneer table are described in secure_abi. They run
+with IRQs masked: the monitor has one stack, and a thread switch in the
+middle of a call would let another call run on top of it.
+
+ */
+
+extern crate posix;
+extern crate secure_abi;
+
+use core::{mem, str};
+use posix::Errno;
+use secure_abi::{
+    CALL_STORAGE_READ, CALL_STORAGE_WRITE, CALL_VERSION, STORAGE_SLOTS, STORAGE_SLOT_SIZE,
+    VENEER_SIZE, VERSION,
+};
+
+use crate::{cpu, decl_c_symbol_addr, info, println, shell_command, warn};
+
+decl_c_symbol_addr!(__secure_veneers, secure_veneers);
+
+/// Address of the veneer of `call`, with the Thumb bit
+fn veneer(call: usize) -> usize {
+    (secure_veneers() + call * VENEER_SIZE) | 1
+}
+
+fn call0(call: usize) -> u32 {
+    let f: extern "C" fn() -> u32 = unsafe { mem::transmute(veneer(call)) };
+    let primask = cpu::irq_save();
+    let ret = f();
+    cpu::irq_restore(primask);
+    ret
+}
+
+fn call3(call: usize, a0: u32, a1: usize, a2: usize) -> Result<usize, Errno> {
+    let f: extern "C" fn(u32, usize, usize) -> i32 = unsafe { mem::transmute(veneer(call)) };
+    let primask = cpu::irq_save();
+    let ret = f(a0, a1, a2);
+    cpu::irq_restore(primask);
+    match ret {
+        n if n < 0 => Err(Errno::try_from(-n).unwrap_or(Errno::EIO)),
+        n => Ok(n as usize),
+    }
+}
+
+pub fn version() -> u32 {
+    call0(CALL_VERSION)
+}
+
+/// Read storage slot `slot` into `buf`; returns the length of its
+/// contents, of which only what fits in `buf` is copied
+pub fn storage_read(slot: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    call3(
+        CALL_STORAGE_READ,
+        slot as u32,
+        buf.as_mut_ptr() as usize,
+        buf.len(),
+    )
+}
+
+/// Replace the contents of storage slot `slot` with `data`
+pub fn storage_write(slot: usize, data: &[u8]) -> Result<(), Errno> {
+    call3(
+        CALL_STORAGE_WRITE,
+        slot as u32,
+        data.as_ptr() as usize,
+        data.len(),
+    )
+    .map(|_| ())
+}
+
+pub fn init() {
+    match version() {
+        VERSION => info!(
+            "secure monitor version {}, {} storage slots of {} bytes",
+            VERSION, STORAGE_SLOTS, STORAGE_SLOT_SIZE
+        ),
+        v => warn!(
+            "secure monitor version {}, expected {}; calls may fail",
+            v, VERSION
+        ),
+    }
+}
+
+fn cmd_secstore(args: &[&str]) -> Result<(), Errno> {
+    let parse_slot = |s: &str| s.parse::<usize>().or(Err(Errno::EINVAL));
+    match args {
+        [_, "get", slot] => {
+            let mut buf = [0u8; STORAGE_SLOT_SIZE];
+            let len = storage_read(parse_slot(slot)?, &mut buf)?;
+            println!("{}", str::from_utf8(&buf[..len]).unwrap_or("(binary)"));
+        }
+        [_, "put", slot, words @ ..] => {
+            storage_write(parse_slot(slot)?, words.join(" ").as_bytes())?;
+        }
+        _ => {
+            println!("usage: secstore get <slot> | secstore put <slot> [text...]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_SECSTORE,
+    "secstore",
+    "read or write a slot of the secure storage",
+    cmd_secstore
+);
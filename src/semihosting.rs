@@ -1,11 +1,231 @@
-use core::arch::asm;
+/*
 
-pub fn shutdown() {
-    unsafe {
-        asm!(
-            "bkpt 0xab",
-            in("r0") 0x18,
-            in("r1") 0x20026,
+ARM semihosting client.
+
+Requests trap to the debugger or emulator (`qemu-system-arm -semihosting`)
+with `bkpt 0xab`; r0 holds the operation number and r1 a parameter, usually
+the address of a parameter block. Failed operations report the host errno
+through SYS_ERRNO, which is mapped to `posix::Errno`.
+
+[refs]
+- https://github.com/ARM-software/abi-aa/blob/main/semihosting/semihosting.rst
+
+ */
+
+extern crate posix;
+
+use core::{arch::asm, str, time::Duration};
+use posix::Errno;
+
+const SYS_OPEN: usize = 0x01;
+const SYS_CLOSE: usize = 0x02;
+const SYS_WRITEC: usize = 0x03;
+const SYS_WRITE0: usize = 0x04;
+const SYS_WRITE: usize = 0x05;
+const SYS_READ: usize = 0x06;
+const SYS_READC: usize = 0x07;
+const SYS_SEEK: usize = 0x0a;
+const SYS_FLEN: usize = 0x0c;
+const SYS_CLOCK: usize = 0x10;
+const SYS_TIME: usize = 0x11;
+const SYS_ERRNO: usize = 0x13;
+const SYS_GET_CMDLINE: usize = 0x15;
+const SYS_EXIT: usize = 0x18;
+const SYS_EXIT_EXTENDED: usize = 0x20;
+const SYS_ELAPSED: usize = 0x30;
+const SYS_TICKFREQ: usize = 0x31;
+
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+const ADP_STOPPED_RUNTIME_ERROR_UNKNOWN: usize = 0x20023;
+
+// Names are passed NUL-terminated, so they are copied into a buffer
+const PATH_MAX: usize = 256;
+
+/// Open modes; all of them are binary
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Read = 1,        // "rb"
+    ReadWrite = 3,   // "r+b"
+    Write = 5,       // "wb"
+    WriteRead = 7,   // "w+b"
+    Append = 9,      // "ab"
+    AppendRead = 11, // "a+b"
+}
+
+/// A file open on the host
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Handle(usize);
+
+unsafe fn call(op: usize, param: usize) -> isize {
+    let ret: usize;
+    asm!(
+        "bkpt 0xab",
+        inout("r0") op => ret,
+        in("r1") param,
+        options(nostack)
+    );
+    ret as isize
+}
+
+fn call_block(op: usize, block: &[usize]) -> isize {
+    unsafe { call(op, block.as_ptr() as usize) }
+}
+
+/// Host errno of the last failed operation
+pub fn errno() -> Errno {
+    let errno = unsafe { call(SYS_ERRNO, 0) };
+    Errno::try_from(errno as i32).unwrap_or(Errno::EIO)
+}
+
+fn check(ret: isize) -> Result<usize, Errno> {
+    if ret == -1 {
+        Err(errno())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+fn with_cstr<F>(s: &str, func: F) -> Result<isize, Errno>
+where
+    F: FnOnce(&[u8]) -> isize,
+{
+    let mut buf: [u8; PATH_MAX] = [0; PATH_MAX];
+    if s.len() >= PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if s.bytes().any(|c| c == 0) {
+        return Err(Errno::EINVAL);
+    }
+    buf[..s.len()].copy_from_slice(s.as_bytes());
+    Ok(func(&buf[..s.len() + 1]))
+}
+
+pub fn open(path: &str, mode: Mode) -> Result<Handle, Errno> {
+    let ret = with_cstr(path, |name| {
+        call_block(
+            SYS_OPEN,
+            &[name.as_ptr() as usize, mode as usize, name.len() - 1],
         )
+    })?;
+    check(ret).map(Handle)
+}
+
+pub fn close(handle: Handle) -> Result<(), Errno> {
+    check(call_block(SYS_CLOSE, &[handle.0])).map(|_| ())
+}
+
+/// Returns the number of bytes read, 0 at the end of the file
+pub fn read(handle: Handle, buf: &mut [u8]) -> Result<usize, Errno> {
+    let ret = call_block(SYS_READ, &[handle.0, buf.as_mut_ptr() as usize, buf.len()]);
+    // the number of bytes *not* read
+    match ret as usize {
+        left if left <= buf.len() => Ok(buf.len() - left),
+        _ => Err(errno()),
+    }
+}
+
+pub fn write(handle: Handle, buf: &[u8]) -> Result<usize, Errno> {
+    let ret = call_block(SYS_WRITE, &[handle.0, buf.as_ptr() as usize, buf.len()]);
+    // the number of bytes *not* written
+    match ret as usize {
+        0 => Ok(buf.len()),
+        left if left < buf.len() => Ok(buf.len() - left),
+        _ => Err(errno()),
+    }
+}
+
+/// Move to the absolute position `pos`
+pub fn seek(handle: Handle, pos: usize) -> Result<(), Errno> {
+    match call_block(SYS_SEEK, &[handle.0, pos]) {
+        0 => Ok(()),
+        _ => Err(errno()),
+    }
+}
+
+/// Length of the file
+pub fn flen(handle: Handle) -> Result<usize, Errno> {
+    check(call_block(SYS_FLEN, &[handle.0]))
+}
+
+/// Write a byte to the debug console
+#[allow(dead_code)]
+pub fn writec(c: u8) {
+    unsafe { call(SYS_WRITEC, &c as *const u8 as usize) };
+}
+
+/// Write a string to the debug console
+#[allow(dead_code)]
+pub fn write0(s: &str) {
+    let mut buf: [u8; 64] = [0; 64];
+    for chunk in s.as_bytes().chunks(buf.len() - 1) {
+        buf[..chunk.len()].copy_from_slice(chunk);
+        buf[chunk.len()] = 0;
+        unsafe { call(SYS_WRITE0, buf.as_ptr() as usize) };
+    }
+}
+
+/// Read a byte from the debug console, blocking
+#[allow(dead_code)]
+pub fn readc() -> u8 {
+    unsafe { call(SYS_READC, 0) as u8 }
+}
+
+/// Execution time since the program started, with centisecond resolution
+#[allow(dead_code)]
+pub fn clock() -> Result<Duration, Errno> {
+    let cs = check(unsafe { call(SYS_CLOCK, 0) })?;
+    Ok(Duration::from_millis(cs as u64 * 10))
+}
+
+/// Seconds since 1970-01-01 00:00:00 UTC on the host
+#[allow(dead_code)]
+pub fn time() -> Result<u64, Errno> {
+    check(unsafe { call(SYS_TIME, 0) }).map(|secs| secs as u64)
+}
+
+/// Ticks since the program started; see `tickfreq()`
+#[allow(dead_code)]
+pub fn elapsed() -> Result<u64, Errno> {
+    let mut ticks: [usize; 2] = [0; 2];
+    match unsafe { call(SYS_ELAPSED, ticks.as_mut_ptr() as usize) } {
+        0 => Ok(ticks[0] as u64 | (ticks[1] as u64) << 32),
+        _ => Err(errno()),
+    }
+}
+
+/// Frequency of the `elapsed()` ticks in Hz
+#[allow(dead_code)]
+pub fn tickfreq() -> Result<u64, Errno> {
+    check(unsafe { call(SYS_TICKFREQ, 0) }).map(|hz| hz as u64)
+}
+
+/// Command line the program was started with (`-append` on QEMU)
+#[allow(dead_code)]
+pub fn get_cmdline(buf: &mut [u8]) -> Result<&str, Errno> {
+    let mut block: [usize; 2] = [buf.as_mut_ptr() as usize, buf.len()];
+    match unsafe { call(SYS_GET_CMDLINE, block.as_mut_ptr() as usize) } {
+        0 => str::from_utf8(&buf[..block[1]]).or(Err(Errno::EINVAL)),
+        _ => Err(errno()),
     }
 }
+
+/// Terminate the emulator with the exit status `code`
+pub fn exit(code: i32) -> ! {
+    let block = [ADP_STOPPED_APPLICATION_EXIT, code as usize];
+    call_block(SYS_EXIT_EXTENDED, &block);
+
+    // SYS_EXIT_EXTENDED is not supported; only success or failure can be told
+    let reason = if code == 0 {
+        ADP_STOPPED_APPLICATION_EXIT
+    } else {
+        ADP_STOPPED_RUNTIME_ERROR_UNKNOWN
+    };
+    unsafe { call(SYS_EXIT, reason) };
+
+    loop {}
+}
+
+pub fn shutdown() {
+    exit(0)
+}
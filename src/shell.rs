@@ -0,0 +1,359 @@
+/*
+
+Kernel command shell on the serial console.
+
+Commands are `Command` statics put in the .shell_cmds section by
+`shell_command!`. The linker script gathers them between __shell_cmds_s
+and __shell_cmds_e, so any module can add its own commands without
+touching this file:
+
+    shell_command!(CMD_PS, "ps", "list threads", cmd_ps);
+
+The line editor handles backspace, Ctrl-C (drops the line) and history
+on the up/down arrow keys. Other escape sequences, like Delete or Home,
+are read to their end and ignored.
+
+ */
+
+extern crate posix;
+extern crate vfs;
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::{mem::size_of, slice};
+use posix::Errno;
+use vfs::OpenMode;
+
+use crate::{backtrace, console, heap, print, println, semihosting, user};
+
+use crate::decl_c_symbol_addr;
+decl_c_symbol_addr!(__shell_cmds_s, shell_cmds_s);
+decl_c_symbol_addr!(__shell_cmds_e, shell_cmds_e);
+
+/// `args[0]` is the command name
+pub type CommandFn = fn(args: &[&str]) -> Result<(), Errno>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub func: CommandFn,
+}
+
+#[macro_export]
+macro_rules! shell_command {
+    ($ident:ident, $name:literal, $help:literal, $func:expr) => {
+        #[used]
+        #[link_section = ".shell_cmds"]
+        static $ident: $crate::shell::Command = $crate::shell::Command {
+            name: $name,
+            help: $help,
+            func: $func,
+        };
+    };
+}
+
+const PROMPT: &str = "# ";
+const LINE_MAX: usize = 128;
+const HISTORY_MAX: usize = 16;
+
+const CTRL_C: u8 = 0x03;
+const BS: u8 = 0x08;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+fn commands() -> &'static [Command] {
+    let n = (shell_cmds_e() - shell_cmds_s()) / size_of::<Command>();
+    unsafe { slice::from_raw_parts(shell_cmds_s() as *const Command, n) }
+}
+
+enum Input {
+    Line(String),
+    Cancel,
+}
+
+#[derive(PartialEq)]
+enum EscState {
+    None,
+    Esc,
+    Csi,
+}
+
+struct Shell {
+    history: VecDeque<String>,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+        }
+    }
+
+    fn redraw(line: &str) {
+        print!("\r\x1b[K{}{}", PROMPT, line);
+    }
+
+    fn read_line(&self) -> Input {
+        let mut line = String::new();
+        let mut pos = self.history.len(); // == len while editing a new line
+        let mut esc = EscState::None;
+        let mut last = 0u8;
+
+        print!("{}", PROMPT);
+        loop {
+            let c = console::getc();
+            let prev = last;
+            last = c;
+
+            match esc {
+                EscState::Esc => {
+                    esc = if c == b'[' {
+                        EscState::Csi
+                    } else {
+                        EscState::None
+                    };
+                    continue;
+                }
+                EscState::Csi => {
+                    // parameter and intermediate bytes, e.g. "3~" or "1;5A",
+                    // up to the final byte, which tells the key
+                    match c {
+                        0x20..=0x3f => continue,
+                        0x40..=0x7e => esc = EscState::None,
+                        _ => {
+                            esc = EscState::None;
+                            continue;
+                        }
+                    }
+                    match c {
+                        b'A' if pos > 0 => pos -= 1,
+                        b'B' if pos < self.history.len() => pos += 1,
+                        _ => continue,
+                    }
+                    line = self.history.get(pos).cloned().unwrap_or_default();
+                    Self::redraw(&line);
+                    continue;
+                }
+                EscState::None => {}
+            }
+
+            match c {
+                // "\r\n" is a single line break
+                b'\n' if prev == b'\r' => {}
+                b'\r' | b'\n' => {
+                    println!();
+                    return Input::Line(line);
+                }
+                CTRL_C => {
+                    println!("^C");
+                    return Input::Cancel;
+                }
+                BS | DEL => {
+                    if line.pop().is_some() {
+                        print!("\x08 \x08");
+                    }
+                }
+                ESC => esc = EscState::Esc,
+                b' '..=b'~' => {
+                    if line.len() < LINE_MAX {
+                        line.push(c as char);
+                        print!("{}", c as char);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn remember(&mut self, line: &str) {
+        if self.history.back().map(|l| l.as_str()) == Some(line) {
+            return;
+        }
+        if self.history.len() == HISTORY_MAX {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.into());
+    }
+
+    fn execute(&mut self, line: &str) {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return;
+        }
+        self.remember(line.trim());
+
+        match commands().iter().find(|cmd| cmd.name == args[0]) {
+            Some(cmd) => {
+                if let Err(e) = (cmd.func)(&args) {
+                    println!("{}: {:?}", args[0], e);
+                }
+            }
+            None => println!("{}: command not found", args[0]),
+        }
+    }
+}
+
+/// Run the shell on the console; only `poweroff` ends it
+pub fn run() -> ! {
+    println!("type 'help' for the list of commands");
+    let mut shell = Shell::new();
+    loop {
+        if let Input::Line(line) = shell.read_line() {
+            shell.execute(&line);
+        }
+    }
+}
+
+fn usage(text: &str) -> Result<(), Errno> {
+    println!("usage: {}", text);
+    Err(Errno::EINVAL)
+}
+
+fn cmd_help(_args: &[&str]) -> Result<(), Errno> {
+    let width = commands()
+        .iter()
+        .map(|cmd| cmd.name.len())
+        .max()
+        .unwrap_or(0);
+    for cmd in commands() {
+        println!("  {:width$}  {}", cmd.name, cmd.help, width = width);
+    }
+    Ok(())
+}
+
+fn cmd_ls(args: &[&str]) -> Result<(), Errno> {
+    let path = match args {
+        [_] => "/",
+        [_, path] => path,
+        _ => return usage("ls [dir]"),
+    };
+
+    let fd = user::open(path, OpenMode::READ)?;
+    let mut dirent = user::Dirent::new();
+    let res = loop {
+        match user::readdir(fd, &mut dirent) {
+            Ok(true) => {
+                let suffix = if dirent.is_dir() { "/" } else { "" };
+                println!("{}{}", dirent.name(), suffix);
+            }
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    user::close(fd)?;
+    res
+}
+
+fn cmd_cat(args: &[&str]) -> Result<(), Errno> {
+    let path = match args {
+        [_, path] => path,
+        _ => return usage("cat <file>"),
+    };
+
+    let fd = user::open(path, OpenMode::READ)?;
+    let mut buf: [u8; 64] = [0; 64];
+    let mut last = b'\n';
+    let res = loop {
+        match user::read(fd, &mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(len) => {
+                for &c in &buf[..len] {
+                    print!("{}", c as char);
+                }
+                last = buf[len - 1];
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    user::close(fd)?;
+    if last != b'\n' {
+        println!();
+    }
+    res
+}
+
+fn cmd_mkdir(args: &[&str]) -> Result<(), Errno> {
+    match args {
+        [_, path] => user::mkdir(path),
+        _ => usage("mkdir <dir>"),
+    }
+}
+
+fn cmd_echo(args: &[&str]) -> Result<(), Errno> {
+    let (words, redirect) = match args.iter().position(|&a| a == ">" || a == ">>") {
+        Some(i) if i + 2 == args.len() => (&args[1..i], Some((args[i], args[i + 1]))),
+        Some(_) => return usage("echo [text...] [> file | >> file]"),
+        None => (&args[1..], None),
+    };
+
+    let mut text = words.join(" ");
+    text.push('\n');
+
+    match redirect {
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+        Some((op, path)) => {
+            let mode = if op == ">>" {
+                OpenMode::WRITE | OpenMode::CREATE | OpenMode::APPEND
+            } else {
+                OpenMode::WRITE | OpenMode::CREATE | OpenMode::TRUNC
+            };
+            let fd = user::open(path, mode)?;
+            let res = user::write(fd, text.as_bytes());
+            user::close(fd)?;
+            res.map(|_| ())
+        }
+    }
+}
+
+fn cmd_sym(args: &[&str]) -> Result<(), Errno> {
+    let addr = match args {
+        [_, addr] => addr,
+        _ => return usage("sym <hex address>"),
+    };
+    let addr = addr.trim_start_matches("0x");
+    let addr = usize::from_str_radix(addr, 16).or(Err(Errno::EINVAL))?;
+    backtrace::print_addr(addr);
+    Ok(())
+}
+
+fn cmd_meminfo(_args: &[&str]) -> Result<(), Errno> {
+    let stats = heap::stats();
+    println!("heap total   : {:8} bytes", stats.total);
+    println!("     used    : {:8} bytes", stats.total - stats.free);
+    println!("     free    : {:8} bytes", stats.free);
+    println!("     largest : {:8} bytes", stats.largest_free);
+    println!("     areas   : {:8}", stats.free_areas);
+    Ok(())
+}
+
+fn cmd_bt(_args: &[&str]) -> Result<(), Errno> {
+    backtrace::trace(16, backtrace::print_frame);
+    Ok(())
+}
+
+fn cmd_poweroff(_args: &[&str]) -> Result<(), Errno> {
+    semihosting::shutdown();
+    Ok(())
+}
+
+shell_command!(CMD_HELP, "help", "list commands", cmd_help);
+shell_command!(CMD_LS, "ls", "list a directory", cmd_ls);
+shell_command!(CMD_CAT, "cat", "print a file", cmd_cat);
+shell_command!(CMD_MKDIR, "mkdir", "make a directory", cmd_mkdir);
+shell_command!(
+    CMD_ECHO,
+    "echo",
+    "print text, or write it to a file",
+    cmd_echo
+);
+shell_command!(CMD_SYM, "sym", "look up the symbol of an address", cmd_sym);
+shell_command!(CMD_MEMINFO, "meminfo", "show heap usage", cmd_meminfo);
+shell_command!(CMD_BT, "bt", "print a backtrace of the shell", cmd_bt);
+shell_command!(
+    CMD_POWEROFF,
+    "poweroff",
+    "shut down the machine",
+    cmd_poweroff
+);
@@ -0,0 +1,458 @@
+/*
+
+Kernel synchronization primitives.
+
+- `critical_section()` runs a closure with IRQs masked.
+- `SpinLock<T>` is a busy-waiting lock on an LDREX/STREX word. It doesn't
+  mask IRQs, so an interrupt handler must never take a SpinLock that
+  Thread mode may hold; it would spin forever on this single core.
+- `IrqSafeLock<T>` masks IRQs while it is held, and is safe to take from
+  anywhere. Keep the sections short, they delay every interrupt.
+- `Mutex<T>`, `Semaphore` and `CondVar` put the waiting thread to sleep
+  instead. Only threads may wait on them; `Semaphore::post()` and
+  `CondVar::notify_*()` are fine in interrupt handlers as well. A system
+  call can't wait in its handler, so it takes a mutex with
+  `lock_or_queue()` and is restarted once it is woken.
+
+The sleeping primitives keep their state in cells that are only touched
+with IRQs masked, which is enough on a single core. A thread queues itself
+and is marked Sleeping before IRQs are unmasked again, and the switch is
+done by PendSV once they are, so a wake-up in between can't get lost.
+
+[refs]
+- https://developer.arm.com/documentation/dht0008/latest (ARM Synchronization Primitives)
+
+ */
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use crate::{cpu, sched};
+
+/// Call `func` with IRQs masked
+pub fn critical_section<F, R>(func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let primask = cpu::irq_save();
+    let res = func();
+    cpu::irq_restore(primask);
+    res
+}
+
+pub struct SpinLock<T> {
+    word: UnsafeCell<u32>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            word: UnsafeCell::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Set the lock word from 0 to 1; None if it is held, Some(false) if
+    /// the exclusive store lost, e.g. to an exception
+    fn try_acquire(&self) -> Option<bool> {
+        let old: u32;
+        let failed: u32;
+        unsafe {
+            asm!(
+                "ldrex {old}, [{word}]",
+                "cmp {old}, #0",
+                "bne 2f",
+                "strex {failed}, {one}, [{word}]",
+                "b 3f",
+                "2:",
+                "clrex",
+                "mov {failed}, #1",
+                "3:",
+                word = in(reg) self.word.get(),
+                one = in(reg) 1u32,
+                old = out(reg) old,
+                failed = out(reg) failed,
+            )
+        }
+        if old != 0 {
+            return None;
+        }
+        if failed != 0 {
+            return Some(false);
+        }
+        unsafe { asm!("dmb") };
+        Some(true)
+    }
+
+    fn release(&self) {
+        unsafe {
+            asm!("dmb");
+            ptr::write_volatile(self.word.get(), 0);
+        }
+    }
+
+    /// Take the lock if it is free
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        loop {
+            match self.try_acquire() {
+                Some(true) => return Some(SpinLockGuard { lock: self }),
+                Some(false) => continue,
+                None => return None,
+            }
+        }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+        }
+    }
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release()
+    }
+}
+
+pub struct IrqSafeLock<T> {
+    lock: SpinLock<T>,
+}
+
+pub struct IrqSafeLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    primask: u32,
+}
+
+impl<T> IrqSafeLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: SpinLock::new(data),
+        }
+    }
+
+    /// Mask IRQs and take the lock; both are undone when the guard drops
+    pub fn lock(&self) -> IrqSafeLockGuard<'_, T> {
+        let primask = cpu::irq_save();
+        let guard = self.lock.lock();
+        core::mem::forget(guard);
+        IrqSafeLockGuard {
+            lock: &self.lock,
+            primask,
+        }
+    }
+
+    /// Mask IRQs and take the lock if it is free, e.g. in a fault handler
+    /// that may have interrupted the holder
+    pub fn try_lock(&self) -> Option<IrqSafeLockGuard<'_, T>> {
+        let primask = cpu::irq_save();
+        match self.lock.try_lock() {
+            Some(guard) => {
+                core::mem::forget(guard);
+                Some(IrqSafeLockGuard {
+                    lock: &self.lock,
+                    primask,
+                })
+            }
+            None => {
+                cpu::irq_restore(primask);
+                None
+            }
+        }
+    }
+}
+
+impl<'a, T> Deref for IrqSafeLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for IrqSafeLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for IrqSafeLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+        cpu::irq_restore(self.primask);
+    }
+}
+
+/// Threads sleeping on a primitive; IRQs must be masked to touch it
+struct WaitQueue {
+    threads: UnsafeCell<Vec<sched::ThreadId>>,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        Self {
+            threads: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Queue the current thread and mark it Sleeping; it switches out once
+    /// IRQs are unmasked
+    unsafe fn sleep(&self) {
+        if let Some(id) = sched::current() {
+            (*self.threads.get()).push(id);
+            sched::sleep_until(u64::MAX);
+        }
+    }
+
+    /// Drop the current thread from the queue, in case it was woken by
+    /// something else
+    unsafe fn forget_current(&self) {
+        if let Some(id) = sched::current() {
+            (*self.threads.get()).retain(|t| *t != id);
+        }
+    }
+
+    unsafe fn wake_one(&self) {
+        let threads = &mut *self.threads.get();
+        if !threads.is_empty() {
+            sched::wake(threads.remove(0));
+        }
+    }
+
+    unsafe fn wake_all(&self) {
+        // no dealloc, so interrupt handlers may call this
+        for id in (*self.threads.get()).drain(..) {
+            sched::wake(id);
+        }
+    }
+
+    /// Sleep until `ready`, called with IRQs masked, returns true
+    fn wait_until<F>(&self, mut ready: F)
+    where
+        F: FnMut() -> bool,
+    {
+        loop {
+            let primask = cpu::irq_save();
+            unsafe { self.forget_current() };
+            if ready() {
+                cpu::irq_restore(primask);
+                return;
+            }
+            unsafe { self.sleep() };
+            cpu::irq_restore(primask);
+        }
+    }
+}
+
+pub struct Mutex<T> {
+    locked: UnsafeCell<bool>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+#[allow(dead_code)]
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: UnsafeCell::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        critical_section(|| unsafe {
+            let locked = &mut *self.locked.get();
+            !core::mem::replace(locked, true)
+        })
+    }
+
+    /// Take the mutex, sleeping while another thread holds it; threads only
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.waiters.wait_until(|| self.try_acquire());
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.try_acquire() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Take the mutex if it is free; otherwise queue the current thread to
+    /// be woken when it is released, mark it Sleeping and return None. For
+    /// system calls, which can't sleep in their handler and are restarted
+    /// instead.
+    pub fn lock_or_queue(&self) -> Option<MutexGuard<'_, T>> {
+        critical_section(|| unsafe {
+            self.waiters.forget_current();
+            if self.try_acquire() {
+                Some(MutexGuard { mutex: self })
+            } else {
+                self.waiters.sleep();
+                None
+            }
+        })
+    }
+
+    /// IRQs must be masked
+    unsafe fn release(&self) {
+        *self.locked.get() = false;
+        self.waiters.wake_one();
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        critical_section(|| unsafe { self.mutex.release() })
+    }
+}
+
+/// Counting semaphore
+pub struct Semaphore {
+    count: UnsafeCell<usize>,
+    waiters: WaitQueue,
+}
+
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
+
+#[allow(dead_code)]
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: UnsafeCell::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    fn try_take(&self) -> bool {
+        critical_section(|| unsafe {
+            let count = &mut *self.count.get();
+            if *count > 0 {
+                *count -= 1;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Take a unit, sleeping while there is none; threads only
+    pub fn wait(&self) {
+        self.waiters.wait_until(|| self.try_take())
+    }
+
+    pub fn try_wait(&self) -> bool {
+        self.try_take()
+    }
+
+    /// Give a unit back and wake a waiter; for interrupt handlers too
+    pub fn post(&self) {
+        critical_section(|| unsafe {
+            *self.count.get() += 1;
+            self.waiters.wake_one();
+        })
+    }
+}
+
+/// Condition variable for `Mutex`; wake-ups may be spurious, so wait in a
+/// loop that checks the condition
+pub struct CondVar {
+    waiters: WaitQueue,
+}
+
+unsafe impl Send for CondVar {}
+unsafe impl Sync for CondVar {}
+
+#[allow(dead_code)]
+impl CondVar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release the mutex, sleep until notified and take the mutex again
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        core::mem::forget(guard);
+
+        let primask = cpu::irq_save();
+        unsafe {
+            self.waiters.forget_current();
+            self.waiters.sleep();
+            mutex.release();
+        }
+        cpu::irq_restore(primask);
+
+        critical_section(|| unsafe { self.waiters.forget_current() });
+        mutex.lock()
+    }
+
+    /// Wake one waiting thread; for interrupt handlers too
+    pub fn notify_one(&self) {
+        critical_section(|| unsafe { self.waiters.wake_one() })
+    }
+
+    /// Wake every waiting thread; for interrupt handlers too
+    pub fn notify_all(&self) {
+        critical_section(|| unsafe { self.waiters.wake_all() })
+    }
+}
+
+impl Default for CondVar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,247 @@
+/*
+
+System calls are issued from Thread mode with `svc #0`:
+
+    r12     : system call number (SYS_*)
+    r0 - r3 : arguments
+    r0      : return value, or -errno on failure
+
+Arguments are taken from the exception frame stacked by hardware, so the
+handler works for both privileged and unprivileged callers, on MSP or PSP.
+It runs at the lowest priority, so interrupts go on during a call, and the
+thread switches it asks for happen on return. If a thread holds the VFS,
+the caller sleeps until it is released and then issues the `svc` again,
+the same call with the same arguments.
+Pointers passed by the caller are checked before they are dereferenced.
+An unprivileged caller may only pass memory it owns: its own stack and,
+for a program run by the loader, the program's image. Privileged callers
+can reach everything anyway; theirs are only checked against the memory
+regions defined in the linker script.
+
+ */
+
+extern crate posix;
+extern crate vfs;
+
+use core::{arch::asm, mem::size_of, slice, str};
+use posix::Errno;
+use vfs::{FileDescriptor, NodeType, OpenMode, Vfs};
+
+use crate::{cpu, decl_c_symbol_addr, fs, loader, sched};
+decl_c_symbol_addr!(__text_s, text_s);
+decl_c_symbol_addr!(__rodata_e, rodata_e);
+decl_c_symbol_addr!(__data_s, data_s);
+decl_c_symbol_addr!(__bss_e, bss_e);
+decl_c_symbol_addr!(__stack_s, stack_s);
+decl_c_symbol_addr!(__stack_e, stack_e);
+decl_c_symbol_addr!(__heap_s, heap_s);
+decl_c_symbol_addr!(__heap_e, heap_e);
+
+pub const SYS_OPEN: u32 = 0;
+pub const SYS_READ: u32 = 1;
+pub const SYS_WRITE: u32 = 2;
+pub const SYS_CLOSE: u32 = 3;
+pub const SYS_MKDIR: u32 = 4;
+pub const SYS_READDIR: u32 = 5;
+pub const SYS_EXIT: u32 = 6;
+
+pub const DIRENT_NAME_MAX: usize = 56;
+
+#[repr(C)]
+pub struct Dirent {
+    pub ntype: u32,
+    pub namelen: u32,
+    pub name: [u8; DIRENT_NAME_MAX],
+}
+
+impl Dirent {
+    pub const fn new() -> Self {
+        Self {
+            ntype: 0,
+            namelen: 0,
+            name: [0; DIRENT_NAME_MAX],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name[..self.namelen as usize]).unwrap_or("?")
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ntype == NodeType::Directory as u32
+    }
+}
+
+type SyscallFn = fn(&mut Vfs, u32, u32, u32, u32) -> Result<u32, Errno>;
+
+const SYSCALLS: [SyscallFn; 7] = [
+    sys_open,
+    sys_read,
+    sys_write,
+    sys_close,
+    sys_mkdir,
+    sys_readdir,
+    sys_exit,
+];
+
+fn is_user_range(addr: u32, len: u32, writable: bool) -> bool {
+    let start = addr as usize;
+    let end = match start.checked_add(len as usize) {
+        Some(end) => end,
+        None => return false,
+    };
+    let inside = |(s, e): (usize, usize)| s <= start && end <= e;
+
+    if cpu::thread_unprivileged() {
+        let image = loader::current_image().map(|r| (r.start, r.end));
+        return [sched::current_stack(), image]
+            .into_iter()
+            .flatten()
+            .any(inside);
+    }
+
+    let ram = [
+        (data_s(), bss_e()),
+        (stack_s(), stack_e()),
+        (heap_s(), heap_e()),
+    ];
+    ram.into_iter().any(inside) || (!writable && inside((text_s(), rodata_e())))
+}
+
+fn user_slice<'a>(addr: u32, len: u32) -> Result<&'a [u8], Errno> {
+    if !is_user_range(addr, len, false) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(unsafe { slice::from_raw_parts(addr as *const u8, len as usize) })
+}
+
+fn user_slice_mut<'a>(addr: u32, len: u32) -> Result<&'a mut [u8], Errno> {
+    if !is_user_range(addr, len, true) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(addr as *mut u8, len as usize) })
+}
+
+fn user_str<'a>(addr: u32, len: u32) -> Result<&'a str, Errno> {
+    str::from_utf8(user_slice(addr, len)?).or(Err(Errno::EINVAL))
+}
+
+fn sys_open(vfs: &mut Vfs, path: u32, path_len: u32, mode: u32, _: u32) -> Result<u32, Errno> {
+    let path = user_str(path, path_len)?;
+    match vfs.open(path, OpenMode::from(mode)) {
+        Ok(fd) => Ok(fd as u32),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_read(vfs: &mut Vfs, fd: u32, buf: u32, len: u32, _: u32) -> Result<u32, Errno> {
+    let buf = user_slice_mut(buf, len)?;
+    match vfs.read(fd as FileDescriptor, buf) {
+        Ok(size) => Ok(size as u32),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_write(vfs: &mut Vfs, fd: u32, buf: u32, len: u32, _: u32) -> Result<u32, Errno> {
+    let buf = user_slice(buf, len)?;
+    match vfs.write(fd as FileDescriptor, buf) {
+        Ok(size) => Ok(size as u32),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_close(vfs: &mut Vfs, fd: u32, _: u32, _: u32, _: u32) -> Result<u32, Errno> {
+    match vfs.close(fd as FileDescriptor) {
+        Ok(()) => Ok(0),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_mkdir(vfs: &mut Vfs, path: u32, path_len: u32, _: u32, _: u32) -> Result<u32, Errno> {
+    let path = user_str(path, path_len)?;
+    match vfs.mkdir(path) {
+        Ok(()) => Ok(0),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_readdir(vfs: &mut Vfs, fd: u32, dirent: u32, _: u32, _: u32) -> Result<u32, Errno> {
+    let buf = user_slice_mut(dirent, size_of::<Dirent>() as u32)?;
+    if buf.as_ptr() as usize % 4 != 0 {
+        return Err(Errno::EFAULT);
+    }
+    let dirent = unsafe { &mut *(buf.as_mut_ptr() as *mut Dirent) };
+
+    match vfs.readdir(fd as FileDescriptor) {
+        Ok(Some(dent)) => {
+            let name = dent.name.as_bytes();
+            if name.len() > DIRENT_NAME_MAX {
+                return Err(Errno::ENAMETOOLONG);
+            }
+            dirent.ntype = dent.ntype as u32;
+            dirent.namelen = name.len() as u32;
+            dirent.name[..name.len()].copy_from_slice(name);
+            Ok(1)
+        }
+        Ok(None) => Ok(0),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+fn sys_exit(_: &mut Vfs, code: u32, _: u32, _: u32, _: u32) -> Result<u32, Errno> {
+    crate::loader::exited(code as i32);
+    // The caller never gets back here; PendSV switches away on return
+    crate::sched::terminate_current();
+    Ok(0)
+}
+
+#[repr(C)]
+struct SvcFrame {
+    r0: u32,
+    r1: u32,
+    r2: u32,
+    r3: u32,
+    r12: u32,
+    lr: u32,
+    return_address: u32,
+    xpsr: u32,
+}
+
+#[no_mangle]
+unsafe extern "C" fn __svc_dispatch(frame: *mut SvcFrame) {
+    let frame = &mut *frame;
+    let ret = match SYSCALLS.get(frame.r12 as usize) {
+        Some(func) => match fs::lock_for_syscall() {
+            Some(mut vfs) => func(&mut vfs, frame.r0, frame.r1, frame.r2, frame.r3),
+            None => {
+                // back to the 2-byte `svc`, run again once woken
+                frame.return_address -= 2;
+                return;
+            }
+        },
+        None => Err(Errno::ENOSYS),
+    };
+    frame.r0 = match ret {
+        Ok(v) => v,
+        Err(errno) => (-(errno as i32)) as u32,
+    };
+}
+
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn __svc() {
+    asm!(
+        // pick the stack the caller frame was pushed on
+        "tst lr, #4",
+        "ite eq",
+        "mrseq r0, msp",
+        "mrsne r0, psp",
+        // a frame record for the unwinders, see backtrace.rs
+        "push {{r7, lr}}",
+        ".save {{r7, lr}}",
+        "mov r7, sp",
+        "bl __svc_dispatch",
+        "pop {{r7, pc}}",
+        options(noreturn)
+    )
+}
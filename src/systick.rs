@@ -0,0 +1,45 @@
+extern crate bitfield;
+extern crate mmio;
+
+use bitfield::bitfield;
+
+use mmio::{Readable, RegisterRW, Writeable};
+
+bitfield! {
+    Csr: u32 {
+        ENABLE[0];
+        TICKINT[1];
+        CLKSOURCE[2];
+        COUNTFLAG[16];
+    }
+}
+
+pub struct SysTick {
+    csr: RegisterRW<0x0, u32, Csr>,
+    rvr: RegisterRW<0x4, u32, u32>,
+    cvr: RegisterRW<0x8, u32, u32>,
+}
+
+const SYST: *mut SysTick = 0xE000_E010 as *mut SysTick;
+
+/// Start SysTick on the processor clock, firing every `reload + 1` cycles
+pub fn init(reload: u32) {
+    unsafe {
+        (*SYST).csr.write(Csr::from(0));
+        (*SYST).rvr.write(reload & 0x00ff_ffff);
+        (*SYST).cvr.write(0);
+        (*SYST)
+            .csr
+            .write(Csr::ENABLE | Csr::TICKINT | Csr::CLKSOURCE);
+    }
+}
+
+/// Current value of the down counter
+pub fn current() -> u32 {
+    unsafe { (*SYST).cvr.read() }
+}
+
+/// Whether the counter reached 0 since the last call; reading clears it
+pub fn wrapped() -> bool {
+    unsafe { (*SYST).csr.read().is_set(Csr::COUNTFLAG) }
+}
@@ -0,0 +1,192 @@
+/*
+
+Monotonic kernel clock on SysTick.
+
+SysTick wraps TICK_HZ times per second, and each wrap advances a 64-bit
+tick counter. `now()` adds the elapsed part of the current tick read from
+the SysTick down counter, so timestamps have a resolution of one processor
+clock.
+
+The wraps are counted by whoever looks at the counter first, the SysTick
+handler or `now()`, from COUNTFLAG, so time keeps going while IRQs are
+masked, e.g. for `Deadline` polling at boot or in a panic. It takes a look
+at least once per tick not to miss one.
+
+ */
+
+use core::{fmt, ops, time::Duration};
+
+use crate::sync::IrqSafeLock;
+use crate::{board, scb, sched, systick};
+
+pub const TICK_HZ: u32 = 1000;
+
+const CYCLES_PER_TICK: u32 = board::SYSCLK_HZ / TICK_HZ;
+const NS_PER_TICK: u64 = 1_000_000_000 / TICK_HZ as u64;
+
+struct Clock {
+    ticks: u64,
+    /// Counter value at the last look
+    last_cvr: u32,
+}
+
+impl Clock {
+    /// Count the wrap since the last look, if any; the ticks and the counter
+    fn update(&mut self) -> (u64, u32) {
+        let mut cvr = systick::current();
+        // read after the counter, so a wrap in between is counted now
+        let wrapped = systick::wrapped();
+        if wrapped {
+            cvr = systick::current();
+        }
+        // the counter going up means a wrap whose flag was cleared
+        if wrapped || cvr > self.last_cvr {
+            self.ticks += 1;
+        }
+        self.last_cvr = cvr;
+        (self.ticks, cvr)
+    }
+}
+
+static CLOCK: IrqSafeLock<Clock> = IrqSafeLock::new(Clock {
+    ticks: 0,
+    last_cvr: CYCLES_PER_TICK - 1,
+});
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Instant {
+    ns: u64,
+}
+
+impl Instant {
+    pub const fn from_nanos(ns: u64) -> Self {
+        Self { ns }
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.ns
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.ns.saturating_sub(earlier.ns))
+    }
+
+    #[allow(dead_code)]
+    pub fn elapsed(&self) -> Duration {
+        now().duration_since(*self)
+    }
+}
+
+impl ops::Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_nanos(self.ns.saturating_add(rhs.as_nanos() as u64))
+    }
+}
+
+impl ops::Sub<Instant> for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:06}",
+            self.ns / 1_000_000_000,
+            (self.ns / 1000) % 1_000_000
+        )
+    }
+}
+
+/// A point in time to give up waiting at
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Deadline {
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Some(now() + timeout),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub const fn at(at: Instant) -> Self {
+        Self { at: Some(at) }
+    }
+
+    #[allow(dead_code)]
+    pub const fn never() -> Self {
+        Self { at: None }
+    }
+
+    pub fn expired(&self) -> bool {
+        match self.at {
+            Some(at) => now() >= at,
+            None => false,
+        }
+    }
+
+    /// `None` if the deadline never expires
+    #[allow(dead_code)]
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at.map(|at| at.duration_since(now()))
+    }
+}
+
+pub fn init() {
+    scb::set_systick_priority(scb::PRIO_TICK);
+    systick::init(CYCLES_PER_TICK - 1);
+    // it went from whatever it was before to a full tick, without a wrap
+    CLOCK.lock().last_cvr = CYCLES_PER_TICK - 1;
+}
+
+pub fn ticks() -> u64 {
+    CLOCK.lock().update().0
+}
+
+pub fn now() -> Instant {
+    let (ticks, cvr) = CLOCK.lock().update();
+    let cycles = (CYCLES_PER_TICK - 1 - cvr) as u64;
+    Instant::from_nanos(ticks * NS_PER_TICK + cycles * 1_000_000_000 / board::SYSCLK_HZ as u64)
+}
+
+/// Ticks in `d`, rounded up
+pub fn duration_to_ticks(d: Duration) -> u64 {
+    let ns = d.as_nanos() as u64;
+    (ns + NS_PER_TICK - 1) / NS_PER_TICK
+}
+
+/// Sleep for at least `d`. Busy-waits if the scheduler isn't running.
+pub fn sleep(d: Duration) {
+    if sched::current().is_none() {
+        let deadline = Deadline::after(d);
+        while !deadline.expired() {}
+        return;
+    }
+
+    // +1 since the current tick has partly elapsed
+    sched::sleep_until(ticks() + duration_to_ticks(d) + 1);
+}
+
+pub fn sleep_ms(ms: u32) {
+    sleep(Duration::from_millis(ms as u64))
+}
+
+/// Busy-wait, without giving up the CPU
+#[allow(dead_code)]
+pub fn delay_us(us: u32) {
+    let deadline = Deadline::after(Duration::from_micros(us as u64));
+    while !deadline.expired() {}
+}
+
+#[no_mangle]
+unsafe extern "C" fn __systick() {
+    let (ticks, _) = CLOCK.lock().update();
+    sched::tick(ticks);
+}
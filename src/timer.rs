@@ -0,0 +1,381 @@
+/*
+
+Software timers.
+
+One-shot and periodic callbacks on a hierarchical timer wheel (see
+timer_wheel), which the first APB timer of the board advances TICK_HZ
+times per second. A callback runs either in the timer interrupt, where it
+has to be short and must not allocate, or on the work queue's thread.
+
+Only thread context allocates and frees: the interrupt moves timers around
+the wheel and takes their callbacks out to run them, and timers that are
+done, i.e. cancelled or one-shots that have run, are dropped later by
+`reap()` on a thread.
+
+`sleep()` is the async version, a future for tasks on the executor that
+completes through a one-shot timer in the interrupt.
+
+ */
+
+extern crate posix;
+extern crate timer_wheel;
+
+use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context as TaskContext, Poll, Waker};
+use core::time::Duration;
+use posix::Errno;
+use timer_wheel::TimerWheel;
+
+pub use timer_wheel::TimerId;
+
+use crate::arm_timer::ArmTimer;
+use crate::sync::IrqSafeLock;
+use crate::{board, cpu, error, nvic, println, scb, shell_command, time, warn, workqueue};
+
+/// Where a timer's callback runs
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Context {
+    /// In the timer interrupt
+    Irq,
+    /// On the work queue's thread
+    Thread,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Armed,
+    Cancelled,
+    /// A one-shot that has run
+    Fired,
+}
+
+type Callback = Box<dyn FnMut() + Send>;
+
+struct SoftTimer {
+    /// In ticks; 0 for a one-shot
+    period: u64,
+    context: Context,
+    state: State,
+    /// Taken out while the callback runs
+    func: Option<Callback>,
+}
+
+const TIMER: *mut ArmTimer = board::TIMERS[0].base as *mut ArmTimer;
+
+struct Timers {
+    wheel: TimerWheel<SoftTimer>,
+    ticks: u64,
+}
+
+static TIMERS: IrqSafeLock<Timers> = IrqSafeLock::new(Timers {
+    wheel: TimerWheel::new(),
+    ticks: 0,
+});
+
+pub fn init() {
+    let irq = board::TIMERS[0].irq;
+    nvic::set_priority(irq, scb::PRIO_TICK);
+    let res = unsafe { (*TIMER).start_irq(irq, interrupt) };
+    match res {
+        Ok(()) => unsafe { (*TIMER).start(board::SYSCLK_HZ / time::TICK_HZ - 1, true) },
+        Err(e) => error!("failed to register the timer interrupt: {:?}", e),
+    }
+}
+
+fn start(delay: Duration, period: u64, context: Context, func: Callback) -> TimerId {
+    reap();
+
+    let timer = SoftTimer {
+        period,
+        context,
+        state: State::Armed,
+        func: Some(func),
+    };
+    let mut timers = TIMERS.lock();
+    // +1 since the current tick has partly elapsed
+    let expires = timers.ticks + time::duration_to_ticks(delay) + 1;
+    timers.wheel.insert(expires, timer)
+}
+
+/// Call `func` once, `delay` from now; from Thread mode only
+pub fn oneshot<F>(delay: Duration, context: Context, func: F) -> TimerId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut func = Some(func);
+    start(
+        delay,
+        0,
+        context,
+        Box::new(move || {
+            if let Some(func) = func.take() {
+                func()
+            }
+        }),
+    )
+}
+
+/// Call `func` every `period`, without drift; from Thread mode only
+pub fn periodic<F>(period: Duration, context: Context, func: F) -> Result<TimerId, Errno>
+where
+    F: FnMut() + Send + 'static,
+{
+    let ticks = time::duration_to_ticks(period);
+    if ticks == 0 {
+        return Err(Errno::EINVAL);
+    }
+    Ok(start(period, ticks, context, Box::new(func)))
+}
+
+/// Stop the timer; a callback that is running or queued to run on the
+/// thread is not called again. ENOENT if the timer is done already.
+pub fn cancel(id: TimerId) -> Result<(), Errno> {
+    let res = {
+        let mut timers = TIMERS.lock();
+        match timers.wheel.get_mut(id) {
+            Some(timer) if timer.state == State::Armed => {
+                timer.state = State::Cancelled;
+                timers.wheel.stop(id);
+                Ok(())
+            }
+            _ => Err(Errno::ENOENT),
+        }
+    };
+
+    if res.is_ok() {
+        schedule_reap();
+    }
+    res
+}
+
+struct Wakeup {
+    fired: AtomicBool,
+    waker: IrqSafeLock<Option<Waker>>,
+}
+
+/// Future of `sleep()`
+pub struct Sleep {
+    delay: Duration,
+    timer: Option<(TimerId, Arc<Wakeup>)>,
+}
+
+/// Complete `delay` from the first poll; Thread mode only
+#[allow(dead_code)]
+pub fn sleep(delay: Duration) -> Sleep {
+    Sleep { delay, timer: None }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<()> {
+        if let Some((_, wakeup)) = &self.timer {
+            if wakeup.fired.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            *wakeup.waker.lock() = Some(cx.waker().clone());
+            // it may have fired before the waker was replaced
+            return match wakeup.fired.load(Ordering::Acquire) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            };
+        }
+
+        let wakeup = Arc::new(Wakeup {
+            fired: AtomicBool::new(false),
+            waker: IrqSafeLock::new(Some(cx.waker().clone())),
+        });
+        let fired = wakeup.clone();
+        let id = oneshot(self.delay, Context::Irq, move || {
+            fired.fired.store(true, Ordering::Release);
+            let waker = fired.waker.lock().take();
+            if let Some(waker) = waker {
+                waker.wake()
+            }
+        });
+        self.timer = Some((id, wakeup));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((id, wakeup)) = &self.timer {
+            if !wakeup.fired.load(Ordering::Acquire) {
+                let _ = cancel(*id);
+            }
+        }
+    }
+}
+
+/// Drop the timers that are done; Thread mode only
+fn reap() {
+    loop {
+        // dropped once the lock is released
+        let timer = {
+            let mut timers = TIMERS.lock();
+            let done = timers
+                .wheel
+                .iter()
+                .find(|(_, timer, _)| timer.state != State::Armed && timer.func.is_some())
+                .map(|(id, _, _)| id);
+            done.and_then(|id| timers.wheel.remove(id))
+        };
+
+        if timer.is_none() {
+            break;
+        }
+    }
+}
+
+fn schedule_reap() {
+    if cpu::in_thread_mode() {
+        reap()
+    } else {
+        let _ = workqueue::queue(|_| reap(), 0);
+    }
+}
+
+/// Run the callback of a timer that came due
+fn run(id: TimerId) {
+    let func = match TIMERS.lock().wheel.get_mut(id) {
+        Some(timer) if timer.state == State::Armed => timer.func.take(),
+        _ => None,
+    };
+
+    let mut func = match func {
+        Some(func) => func,
+        None => return,
+    };
+    func();
+
+    let done = {
+        let mut timers = TIMERS.lock();
+        // not reaped while its callback is out
+        let timer = timers.wheel.get_mut(id).unwrap();
+        timer.func = Some(func);
+        if timer.period == 0 && timer.state == State::Armed {
+            timer.state = State::Fired;
+        }
+        timer.state != State::Armed
+    };
+
+    if done {
+        schedule_reap();
+    }
+}
+
+fn run_work(id: usize) {
+    run(TimerId::from_raw(id as u32))
+}
+
+fn interrupt() {
+    let now = {
+        let mut timers = TIMERS.lock();
+        timers.ticks += 1;
+        timers.ticks
+    };
+
+    loop {
+        let due = {
+            let mut timers = TIMERS.lock();
+            let wheel = &mut timers.wheel;
+            wheel.poll(now).map(|id| {
+                let timer = wheel.get(id).unwrap();
+                let (period, context) = (timer.period, timer.context);
+                if period > 0 {
+                    wheel.rearm(id, now + period);
+                }
+                (id, context)
+            })
+        };
+
+        match due {
+            Some((id, Context::Irq)) => run(id),
+            Some((id, Context::Thread)) => {
+                if workqueue::queue(run_work, id.into_raw() as usize).is_err() {
+                    warn!("timer {} missed", id);
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+fn list() {
+    let (now, timers): (u64, Vec<_>) = {
+        let timers = TIMERS.lock();
+        let list = timers
+            .wheel
+            .iter()
+            .map(|(id, timer, expires)| (id, expires, timer.period, timer.context, timer.state))
+            .collect();
+        (timers.ticks, list)
+    };
+
+    let ms = |ticks: u64| ticks * 1000 / time::TICK_HZ as u64;
+    println!("        ID  DUE(ms)  PERIOD(ms)  CONTEXT  STATE");
+    for (id, expires, period, context, state) in timers {
+        let due = match expires {
+            Some(at) => format!("{}", ms(at.saturating_sub(now))),
+            None => "-".into(),
+        };
+        let period = match period {
+            0 => "-".into(),
+            p => format!("{}", ms(p)),
+        };
+        println!(
+            "{:10}  {:>7}  {:>10}  {:7}  {:?}",
+            id.into_raw(),
+            due,
+            period,
+            format!("{:?}", context),
+            state
+        );
+    }
+}
+
+fn cmd_timer(args: &[&str]) -> Result<(), Errno> {
+    let parse_ms = |s: &str| {
+        s.parse::<u64>()
+            .map(Duration::from_millis)
+            .or(Err(Errno::EINVAL))
+    };
+    match args {
+        [_] => list(),
+        [_, "after", ms, words @ ..] => {
+            let text = words.join(" ");
+            let id = oneshot(parse_ms(ms)?, Context::Thread, move || {
+                println!("[{}] {}", time::now(), text)
+            });
+            println!("timer {}", id);
+        }
+        [_, "every", ms, words @ ..] => {
+            let text = words.join(" ");
+            let id = periodic(parse_ms(ms)?, Context::Thread, move || {
+                println!("[{}] {}", time::now(), text)
+            })?;
+            println!("timer {}", id);
+        }
+        [_, "cancel", id] => {
+            let id = id.parse::<u32>().or(Err(Errno::EINVAL))?;
+            cancel(TimerId::from_raw(id))?;
+        }
+        _ => {
+            println!("usage: timer [after <ms> [text...] | every <ms> [text...] | cancel <id>]");
+            return Err(Errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(
+    CMD_TIMER,
+    "timer",
+    "list the software timers, or print a text after or every some ms",
+    cmd_timer
+);
@@ -0,0 +1,139 @@
+/*
+
+Thin wrappers around the system calls for code running in Thread mode,
+privileged or not. See syscall.rs for the calling convention.
+
+The kernel only takes memory that an unprivileged caller owns, i.e. its
+stack, so paths and data to write, often string literals in the kernel's
+.rodata, are passed through a copy on the stack.
+
+ */
+
+extern crate posix;
+extern crate vfs;
+
+use core::arch::asm;
+use posix::Errno;
+use vfs::{FileDescriptor, OpenMode};
+
+use crate::syscall::{SYS_CLOSE, SYS_EXIT, SYS_MKDIR, SYS_OPEN, SYS_READ, SYS_READDIR, SYS_WRITE};
+
+pub use crate::syscall::Dirent;
+
+unsafe fn syscall(nr: u32, a0: u32, a1: u32, a2: u32, a3: u32) -> u32 {
+    let ret: u32;
+    asm!(
+        "svc #0",
+        inout("r0") a0 => ret,
+        in("r1") a1,
+        in("r2") a2,
+        in("r3") a3,
+        in("r12") nr,
+    );
+    ret
+}
+
+/// Size of the copies on the stack; the longest path
+const BOUNCE_SIZE: usize = 128;
+
+/// Call `func` with a copy of `data` on the stack
+fn on_stack<F>(data: &[u8], func: F) -> Result<u32, Errno>
+where
+    F: FnOnce(&[u8]) -> u32,
+{
+    let mut buf = [0u8; BOUNCE_SIZE];
+    let copy = buf.get_mut(..data.len()).ok_or(Errno::ENAMETOOLONG)?;
+    copy.copy_from_slice(data);
+    result(func(copy))
+}
+
+fn result(ret: u32) -> Result<u32, Errno> {
+    let ret = ret as i32;
+    if ret < 0 {
+        Err(Errno::try_from(-ret).unwrap_or(Errno::EINVAL))
+    } else {
+        Ok(ret as u32)
+    }
+}
+
+pub fn open(path: &str, mode: OpenMode) -> Result<FileDescriptor, Errno> {
+    on_stack(path.as_bytes(), |path| unsafe {
+        syscall(
+            SYS_OPEN,
+            path.as_ptr() as u32,
+            path.len() as u32,
+            mode.into(),
+            0,
+        )
+    })
+    .map(|fd| fd as FileDescriptor)
+}
+
+pub fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<usize, Errno> {
+    let ret = unsafe {
+        syscall(
+            SYS_READ,
+            fd as u32,
+            buf.as_mut_ptr() as u32,
+            buf.len() as u32,
+            0,
+        )
+    };
+    result(ret).map(|size| size as usize)
+}
+
+/// Written in pieces of BOUNCE_SIZE; stops at the first short write or error
+pub fn write(fd: FileDescriptor, buf: &[u8]) -> Result<usize, Errno> {
+    let mut written = 0;
+    for chunk in buf.chunks(BOUNCE_SIZE) {
+        let res = on_stack(chunk, |chunk| unsafe {
+            syscall(
+                SYS_WRITE,
+                fd as u32,
+                chunk.as_ptr() as u32,
+                chunk.len() as u32,
+                0,
+            )
+        });
+        let size = match res {
+            Ok(size) => size as usize,
+            Err(e) if written == 0 => return Err(e),
+            Err(_) => break,
+        };
+        written += size;
+        if size < chunk.len() {
+            break;
+        }
+    }
+    Ok(written)
+}
+
+pub fn close(fd: FileDescriptor) -> Result<(), Errno> {
+    let ret = unsafe { syscall(SYS_CLOSE, fd as u32, 0, 0, 0) };
+    result(ret).map(|_| ())
+}
+
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    on_stack(path.as_bytes(), |path| unsafe {
+        syscall(SYS_MKDIR, path.as_ptr() as u32, path.len() as u32, 0, 0)
+    })
+    .map(|_| ())
+}
+
+/// Returns `Ok(false)` at the end of the directory
+pub fn readdir(fd: FileDescriptor, dirent: &mut Dirent) -> Result<bool, Errno> {
+    let ret = unsafe { syscall(SYS_READDIR, fd as u32, dirent as *mut _ as u32, 0, 0) };
+    result(ret).map(|n| n != 0)
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall(SYS_EXIT, code as u32, 0, 0, 0) };
+    loop {}
+}
+
+/// Entry point of unprivileged threads; see `sched::spawn_user()`
+pub extern "C" fn thread_start(entry: usize) -> ! {
+    let entry: extern "C" fn() = unsafe { core::mem::transmute(entry) };
+    entry();
+    exit(0)
+}
@@ -0,0 +1,95 @@
+/*
+
+Work queue: functions queued from interrupt handlers, or from anywhere
+else, run one after another in Thread mode on the "kworker" thread.
+
+An item is a function and an argument kept in a fixed ring, so queueing
+doesn't allocate and is safe in interrupt handlers. The worker sleeps while
+the ring is empty and `queue()` wakes it.
+
+ */
+
+extern crate posix;
+
+use posix::Errno;
+
+use crate::sync::IrqSafeLock;
+use crate::{sched, warn};
+
+type Work = (fn(usize), usize);
+
+const CAPACITY: usize = 32;
+
+struct Queue {
+    items: [Option<Work>; CAPACITY],
+    head: usize,
+    len: usize,
+    worker: Option<sched::ThreadId>,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % CAPACITY;
+        self.len -= 1;
+        work
+    }
+}
+
+static QUEUE: IrqSafeLock<Queue> = IrqSafeLock::new(Queue {
+    items: [None; CAPACITY],
+    head: 0,
+    len: 0,
+    worker: None,
+});
+
+pub fn init() {
+    let id = sched::spawn("kworker", worker);
+    QUEUE.lock().worker = Some(id);
+}
+
+/// Have the worker call `func(arg)`; EAGAIN if the queue is full
+pub fn queue(func: fn(usize), arg: usize) -> Result<(), Errno> {
+    let res = {
+        let mut queue = QUEUE.lock();
+        if queue.len == CAPACITY {
+            Err(Errno::EAGAIN)
+        } else {
+            let idx = (queue.head + queue.len) % CAPACITY;
+            queue.items[idx] = Some((func, arg));
+            queue.len += 1;
+            if let Some(worker) = queue.worker {
+                sched::wake(worker);
+            }
+            Ok(())
+        }
+    };
+
+    if res.is_err() {
+        warn!("queue full, work dropped");
+    }
+    res
+}
+
+fn worker() {
+    loop {
+        let work = {
+            let mut queue = QUEUE.lock();
+            let work = queue.pop();
+            if work.is_none() {
+                // The switch waits for IRQs to be unmasked, by which time
+                // the thread is Sleeping; a wake-up from queue() can't get
+                // lost.
+                sched::sleep_until(u64::MAX);
+            }
+            work
+        };
+
+        if let Some((func, arg)) = work {
+            func(arg);
+        }
+    }
+}